@@ -0,0 +1,154 @@
+use anyhow::{anyhow, Result};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+use tokio::runtime::Runtime;
+use typey_pipe::api::{OutputEvent, OutputEvents, SessionBuilder, SessionHandle};
+
+const SHELL: &str = "/bin/sh";
+
+/// Printed after a benchmark's workload so the reader knows it has seen everything
+const DONE_MARKER: &str = "__tp_bench_done__";
+
+/// How long a single measurement may take before it is reported as stuck
+const MEASUREMENT_TIMEOUT: Duration = Duration::from_secs(60);
+
+fn pty(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let bytes = 4 * 1024 * 1024;
+
+    let mut group = c.benchmark_group("pty");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(bytes as u64));
+    group.bench_function("read_throughput", |b| {
+        b.iter(|| runtime.block_on(pty_throughput(bytes, 1)).unwrap())
+    });
+    group.finish();
+}
+
+fn fanout(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let bytes = 1024 * 1024;
+
+    let mut group = c.benchmark_group("tee");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(bytes as u64 * 5));
+    group.bench_function("fanout_1mib_5_readers", |b| {
+        b.iter(|| runtime.block_on(pty_throughput(bytes, 5)).unwrap())
+    });
+    group.finish();
+}
+
+fn injection(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+
+    let mut group = c.benchmark_group("queue");
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(20));
+    group.bench_function("enqueue_to_output", |b| {
+        // Measured inside one session, so shell startup is not part of the latency
+        b.iter_custom(|iters| {
+            runtime
+                .block_on(injection_latency(iters as usize))
+                .unwrap()
+                * iters as u32
+        })
+    });
+    group.finish();
+}
+
+/// Time for `readers` subscribers to each receive `bytes` bytes printed by the shell
+async fn pty_throughput(bytes: usize, readers: usize) -> Result<Duration> {
+    let temp_dir = TempDir::new()?;
+    let session = spawn(&temp_dir).await?;
+    let events = (0..readers)
+        .map(|_| {
+            session
+                .events()
+                .ok_or_else(|| anyhow!("Shell exited before the benchmark started"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let started = Instant::now();
+    session
+        .write(
+            format!(
+                "head -c {} /dev/zero | tr '\\0' x; echo; {}\n",
+                bytes,
+                marker_command()
+            )
+            .as_bytes(),
+        )
+        .await?;
+    let readers: Vec<_> = events
+        .into_iter()
+        .map(|mut events| tokio::spawn(async move { read_until_marker(&mut events).await }))
+        .collect();
+    for reader in readers {
+        reader.await??;
+    }
+    Ok(started.elapsed())
+}
+
+/// Average time from enqueueing a command to seeing its output, over `samples` commands
+async fn injection_latency(samples: usize) -> Result<Duration> {
+    let temp_dir = TempDir::new()?;
+    let session = spawn(&temp_dir).await?;
+    let mut events = session
+        .events()
+        .ok_or_else(|| anyhow!("Shell exited before the benchmark started"))?;
+    let queue = session.queue();
+
+    let mut total = Duration::ZERO;
+    for _ in 0..samples {
+        let started = Instant::now();
+        queue.command(&marker_command()).await?;
+        read_until_marker(&mut events).await?;
+        total += started.elapsed();
+    }
+    Ok(total / samples.max(1) as u32)
+}
+
+async fn spawn(temp_dir: &TempDir) -> Result<SessionHandle> {
+    SessionBuilder::new(temp_dir.path().join("bench"))
+        .shell(SHELL)
+        .scrollback_lines(0)
+        .spawn()
+        .await
+}
+
+/// Prints the marker without the echoed command line containing it
+fn marker_command() -> String {
+    let (head, tail) = DONE_MARKER.split_at(DONE_MARKER.len() / 2);
+    format!("echo {}''{}", head, tail)
+}
+
+/// Read output until the marker has been printed
+async fn read_until_marker(events: &mut OutputEvents) -> Result<()> {
+    let mut tail = Vec::new();
+    tokio::time::timeout(MEASUREMENT_TIMEOUT, async {
+        loop {
+            match events.next().await {
+                Some(OutputEvent::Output(chunk)) => {
+                    tail.extend_from_slice(&chunk);
+                    if String::from_utf8_lossy(&tail).contains(DONE_MARKER) {
+                        return Ok(());
+                    }
+                    // Keep enough to find a marker split across chunks
+                    let keep = tail.len().saturating_sub(DONE_MARKER.len());
+                    tail.drain(..keep);
+                }
+                // A lagging reader skips output, which would make the run look faster
+                Some(OutputEvent::Lagged(skipped)) => {
+                    return Err(anyhow!("Benchmark reader lagged by {} chunks", skipped))
+                }
+                _ => return Err(anyhow!("Shell exited during the benchmark")),
+            }
+        }
+    })
+    .await
+    .map_err(|_| anyhow!("Benchmark timed out"))?
+}
+
+criterion_group!(benches, pty, fanout, injection);
+criterion_main!(benches);
@@ -0,0 +1,393 @@
+use crate::shell::context::SessionContext;
+use crate::shell::control::control_socket_path;
+use crate::shell::headless::{self, run_headless, run_headless_session};
+use crate::shell::pty::PtySession;
+use crate::shell::queue::{enqueue_file, wait_for_injection};
+use crate::shell::tee::{OutputChunk, OutputSubscriptions, ReaderEnd};
+use crate::shell::types::ShellConfig;
+use anyhow::{anyhow, Result};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, Mutex};
+
+pub use crate::shell::ansi::strip_ansi;
+pub use crate::shell::auth::{Authorizer, Decision, Source};
+pub use crate::shell::mock::MockPty;
+pub use crate::shell::types::{
+    FailureCode, QueueFailure, QueueMessage, Terminator, QUEUE_MESSAGE_VERSION,
+};
+pub use bytes::Bytes;
+
+/// Configures and starts a session: a shell in a PTY with a queue directory whose files are
+/// injected into it, and nothing attached to the terminal.
+///
+/// **Defaults:**
+/// - Shell: `$SHELL`, else `/bin/bash`, 80x24, starting in the home directory
+/// - Log file: `<queue dir>.log` next to the queue directory
+/// - Scrollback: 10000 lines
+/// - Transcript: none
+/// - Terminator: `\n`, with no authorizer
+#[derive(Clone)]
+pub struct SessionBuilder {
+    config: ShellConfig,
+    queue_dir: PathBuf,
+    log_file: Option<PathBuf>,
+    transcript_file: Option<PathBuf>,
+    terminator: Terminator,
+    authorizer: Option<Arc<dyn Authorizer>>,
+    mock: Option<MockPty>,
+}
+
+impl std::fmt::Debug for SessionBuilder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionBuilder")
+            .field("config", &self.config)
+            .field("queue_dir", &self.queue_dir)
+            .field("log_file", &self.log_file)
+            .field("transcript_file", &self.transcript_file)
+            .field("terminator", &self.terminator)
+            .field("authorizer", &self.authorizer.is_some())
+            .field("mock", &self.mock)
+            .finish()
+    }
+}
+
+impl SessionBuilder {
+    /// A session whose queue is `queue_dir`, created if it does not exist
+    pub fn new(queue_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            config: ShellConfig::default(),
+            queue_dir: queue_dir.into(),
+            log_file: None,
+            transcript_file: None,
+            terminator: Terminator::Lf,
+            authorizer: None,
+            mock: None,
+        }
+    }
+
+    pub fn shell(mut self, path: impl Into<String>) -> Self {
+        self.config.shell_path = path.into();
+        self
+    }
+
+    pub fn size(mut self, cols: u16, rows: u16) -> Self {
+        self.config.cols = cols;
+        self.config.rows = rows;
+        self
+    }
+
+    pub fn cwd(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config.cwd = Some(dir.into());
+        self
+    }
+
+    /// Set an environment variable in the shell; may be called repeatedly
+    pub fn env(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.config.env.push((name.into(), value.into()));
+        self
+    }
+
+    /// Output lines kept for `SessionHandle::scrollback` (0 keeps none)
+    pub fn scrollback_lines(mut self, lines: usize) -> Self {
+        self.config.scroll_buffer_size = lines;
+        self
+    }
+
+    pub fn log_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.log_file = Some(path.into());
+        self
+    }
+
+    /// Record the shell's output to `path`, each line prefixed with the time it was read
+    pub fn transcript(mut self, path: impl Into<PathBuf>) -> Self {
+        self.transcript_file = Some(path.into());
+        self
+    }
+
+    /// Appended to queued commands that do not choose their own terminator
+    pub fn terminator(mut self, terminator: Terminator) -> Self {
+        self.terminator = terminator;
+        self
+    }
+
+    /// Consult `authorizer` about every queued message before it reaches this session's
+    /// shell
+    pub fn authorizer(mut self, authorizer: Arc<dyn Authorizer>) -> Self {
+        self.authorizer = Some(authorizer);
+        self
+    }
+
+    /// Talk to `pty`'s scripted shell instead of spawning a real one
+    pub fn mock(mut self, pty: MockPty) -> Self {
+        self.mock = Some(pty);
+        self
+    }
+
+    /// Start the shell and its queue engine
+    pub async fn spawn(self) -> Result<SessionHandle> {
+        let log_file = match self.log_file {
+            Some(log_file) => log_file,
+            None => {
+                let name = self.queue_dir.file_name().ok_or_else(|| {
+                    anyhow!("Queue directory {} has no name", self.queue_dir.display())
+                })?;
+                self.queue_dir
+                    .with_file_name(format!("{}.log", name.to_string_lossy()))
+            }
+        };
+        let context = Arc::new(SessionContext::new(self.terminator).authorizer(self.authorizer));
+        let inner = match self.mock {
+            Some(pty) => {
+                let (pty_parent, child) = pty.start(self.config.cols, self.config.rows);
+                let session = PtySession::from_parts(self.config, pty_parent, child)?;
+                run_headless_session(
+                    Arc::new(Mutex::new(session)),
+                    self.queue_dir,
+                    log_file,
+                    self.transcript_file,
+                    context,
+                )
+                .await?
+            }
+            None => {
+                run_headless(
+                    self.config,
+                    self.queue_dir,
+                    log_file,
+                    self.transcript_file,
+                    context,
+                )
+                .await?
+            }
+        };
+        Ok(SessionHandle { inner })
+    }
+}
+
+/// A running session started by `SessionBuilder::spawn`. Dropping it stops the queue engine
+/// and kills the shell.
+pub struct SessionHandle {
+    inner: headless::SessionHandle,
+}
+
+impl SessionHandle {
+    pub fn session_id(&self) -> &str {
+        self.inner.session_id()
+    }
+
+    /// A client for this session's queue
+    pub fn queue(&self) -> QueueClient {
+        QueueClient::new(self.inner.queue_dir())
+    }
+
+    /// Output from now on, or `None` once the shell has closed the PTY
+    pub fn events(&self) -> Option<OutputEvents> {
+        self.inner.subscribe().map(|receiver| OutputEvents {
+            receiver,
+            output: self.inner.output(),
+            exit_reported: false,
+        })
+    }
+
+    /// The last `count` complete lines of output, escape sequences included
+    pub fn scrollback(&self, count: usize) -> Vec<String> {
+        self.inner
+            .scrollback()
+            .lock()
+            .map(|scrollback| scrollback.tail(count))
+            .unwrap_or_default()
+    }
+
+    /// Write `bytes` to the shell right away, bypassing the queue
+    pub async fn write(&self, bytes: &[u8]) -> Result<()> {
+        self.inner.session().lock().await.send_bytes(bytes)
+    }
+
+    pub async fn resize(&self, cols: u16, rows: u16) -> Result<()> {
+        self.inner.session().lock().await.resize(rows, cols)
+    }
+
+    /// Wait for the shell to exit on its own and return its exit code
+    pub async fn wait(&mut self) -> Result<u32> {
+        self.inner.wait().await
+    }
+
+    /// Let the queue drain, then hang up on the shell, killing it if it outlives `grace`
+    pub async fn shutdown(&mut self, grace: Duration) -> Result<u32> {
+        self.inner.shutdown(grace).await
+    }
+
+    /// Kill the shell, dropping anything still queued
+    pub async fn kill(&mut self) -> Result<u32> {
+        self.inner.kill().await
+    }
+}
+
+/// Something that happened to a session's output
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum OutputEvent {
+    /// Bytes the shell wrote, escape sequences included, shared with the session's other
+    /// readers rather than copied
+    Output(Bytes),
+    /// This many chunks were skipped because the receiver fell behind
+    Lagged(u64),
+    /// The shell closed the PTY, normally by exiting, and no more output will come.
+    /// `SessionHandle::wait` returns its exit code.
+    ChildExited,
+}
+
+/// The output of a session, as returned by `SessionHandle::events`
+pub struct OutputEvents {
+    receiver: broadcast::Receiver<OutputChunk>,
+    output: OutputSubscriptions,
+    exit_reported: bool,
+}
+
+impl OutputEvents {
+    /// The next event, or `None` after the last output. `ChildExited` comes before the end
+    /// when the shell closed the PTY, but not when reading it failed.
+    pub async fn next(&mut self) -> Option<OutputEvent> {
+        match self.receiver.recv().await {
+            Ok(chunk) => Some(OutputEvent::Output(chunk)),
+            Err(broadcast::error::RecvError::Lagged(skipped)) => Some(OutputEvent::Lagged(skipped)),
+            Err(broadcast::error::RecvError::Closed) => {
+                let exited = self.output.reader_end() == Some(ReaderEnd::ChildExited);
+                (exited && !std::mem::replace(&mut self.exit_reported, true))
+                    .then_some(OutputEvent::ChildExited)
+            }
+        }
+    }
+}
+
+/// Adds messages to a session's queue directory the way every producer should: written
+/// beside the directory and moved in atomically, so a half-written file is never injected.
+/// Works for sessions started by this library and by the `typeypipe` command alike.
+#[derive(Debug, Clone)]
+pub struct QueueClient {
+    queue_dir: PathBuf,
+}
+
+impl QueueClient {
+    pub fn new(queue_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            queue_dir: queue_dir.into(),
+        }
+    }
+
+    /// The queue of the `typeypipe` session named `name` whose files live in `tp_dir`
+    pub fn for_session(tp_dir: &Path, name: &str) -> Self {
+        Self::new(tp_dir.join(name))
+    }
+
+    pub fn queue_dir(&self) -> &Path {
+        &self.queue_dir
+    }
+
+    /// Where the control socket of the session owning this queue listens, if it has one
+    pub fn control_socket(&self) -> Option<PathBuf> {
+        let tp_dir = self.queue_dir.parent()?;
+        let name = self.queue_dir.file_name()?.to_str()?;
+        Some(control_socket_path(tp_dir, name))
+    }
+
+    /// Queue a command line, submitted with the session's terminator. Returns the queue file.
+    pub async fn command(&self, command: &str) -> Result<PathBuf> {
+        self.send(&QueueMessage::Command {
+            command: command.to_string(),
+            terminator: None,
+        })
+        .await
+    }
+
+    /// Queue tmux key names such as `C-c`, `Up` or `Enter`
+    pub async fn keys(&self, keys: &[&str]) -> Result<PathBuf> {
+        self.send(&QueueMessage::Keys {
+            keys: keys.iter().map(|key| key.to_string()).collect(),
+        })
+        .await
+    }
+
+    /// Queue any message
+    pub async fn send(&self, message: &QueueMessage) -> Result<PathBuf> {
+        enqueue_file(&self.queue_dir, "api", &message.encode()?).await
+    }
+
+    /// Wait until the session has handled `queue_file`, returned by one of the methods above.
+    /// `None` means it was written to the shell; otherwise the failure says why it was
+    /// dropped. Fails when it is still queued after `timeout`.
+    pub async fn outcome(
+        &self,
+        queue_file: &Path,
+        timeout: Option<Duration>,
+    ) -> Result<Option<QueueFailure>> {
+        match wait_for_injection(queue_file, timeout).await {
+            Ok(()) => Ok(None),
+            Err(e) => e.downcast::<QueueFailure>().map(Some),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FailureCode, OutputEvent, QueueClient, SessionBuilder};
+    use crate::shell::queue::enqueue_file;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_sessions_are_driven_through_the_facade() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut session = SessionBuilder::new(temp_dir.path().join("api"))
+            .shell("/bin/sh")
+            .env("GREETING", "facade")
+            .transcript(temp_dir.path().join("api.transcript"))
+            .spawn()
+            .await
+            .unwrap();
+        let mut events = session.events().unwrap();
+
+        let queue = QueueClient::for_session(temp_dir.path(), "api");
+        queue.command("echo $GREETING-$((40 + 2))").await.unwrap();
+        let mut seen = Vec::new();
+        let found = tokio::time::timeout(Duration::from_secs(10), async {
+            while let Some(event) = events.next().await {
+                if let OutputEvent::Output(bytes) = event {
+                    seen.extend_from_slice(&bytes);
+                }
+                if String::from_utf8_lossy(&seen).contains("facade-42") {
+                    return true;
+                }
+            }
+            false
+        })
+        .await;
+        assert_eq!(found.ok(), Some(true));
+
+        // A message from a newer release is dropped, and its producer is told why
+        let invalid = enqueue_file(
+            queue.queue_dir(),
+            "newer",
+            r#"{"version":99,"type":"command","command":"ls"}"#,
+        )
+        .await
+        .unwrap();
+        let failure = queue
+            .outcome(&invalid, Some(Duration::from_secs(10)))
+            .await
+            .unwrap();
+        assert_eq!(
+            failure.map(|failure| failure.code),
+            Some(FailureCode::InvalidMessage)
+        );
+
+        session.queue().command("exit 4").await.unwrap();
+        let code = tokio::time::timeout(Duration::from_secs(10), session.wait()).await;
+        assert_eq!(code.ok().map(Result::unwrap), Some(4));
+        assert!(temp_dir.path().join("api.log").exists());
+        let transcript = std::fs::read_to_string(temp_dir.path().join("api.transcript")).unwrap();
+        assert!(transcript.contains("] facade-42"));
+    }
+}
@@ -0,0 +1,1355 @@
+use crate::shell::config::{default_config_path, expand_home, load_profile};
+use crate::shell::context::SessionContext;
+use crate::shell::control::control_socket_path;
+use crate::shell::encryption::parse_recipient;
+use crate::shell::glyphs::label;
+use crate::shell::history::history_db_path;
+use crate::shell::integration::IntegrationShell;
+use crate::shell::keys::translate_tmux_keys;
+use crate::shell::location::resolve_tp_dir;
+use crate::shell::logs::{parse_since, run_logs, LogsOptions};
+use crate::shell::play::{play, PlayOptions};
+use crate::shell::queue::wait_for_injection;
+use crate::shell::rotation::parse_size;
+use crate::shell::sandbox::run_sandboxed;
+use crate::shell::status::status_file_path;
+use crate::shell::stopwatch::format_duration;
+use crate::shell::terminfo::{resolve_colorterm, resolve_term, term_warnings, DEFAULT_TERM};
+use crate::shell::{
+    check_health, decrypt_file, enqueue_file, install_panic_hook, parse_filters,
+    set_encryption_recipient, set_fsync_policy, set_notifier, set_panic_policy, set_plain_text,
+    set_rotation_policy, set_socket_access, AgentEcho, AuditLog, BellPolicy, ControlClient,
+    ControlRequest, FsyncPolicy, HistoryDb, HistoryQuery, InputLog, InteractiveOptions, McpServer,
+    NestedPolicy, NotifyMethod, PanicPolicy, QueueMessage, RotationPolicy, ScheduledCommand,
+    SessionHistory, SessionTarget, ShellConfig, SocketAccess, Terminator, WatchRule,
+};
+use anyhow::{anyhow, Result};
+use clap::{parser::ValueSource, Arg, ArgAction, Command};
+use std::{env, ffi::OsStr, path::PathBuf, sync::Arc, time::Duration};
+use which::which;
+
+/// Run the `typeypipe` command with the process's arguments
+pub async fn run() -> Result<()> {
+    let default_shell_path: &'static OsStr = Box::leak(Box::new(
+        which("bash")
+            .or_else(|_| env::var("SHELL").map(PathBuf::from))
+            .unwrap_or_else(|_| PathBuf::from("bash"))
+            .into_os_string(),
+    ))
+    .as_os_str();
+    let cli = Command::new("typeypipe")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Transparent shell messaging system")
+        .arg(
+            Arg::new("shell")
+                .short('s')
+                .long("shell")
+                .value_name("SHELL")
+                .help("Shell to use")
+                .default_value_os(default_shell_path)
+        )
+        .arg(
+            Arg::new("target")
+                .long("target")
+                .value_name("TARGET")
+                .help("Where to run the shell: local, docker:<container> or podman:<container>")
+                .default_value("local")
+        )
+        .arg(
+            Arg::new("tp-dir")
+                .long("tp-dir")
+                .value_name("DIR")
+                .help("Directory for queues, logs and sockets [default: TP_DIR, else .tp at the git root, else ~/.local/state/typeypipe]")
+                .global(true)
+        )
+        .arg(
+            Arg::new("plain")
+                .long("plain")
+                .help("Use text labels instead of emoji in messages, logs and titles (for screen readers and limited fonts)")
+                .action(clap::ArgAction::SetTrue)
+                .global(true)
+        )
+        .arg(
+            Arg::new("queue-dir")
+                .short('q')
+                .long("queue-dir")
+                .value_name("NAME")
+                .help("Queue directory name under .tp/ directory (default: process ID)")
+        )
+        .arg(
+            Arg::new("input-timeout")
+                .short('t')
+                .long("input-timeout")
+                .value_name("SECONDS")
+                .help("Seconds to wait after user input before resuming queue processing")
+                .default_value("30")
+        )
+        .arg(
+            Arg::new("scroll-buffer-size")
+                .long("scroll-buffer-size")
+                .value_name("LINES")
+                .help("Number of output lines to keep in the session scrollback (0 disables it)")
+                .default_value("10000")
+        )
+        .arg(
+            Arg::new("record")
+                .short('r')
+                .long("record")
+                .help("Record all shell output to a timestamped transcript next to the log file")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("record-input")
+                .long("record-input")
+                .help("Record every byte sent to the shell, with timing, to an owner-only input log next to the log file")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("log-max-size")
+                .long("log-max-size")
+                .value_name("SIZE")
+                .help("Rotate the log and transcript once they reach SIZE bytes (accepts K, M, G suffixes)")
+        )
+        .arg(
+            Arg::new("log-max-age")
+                .long("log-max-age")
+                .value_name("MINUTES")
+                .help("Rotate the log and transcript once they are older than MINUTES")
+        )
+        .arg(
+            Arg::new("log-retain")
+                .long("log-retain")
+                .value_name("COUNT")
+                .help("Number of rotated log and transcript files to keep")
+                .default_value("5")
+        )
+        .arg(
+            Arg::new("audit-file")
+                .long("audit-file")
+                .value_name("FILE")
+                .help("Append every injected command with its source and result to FILE (fsynced JSON lines)")
+        )
+        .arg(
+            Arg::new("output-file")
+                .long("output-file")
+                .value_name("FILE")
+                .help("Append all shell output to FILE, one line at a time (can be shared by several sessions)")
+        )
+        .arg(
+            Arg::new("output-filter")
+                .long("output-filter")
+                .value_name("FILTERS")
+                .help("Comma-separated filters for --output-file: strip-ansi, timestamp, prefix:<text>")
+                .requires("output-file")
+        )
+        .arg(
+            Arg::new("watch")
+                .long("watch")
+                .value_name("PATTERN=COMMAND")
+                .help("Queue COMMAND whenever a file matching PATTERN changes, e.g. 'src/**=cargo test' (repeatable)")
+                .action(ArgAction::Append)
+        )
+        .arg(
+            Arg::new("watch-debounce")
+                .long("watch-debounce")
+                .value_name("MS")
+                .help("Milliseconds without further changes before a watch command is queued")
+                .default_value("500")
+        )
+        .arg(
+            Arg::new("schedule")
+                .long("schedule")
+                .value_name("SCHEDULE=COMMAND")
+                .help("Queue COMMAND on a schedule: '@every 5m', a cron expression such as '*/15 9-17 * * 1-5', or @hourly/@daily (repeatable)")
+                .action(ArgAction::Append)
+        )
+        .arg(
+            Arg::new("notify")
+                .long("notify")
+                .value_name("METHOD")
+                .help("Desktop notifications for failed injections and --notify-on matches: osc9, osc777 or notify-send")
+        )
+        .arg(
+            Arg::new("notify-on")
+                .long("notify-on")
+                .value_name("TEXT")
+                .help("Notify when a line of output contains TEXT (repeatable)")
+                .action(ArgAction::Append)
+                .requires("notify")
+        )
+        .arg(
+            Arg::new("bell")
+                .long("bell")
+                .value_name("POLICY")
+                .help("Bells from the shell: forward them to your terminal and mark its title, or mute them")
+                .default_value("forward")
+        )
+        .arg(
+            Arg::new("monitor-activity")
+                .long("monitor-activity")
+                .value_name("SECONDS")
+                .help("Ring the bell and mark the title when output resumes after SECONDS of silence while you are away")
+        )
+        .arg(
+            Arg::new("title")
+                .long("title")
+                .value_name("TEMPLATE")
+                .help("Outer terminal title, e.g. '{session} — {cwd} — {last_command}'; {title} is the title the shell set")
+        )
+        .arg(
+            Arg::new("suppress-inner-title")
+                .long("suppress-inner-title")
+                .help("Ignore title changes from the shell")
+                .action(ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("agent-echo")
+                .long("agent-echo")
+                .value_name("STYLE")
+                .help("How the echo of queued and control socket input is shown: show, dim or hide")
+                .default_value("show")
+        )
+        .arg(
+            Arg::new("paste-guard")
+                .long("paste-guard")
+                .value_name("SIZE")
+                .help("Ask before pasting more than SIZE bytes into the shell (accepts K, M, G suffixes; 0 turns it off)")
+                .default_value("256K")
+        )
+        .arg(
+            Arg::new("max-command-output")
+                .long("max-command-output")
+                .value_name("SIZE")
+                .help("Largest output returned with the result of a control socket run; longer output is cut with a marker (accepts K, M, G suffixes)")
+                .default_value("64K")
+        )
+        .arg(
+            Arg::new("socket-allow")
+                .long("socket-allow")
+                .value_name("LIST")
+                .help("Also let these peers use the control socket: uid:<n>, gid:<n>, user:<name>, group:<name> (comma-separated)")
+        )
+        .arg(
+            Arg::new("fsync")
+                .long("fsync")
+                .value_name("POLICY")
+                .help("When to force log and audit entries to disk: never, command, or interval:<seconds>")
+                .default_value("command")
+        )
+        .arg(
+            Arg::new("terminator")
+                .long("terminator")
+                .value_name("STYLE")
+                .help("What submits a queued command: cr, lf, crlf, or none")
+                .default_value("cr")
+        )
+        .arg(
+            Arg::new("on-panic")
+                .long("on-panic")
+                .value_name("POLICY")
+                .help("When a session task panics: restart it, stop it, or exit")
+                .default_value("restart")
+        )
+        .arg(
+            Arg::new("encrypt-to")
+                .long("encrypt-to")
+                .value_name("RECIPIENT")
+                .help("Encrypt the transcript, input, output and audit logs at rest to an age x25519 recipient (age1...); cannot be combined with --history")
+        )
+        .arg(
+            Arg::new("command-hook")
+                .long("command-hook")
+                .value_name("PROGRAM")
+                .help("Pass each queued command through PROGRAM (stdin -> rewritten command on stdout, non-zero exit rejects)")
+        )
+        .arg(
+            Arg::new("post-command-hook")
+                .long("post-command-hook")
+                .value_name("PROGRAM")
+                .help("Run PROGRAM after each command the shell finishes (needs shell integration)")
+        )
+        .arg(
+            Arg::new("history")
+                .long("history")
+                .help("Record injected commands and their results in .tp/history.sqlite")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("history-interactive")
+                .long("history-interactive")
+                .help("Also record commands typed by the user (implies --history, needs shell integration)")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("sandbox")
+                .long("sandbox")
+                .help("Linux only: restrict typeypipe's own file writes to .tp/ and the audit log directory (Landlock)")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("trace-file")
+                .long("trace-file")
+                .value_name("FILE")
+                .help("Write JSON tracing spans for queue processing to FILE")
+        )
+        .arg(
+            Arg::new("control-stdio")
+                .long("control-stdio")
+                .help("Speak JSON-RPC on stdin/stdout instead of attaching to the terminal (for editor integrations)")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .value_name("NAME")
+                .help("Start with the options of profile NAME from the config file; options given here override it")
+        )
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .value_name("FILE")
+                .help("Config file with profiles [default: $XDG_CONFIG_HOME/typeypipe/config.kdl, else ~/.config/typeypipe/config.kdl]")
+        )
+        .arg(
+            Arg::new("cwd")
+                .long("cwd")
+                .value_name("DIR")
+                .help("Directory the shell starts in [default: your home directory]")
+        )
+        .arg(
+            Arg::new("env")
+                .long("env")
+                .value_name("NAME=VALUE")
+                .help("Set an environment variable in the shell (repeatable)")
+                .action(ArgAction::Append)
+        )
+        .arg(
+            Arg::new("term")
+                .long("term")
+                .value_name("NAME")
+                .help("TERM for the shell, or inherit to pass this terminal's through")
+                .default_value(DEFAULT_TERM)
+        )
+        .arg(
+            Arg::new("colorterm")
+                .long("colorterm")
+                .value_name("VALUE")
+                .help("COLORTERM for the shell (e.g. truecolor), inherit to pass this terminal's through, or none")
+                .default_value("inherit")
+        )
+        .arg(
+            Arg::new("startup")
+                .long("startup")
+                .value_name("COMMAND")
+                .help("Queue COMMAND as soon as the session starts (repeatable)")
+                .action(ArgAction::Append)
+        )
+        .arg(
+            Arg::new("init-command")
+                .long("init-command")
+                .value_name("COMMAND")
+                .help("Run COMMAND at the shell's first prompt, before queue processing starts (repeatable)")
+                .action(ArgAction::Append)
+        )
+        .arg(
+            Arg::new("stdin-queue")
+                .long("stdin-queue")
+                .help("Queue each line piped into typeypipe as a command before the session starts, then stay interactive")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with("control-stdio")
+        )
+        .arg(
+            Arg::new("nested")
+                .long("nested")
+                .value_name("POLICY")
+                .help("When started inside another session: refuse, warn, or route stdin lines to the outer session's queue")
+                .default_value("refuse")
+        )
+        .arg(
+            Arg::new("quiet")
+                .short('u')
+                .long("quiet")
+                .help("Suppress startup messages")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .subcommand(
+            Command::new("health")
+                .about("Check that a running session is alive and processing its queue")
+                .arg(
+                    Arg::new("name")
+                        .value_name("NAME")
+                        .help("Queue directory name of the session to check")
+                        .required(true)
+                )
+                .arg(output_format_arg())
+        )
+        .subcommand(
+            Command::new("mcp")
+                .about("Serve a running session to MCP clients over stdio")
+                .arg(
+                    Arg::new("name")
+                        .value_name("NAME")
+                        .help("Queue directory name of the session to expose")
+                        .required(true)
+                )
+        )
+        .subcommand(
+            Command::new("send-keys")
+                .about("Send keys to a running session, with the same arguments as tmux send-keys")
+                .arg(
+                    Arg::new("target")
+                        .short('t')
+                        .value_name("SESSION")
+                        .help("Queue directory name of the session (a tmux-style :window.pane suffix is ignored)")
+                        .required(true)
+                )
+                .arg(
+                    Arg::new("literal")
+                        .short('l')
+                        .help("Send the keys as literal text without looking up key names")
+                        .action(clap::ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("hex")
+                        .short('H')
+                        .help("Treat each key as a hexadecimal byte")
+                        .action(clap::ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("keys")
+                        .value_name("KEYS")
+                        .help("Key names (Enter, C-c, Up, ...) or text")
+                        .num_args(1..)
+                        .allow_hyphen_values(true)
+                        .required(true)
+                )
+        )
+        .subcommand(
+            Command::new("pipe")
+                .about("Run each line of stdin in a running session, one at a time")
+                .arg(
+                    Arg::new("name")
+                        .value_name("NAME")
+                        .help("Queue directory name of the session")
+                        .required(true)
+                )
+        )
+        .subcommand(
+            Command::new("snapshot")
+                .about("Print the screen of a running session")
+                .arg(
+                    Arg::new("name")
+                        .value_name("NAME")
+                        .help("Queue directory name of the session")
+                        .required(true)
+                )
+                .arg(
+                    Arg::new("ansi")
+                        .long("ansi")
+                        .help("Keep colors and other escape sequences")
+                        .action(clap::ArgAction::SetTrue)
+                )
+                .arg(output_format_arg())
+        )
+        .subcommand(
+            Command::new("logs")
+                .about("Show the queue log and transcript of sessions without raw escape sequences")
+                .arg(
+                    Arg::new("name")
+                        .value_name("NAME")
+                        .help("Queue directory name of each session to show")
+                        .required(true)
+                        .num_args(1..)
+                )
+                .arg(
+                    Arg::new("follow")
+                        .short('f')
+                        .long("follow")
+                        .help("Keep printing new entries as they are written")
+                        .action(clap::ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("since")
+                        .long("since")
+                        .value_name("WHEN")
+                        .help("Only entries since a duration ago (10m, 2h) or a local time (2025-03-01 10:00)")
+                )
+                .arg(
+                    Arg::new("lines")
+                        .short('n')
+                        .long("lines")
+                        .value_name("COUNT")
+                        .help("Number of existing entries to show [default: 20, or all with --since]")
+                )
+                .arg(
+                    Arg::new("source")
+                        .long("source")
+                        .value_name("SOURCE")
+                        .help("Show the queue log, the transcript or both: log, transcript or all")
+                        .default_value("all")
+                )
+                .arg(
+                    Arg::new("ansi")
+                        .long("ansi")
+                        .help("Keep colors (other escape sequences are still removed)")
+                        .action(clap::ArgAction::SetTrue)
+                )
+        )
+        .subcommand(
+            Command::new("play")
+                .about("Replay a recorded session: space pauses, + and - change the speed, q stops")
+                .arg(
+                    Arg::new("file")
+                        .value_name("FILE")
+                        .help("A --record transcript or an asciicast v2 (.cast) recording")
+                        .required(true)
+                )
+                .arg(
+                    Arg::new("speed")
+                        .long("speed")
+                        .value_name("FACTOR")
+                        .help("Play this many times faster than recorded")
+                        .default_value("1")
+                )
+                .arg(
+                    Arg::new("idle-limit")
+                        .long("idle-limit")
+                        .value_name("SECONDS")
+                        .help("Shorten pauses longer than SECONDS to SECONDS")
+                )
+        )
+        .subcommand(
+            Command::new("setup")
+                .about("Print or install shell integration for sessions")
+                .arg(
+                    Arg::new("shell-integration")
+                        .long("shell-integration")
+                        .value_name("SHELL")
+                        .help("Emit the rc snippet for bash, zsh or fish")
+                        .required(true)
+                )
+                .arg(
+                    Arg::new("install")
+                        .long("install")
+                        .help("Append the snippet to the shell's rc file instead of printing it")
+                        .action(clap::ArgAction::SetTrue)
+                )
+        )
+        .subcommand(
+            Command::new("decrypt")
+                .about("Print the plaintext of a transcript or log written with --encrypt-to")
+                .arg(
+                    Arg::new("identity")
+                        .short('i')
+                        .long("identity")
+                        .value_name("FILE")
+                        .help("age identity file containing the AGE-SECRET-KEY")
+                        .required(true)
+                )
+                .arg(
+                    Arg::new("file")
+                        .value_name("FILE")
+                        .help("Encrypted transcript or audit log")
+                        .required(true)
+                )
+        )
+        .subcommand(
+            Command::new("history")
+                .about("Search the command history recorded with --history")
+                .arg(
+                    Arg::new("grep")
+                        .long("grep")
+                        .value_name("TEXT")
+                        .help("Only commands containing TEXT")
+                )
+                .arg(
+                    Arg::new("failed")
+                        .long("failed")
+                        .help("Only commands that exited non-zero or were rejected")
+                        .action(clap::ArgAction::SetTrue)
+                )
+                .arg(
+                    Arg::new("session")
+                        .long("session")
+                        .value_name("NAME")
+                        .help("Only commands from this session")
+                )
+                .arg(
+                    Arg::new("limit")
+                        .short('n')
+                        .long("limit")
+                        .value_name("COUNT")
+                        .help("Number of commands to show")
+                        .default_value("50")
+                )
+                .arg(output_format_arg())
+        )
+        .args_conflicts_with_subcommands(true)
+        // Options from a profile come first, so the same option on the command line wins
+        .args_override_self(true);
+    let mut matches = cli.clone().get_matches();
+    if let Some(profile) = matches.get_one::<String>("profile").cloned() {
+        matches = apply_profile(
+            cli,
+            &profile,
+            matches.get_one::<String>("config").map(PathBuf::from),
+        )?;
+    }
+
+    let tp_dir = resolve_tp_dir(
+        matches
+            .get_one::<String>("tp-dir")
+            .map(std::path::Path::new),
+    )?;
+    let tp_base_dir = tp_dir.path.clone();
+    set_plain_text(matches.get_flag("plain"));
+
+    match matches.subcommand() {
+        Some(("health", health_matches)) => {
+            let name = health_matches.get_one::<String>("name").unwrap();
+            run_health(&tp_base_dir, name, json_output(health_matches));
+        }
+        Some(("mcp", mcp_matches)) => {
+            let name = mcp_matches.get_one::<String>("name").unwrap();
+            return McpServer::new(control_socket_path(&tp_base_dir, name))
+                .serve_stdio()
+                .await;
+        }
+        Some(("send-keys", send_keys_matches)) => {
+            return run_send_keys(&tp_base_dir, send_keys_matches).await;
+        }
+        Some(("pipe", pipe_matches)) => {
+            let name = pipe_matches.get_one::<String>("name").unwrap();
+            return run_pipe(&tp_base_dir, name).await;
+        }
+        Some(("snapshot", snapshot_matches)) => {
+            let name = snapshot_matches.get_one::<String>("name").unwrap();
+            let mut client =
+                ControlClient::connect(&control_socket_path(&tp_base_dir, name)).await?;
+            let screen = client
+                .request(&ControlRequest::Screen {
+                    strip_ansi: !snapshot_matches.get_flag("ansi"),
+                })
+                .await?;
+            if json_output(snapshot_matches) {
+                println!(
+                    "{}",
+                    serde_json::json!({ "session": name, "rows": screen["rows"], "text": screen["text"] })
+                );
+            } else {
+                println!("{}", screen["text"].as_str().unwrap_or_default());
+            }
+            return Ok(());
+        }
+        Some(("decrypt", decrypt_matches)) => {
+            use std::io::Write;
+            let identity = decrypt_matches.get_one::<String>("identity").unwrap();
+            let file = decrypt_matches.get_one::<String>("file").unwrap();
+            let plaintext =
+                decrypt_file(std::path::Path::new(file), std::path::Path::new(identity))?;
+            std::io::stdout().write_all(&plaintext)?;
+            return Ok(());
+        }
+        Some(("logs", logs_matches)) => {
+            return run_logs(&tp_base_dir, &logs_options(logs_matches)?).await;
+        }
+        Some(("history", history_matches)) => {
+            return run_history(&tp_base_dir, history_matches);
+        }
+        Some(("play", play_matches)) => {
+            return play(&play_options(play_matches)?);
+        }
+        Some(("setup", setup_matches)) => {
+            let shell: IntegrationShell = setup_matches
+                .get_one::<String>("shell-integration")
+                .unwrap()
+                .parse()?;
+            if setup_matches.get_flag("install") {
+                let (rc_file, changed) = shell.install()?;
+                if changed {
+                    println!(
+                        "{} Added shell integration to {}",
+                        label("✅"),
+                        rc_file.display()
+                    );
+                } else {
+                    println!("Shell integration is already in {}", rc_file.display());
+                }
+            } else {
+                print!("{}", shell.snippet());
+            }
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    // Sessions export TP_SESSION to their shell, so its presence means we are nested
+    if let Ok(outer_session) = env::var("TP_SESSION") {
+        match matches
+            .get_one::<String>("nested")
+            .unwrap()
+            .parse::<NestedPolicy>()?
+        {
+            NestedPolicy::Refuse => {
+                return Err(anyhow!(
+                    "Already inside typeypipe session '{}' (use --nested warn to start anyway, or --nested route to queue commands to it)",
+                    outer_session
+                ));
+            }
+            NestedPolicy::Warn => {
+                eprintln!(
+                    "{}  Starting a session inside typeypipe session '{}'",
+                    label("⚠️"),
+                    outer_session
+                );
+            }
+            NestedPolicy::Route => return route_to_outer_session().await,
+        }
+    }
+
+    if let Some(trace_file) = matches.get_one::<String>("trace-file") {
+        init_tracing(trace_file)?;
+    }
+
+    // Parse configuration
+    let target: SessionTarget = matches.get_one::<String>("target").unwrap().parse()?;
+
+    // The host's default shell rarely exists inside a container, so fall back to sh there
+    let shell_path = match (&target, matches.value_source("shell")) {
+        (SessionTarget::Container { .. }, Some(ValueSource::DefaultValue)) => "/bin/sh".to_string(),
+        _ => matches.get_one::<String>("shell").unwrap().clone(),
+    };
+
+    let mut config = ShellConfig {
+        shell_path,
+        target,
+        cols: 120,
+        rows: 30,
+        scroll_buffer_size: matches
+            .get_one::<String>("scroll-buffer-size")
+            .unwrap()
+            .parse()
+            .unwrap_or(10_000),
+        cwd: matches.get_one::<String>("cwd").map(|dir| expand_home(dir)),
+        ..ShellConfig::default()
+    };
+    if let (Some(cwd), SessionTarget::Local) = (&config.cwd, &config.target) {
+        if !cwd.is_dir() {
+            return Err(anyhow!("--cwd {} is not a directory", cwd.display()));
+        }
+    }
+
+    set_rotation_policy(RotationPolicy {
+        max_bytes: matches
+            .get_one::<String>("log-max-size")
+            .map(|size| parse_size(size).ok_or_else(|| anyhow!("Invalid --log-max-size: {}", size)))
+            .transpose()?,
+        max_age: matches
+            .get_one::<String>("log-max-age")
+            .map(|minutes| minutes.parse::<u64>().map(|m| Duration::from_secs(m * 60)))
+            .transpose()?,
+        retain: matches
+            .get_one::<String>("log-retain")
+            .unwrap()
+            .parse()
+            .unwrap_or(5),
+    });
+
+    let input_timeout_secs: u64 = matches
+        .get_one::<String>("input-timeout")
+        .unwrap()
+        .parse()
+        .unwrap_or(30);
+
+    // Create .tp directory structure
+    tokio::fs::create_dir_all(&tp_base_dir).await?;
+
+    // Determine queue directory name and create paths
+    let queue_name = matches
+        .get_one::<String>("queue-dir")
+        .map(|s| s.as_str())
+        .unwrap_or_else(|| {
+            // Use process ID as default to ensure uniqueness
+            Box::leak(std::process::id().to_string().into_boxed_str())
+        });
+
+    let queue_dir = tp_base_dir.join(queue_name);
+    let log_file = tp_base_dir.join(format!("{}.log", queue_name));
+    let transcript_file = matches
+        .get_flag("record")
+        .then(|| tp_base_dir.join(format!("{}.transcript", queue_name)));
+    let input_log_file = matches
+        .get_flag("record-input")
+        .then(|| tp_base_dir.join(format!("{}.input", queue_name)));
+    let status_file = status_file_path(&tp_base_dir, queue_name);
+    let control_socket = control_socket_path(&tp_base_dir, queue_name);
+
+    // Let scripts inside the session find their own queue and log
+    config.env = vec![
+        ("TP_SESSION".to_string(), queue_name.to_string()),
+        ("TP_DIR".to_string(), tp_base_dir.display().to_string()),
+        ("TP_QUEUE_DIR".to_string(), queue_dir.display().to_string()),
+        ("TP_LOG_FILE".to_string(), log_file.display().to_string()),
+    ];
+    for variable in matches.get_many::<String>("env").unwrap_or_default() {
+        let (name, value) = variable
+            .split_once('=')
+            .filter(|(name, _)| !name.is_empty())
+            .ok_or_else(|| anyhow!("--env expects NAME=VALUE, got '{}'", variable))?;
+        config.env.push((name.to_string(), value.to_string()));
+    }
+    config.term = resolve_term(matches.get_one::<String>("term").unwrap());
+    config.colorterm = resolve_colorterm(matches.get_one::<String>("colorterm").unwrap());
+    if !matches.get_flag("control-stdio") {
+        for warning in term_warnings(&config) {
+            eprintln!("{}  {}", label("⚠️"), warning);
+        }
+    }
+
+    set_fsync_policy(
+        matches
+            .get_one::<String>("fsync")
+            .unwrap()
+            .parse::<FsyncPolicy>()?,
+    );
+    let terminator = matches
+        .get_one::<String>("terminator")
+        .unwrap()
+        .parse::<Terminator>()?;
+    set_panic_policy(
+        matches
+            .get_one::<String>("on-panic")
+            .unwrap()
+            .parse::<PanicPolicy>()?,
+    );
+    install_panic_hook();
+    if let Some(allow) = matches.get_one::<String>("socket-allow") {
+        set_socket_access(SocketAccess::parse(allow)?);
+    }
+
+    let history_interactive = matches.get_flag("history-interactive");
+    let history_enabled = matches.get_flag("history") || history_interactive;
+    // The history is a database queried in place, which line-by-line encryption cannot cover
+    if history_enabled && matches.contains_id("encrypt-to") {
+        return Err(anyhow!(
+            "--encrypt-to cannot encrypt the history database; leave out --history or --encrypt-to"
+        ));
+    }
+    let history = if history_enabled {
+        tokio::fs::create_dir_all(&tp_base_dir).await?;
+        Some(SessionHistory::open(
+            &history_db_path(&tp_base_dir),
+            queue_name,
+            history_interactive,
+        )?)
+    } else {
+        None
+    };
+
+    if let Some(recipient) = matches.get_one::<String>("encrypt-to") {
+        set_encryption_recipient(Some(parse_recipient(recipient)?));
+    }
+
+    let audit_log = matches
+        .get_one::<String>("audit-file")
+        .map(|audit_file| AuditLog::open(std::path::Path::new(audit_file), queue_name))
+        .transpose()?;
+
+    let control_stdio = matches.get_flag("control-stdio");
+
+    let notify_method = matches
+        .get_one::<String>("notify")
+        .map(|method| method.parse::<NotifyMethod>())
+        .transpose()?;
+    if control_stdio
+        && matches!(
+            notify_method,
+            Some(NotifyMethod::Osc9 | NotifyMethod::Osc777)
+        )
+    {
+        return Err(anyhow!("--control-stdio has no terminal to send OSC notifications to; use --notify notify-send"));
+    }
+    set_notifier(notify_method, queue_name);
+
+    // Startup messages (unless quiet mode, or stdout belongs to JSON-RPC)
+    if !matches.get_flag("quiet") && !control_stdio {
+        println!("{} Typey Pipe - Shell messaging system", label("🚀"));
+        println!("{} Message queue: {}", label("📁"), queue_dir.display());
+        if let Some(ref transcript_file) = transcript_file {
+            println!("{} Transcript: {}", label("📼"), transcript_file.display());
+        }
+        if let Some(ref input_log_file) = input_log_file {
+            println!("{} Input log: {}", label("⌨️"), input_log_file.display());
+        }
+        println!();
+    }
+
+    // Clear existing log file if it exists
+    if log_file.exists() {
+        tokio::fs::remove_file(&log_file).await.ok(); // Ignore errors if file doesn't exist
+    }
+
+    // Create the log file at startup
+    tokio::fs::File::create(&log_file).await?;
+
+    // Start each recording with an empty transcript
+    if let Some(ref transcript_file) = transcript_file {
+        tokio::fs::File::create(transcript_file).await?;
+    }
+    let input_log = input_log_file.as_deref().map(InputLog::open).transpose()?;
+
+    // Clear and recreate queue directory
+    if queue_dir.exists() {
+        tokio::fs::remove_dir_all(&queue_dir).await.ok(); // Ignore errors if directory doesn't exist
+    }
+    tokio::fs::create_dir_all(&queue_dir).await?;
+
+    for (number, command) in matches
+        .get_many::<String>("startup")
+        .unwrap_or_default()
+        .enumerate()
+    {
+        let message = QueueMessage::Command {
+            command: command.clone(),
+            terminator: None,
+        };
+        enqueue_file(
+            &queue_dir,
+            &format!("startup-{:05}", number + 1),
+            &message.encode()?,
+        )
+        .await?;
+    }
+
+    if matches.get_flag("stdin-queue") {
+        let queued = queue_stdin(&queue_dir).await?;
+        if !matches.get_flag("quiet") {
+            println!("{} Queued {} command(s) from stdin", label("📁"), queued);
+        }
+    }
+
+    let watch_debounce = Duration::from_millis(
+        matches
+            .get_one::<String>("watch-debounce")
+            .unwrap()
+            .parse()
+            .map_err(|_| anyhow!("--watch-debounce expects a number of milliseconds"))?,
+    );
+    let watch_rules = matches
+        .get_many::<String>("watch")
+        .unwrap_or_default()
+        .map(|rule| {
+            Ok(WatchRule {
+                debounce: watch_debounce,
+                ..rule.parse()?
+            })
+        })
+        .collect::<Result<Vec<WatchRule>>>()?;
+    let schedules = matches
+        .get_many::<String>("schedule")
+        .unwrap_or_default()
+        .map(|schedule| schedule.parse())
+        .collect::<Result<Vec<ScheduledCommand>>>()?;
+    let bell = matches
+        .get_one::<String>("bell")
+        .unwrap()
+        .parse::<BellPolicy>()?;
+    let agent_echo = matches
+        .get_one::<String>("agent-echo")
+        .unwrap()
+        .parse::<AgentEcho>()?;
+    let paste_guard = matches
+        .get_one::<String>("paste-guard")
+        .map(|size| {
+            parse_size(size).ok_or_else(|| {
+                anyhow!(
+                    "Invalid --paste-guard '{}' (expected a size like 256K)",
+                    size
+                )
+            })
+        })
+        .transpose()?
+        .filter(|&limit| limit > 0);
+    let max_command_output = matches
+        .get_one::<String>("max-command-output")
+        .map(|size| {
+            parse_size(size).ok_or_else(|| {
+                anyhow!(
+                    "Invalid --max-command-output '{}' (expected a size like 64K)",
+                    size
+                )
+            })
+        })
+        .transpose()?
+        .unwrap_or(64 * 1024) as usize;
+    let activity_quiet = matches
+        .get_one::<String>("monitor-activity")
+        .map(|seconds| {
+            seconds
+                .parse()
+                .map(Duration::from_secs)
+                .map_err(|_| anyhow!("--monitor-activity expects a number of seconds"))
+        })
+        .transpose()?;
+
+    // Create the shared PTY session
+    let session = crate::shell::create_pty_session(config.clone()).await?;
+
+    let options = InteractiveOptions {
+        queue_dir: Some(queue_dir),
+        log_file: Some(log_file),
+        transcript_file,
+        output_file: matches.get_one::<String>("output-file").map(PathBuf::from),
+        output_filters: parse_filters(
+            matches
+                .get_one::<String>("output-filter")
+                .map(String::as_str)
+                .unwrap_or_default(),
+        )?,
+        watch_rules,
+        // For a container target --cwd names a directory inside the container
+        watch_root: match config.target {
+            SessionTarget::Local => config.cwd.clone(),
+            _ => None,
+        },
+        schedules,
+        bell,
+        activity_quiet,
+        title_template: matches.get_one::<String>("title").cloned(),
+        suppress_inner_title: matches.get_flag("suppress-inner-title"),
+        agent_echo,
+        paste_guard,
+        max_command_output,
+        notify_patterns: matches
+            .get_many::<String>("notify-on")
+            .unwrap_or_default()
+            .cloned()
+            .collect(),
+        status_file: Some(status_file),
+        control_socket: Some(control_socket),
+        control_stdio,
+        input_timeout_secs,
+        tp_dir: Some(tp_dir),
+        init_commands: matches
+            .get_many::<String>("init-command")
+            .unwrap_or_default()
+            .cloned()
+            .collect(),
+    };
+    let context = Arc::new(
+        SessionContext::new(terminator)
+            .audit_log(audit_log)
+            .history(history)
+            .input_log(input_log)
+            .hooks(
+                matches.get_one::<String>("command-hook").cloned(),
+                matches.get_one::<String>("post-command-hook").cloned(),
+            ),
+    );
+
+    // The shell is already running, so the sandbox only applies to typeypipe itself
+    if matches.get_flag("sandbox") {
+        let mut writable = vec![tp_base_dir.clone()];
+        if let Some(audit_file) = matches.get_one::<String>("audit-file") {
+            let audit_dir = std::path::Path::new(audit_file)
+                .parent()
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .unwrap_or(std::path::Path::new("."));
+            writable.push(audit_dir.canonicalize()?);
+        }
+        if let Some(output_file) = matches.get_one::<String>("output-file") {
+            let output_dir = std::path::Path::new(output_file)
+                .parent()
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .unwrap_or(std::path::Path::new("."));
+            writable.push(output_dir.canonicalize()?);
+        }
+        run_sandboxed(writable, move || {
+            crate::shell::setup_interactive_pty(session, context, options)
+        })
+        .await?;
+    } else {
+        // Start interactive shell with integrated queue processing
+        crate::shell::setup_interactive_pty(session, context, options).await?;
+    }
+
+    Ok(())
+}
+
+/// `--output` for subcommands that print something other tools may want to parse
+fn output_format_arg() -> Arg {
+    Arg::new("output")
+        .long("output")
+        .value_name("FORMAT")
+        .help("Output format: text or json")
+        .value_parser(["text", "json"])
+        .default_value("text")
+}
+
+fn json_output(matches: &clap::ArgMatches) -> bool {
+    matches
+        .get_one::<String>("output")
+        .is_some_and(|format| format == "json")
+}
+
+/// Print the health of a session and exit non-zero when any check fails
+fn run_health(tp_base_dir: &std::path::Path, name: &str, json: bool) -> ! {
+    let report = check_health(tp_base_dir, name);
+
+    if json {
+        println!("{}", serde_json::to_string(&report).unwrap_or_default());
+    } else {
+        for check in &report.checks {
+            let marker = label(if check.ok { "✅" } else { "❌" });
+            println!("{} {}: {}", marker, check.name, check.detail);
+        }
+    }
+
+    std::process::exit(if report.healthy { 0 } else { 1 });
+}
+
+/// Write tmux-style keys to a session through its control socket
+async fn run_send_keys(tp_base_dir: &std::path::Path, matches: &clap::ArgMatches) -> Result<()> {
+    let target = matches.get_one::<String>("target").unwrap();
+    let name = target.split(':').next().unwrap_or(target);
+    let keys: Vec<String> = matches
+        .get_many::<String>("keys")
+        .unwrap()
+        .cloned()
+        .collect();
+
+    let bytes = translate_tmux_keys(&keys, matches.get_flag("literal"), matches.get_flag("hex"))?;
+    let data = String::from_utf8(bytes).map_err(|_| anyhow!("Keys are not valid UTF-8"))?;
+
+    let mut client = ControlClient::connect(&control_socket_path(tp_base_dir, name)).await?;
+    client
+        .request(&ControlRequest::Write {
+            data,
+            automated: false,
+        })
+        .await?;
+    Ok(())
+}
+
+/// Queue each line of stdin as a command, waiting for the session to inject it before sending
+/// the next so a long input never floods the queue
+async fn run_pipe(tp_base_dir: &std::path::Path, name: &str) -> Result<()> {
+    use tokio::io::AsyncBufReadExt;
+
+    let mut client = ControlClient::connect(&control_socket_path(tp_base_dir, name)).await?;
+    let mut lines = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let queued = client
+            .request(
+                &QueueMessage::Command {
+                    command: line,
+                    terminator: None,
+                }
+                .try_into()?,
+            )
+            .await?;
+        if let Some(file) = queued.get("file").and_then(|file| file.as_str()) {
+            wait_for_injection(std::path::Path::new(file), None).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Parse the command line again with the options of profile `name` in front of it
+fn apply_profile(
+    cli: Command,
+    name: &str,
+    config_file: Option<PathBuf>,
+) -> Result<clap::ArgMatches> {
+    let config_file = config_file.or_else(default_config_path).ok_or_else(|| {
+        anyhow!(
+            "No config file to read profile '{}' from (HOME is not set; use --config)",
+            name
+        )
+    })?;
+    let profile = load_profile(&config_file, name)?;
+
+    let mut args: Vec<std::ffi::OsString> = env::args_os().take(1).collect();
+    args.extend(profile.to_args()?.into_iter().map(Into::into));
+    args.extend(env::args_os().skip(1));
+    Ok(cli.try_get_matches_from(args).unwrap_or_else(|e| {
+        eprintln!(
+            "With the options of profile '{}' from {}:",
+            name,
+            config_file.display()
+        );
+        e.exit()
+    }))
+}
+
+/// Queue each line of piped stdin as a command, numbered so they run in the order given.
+/// Keyboard input is read from the terminal itself afterwards, so the session stays interactive.
+async fn queue_stdin(queue_dir: &std::path::Path) -> Result<usize> {
+    use std::io::IsTerminal;
+    use tokio::io::AsyncReadExt;
+
+    if std::io::stdin().is_terminal() {
+        return Err(anyhow!(
+            "--stdin-queue reads commands piped into typeypipe, but stdin is a terminal"
+        ));
+    }
+    let mut script = String::new();
+    tokio::io::stdin().read_to_string(&mut script).await?;
+
+    let mut queued = 0;
+    for line in script.lines().filter(|line| !line.trim().is_empty()) {
+        queued += 1;
+        let message = QueueMessage::Command {
+            command: line.to_string(),
+            terminator: None,
+        };
+        enqueue_file(
+            queue_dir,
+            &format!("stdin-{:05}", queued),
+            &message.encode()?,
+        )
+        .await?;
+    }
+    Ok(queued)
+}
+
+/// Queue each line of stdin into the session this shell runs in, found through TP_QUEUE_DIR
+async fn route_to_outer_session() -> Result<()> {
+    use tokio::io::AsyncBufReadExt;
+
+    let queue_dir = PathBuf::from(
+        env::var("TP_QUEUE_DIR")
+            .map_err(|_| anyhow!("TP_QUEUE_DIR is not set; cannot route to the outer session"))?,
+    );
+    let mut lines = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        enqueue_file(
+            &queue_dir,
+            "nested",
+            &QueueMessage::Command {
+                command: line,
+                terminator: None,
+            }
+            .encode()?,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Print recorded commands matching the history filters
+fn run_history(tp_base_dir: &std::path::Path, matches: &clap::ArgMatches) -> Result<()> {
+    let db = HistoryDb::open(&history_db_path(tp_base_dir))?;
+    let entries = db.query(&HistoryQuery {
+        grep: matches.get_one::<String>("grep").cloned(),
+        failed: matches.get_flag("failed"),
+        session: matches.get_one::<String>("session").cloned(),
+        limit: matches.get_one::<String>("limit").unwrap().parse()?,
+    })?;
+
+    if json_output(matches) {
+        println!("{}", serde_json::to_string(&entries)?);
+        return Ok(());
+    }
+
+    for entry in entries {
+        let outcome = match (entry.exit_status, entry.result.as_str()) {
+            (Some(0), _) => label("✅").into_owned(),
+            (Some(status), _) => format!("{} {}", label("❌"), status),
+            (None, "injected") => label("⏳").into_owned(),
+            (None, result) => format!("{} {}", label("🚫"), result),
+        };
+        let outcome = match entry.duration() {
+            Some(duration) => format!("{} ({})", outcome, format_duration(duration)),
+            None => outcome,
+        };
+        println!(
+            "{} [{}] {} {}",
+            entry.recorded_at.format("%Y-%m-%d %H:%M:%S"),
+            entry.session,
+            outcome,
+            entry.command
+        );
+    }
+    Ok(())
+}
+
+fn logs_options(matches: &clap::ArgMatches) -> Result<LogsOptions> {
+    let since = matches
+        .get_one::<String>("since")
+        .map(|since| parse_since(since, chrono::Utc::now()))
+        .transpose()?;
+    let lines = match matches.get_one::<String>("lines") {
+        Some(lines) => Some(
+            lines
+                .parse()
+                .map_err(|_| anyhow!("Invalid --lines: {}", lines))?,
+        ),
+        None if since.is_none() => Some(20),
+        None => None,
+    };
+    Ok(LogsOptions {
+        sessions: matches
+            .get_many::<String>("name")
+            .unwrap()
+            .cloned()
+            .collect(),
+        source: matches.get_one::<String>("source").unwrap().parse()?,
+        since,
+        lines,
+        ansi: matches.get_flag("ansi"),
+        follow: matches.get_flag("follow"),
+    })
+}
+
+fn play_options(matches: &clap::ArgMatches) -> Result<PlayOptions> {
+    let speed = matches.get_one::<String>("speed").unwrap();
+    let speed = speed
+        .parse::<f64>()
+        .ok()
+        .filter(|speed| *speed > 0.0)
+        .ok_or_else(|| anyhow!("Invalid --speed '{}' (expected a number above 0)", speed))?;
+    let idle_limit = matches
+        .get_one::<String>("idle-limit")
+        .map(|seconds| {
+            seconds
+                .parse::<f64>()
+                .ok()
+                .filter(|seconds| *seconds >= 0.0)
+                .map(Duration::from_secs_f64)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Invalid --idle-limit '{}' (expected a number of seconds)",
+                        seconds
+                    )
+                })
+        })
+        .transpose()?;
+    Ok(PlayOptions {
+        path: PathBuf::from(matches.get_one::<String>("file").unwrap()),
+        speed,
+        idle_limit,
+    })
+}
+
+/// Send tracing output as JSON lines to a file; span close events carry busy/idle timings
+fn init_tracing(trace_file: &str) -> Result<()> {
+    use tracing_subscriber::fmt::format::FmtSpan;
+
+    let file = std::fs::File::create(trace_file)?;
+    tracing_subscriber::fmt()
+        .json()
+        .with_span_events(FmtSpan::CLOSE)
+        .with_max_level(tracing::Level::DEBUG)
+        .with_writer(std::sync::Mutex::new(file))
+        .init();
+    Ok(())
+}
@@ -0,0 +1,132 @@
+use anyhow::Result;
+
+/// Translate `tmux send-keys` arguments into the bytes the shell should receive.
+///
+/// **Semantics (as in tmux):**
+/// - Each argument is looked up as a key name (`Enter`, `Escape`, `Up`, `F5`, ...) and sent as
+///   that key; anything that is not a key name is sent as literal text
+/// - `C-<key>` sends the key with Control, `M-<key>` prefixes it with Escape (Meta)
+/// - With `literal` (`-l`) every argument is sent as text, no lookups
+/// - With `hex` (`-H`) every argument is a hexadecimal byte such as `03` or `0x1b`
+pub fn translate_tmux_keys(keys: &[String], literal: bool, hex: bool) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+
+    for key in keys {
+        if hex {
+            let digits = key.trim_start_matches("0x").trim_start_matches("0X");
+            let byte = u8::from_str_radix(digits, 16)
+                .map_err(|_| anyhow::anyhow!("Invalid hex key '{}'", key))?;
+            bytes.push(byte);
+        } else if literal {
+            bytes.extend_from_slice(key.as_bytes());
+        } else {
+            match key_bytes(key) {
+                Some(key) => bytes.extend(key),
+                None => bytes.extend_from_slice(key.as_bytes()),
+            }
+        }
+    }
+
+    Ok(bytes)
+}
+
+/// Bytes for a single tmux key name, with optional `C-`/`M-` modifiers
+fn key_bytes(key: &str) -> Option<Vec<u8>> {
+    if let Some(rest) = key.strip_prefix("M-") {
+        let mut bytes = vec![0x1b];
+        bytes.extend(key_bytes(rest).or_else(|| single_char(rest))?);
+        return Some(bytes);
+    }
+
+    if let Some(rest) = key.strip_prefix("C-").or_else(|| key.strip_prefix('^')) {
+        let c = match rest.chars().collect::<Vec<_>>().as_slice() {
+            [c] => *c,
+            _ if rest.eq_ignore_ascii_case("space") => ' ',
+            _ => return None,
+        };
+        return control_byte(c).map(|byte| vec![byte]);
+    }
+
+    let bytes: &[u8] = match key {
+        "Enter" => b"\r",
+        "Tab" => b"\t",
+        "BTab" => b"\x1b[Z",
+        "Escape" => b"\x1b",
+        "Space" => b" ",
+        "BSpace" => b"\x7f",
+        "Up" => b"\x1b[A",
+        "Down" => b"\x1b[B",
+        "Right" => b"\x1b[C",
+        "Left" => b"\x1b[D",
+        "Home" => b"\x1b[H",
+        "End" => b"\x1b[F",
+        "IC" | "Insert" => b"\x1b[2~",
+        "DC" | "Delete" => b"\x1b[3~",
+        "PPage" | "PageUp" | "PgUp" => b"\x1b[5~",
+        "NPage" | "PageDown" | "PgDn" => b"\x1b[6~",
+        "F1" => b"\x1bOP",
+        "F2" => b"\x1bOQ",
+        "F3" => b"\x1bOR",
+        "F4" => b"\x1bOS",
+        "F5" => b"\x1b[15~",
+        "F6" => b"\x1b[17~",
+        "F7" => b"\x1b[18~",
+        "F8" => b"\x1b[19~",
+        "F9" => b"\x1b[20~",
+        "F10" => b"\x1b[21~",
+        "F11" => b"\x1b[23~",
+        "F12" => b"\x1b[24~",
+        _ => return None,
+    };
+    Some(bytes.to_vec())
+}
+
+fn single_char(key: &str) -> Option<Vec<u8>> {
+    let mut chars = key.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c.to_string().into_bytes()),
+        _ => None,
+    }
+}
+
+/// The control code for `C-<c>`, e.g. `C-c` is 0x03 and `C-[` is Escape
+fn control_byte(c: char) -> Option<u8> {
+    match c {
+        ' ' | '@' | '2' => Some(0x00),
+        '?' => Some(0x7f),
+        c if c.is_ascii_alphabetic() || ('['..='_').contains(&c) => {
+            Some(c.to_ascii_uppercase() as u8 & 0x1f)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::translate_tmux_keys;
+
+    fn keys(keys: &[&str]) -> Vec<String> {
+        keys.iter().map(|key| key.to_string()).collect()
+    }
+
+    #[test]
+    fn test_translate_tmux_keys() {
+        assert_eq!(
+            translate_tmux_keys(&keys(&["ls -la", "Enter"]), false, false).unwrap(),
+            b"ls -la\r"
+        );
+        assert_eq!(
+            translate_tmux_keys(&keys(&["C-c", "M-b", "Up", "^D"]), false, false).unwrap(),
+            b"\x03\x1bb\x1b[A\x04"
+        );
+        assert_eq!(
+            translate_tmux_keys(&keys(&["Enter"]), true, false).unwrap(),
+            b"Enter"
+        );
+        assert_eq!(
+            translate_tmux_keys(&keys(&["03", "0x1b"]), false, true).unwrap(),
+            b"\x03\x1b"
+        );
+        assert!(translate_tmux_keys(&keys(&["zz"]), false, true).is_err());
+    }
+}
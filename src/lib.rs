@@ -1,9 +1,20 @@
-pub mod shell;
+pub mod keys;
+pub mod messages;
 
-// Re-export main shell functionality for library use
-pub use shell::{ShellConfig, CommandResult, PtyQueueProcessor, create_pty_session, setup_interactive_pty};
+#[cfg(feature = "runtime")]
+pub mod api;
 
-// Convenience functions for common use cases
-pub mod prelude {
-    pub use crate::shell::{ShellConfig, CommandResult, PtyQueueProcessor, create_pty_session, setup_interactive_pty};
-}
\ No newline at end of file
+/// The `typeypipe` command line, run by its `main`. Not covered by semver.
+#[cfg(feature = "runtime")]
+#[doc(hidden)]
+pub mod cli;
+
+/// The building blocks of the `typeypipe` command. Library users go through `api`, which
+/// stays stable when these are reorganized.
+#[cfg(feature = "runtime")]
+pub(crate) mod shell;
+
+/// Entry points for fuzzers over the terminal stream parsers
+#[cfg(feature = "runtime")]
+#[doc(hidden)]
+pub use shell::fuzz;
@@ -0,0 +1,409 @@
+use crate::keys::translate_tmux_keys;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Bytes appended to a queued command to submit it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Terminator {
+    /// Carriage return, what the Enter key sends
+    #[default]
+    Cr,
+    /// Line feed, for REPLs reading their input line by line
+    Lf,
+    Crlf,
+    /// Nothing, leaving the command as partial input on the line
+    None,
+}
+
+impl Terminator {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Terminator::Cr => "\r",
+            Terminator::Lf => "\n",
+            Terminator::Crlf => "\r\n",
+            Terminator::None => "",
+        }
+    }
+}
+
+impl FromStr for Terminator {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "cr" => Ok(Terminator::Cr),
+            "lf" => Ok(Terminator::Lf),
+            "crlf" => Ok(Terminator::Crlf),
+            "none" => Ok(Terminator::None),
+            _ => Err(anyhow::anyhow!(
+                "Invalid terminator '{}': expected cr, lf, crlf or none",
+                value
+            )),
+        }
+    }
+}
+
+/// Command execution result
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CommandResult {
+    pub output: String,
+    pub success: bool,
+    /// Exit status reported by the shell integration (OSC 133), when available
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+    #[serde(default)]
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    #[serde(default)]
+    pub finished_at: chrono::DateTime<chrono::Utc>,
+    /// Number of output bytes read back from the shell
+    #[serde(default)]
+    pub stdout_bytes: usize,
+    /// Whether more output was available than was captured in `output`
+    #[serde(default)]
+    pub truncated: bool,
+}
+
+impl CommandResult {
+    /// Cut `output` down to `max_bytes`, ending it with a marker that gives the full size.
+    /// `stdout_bytes` keeps counting all the output the command produced, so `output` may
+    /// already hold only the start of it.
+    pub fn limit_output(&mut self, max_bytes: usize) {
+        if self.output.len() <= max_bytes {
+            return;
+        }
+
+        let mut end = max_bytes;
+        while !self.output.is_char_boundary(end) {
+            end -= 1;
+        }
+        let full_size = self.stdout_bytes.max(self.output.len());
+        self.output.truncate(end);
+        self.output.push_str(&format!(
+            "\n[output truncated: {} of {} bytes shown]",
+            end, full_size
+        ));
+        self.truncated = true;
+    }
+}
+
+/// Version of the `QueueMessage` schema written by this build
+pub const QUEUE_MESSAGE_VERSION: u32 = 1;
+
+/// Something to deliver to a session, in the one shape every producer uses: queue files,
+/// the control socket and the MCP server.
+///
+/// **Encoding:**
+/// - Queue files written by typeypipe hold one JSON object with a `version` and a `type`:
+///   `{"version":1,"type":"command","command":"make test"}`
+/// - Any other file content is a plain command, so `echo "ls" > .tp/myapp/cmd` keeps working
+/// - Messages with a newer `version` than this build understands are refused instead of being
+///   typed into the shell
+/// - An optional `target` names the session the message is meant for; a session hands
+///   messages for other sessions on to their queue (see `queue::route_to_session`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum QueueMessage {
+    /// A command line, submitted after the command hook has approved it. `terminator`
+    /// overrides the queue's default (Enter for interactive sessions).
+    Command {
+        command: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        terminator: Option<Terminator>,
+    },
+    /// Text written to the shell exactly as given, without a trailing Enter
+    Input { data: String },
+    /// tmux key names such as `C-c`, `Up` or `Enter`, for interrupting commands and driving
+    /// TUIs; anything that is not a key name is typed as text
+    Keys { keys: Vec<String> },
+    /// Arbitrary bytes, base64 encoded
+    Bytes { data: String },
+}
+
+#[derive(Serialize, Deserialize)]
+struct VersionedMessage {
+    version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    target: Option<String>,
+    #[serde(flatten)]
+    message: QueueMessage,
+}
+
+impl QueueMessage {
+    /// Serialize with the current schema version
+    pub fn encode(&self) -> anyhow::Result<String> {
+        self.encode_for(None)
+    }
+
+    /// Serialize addressed to the session named `target`
+    pub fn encode_for(&self, target: Option<&str>) -> anyhow::Result<String> {
+        serde_json::to_string(&VersionedMessage {
+            version: QUEUE_MESSAGE_VERSION,
+            target: target.map(str::to_string),
+            message: self.clone(),
+        })
+        .map_err(|e| anyhow::anyhow!("Failed to serialize queue message: {}", e))
+    }
+
+    /// Read a queue file's contents, accepting both encoded messages and plain commands
+    pub fn decode(contents: &str) -> anyhow::Result<Self> {
+        Self::decode_addressed(contents).map(|(_, message)| message)
+    }
+
+    /// Like `decode`, also returning the session the message is addressed to, if any
+    pub fn decode_addressed(contents: &str) -> anyhow::Result<(Option<String>, Self)> {
+        let trimmed = contents.trim();
+        let object = match serde_json::from_str::<serde_json::Value>(trimmed) {
+            Ok(serde_json::Value::Object(object)) if object.contains_key("version") => object,
+            // Not a message, e.g. `{ make; make test; }`
+            _ => {
+                return Ok((
+                    None,
+                    QueueMessage::Command {
+                        command: trimmed.to_string(),
+                        terminator: None,
+                    },
+                ))
+            }
+        };
+
+        let version = object
+            .get("version")
+            .and_then(serde_json::Value::as_u64)
+            .ok_or_else(|| anyhow::anyhow!("Queue message version must be a number"))?;
+        if version > QUEUE_MESSAGE_VERSION as u64 {
+            return Err(anyhow::anyhow!(
+                "Queue message version {} is newer than the supported version {}",
+                version,
+                QUEUE_MESSAGE_VERSION
+            ));
+        }
+
+        let versioned: VersionedMessage = serde_json::from_value(object.into())
+            .map_err(|e| anyhow::anyhow!("Invalid queue message: {}", e))?;
+        // Catch undecodable payloads before the message reaches the queue engine
+        versioned.message.input_bytes()?;
+        Ok((versioned.target, versioned.message))
+    }
+
+    /// The bytes written to the shell as-is, or `None` for a command (which is hooked and
+    /// terminated first)
+    pub fn input_bytes(&self) -> anyhow::Result<Option<Vec<u8>>> {
+        match self {
+            QueueMessage::Command { .. } => Ok(None),
+            QueueMessage::Input { data } => Ok(Some(data.clone().into_bytes())),
+            QueueMessage::Keys { keys } => translate_tmux_keys(keys, false, false).map(Some),
+            QueueMessage::Bytes { data } => BASE64
+                .decode(data)
+                .map(Some)
+                .map_err(|e| anyhow::anyhow!("Invalid base64 in bytes message: {}", e)),
+        }
+    }
+
+    /// How the message reads in logs and the recent command list
+    pub fn describe(&self) -> String {
+        match self {
+            QueueMessage::Command { command, .. } => command.clone(),
+            QueueMessage::Input { data } => data.clone(),
+            QueueMessage::Keys { keys } => keys.join(" "),
+            QueueMessage::Bytes { data } => format!("bytes:{}", data),
+        }
+    }
+}
+
+/// Why a queued message was dropped without reaching the shell
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureCode {
+    /// Not a queue message this build can decode
+    InvalidMessage,
+    /// Addressed to a session that is not running
+    Unroutable,
+    /// Refused by the session's `Authorizer`
+    RejectedByAuthorizer,
+    /// Refused by the `--command-hook`
+    RejectedByHook,
+    /// The `--command-hook` itself failed
+    HookFailed,
+    /// Writing to the shell's PTY failed on several passes. A queue file is only dropped
+    /// with this code after a few passes; until then it stays queued and is tried again.
+    WriteFailed,
+}
+
+impl FailureCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FailureCode::InvalidMessage => "invalid_message",
+            FailureCode::Unroutable => "unroutable",
+            FailureCode::RejectedByAuthorizer => "rejected_by_authorizer",
+            FailureCode::RejectedByHook => "rejected_by_hook",
+            FailureCode::HookFailed => "hook_failed",
+            FailureCode::WriteFailed => "write_failed",
+        }
+    }
+}
+
+/// What a producer is told when its message was dropped: left as JSON in the session's
+/// `<queue dir>.failed` directory for queue files, and as the `code` of control socket errors
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueueFailure {
+    /// Name of the queue file
+    pub file: String,
+    pub code: FailureCode,
+    /// Human-readable reason, as written to the session log
+    pub error: String,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+impl std::fmt::Display for QueueFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.error, self.code.as_str())
+    }
+}
+
+impl std::error::Error for QueueFailure {}
+
+#[cfg(test)]
+mod tests {
+    use super::{CommandResult, FailureCode, QueueFailure, QueueMessage, Terminator};
+
+    #[test]
+    fn test_limit_output_marks_truncation() {
+        let epoch = chrono::DateTime::<chrono::Utc>::default();
+        let mut result = CommandResult {
+            output: "héllo world".to_string(),
+            success: true,
+            exit_code: Some(0),
+            started_at: epoch,
+            finished_at: epoch,
+            stdout_bytes: 12,
+            truncated: false,
+        };
+
+        result.limit_output(64);
+        assert!(!result.truncated);
+
+        // Byte 2 falls inside the two-byte 'é'
+        result.limit_output(2);
+        assert_eq!(result.output, "h\n[output truncated: 1 of 12 bytes shown]");
+        assert!(result.truncated);
+        assert_eq!(result.stdout_bytes, 12);
+    }
+
+    #[test]
+    fn test_versioned_messages_round_trip() {
+        // Producers write version 1, the current schema
+        assert_eq!(
+            QueueMessage::decode(r#"{"version":1,"type":"command","command":"make test"}"#)
+                .unwrap(),
+            QueueMessage::Command {
+                command: "make test".to_string(),
+                terminator: None
+            }
+        );
+        assert_eq!(
+            QueueMessage::decode(
+                r#"{"version":1,"type":"command","command":"1 + 1","terminator":"lf"}"#
+            )
+            .unwrap(),
+            QueueMessage::Command {
+                command: "1 + 1".to_string(),
+                terminator: Some(Terminator::Lf)
+            }
+        );
+
+        // Plain queue files are commands
+        assert_eq!(
+            QueueMessage::decode("ls -la\n").unwrap(),
+            QueueMessage::Command {
+                command: "ls -la".to_string(),
+                terminator: None
+            }
+        );
+        assert_eq!(
+            QueueMessage::decode("{ make; make test; }").unwrap(),
+            QueueMessage::Command {
+                command: "{ make; make test; }".to_string(),
+                terminator: None
+            }
+        );
+
+        for message in [
+            QueueMessage::Command {
+                command: "cargo build".to_string(),
+                terminator: Some(Terminator::Crlf),
+            },
+            QueueMessage::Input {
+                data: "\x1b[A".to_string(),
+            },
+            QueueMessage::Keys {
+                keys: vec!["C-c".to_string(), "Up".to_string(), "Enter".to_string()],
+            },
+            QueueMessage::Bytes {
+                data: "G1s/MjVo".to_string(),
+            },
+        ] {
+            assert_eq!(
+                QueueMessage::decode(&message.encode().unwrap()).unwrap(),
+                message
+            );
+        }
+
+        let keys =
+            QueueMessage::decode(r#"{"version":1,"type":"keys","keys":["C-c","Up","Enter"]}"#)
+                .unwrap();
+        assert_eq!(keys.input_bytes().unwrap().unwrap(), b"\x03\x1b[A\r");
+        let bytes =
+            QueueMessage::decode(r#"{"version":1,"type":"bytes","data":"G1s/MjVo"}"#).unwrap();
+        assert_eq!(bytes.input_bytes().unwrap().unwrap(), b"\x1b[?25h");
+        assert!(QueueMessage::decode(r#"{"version":1,"type":"bytes","data":"%%"}"#).is_err());
+
+        assert!(QueueMessage::decode(r#"{"version":2,"type":"command","command":"ls"}"#).is_err());
+        assert!(QueueMessage::decode(r#"{"version":1,"type":"launch"}"#).is_err());
+    }
+
+    #[test]
+    fn test_target_addresses_a_session() {
+        let message = QueueMessage::Command {
+            command: "make".to_string(),
+            terminator: None,
+        };
+
+        let (target, decoded) =
+            QueueMessage::decode_addressed(&message.encode_for(Some("build")).unwrap()).unwrap();
+        assert_eq!(target.as_deref(), Some("build"));
+        assert_eq!(decoded, message);
+
+        let (target, _) = QueueMessage::decode_addressed(&message.encode().unwrap()).unwrap();
+        assert_eq!(target, None);
+        assert!(!message.encode().unwrap().contains("target"));
+
+        let (target, _) = QueueMessage::decode_addressed("make").unwrap();
+        assert_eq!(target, None);
+    }
+
+    #[test]
+    fn test_failure_codes_serialize_as_their_names() {
+        for code in [
+            FailureCode::InvalidMessage,
+            FailureCode::Unroutable,
+            FailureCode::RejectedByAuthorizer,
+            FailureCode::RejectedByHook,
+            FailureCode::HookFailed,
+            FailureCode::WriteFailed,
+        ] {
+            let json = serde_json::to_string(&code).unwrap();
+            assert_eq!(json, format!("\"{}\"", code.as_str()));
+            assert_eq!(serde_json::from_str::<FailureCode>(&json).unwrap(), code);
+        }
+
+        let failure: QueueFailure = serde_json::from_str(
+            r#"{"file":"cmd","code":"rejected_by_hook","error":"no sudo","timestamp":"2025-03-01T10:07:12Z"}"#,
+        )
+        .unwrap();
+        assert_eq!(failure.code, FailureCode::RejectedByHook);
+        assert_eq!(failure.to_string(), "no sudo (rejected_by_hook)");
+    }
+}
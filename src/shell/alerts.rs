@@ -0,0 +1,186 @@
+use crate::shell::glyphs::label;
+use std::borrow::Cow;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// What happens to BEL characters the shell prints
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BellPolicy {
+    /// Pass the bell to the outer terminal (which may beep or set its urgency hint) and
+    /// mark the terminal title until the user next types
+    #[default]
+    Forward,
+    /// Drop the bell and leave the title alone
+    Mute,
+}
+
+impl FromStr for BellPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "forward" => Ok(BellPolicy::Forward),
+            "mute" => Ok(BellPolicy::Mute),
+            _ => Err(anyhow::anyhow!(
+                "Invalid bell policy '{}': expected forward or mute",
+                value
+            )),
+        }
+    }
+}
+
+/// Whether the title currently carries an alert marker, with the previous title saved on
+/// the terminal's title stack
+static TITLE_FLAGGED: AtomicBool = AtomicBool::new(false);
+
+/// Save the outer terminal's title and replace it with an alert marker
+const FLAG_TITLE: &str = "\x1b[22;0t\x1b]2;";
+/// Restore the title saved by `FLAG_TITLE`
+const RESTORE_TITLE: &str = "\x1b[23;0t";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum StreamState {
+    #[default]
+    Ground,
+    Escape,
+    /// Inside an OSC, DCS, APC or PM string, where BEL is a terminator rather than a bell
+    String,
+    StringEscape,
+}
+
+/// Watches output on its way to the outer terminal for bells and for activity after a
+/// quiet spell, and marks the terminal title when either happens.
+///
+/// **Alerts:**
+/// - Bell: a BEL outside an escape sequence. Forwarded or dropped according to `BellPolicy`
+/// - Activity: output after `activity_quiet` without any, while the user is not typing. Rings
+///   the outer terminal's bell unless bells are muted
+/// - The title shows `🔔 <session>` (`[alert] <session>` with `--plain`) until the user next
+///   types (see `clear_alert`)
+pub struct AlertMonitor {
+    session: String,
+    bell: BellPolicy,
+    activity_quiet: Option<Duration>,
+    last_output: Instant,
+    state: StreamState,
+    /// Alert output waiting for the stream to leave an escape sequence
+    pending_bell: bool,
+    pending_flag: bool,
+}
+
+impl AlertMonitor {
+    pub fn new(session: &str, bell: BellPolicy, activity_quiet: Option<Duration>) -> Self {
+        Self {
+            session: session.to_string(),
+            bell,
+            activity_quiet,
+            last_output: Instant::now(),
+            state: StreamState::default(),
+            pending_bell: false,
+            pending_flag: false,
+        }
+    }
+
+    /// Bytes to write to the outer terminal for `chunk`; `user_idle` says whether the user
+    /// has been away from the keyboard long enough for output to count as activity
+    pub fn process<'a>(&mut self, chunk: &'a [u8], user_idle: bool) -> Cow<'a, [u8]> {
+        let mut bells = Vec::new();
+        for (index, &byte) in chunk.iter().enumerate() {
+            self.state = match (self.state, byte) {
+                (StreamState::Ground, 0x07) => {
+                    bells.push(index);
+                    StreamState::Ground
+                }
+                (StreamState::Ground, 0x1b) => StreamState::Escape,
+                (StreamState::Ground, _) => StreamState::Ground,
+                (StreamState::Escape, b']' | b'P' | b'_' | b'^') => StreamState::String,
+                (StreamState::Escape, 0x1b) => StreamState::Escape,
+                (StreamState::Escape, _) => StreamState::Ground,
+                (StreamState::String, 0x07) => StreamState::Ground,
+                (StreamState::String, 0x1b) => StreamState::StringEscape,
+                (StreamState::String, _) => StreamState::String,
+                (StreamState::StringEscape, b'\\') => StreamState::Ground,
+                (StreamState::StringEscape, _) => StreamState::String,
+            };
+        }
+
+        let now = Instant::now();
+        let active = user_idle
+            && self
+                .activity_quiet
+                .is_some_and(|quiet| now.duration_since(self.last_output) >= quiet);
+        self.last_output = now;
+
+        let muted = self.bell == BellPolicy::Mute;
+        let mut output: Cow<[u8]> = if muted && !bells.is_empty() {
+            let mut kept = Vec::with_capacity(chunk.len());
+            let mut start = 0;
+            for &bell in &bells {
+                kept.extend_from_slice(&chunk[start..bell]);
+                start = bell + 1;
+            }
+            kept.extend_from_slice(&chunk[start..]);
+            Cow::Owned(kept)
+        } else {
+            Cow::Borrowed(chunk)
+        };
+
+        self.pending_bell |= active && !muted;
+        self.pending_flag |= active || (!bells.is_empty() && !muted);
+
+        // Anything added mid-sequence would corrupt it, so wait for the sequence to end
+        if self.state == StreamState::Ground && (self.pending_bell || self.pending_flag) {
+            let output = output.to_mut();
+            if std::mem::take(&mut self.pending_bell) {
+                output.push(0x07);
+            }
+            if std::mem::take(&mut self.pending_flag)
+                && !TITLE_FLAGGED.swap(true, Ordering::Relaxed)
+            {
+                let flag = format!("{}{} {}\x07", FLAG_TITLE, label("🔔"), self.session);
+                output.extend_from_slice(flag.as_bytes());
+            }
+        }
+        output
+    }
+}
+
+/// Put back the title an alert replaced; called when the user types, since they are
+/// evidently looking at the session
+pub fn clear_alert() {
+    if TITLE_FLAGGED.swap(false, Ordering::Relaxed) {
+        let mut stdout = std::io::stdout().lock();
+        let _ = stdout.write_all(RESTORE_TITLE.as_bytes());
+        let _ = stdout.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AlertMonitor, BellPolicy, TITLE_FLAGGED};
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+
+    #[test]
+    fn test_bells_are_told_apart_from_osc_terminators() {
+        let mut muted = AlertMonitor::new("web", BellPolicy::Mute, None);
+        assert_eq!(&*muted.process(b"a\x07b\x1b]0;ti", false), b"ab\x1b]0;ti");
+        assert_eq!(&*muted.process(b"tle\x07c\x07", false), b"tle\x07c");
+        assert!(!TITLE_FLAGGED.load(Ordering::Relaxed));
+
+        let mut forward = AlertMonitor::new("web", BellPolicy::Forward, None);
+        let output = forward.process(b"done\x07", false).into_owned();
+        assert!(output.starts_with(b"done\x07\x1b[22;0t\x1b]2;"));
+        assert!(String::from_utf8_lossy(&output).contains("🔔 web"));
+        assert_eq!(&*forward.process(b"again\x07", false), b"again\x07");
+
+        // Output after a quiet spell rings the bell, but only while the user is away
+        let mut activity = AlertMonitor::new("web", BellPolicy::Forward, Some(Duration::ZERO));
+        assert_eq!(&*activity.process(b"x", true), b"x\x07");
+        assert_eq!(&*activity.process(b"x", false), b"x");
+        assert_eq!(&*activity.process(b"\x1b]0;", true), b"\x1b]0;");
+        assert_eq!(&*activity.process(b"t\x07", false), b"t\x07\x07");
+    }
+}
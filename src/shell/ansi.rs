@@ -0,0 +1,56 @@
+/// Remove ANSI escape sequences (CSI, OSC, and two-byte escapes) and stray control characters
+/// from terminal output, keeping newlines and tabs.
+pub fn strip_ansi(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => match chars.next() {
+                // CSI: parameters and intermediates up to a final byte in 0x40..=0x7e
+                Some('[') => {
+                    for c in chars.by_ref() {
+                        if ('\x40'..='\x7e').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                // OSC (and DCS/APC/PM): terminated by BEL or ST (ESC \)
+                Some(']' | 'P' | '_' | '^') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\x07' {
+                            break;
+                        }
+                        if c == '\x1b' && chars.peek() == Some(&'\\') {
+                            chars.next();
+                            break;
+                        }
+                    }
+                }
+                // Charset selection takes one more character
+                Some('(' | ')' | '*' | '+') => {
+                    chars.next();
+                }
+                _ => {}
+            },
+            '\n' | '\t' => output.push(c),
+            c if c.is_control() => {}
+            c => output.push(c),
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::strip_ansi;
+
+    #[test]
+    fn test_strip_ansi_removes_escape_sequences() {
+        assert_eq!(strip_ansi("\x1b[1;32mok\x1b[0m\r\n"), "ok\n");
+        assert_eq!(strip_ansi("\x1b]0;title\x07$ ls"), "$ ls");
+        assert_eq!(strip_ansi("\x1b]7;file:///tmp\x1b\\done"), "done");
+        assert_eq!(strip_ansi("\x1b[?2004hroot# "), "root# ");
+    }
+}
@@ -0,0 +1,200 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Mark the shell integration prints when it draws a fresh prompt
+const PROMPT_MARK: &[u8] = b"\x1b]133;A";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    #[default]
+    None,
+    Start,
+    Csi,
+    Ss3,
+}
+
+/// Follows the bytes written to the shell to know whether the user has a partially typed
+/// line at the prompt.
+///
+/// **Line editing understood:**
+/// - Enter, Ctrl-C and Ctrl-U end or discard the line
+/// - Backspace removes a character and Ctrl-W a word
+/// - Escape sequences (arrow keys, function keys) move the cursor without changing the line
+///
+/// Anything the tracker cannot follow (history recall, completion) leaves the line pending
+/// until Enter or a fresh prompt.
+#[derive(Debug, Default)]
+pub struct LineTracker {
+    line: Vec<u8>,
+    escape: Escape,
+}
+
+impl LineTracker {
+    pub const fn new() -> Self {
+        Self {
+            line: Vec::new(),
+            escape: Escape::None,
+        }
+    }
+
+    /// Follow bytes written to the shell
+    pub fn observe(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.escape = match self.escape {
+                Escape::Start if byte == b'[' => Escape::Csi,
+                Escape::Start if byte == b'O' => Escape::Ss3,
+                Escape::Start | Escape::Ss3 => Escape::None,
+                Escape::Csi if (0x40..=0x7e).contains(&byte) => Escape::None,
+                Escape::Csi => Escape::Csi,
+                Escape::None => {
+                    self.edit(byte);
+                    if byte == 0x1b {
+                        Escape::Start
+                    } else {
+                        Escape::None
+                    }
+                }
+            };
+        }
+    }
+
+    fn edit(&mut self, byte: u8) {
+        match byte {
+            b'\r' | b'\n' | 0x03 | 0x15 => self.line.clear(),
+            0x7f | 0x08 => {
+                // Drop one UTF-8 character: its continuation bytes and then its first byte
+                while self.line.pop().is_some_and(|b| b & 0xc0 == 0x80) {}
+            }
+            0x17 => {
+                while self.line.last() == Some(&b' ') {
+                    self.line.pop();
+                }
+                while self.line.last().is_some_and(|&b| b != b' ') {
+                    self.line.pop();
+                }
+            }
+            b'\t' => self.line.push(byte),
+            0x1b => {}
+            byte if byte < 0x20 => {}
+            byte => self.line.push(byte),
+        }
+    }
+
+    /// The shell drew a new prompt, so whatever was typed before is gone
+    pub fn prompt_shown(&mut self) {
+        self.line.clear();
+        self.escape = Escape::None;
+    }
+
+    pub fn is_pending(&self) -> bool {
+        !self.line.is_empty()
+    }
+}
+
+/// Finds the prompt mark in output that arrives in arbitrary chunks, including a mark split
+/// across two chunks
+#[derive(Debug, Default)]
+pub struct PromptMarks {
+    matched: usize,
+}
+
+impl PromptMarks {
+    pub const fn new() -> Self {
+        Self { matched: 0 }
+    }
+
+    /// Whether a prompt mark ends within `chunk`
+    pub fn feed(&mut self, chunk: &[u8]) -> bool {
+        let mut found = false;
+        for &byte in chunk {
+            // The mark's only ESC is its first byte, so a mismatch restarts at ESC or nothing
+            self.matched = if byte == PROMPT_MARK[self.matched] {
+                self.matched + 1
+            } else {
+                usize::from(byte == PROMPT_MARK[0])
+            };
+            if self.matched == PROMPT_MARK.len() {
+                found = true;
+                self.matched = 0;
+            }
+        }
+        found
+    }
+}
+
+/// Prompts in one session's output and the line typed at them, shared by its input tasks,
+/// the output sink that watches for prompts and the queue processor
+#[derive(Debug, Default)]
+pub struct Arbiter {
+    prompt_marks: Mutex<PromptMarks>,
+    /// Whether the shell has ever marked a prompt, i.e. runs the shell integration
+    prompt_marks_seen: AtomicBool,
+    /// Line the user (or a `write` control request) is typing
+    line: Mutex<LineTracker>,
+}
+
+impl Arbiter {
+    /// Record bytes written to the shell by anything other than the queue
+    pub fn observe_input(&self, bytes: &[u8]) {
+        if let Ok(mut line) = self.line.lock() {
+            line.observe(bytes);
+        }
+    }
+
+    /// Look for a fresh prompt in a chunk of shell output, returning whether one was drawn
+    pub fn observe_output(&self, chunk: &[u8]) -> bool {
+        if !self
+            .prompt_marks
+            .lock()
+            .is_ok_and(|mut marks| marks.feed(chunk))
+        {
+            return false;
+        }
+        self.prompt_marks_seen.store(true, Ordering::Relaxed);
+        if let Ok(mut line) = self.line.lock() {
+            line.prompt_shown();
+        }
+        true
+    }
+
+    /// Whether prompts can be detected in this session's output
+    pub fn prompt_marks_seen(&self) -> bool {
+        self.prompt_marks_seen.load(Ordering::Relaxed)
+    }
+
+    /// Whether queued commands must wait because a typed line has not been submitted yet
+    pub fn line_pending(&self) -> bool {
+        self.line.lock().is_ok_and(|line| line.is_pending())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LineTracker;
+
+    #[test]
+    fn test_tracks_partially_typed_line() {
+        let mut tracker = LineTracker::new();
+        assert!(!tracker.is_pending());
+
+        tracker.observe(b"git st");
+        assert!(tracker.is_pending());
+
+        // Arrow keys and backspacing over everything leave nothing pending
+        tracker.observe(b"\x1b[D\x1bOC\x7f\x7f\x7f\x7f\x7f\x7f");
+        assert!(!tracker.is_pending());
+
+        tracker.observe("ls -la é".as_bytes());
+        tracker.observe(b"\x7f\x17");
+        assert!(tracker.is_pending());
+        tracker.observe(b"\x17");
+        assert!(!tracker.is_pending());
+
+        tracker.observe(b"make test\r");
+        assert!(!tracker.is_pending());
+
+        tracker.observe(b"sleep 10");
+        tracker.prompt_shown();
+        assert!(!tracker.is_pending());
+    }
+}
@@ -0,0 +1,107 @@
+use crate::shell::durability::sync_required;
+use crate::shell::encryption::seal;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// One line of the audit log.
+///
+/// The audit log is separate from the operational `.log` file: it only records commands that
+/// reached the shell (or were accepted for the queue), who sent them and what happened, one JSON
+/// object per line. Entries are fsynced according to `--fsync` (by default before the write
+/// returns), and the file is never rotated or truncated by typeypipe.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    /// Queue name of the session
+    pub session: String,
+    /// Where the command came from, e.g. `queue:<file>` or `socket:pid=<pid>,uid=<uid>`
+    pub source: String,
+    pub command: String,
+    /// `queued`, `injected`, `written`, or `failed: <reason>`
+    pub result: String,
+}
+
+/// The audit log of one session, written by its queue processor and control socket
+pub struct AuditLog {
+    file: Mutex<File>,
+    path: PathBuf,
+    session: String,
+}
+
+impl AuditLog {
+    /// Start appending audit entries for `session` to `path`
+    pub fn open(path: &Path, session: &str) -> Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open audit log {}", path.display()))?;
+
+        Ok(Self {
+            file: Mutex::new(file),
+            path: path.to_path_buf(),
+            session: session.to_string(),
+        })
+    }
+
+    /// Append an entry. With the default fsync policy, entries are durable once this returns.
+    pub fn record(&self, source: &str, command: &str, result: &str) {
+        let Ok(mut file) = self.file.lock() else {
+            return;
+        };
+
+        let entry = AuditEntry {
+            timestamp: chrono::Utc::now(),
+            session: self.session.clone(),
+            source: source.to_string(),
+            command: command.to_string(),
+            result: result.to_string(),
+        };
+
+        if let Err(e) = write_entry(&mut file, &self.path, &entry) {
+            tracing::error!(error = %e, "failed to write audit entry");
+        }
+    }
+}
+
+fn write_entry(file: &mut File, path: &Path, entry: &AuditEntry) -> Result<()> {
+    let mut line = serde_json::to_vec(entry).context("Failed to serialize audit entry")?;
+    line.push(b'\n');
+    let line = seal(&line)?;
+    file.write_all(&line)
+        .context("Failed to write audit entry")?;
+    if sync_required(path) {
+        file.sync_data().context("Failed to sync audit log")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AuditEntry, AuditLog};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_audit_entries_are_appended_as_json_lines() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("session.audit");
+
+        let audit_log = AuditLog::open(&path, "session").unwrap();
+        audit_log.record("queue:cmd-1", "ls -la", "injected");
+        audit_log.record("socket:pid=1,uid=0", "\u{3}", "written");
+
+        let entries: Vec<AuditEntry> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].source, "queue:cmd-1");
+        assert_eq!(entries[1].command, "\u{3}");
+        assert!(entries.iter().all(|entry| entry.session == "session"));
+    }
+}
@@ -0,0 +1,103 @@
+use crate::shell::types::QueueMessage;
+use std::fmt;
+
+/// Where a message came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// A file in the session's queue directory
+    Queue { file: String },
+    /// A control socket client, identified by its peer credentials
+    Socket { pid: Option<i32>, uid: u32 },
+    /// JSON-RPC over stdin with `--control-stdio`
+    Stdio,
+}
+
+/// Formatted the way the audit log and history record sources
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Queue { file } => write!(f, "queue:{}", file),
+            Source::Socket { pid, uid } => match pid {
+                Some(pid) => write!(f, "socket:pid={},uid={}", pid, uid),
+                None => write!(f, "socket:pid=?,uid={}", uid),
+            },
+            Source::Stdio => write!(f, "stdio"),
+        }
+    }
+}
+
+/// What an `Authorizer` decided about a message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    Allow,
+    /// Refuse the message; the reason is logged and recorded like a command hook rejection
+    Deny(String),
+}
+
+/// Policy consulted for every message before it reaches the shell, for programs embedding
+/// typeypipe that decide in-process what may run. Given to each session it applies to with
+/// `SessionBuilder::authorizer`.
+///
+/// **When it is called:**
+/// - Queue files: after decoding, before routing, the command hook or injection
+/// - Control socket and JSON-RPC: `enqueue` and `write` requests, before they are accepted.
+///   Raw `write` data is presented as `QueueMessage::Bytes`
+/// - An enqueued command is checked again, as `Source::Queue`, when its file is injected
+///
+/// Any `Fn(&QueueMessage, &Source) -> Decision` closure is an `Authorizer`.
+pub trait Authorizer: Send + Sync {
+    fn authorize(&self, message: &QueueMessage, source: &Source) -> Decision;
+}
+
+impl<F> Authorizer for F
+where
+    F: Fn(&QueueMessage, &Source) -> Decision + Send + Sync,
+{
+    fn authorize(&self, message: &QueueMessage, source: &Source) -> Decision {
+        self(message, source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Decision, Source};
+    use crate::api::{FailureCode, SessionBuilder};
+    use crate::shell::types::QueueMessage;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_headless_session_drops_denied_queue_files() {
+        let authorizer = |message: &QueueMessage, source: &Source| match message {
+            QueueMessage::Command { command, .. } if command.contains("denied") => {
+                Decision::Deny(format!("{} may not run this", source))
+            }
+            _ => Decision::Allow,
+        };
+
+        let temp_dir = TempDir::new().unwrap();
+        let mut session = SessionBuilder::new(temp_dir.path().join("auth"))
+            .shell("/bin/sh")
+            .authorizer(Arc::new(authorizer))
+            .spawn()
+            .await
+            .unwrap();
+        let queue = session.queue();
+        let denied = queue.command("echo denied").await.unwrap();
+        let failure = queue
+            .outcome(&denied, Some(Duration::from_secs(10)))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(failure.code, FailureCode::RejectedByAuthorizer);
+        let queue_file = denied.file_name().unwrap().to_string_lossy().into_owned();
+        assert_eq!(
+            failure.error,
+            format!("queue:{} may not run this", queue_file)
+        );
+        assert!(!denied.exists());
+        session.kill().await.unwrap();
+    }
+}
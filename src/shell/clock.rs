@@ -0,0 +1,84 @@
+#[cfg(test)]
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(test)]
+use std::time::Duration;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Source of the wall-clock time a session reads for typing detection and the queue loop's
+/// heartbeat.
+///
+/// **Virtual time in tests:**
+/// - Give the session's `SessionContext` a `VirtualClock` and move it with `advance` instead
+///   of sleeping through the typing pause
+/// - Queue polling, PTY write retries, `wait_for_injection` timeouts and headless shutdown
+///   deadlines use tokio's timer instead, which a test started with
+///   `#[tokio::test(start_paused = true)]` runs in virtual time
+pub trait Clock: Send + Sync {
+    /// Milliseconds since the Unix epoch
+    fn now_ms(&self) -> u64;
+}
+
+/// The real time, used unless a test installs another clock
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_ms(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64
+    }
+}
+
+/// A clock that only moves when told to
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct VirtualClock {
+    now_ms: AtomicU64,
+}
+
+#[cfg(test)]
+impl VirtualClock {
+    /// A clock standing at `start_ms` milliseconds since the Unix epoch
+    pub fn new(start_ms: u64) -> Self {
+        Self {
+            now_ms: AtomicU64::new(start_ms),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.now_ms
+            .fetch_add(by.as_millis() as u64, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+impl Clock for VirtualClock {
+    fn now_ms(&self) -> u64 {
+        self.now_ms.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VirtualClock;
+    use crate::shell::context::SessionContext;
+    use crate::shell::types::Terminator;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_typing_pause_expires_in_virtual_time() {
+        let clock = Arc::new(VirtualClock::new(1_000_000));
+        let context = SessionContext::new(Terminator::Cr).clock(clock.clone());
+        context.set_input_timeout(30);
+
+        assert!(!context.is_user_typing());
+        context.update_user_input();
+        clock.advance(Duration::from_secs(29));
+        assert!(context.is_user_typing());
+        clock.advance(Duration::from_secs(2));
+        assert!(!context.is_user_typing());
+    }
+}
@@ -0,0 +1,304 @@
+use anyhow::{anyhow, Context, Result};
+use std::path::{Path, PathBuf};
+
+/// A value given to a profile setting
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SettingValue {
+    Text(String),
+    /// Bare `true` or `false`, for on/off options such as `record`
+    Bool(bool),
+}
+
+/// A named set of command line options from the config file, selected with `--profile`.
+///
+/// **Settings:**
+/// - Each setting is a long option name followed by its values: `shell "zsh"`,
+///   `input-timeout 10`, `watch "src/*.rs=cargo check"`
+/// - A setting given several values, or several times, repeats the option
+/// - A setting without values, or with `true`, turns an on/off option on; `false` leaves it off
+/// - `queue` is short for `queue-dir`, and `env "NAME" "value"` sets a variable in the shell
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    pub name: String,
+    pub settings: Vec<(String, Vec<SettingValue>)>,
+}
+
+impl Profile {
+    /// The command line options the profile stands for
+    pub fn to_args(&self) -> Result<Vec<String>> {
+        let mut args = Vec::new();
+        for (key, values) in &self.settings {
+            let option = match key.as_str() {
+                "queue" => "--queue-dir".to_string(),
+                key => format!("--{}", key),
+            };
+            let text = |value: &SettingValue| match value {
+                SettingValue::Text(text) => Ok(text.clone()),
+                SettingValue::Bool(_) => Err(anyhow!(
+                    "Profile '{}': {} expects text, not true or false",
+                    self.name,
+                    key
+                )),
+            };
+            match (key.as_str(), values.as_slice()) {
+                (_, []) | (_, [SettingValue::Bool(true)]) => args.push(option),
+                (_, [SettingValue::Bool(false)]) => {}
+                ("env", [name, value]) => {
+                    args.push(option);
+                    args.push(format!("{}={}", text(name)?, text(value)?));
+                }
+                (_, values) => {
+                    for value in values {
+                        args.push(option.clone());
+                        args.push(text(value)?);
+                    }
+                }
+            }
+        }
+        Ok(args)
+    }
+}
+
+/// Where the config file is looked for when `--config` is not given:
+/// `$XDG_CONFIG_HOME/typeypipe/config.kdl`, else `~/.config/typeypipe/config.kdl`
+pub fn default_config_path() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join("typeypipe").join("config.kdl"))
+}
+
+/// `path` with a leading `~` replaced by the home directory, as a shell would
+pub fn expand_home(path: &str) -> PathBuf {
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    match (path.strip_prefix('~'), home) {
+        (Some(""), Some(home)) => home,
+        (Some(rest), Some(home)) if rest.starts_with('/') => home.join(&rest[1..]),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// The profile called `name` in the config file at `path`
+pub fn load_profile(path: &Path, name: &str) -> Result<Profile> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file {}", path.display()))?;
+    let profiles = parse_config(&text).with_context(|| format!("In {}", path.display()))?;
+    let names: Vec<&str> = profiles.iter().map(|p| p.name.as_str()).collect();
+    profiles
+        .iter()
+        .find(|profile| profile.name == name)
+        .cloned()
+        .ok_or_else(|| {
+            anyhow!(
+                "No profile '{}' in {} (profiles: {})",
+                name,
+                path.display(),
+                if names.is_empty() {
+                    "none".to_string()
+                } else {
+                    names.join(", ")
+                }
+            )
+        })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Open,
+    Close,
+    /// `;` or a line break
+    End,
+}
+
+/// Split config text into tokens: bare words, quoted strings (with `\"`, `\\`, `\n` and `\t`
+/// escapes), braces and terminators. `//` starts a comment running to the end of the line.
+fn tokenize(text: &str) -> Result<Vec<(Token, usize)>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => {
+                tokens.push((Token::End, line));
+                line += 1;
+            }
+            ';' => tokens.push((Token::End, line)),
+            '{' => tokens.push((Token::Open, line)),
+            '}' => tokens.push((Token::Close, line)),
+            c if c.is_whitespace() => {}
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.peek().is_some_and(|&c| c != '\n') {
+                    chars.next();
+                }
+            }
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => text.push('\n'),
+                            Some('t') => text.push('\t'),
+                            Some(c @ ('"' | '\\')) => text.push(c),
+                            other => {
+                                return Err(anyhow!(
+                                    "Line {}: unknown escape \\{}",
+                                    line,
+                                    other.map(String::from).unwrap_or_default()
+                                ))
+                            }
+                        },
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            text.push(c);
+                        }
+                        None => return Err(anyhow!("Line {}: unterminated string", line)),
+                    }
+                }
+                tokens.push((Token::Quoted(text), line));
+            }
+            c => {
+                let mut word = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || matches!(c, ';' | '{' | '}' | '"') {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push((Token::Word(word), line));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Every `profile "name" { ... }` block in the config text
+fn parse_config(text: &str) -> Result<Vec<Profile>> {
+    let mut tokens = tokenize(text)?.into_iter().peekable();
+    let mut profiles: Vec<Profile> = Vec::new();
+
+    while let Some((token, line)) = tokens.next() {
+        match token {
+            Token::End => continue,
+            Token::Word(word) if word == "profile" => {}
+            other => {
+                return Err(anyhow!(
+                    "Line {}: expected `profile \"name\" {{`, found {:?}",
+                    line,
+                    other
+                ))
+            }
+        }
+        let name = match tokens.next() {
+            Some((Token::Quoted(name) | Token::Word(name), _)) => name,
+            _ => return Err(anyhow!("Line {}: profile needs a name", line)),
+        };
+        if !matches!(tokens.next(), Some((Token::Open, _))) {
+            return Err(anyhow!(
+                "Line {}: expected {{ after profile '{}'",
+                line,
+                name
+            ));
+        }
+        if profiles.iter().any(|profile| profile.name == name) {
+            return Err(anyhow!(
+                "Line {}: profile '{}' is defined twice",
+                line,
+                name
+            ));
+        }
+
+        let mut settings = Vec::new();
+        loop {
+            let (token, line) = tokens
+                .next()
+                .ok_or_else(|| anyhow!("Profile '{}' is missing its closing }}", name))?;
+            let key = match token {
+                Token::Close => break,
+                Token::End => continue,
+                Token::Word(key) => key,
+                other => {
+                    return Err(anyhow!(
+                        "Line {}: expected a setting, found {:?}",
+                        line,
+                        other
+                    ))
+                }
+            };
+            let mut values = Vec::new();
+            while let Some((token, _)) = tokens.peek() {
+                let value = match token {
+                    Token::Word(word) if word == "true" => SettingValue::Bool(true),
+                    Token::Word(word) if word == "false" => SettingValue::Bool(false),
+                    Token::Word(text) | Token::Quoted(text) => SettingValue::Text(text.clone()),
+                    Token::Open => {
+                        return Err(anyhow!("Line {}: settings cannot have blocks", line))
+                    }
+                    Token::Close | Token::End => break,
+                };
+                values.push(value);
+                tokens.next();
+            }
+            settings.push((key, values));
+        }
+        profiles.push(Profile { name, settings });
+    }
+    Ok(profiles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_config;
+
+    #[test]
+    fn test_profiles_become_command_line_options() {
+        let text = r#"
+            // Rails app with its dev server
+            profile "rails" { shell "zsh"; cwd "~/app"; startup "bin/dev"; queue "rails" }
+
+            profile ci {
+                env "RAILS_ENV" "test"
+                record
+                history false
+                watch "app/*.rb=bin/rails test" "spec/*.rb=bin/rspec"
+            }
+        "#;
+        let profiles = parse_config(text).unwrap();
+        assert_eq!(profiles.len(), 2);
+        assert_eq!(
+            profiles[0].to_args().unwrap(),
+            [
+                "--shell",
+                "zsh",
+                "--cwd",
+                "~/app",
+                "--startup",
+                "bin/dev",
+                "--queue-dir",
+                "rails"
+            ]
+        );
+        assert_eq!(
+            profiles[1].to_args().unwrap(),
+            [
+                "--env",
+                "RAILS_ENV=test",
+                "--record",
+                "--watch",
+                "app/*.rb=bin/rails test",
+                "--watch",
+                "spec/*.rb=bin/rspec"
+            ]
+        );
+
+        assert!(parse_config("profile \"a\" { shell \"zsh\"").is_err());
+        assert!(parse_config("shell \"zsh\"").is_err());
+        assert!(parse_config("profile a {}\nprofile a {}").is_err());
+    }
+}
@@ -0,0 +1,498 @@
+use crate::shell::alerts::clear_alert;
+use crate::shell::arbiter::Arbiter;
+use crate::shell::audit::AuditLog;
+use crate::shell::auth::{Authorizer, Decision, Source};
+use crate::shell::clock::{Clock, SystemClock};
+use crate::shell::echo::AgentInput;
+use crate::shell::history::SessionHistory;
+use crate::shell::hooks::{run_post_command_hook, transform_command, HookOutcome};
+use crate::shell::input_log::InputLog;
+use crate::shell::osc::FinishedCommand;
+use crate::shell::progress::LatestProgress;
+use crate::shell::stopwatch::Stopwatch;
+use crate::shell::types::{QueueFailure, QueueMessage, Terminator};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Output this soon after user input is treated as its echo, which keeps the queue paused
+/// while a long paste or a completion menu is still being drawn
+const ECHO_WINDOW_MS: u64 = 500;
+
+/// Queued commands kept for the `stats` control verb and the palette
+const RECENT_COMMANDS_KEPT: usize = 50;
+
+/// A queued command and what happened to it
+#[derive(Debug, Clone, Serialize)]
+pub struct RecentCommand {
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub source: String,
+    pub command: String,
+    pub result: String,
+    /// From injection to the shell's next prompt, once that prompt has been drawn
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+}
+
+/// What the queue processor did with a queue file, for control socket `run` requests
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum QueueEvent {
+    /// The message is about to be written to the shell; its output follows
+    Writing { source: String },
+    /// The result recorded in the audit log and history: `injected`, `rejected: ...`, ...
+    /// with what the producer is told when the message was dropped
+    Outcome {
+        source: String,
+        result: String,
+        failure: Option<QueueFailure>,
+    },
+}
+
+/// State of one session, shared by its input loop, queue engine and control socket, so
+/// sessions running in the same process do not hold or lock each other's queues or write to
+/// each other's logs.
+///
+/// **Held here:**
+/// - The terminator, authorizer and clock the session was started with
+/// - Its audit log, history database, input log and command hooks, when it has them
+/// - Why the queue is waiting: takeover, a control request, init commands, user typing
+/// - Prompts seen in its output, the line typed at them, and the command and progress in
+///   flight
+/// - Whether the shell has closed the PTY
+/// - The recent commands and the receivers of queue events
+///
+/// Settings of the whole process (fsync and rotation policy, encryption, notifications,
+/// panic policy) and the state of the terminal an interactive session is attached to stay
+/// global; `setup_interactive_pty` refuses to attach a second session.
+pub struct SessionContext {
+    terminator: Terminator,
+    authorizer: Option<Arc<dyn Authorizer>>,
+    clock: Arc<dyn Clock>,
+    audit_log: Option<AuditLog>,
+    history: Option<SessionHistory>,
+    input_log: Option<InputLog>,
+    command_hook: Option<String>,
+    post_command_hook: Option<String>,
+    arbiter: Arbiter,
+    stopwatch: Stopwatch,
+    progress: LatestProgress,
+    agent_input: AgentInput,
+    input_timeout_ms: AtomicU64,
+    /// When the user last typed, or their input was last echoed
+    last_user_input_ms: AtomicU64,
+    /// When input last reached the PTY from the user, a control client or stdin
+    last_input_bytes_ms: AtomicU64,
+    /// Heartbeat of the queue processing loop, used to detect a stuck session
+    last_queue_check_ms: AtomicU64,
+    /// The shell has closed the PTY: input loops stop and queue files are left for the next
+    /// session
+    output_ended: AtomicBool,
+    /// Queue locked by the user with the takeover key until they press it again
+    takeover: AtomicBool,
+    takeover_logged: AtomicBool,
+    /// Queue processing held by a control request, independent of user typing
+    held: AtomicBool,
+    held_logged: AtomicBool,
+    paused_logged: AtomicBool,
+    /// Queue processing waiting for a partially typed line to be submitted
+    line_pending_logged: AtomicBool,
+    /// Init commands are still running; queue processing starts once they are done
+    init_running: AtomicBool,
+    recent_commands: Mutex<VecDeque<RecentCommand>>,
+    /// Passes that failed to write each queue file still waiting, by source
+    write_failures: Mutex<HashMap<String, usize>>,
+    /// Receivers of queue events; dropped receivers are pruned on the next event
+    queue_watchers: Mutex<Vec<mpsc::UnboundedSender<QueueEvent>>>,
+}
+
+impl SessionContext {
+    /// A session appending `terminator` to queued commands without one of their own
+    pub fn new(terminator: Terminator) -> Self {
+        Self {
+            terminator,
+            authorizer: None,
+            clock: Arc::new(SystemClock),
+            audit_log: None,
+            history: None,
+            input_log: None,
+            command_hook: None,
+            post_command_hook: None,
+            arbiter: Arbiter::default(),
+            stopwatch: Stopwatch::default(),
+            progress: LatestProgress::default(),
+            agent_input: AgentInput::default(),
+            input_timeout_ms: AtomicU64::new(30_000),
+            last_user_input_ms: AtomicU64::new(0),
+            last_input_bytes_ms: AtomicU64::new(0),
+            last_queue_check_ms: AtomicU64::new(0),
+            output_ended: AtomicBool::new(false),
+            takeover: AtomicBool::new(false),
+            takeover_logged: AtomicBool::new(false),
+            held: AtomicBool::new(false),
+            held_logged: AtomicBool::new(false),
+            paused_logged: AtomicBool::new(false),
+            line_pending_logged: AtomicBool::new(false),
+            init_running: AtomicBool::new(false),
+            recent_commands: Mutex::new(VecDeque::new()),
+            write_failures: Mutex::new(HashMap::new()),
+            queue_watchers: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Consult `authorizer` about every message for this session; everything is allowed
+    /// without one
+    pub fn authorizer(mut self, authorizer: Option<Arc<dyn Authorizer>>) -> Self {
+        self.authorizer = authorizer;
+        self
+    }
+
+    /// Record what happens to queued commands and control requests in `audit_log`
+    pub fn audit_log(mut self, audit_log: Option<AuditLog>) -> Self {
+        self.audit_log = audit_log;
+        self
+    }
+
+    /// Record queued commands, and the commands the shell finishes, in `history`
+    pub fn history(mut self, history: Option<SessionHistory>) -> Self {
+        self.history = history;
+        self
+    }
+
+    /// Record every byte written to the shell in `input_log`
+    pub fn input_log(mut self, input_log: Option<InputLog>) -> Self {
+        self.input_log = input_log;
+        self
+    }
+
+    /// Pass queued commands through `command_hook` and run `post_command_hook` after each
+    /// command the shell finishes
+    pub fn hooks(
+        mut self,
+        command_hook: Option<String>,
+        post_command_hook: Option<String>,
+    ) -> Self {
+        self.command_hook = command_hook;
+        self.post_command_hook = post_command_hook;
+        self
+    }
+
+    /// Read the time for typing detection from `clock` instead of the system clock
+    #[cfg(test)]
+    pub(crate) fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub(crate) fn terminator(&self) -> Terminator {
+        self.terminator
+    }
+
+    /// Ask the session's authorizer about a message
+    pub(crate) fn authorize(&self, message: &QueueMessage, source: &Source) -> Decision {
+        match &self.authorizer {
+            Some(authorizer) => authorizer.authorize(message, source),
+            None => Decision::Allow,
+        }
+    }
+
+    /// Append an entry to the session's audit log, if it has one
+    pub(crate) fn audit(&self, source: &str, command: &str, result: &str) {
+        if let Some(audit_log) = &self.audit_log {
+            audit_log.record(source, command, result);
+        }
+    }
+
+    /// Record a queued command in the session's history, if it keeps one
+    pub(crate) fn record_history(&self, source: &str, command: &str, result: &str) {
+        if let Some(history) = &self.history {
+            history.record(source, command, result);
+        }
+    }
+
+    /// Whether finished commands are wanted, for the history or the post-command hook
+    pub(crate) fn tracks_finished_commands(&self) -> bool {
+        self.history.is_some() || self.post_command_hook.is_some()
+    }
+
+    /// Record a command the shell finished in the history and run the post-command hook
+    pub(crate) fn command_finished(&self, finished: FinishedCommand) {
+        if let Some(history) = &self.history {
+            history.record_finished(&finished);
+        }
+        if let Some(hook) = self.post_command_hook.clone() {
+            tokio::spawn(run_post_command_hook(hook, finished));
+        }
+    }
+
+    /// Append bytes just written to the shell to the session's input log, if it has one
+    pub(crate) fn record_input(&self, source: &str, bytes: &[u8]) {
+        if let Some(input_log) = &self.input_log {
+            input_log.record(source, bytes);
+        }
+    }
+
+    /// Pass a queued command through the session's command hook
+    pub(crate) async fn transform_command(&self, command: &str) -> anyhow::Result<HookOutcome> {
+        transform_command(self.command_hook.as_deref(), command).await
+    }
+
+    /// Prompts in the shell's output and the line typed at them
+    pub(crate) fn arbiter(&self) -> &Arbiter {
+        &self.arbiter
+    }
+
+    /// The queued command the shell is running
+    pub(crate) fn stopwatch(&self) -> &Stopwatch {
+        &self.stopwatch
+    }
+
+    /// Progress of the running command, as read from the shell's output
+    pub(crate) fn progress(&self) -> &LatestProgress {
+        &self.progress
+    }
+
+    /// Automated input whose echo is restyled
+    pub(crate) fn agent_input(&self) -> &AgentInput {
+        &self.agent_input
+    }
+
+    /// Milliseconds since the Unix epoch according to the session's clock
+    pub(crate) fn now_ms(&self) -> u64 {
+        self.clock.now_ms()
+    }
+
+    pub(crate) fn set_input_timeout(&self, timeout_secs: u64) {
+        self.input_timeout_ms
+            .store(timeout_secs * 1000, Ordering::Relaxed);
+    }
+
+    /// Record user interaction: keys typed locally, stdin lines in line mode and writes from
+    /// control clients that are not marked as automated
+    pub(crate) fn update_user_input(&self) {
+        let now = self.now_ms();
+        self.last_input_bytes_ms.store(now, Ordering::Relaxed);
+        self.last_user_input_ms.store(now, Ordering::Relaxed);
+        clear_alert();
+    }
+
+    /// Extend the typing pause while the shell echoes recent user input. Measured from the
+    /// input itself, so a command that keeps printing does not hold the queue forever.
+    pub(crate) fn observe_echo(&self) {
+        let now = self.now_ms();
+        let since_input = now.saturating_sub(self.last_input_bytes_ms.load(Ordering::Relaxed));
+        if since_input <= ECHO_WINDOW_MS {
+            self.last_user_input_ms.store(now, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn is_user_typing(&self) -> bool {
+        let since_input = self
+            .now_ms()
+            .saturating_sub(self.last_user_input_ms.load(Ordering::Relaxed));
+        since_input <= self.input_timeout_ms.load(Ordering::Relaxed)
+    }
+
+    /// Note that the queue loop is alive
+    pub(crate) fn queue_checked(&self) {
+        self.last_queue_check_ms
+            .store(self.now_ms(), Ordering::Relaxed);
+    }
+
+    /// How long ago the queue loop last checked the queue
+    pub(crate) fn since_queue_check(&self) -> Duration {
+        Duration::from_millis(
+            self.now_ms()
+                .saturating_sub(self.last_queue_check_ms.load(Ordering::Relaxed)),
+        )
+    }
+
+    /// When the queue loop last checked the queue, in milliseconds since the Unix epoch
+    pub(crate) fn last_queue_check_ms(&self) -> u64 {
+        self.last_queue_check_ms.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn end_output(&self) {
+        self.output_ended.store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn output_ended(&self) -> bool {
+        self.output_ended.load(Ordering::Relaxed)
+    }
+
+    /// Lock the queue for the user or release it again; returns whether it is now locked
+    pub(crate) fn toggle_takeover(&self) -> bool {
+        !self.takeover.fetch_xor(true, Ordering::Relaxed)
+    }
+
+    pub(crate) fn taken_over(&self) -> bool {
+        self.takeover.load(Ordering::Relaxed)
+    }
+
+    /// Hold (or release) queue processing until told otherwise, e.g. while an editor shows a
+    /// prompt
+    pub(crate) fn hold_queue(&self, held: bool) {
+        self.held.store(held, Ordering::Relaxed);
+    }
+
+    pub(crate) fn queue_held(&self) -> bool {
+        self.held.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_init_running(&self, running: bool) {
+        self.init_running.store(running, Ordering::Relaxed);
+    }
+
+    pub(crate) fn init_running(&self) -> bool {
+        self.init_running.load(Ordering::Relaxed)
+    }
+
+    /// Whether the queue was last seen locked by a takeover, updating it to `locked`
+    pub(crate) fn swap_takeover_logged(&self, locked: bool) -> bool {
+        self.takeover_logged.swap(locked, Ordering::Relaxed)
+    }
+
+    /// Whether the queue was last seen held, updating it to `held`
+    pub(crate) fn swap_held_logged(&self, held: bool) -> bool {
+        self.held_logged.swap(held, Ordering::Relaxed)
+    }
+
+    /// Whether the queue was last seen paused for typing, updating it to `paused`
+    pub(crate) fn swap_paused_logged(&self, paused: bool) -> bool {
+        self.paused_logged.swap(paused, Ordering::Relaxed)
+    }
+
+    /// Whether the queue was last seen waiting for a typed line, updating it to `pending`
+    pub(crate) fn swap_line_pending_logged(&self, pending: bool) -> bool {
+        self.line_pending_logged.swap(pending, Ordering::Relaxed)
+    }
+
+    /// Whether queue processing is currently waiting, for any reason
+    pub(crate) fn queue_paused(&self) -> bool {
+        self.taken_over()
+            || self.queue_held()
+            || self.paused_logged.load(Ordering::Relaxed)
+            || self.line_pending_logged.load(Ordering::Relaxed)
+    }
+
+    /// What the queue is doing, in words, for the help overlay
+    pub(crate) fn queue_state(&self) -> &'static str {
+        if self.taken_over() {
+            "locked (Ctrl+])"
+        } else if self.queue_held() {
+            "held by a control request"
+        } else if self.line_pending_logged.load(Ordering::Relaxed) {
+            "waiting for a typed line to be submitted"
+        } else if self.paused_logged.load(Ordering::Relaxed) {
+            "paused while you type"
+        } else {
+            "running"
+        }
+    }
+
+    /// Queued commands most recently handled by this session, oldest first
+    pub(crate) fn recent_commands(&self) -> Vec<RecentCommand> {
+        self.recent_commands
+            .lock()
+            .map(|recent| recent.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Add a queued command and what happened to it to the recent commands
+    pub(crate) fn remember_command(&self, source: &str, command: &str, result: &str) {
+        if let Ok(mut recent) = self.recent_commands.lock() {
+            if recent.len() == RECENT_COMMANDS_KEPT {
+                recent.pop_front();
+            }
+            recent.push_back(RecentCommand {
+                timestamp: chrono::Utc::now(),
+                source: source.to_string(),
+                command: command.to_string(),
+                result: result.to_string(),
+                duration_ms: None,
+            });
+        }
+    }
+
+    /// Note how long the last injection of `command` from `source` took to finish
+    pub(crate) fn record_duration(&self, source: &str, command: &str, duration: Duration) {
+        if let Ok(mut recent) = self.recent_commands.lock() {
+            if let Some(entry) = recent.iter_mut().rev().find(|entry| {
+                entry.source == source && entry.command == command && entry.result == "injected"
+            }) {
+                entry.duration_ms = Some(duration.as_millis() as u64);
+            }
+        }
+    }
+
+    /// Count another pass that failed to write the queue file of `source`, returning how
+    /// many have failed so far
+    pub(crate) fn count_write_failure(&self, source: &str) -> usize {
+        let Ok(mut failures) = self.write_failures.lock() else {
+            return 1;
+        };
+        let count = failures.entry(source.to_string()).or_default();
+        *count += 1;
+        *count
+    }
+
+    /// Forget the failed writes of a queue file that was written or dropped
+    pub(crate) fn clear_write_failures(&self, source: &str) {
+        if let Ok(mut failures) = self.write_failures.lock() {
+            failures.remove(source);
+        }
+    }
+
+    /// Receive every queue event from now on. Sources are `queue:<file name>`.
+    pub(crate) fn watch_queue(&self) -> mpsc::UnboundedReceiver<QueueEvent> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        if let Ok(mut watchers) = self.queue_watchers.lock() {
+            watchers.push(sender);
+        }
+        receiver
+    }
+
+    pub(crate) fn emit_queue_event(&self, event: QueueEvent) {
+        if let Ok(mut watchers) = self.queue_watchers.lock() {
+            watchers.retain(|watcher| watcher.send(event.clone()).is_ok());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SessionContext;
+    use crate::shell::auth::{Decision, Source};
+    use crate::shell::types::{QueueMessage, Terminator};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_sessions_keep_their_own_queue_state() {
+        let deny_all = |_: &QueueMessage, _: &Source| Decision::Deny("no".to_string());
+        let locked = SessionContext::new(Terminator::Cr).authorizer(Some(Arc::new(deny_all)));
+        let open = SessionContext::new(Terminator::Lf);
+
+        locked.toggle_takeover();
+        locked.hold_queue(true);
+        locked.remember_command("queue:a", "make", "injected");
+
+        assert!(locked.queue_paused());
+        assert!(!open.queue_paused());
+        assert!(open.recent_commands().is_empty());
+        let message = QueueMessage::Command {
+            command: "make".to_string(),
+            terminator: None,
+        };
+        let source = Source::Queue {
+            file: "a".to_string(),
+        };
+        assert!(matches!(
+            locked.authorize(&message, &source),
+            Decision::Deny(_)
+        ));
+        assert_eq!(open.authorize(&message, &source), Decision::Allow);
+        assert_eq!(open.terminator(), Terminator::Lf);
+    }
+}
@@ -0,0 +1,997 @@
+use crate::shell::ansi::strip_ansi;
+use crate::shell::arbiter::PromptMarks;
+use crate::shell::auth::{Decision, Source};
+use crate::shell::context::{QueueEvent, SessionContext};
+use crate::shell::filter::{OutputFilter, StripAnsi};
+use crate::shell::osc::CommandTracker;
+use crate::shell::queue::enqueue_file;
+use crate::shell::scrollback::SharedScrollback;
+use crate::shell::status::is_process_alive;
+use crate::shell::tee::OutputSubscriptions;
+use crate::shell::types::{CommandResult, FailureCode, QueueFailure, QueueMessage, Terminator};
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{broadcast, mpsc};
+
+/// The control socket lets local processes talk to a running session directly instead of
+/// going through files in the queue directory.
+///
+/// **Protocol:**
+/// - Unix stream socket at `.tp/<name>.sock`, only accessible to the owning user
+/// - Newline-delimited JSON: each line is a `ControlRequest`, answered by one `ControlResponse` line
+/// - A connection may send any number of requests
+/// - `run` is the exception: it is answered by a `queued` frame, `output` frames while the
+///   command runs and an `end` frame with its exit status, each a response line whose result
+///   has an `event` field
+///
+/// ```bash
+/// echo '{"verb":"scrollback","lines":20,"strip_ansi":true}' | socat - UNIX-CONNECT:.tp/myshell.sock
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "verb", rename_all = "snake_case")]
+pub enum ControlRequest {
+    /// Add a command to the session queue; it runs like any other queue file
+    Enqueue {
+        command: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        terminator: Option<Terminator>,
+    },
+    /// Write text straight to the shell, bypassing the queue and its typing pause. The write
+    /// counts as user input (pausing the queue) unless `automated` is set.
+    Write {
+        data: String,
+        #[serde(default)]
+        automated: bool,
+    },
+    /// The last `lines` lines of output (the whole scrollback when omitted)
+    Scrollback {
+        lines: Option<usize>,
+        #[serde(default)]
+        strip_ansi: bool,
+    },
+    /// The output currently visible in the terminal window
+    Screen {
+        #[serde(default)]
+        strip_ansi: bool,
+    },
+    /// Hold queue processing until `resume`, regardless of typing activity
+    Pause,
+    /// Release a hold placed with `pause`
+    Resume,
+    /// Uptime, queue depth, child process state and the last `recent` queued commands
+    Stats { recent: Option<usize> },
+    /// Queue a command like `enqueue`, then stream its output until the shell's next prompt.
+    /// Needs shell integration to tell when the command has finished.
+    Run {
+        command: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        terminator: Option<Terminator>,
+        #[serde(default)]
+        strip_ansi: bool,
+        /// Stop streaming after this long; the command itself keeps running
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timeout_secs: Option<u64>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ControlResponse {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub result: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Why a queued command was dropped, for clients that act on the kind of failure
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<FailureCode>,
+}
+
+impl TryFrom<QueueMessage> for ControlRequest {
+    type Error = anyhow::Error;
+
+    /// Commands are queued; everything else is written straight to the shell, which needs
+    /// the bytes to be valid UTF-8
+    fn try_from(message: QueueMessage) -> Result<Self> {
+        match message {
+            QueueMessage::Command {
+                command,
+                terminator,
+            } => Ok(ControlRequest::Enqueue {
+                command,
+                terminator,
+            }),
+            other => {
+                let bytes = other.input_bytes()?.unwrap_or_default();
+                let data = String::from_utf8(bytes)
+                    .map_err(|_| anyhow::anyhow!("Input is not valid UTF-8"))?;
+                Ok(ControlRequest::Write {
+                    data,
+                    automated: true,
+                })
+            }
+        }
+    }
+}
+
+impl ControlResponse {
+    fn from_result(result: Result<Value>) -> Self {
+        match result {
+            Ok(result) => Self {
+                ok: true,
+                result,
+                error: None,
+                code: None,
+            },
+            Err(e) => Self {
+                ok: false,
+                result: Value::Null,
+                error: Some(format!("{:#}", e)),
+                code: e.downcast_ref::<QueueFailure>().map(|failure| failure.code),
+            },
+        }
+    }
+}
+
+/// Session state the control socket operates on
+pub struct ControlContext {
+    /// Queue state, authorizer and recent commands of the session
+    pub session: Arc<SessionContext>,
+    pub queue_dir: Option<PathBuf>,
+    pub scrollback: SharedScrollback,
+    /// Bytes sent here are written to the PTY by the input task
+    pub input: mpsc::UnboundedSender<Vec<u8>>,
+    /// Subscribe here to receive every chunk of PTY output
+    pub output: OutputSubscriptions,
+    /// Height of the terminal window, used for `screen`
+    pub rows: u16,
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    pub child_pid: Option<u32>,
+    /// Largest `CommandResult::output` sent at the end of a `run`
+    pub max_command_output: usize,
+}
+
+/// Who besides the owning user may use the control socket, from `--socket-allow`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SocketAccess {
+    pub uids: Vec<u32>,
+    pub gids: Vec<u32>,
+}
+
+impl SocketAccess {
+    /// Parse a comma-separated list of `uid:<n>`, `gid:<n>`, `user:<name>` and `group:<name>`
+    pub fn parse(list: &str) -> Result<Self> {
+        use nix::unistd::{Group, User};
+
+        let mut access = SocketAccess::default();
+        for entry in list.split(',').filter(|entry| !entry.is_empty()) {
+            let invalid = || {
+                anyhow::anyhow!(
+                    "Invalid socket access entry '{}': expected uid:<n>, gid:<n>, user:<name> or group:<name>",
+                    entry
+                )
+            };
+            match entry.split_once(':').ok_or_else(invalid)? {
+                ("uid", uid) => access.uids.push(uid.parse().map_err(|_| invalid())?),
+                ("gid", gid) => access.gids.push(gid.parse().map_err(|_| invalid())?),
+                ("user", name) => {
+                    let user = User::from_name(name)?
+                        .ok_or_else(|| anyhow::anyhow!("Unknown user '{}'", name))?;
+                    access.uids.push(user.uid.as_raw());
+                }
+                ("group", name) => {
+                    let group = Group::from_name(name)?
+                        .ok_or_else(|| anyhow::anyhow!("Unknown group '{}'", name))?;
+                    access.gids.push(group.gid.as_raw());
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(access)
+    }
+
+    /// Whether a peer with this uid and primary gid may connect; the owner always may
+    fn allows(&self, owner: u32, uid: u32, gid: u32) -> bool {
+        uid == owner || self.uids.contains(&uid) || self.gids.contains(&gid)
+    }
+
+    fn is_owner_only(&self) -> bool {
+        self.uids.is_empty() && self.gids.is_empty()
+    }
+}
+
+/// Peers allowed on the control socket besides the owning user.
+///
+/// **Enforcement:**
+/// - Every connection's `SO_PEERCRED` credentials are checked before any request is read;
+///   connections from other users, or whose credentials cannot be read, are refused
+/// - Only the peer's primary group is known to the kernel check, not supplementary groups
+/// - With an allow-list the socket file is made world-connectable (0666) so the listed users
+///   can reach it at all; the credential check is what keeps everyone else out
+static SOCKET_ACCESS: RwLock<SocketAccess> = RwLock::new(SocketAccess {
+    uids: Vec::new(),
+    gids: Vec::new(),
+});
+
+pub fn set_socket_access(access: SocketAccess) {
+    if let Ok(mut current) = SOCKET_ACCESS.write() {
+        *current = access;
+    }
+}
+
+fn socket_access() -> SocketAccess {
+    SOCKET_ACCESS
+        .read()
+        .map(|access| access.clone())
+        .unwrap_or_default()
+}
+
+/// How long a refused peer gets to send its first request before the connection is closed
+const REFUSAL_WAIT: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Shell integration mark written once the prompt has been drawn; the command line follows
+const PROMPT_END_MARK: &[u8] = b"\x1b]133;B\x07";
+
+/// Queued commands returned by `stats` when the request does not say
+const DEFAULT_RECENT_COMMANDS: usize = 10;
+
+/// Location of the control socket for a session named `name` inside the `.tp` directory
+pub fn control_socket_path(tp_dir: &Path, name: &str) -> PathBuf {
+    tp_dir.join(format!("{}.sock", name))
+}
+
+/// Listen on `socket_path` and answer control requests until the task is aborted
+pub async fn serve(socket_path: PathBuf, context: Arc<ControlContext>) -> Result<()> {
+    // A socket left behind by a session that did not shut down cleanly blocks bind()
+    let _ = std::fs::remove_file(&socket_path);
+
+    let listener = UnixListener::bind(&socket_path).context("Failed to bind control socket")?;
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = if socket_access().is_owner_only() {
+            0o600
+        } else {
+            0o666
+        };
+        std::fs::set_permissions(&socket_path, std::fs::Permissions::from_mode(mode))
+            .context("Failed to restrict control socket permissions")?;
+    }
+
+    let owner = nix::unistd::getuid().as_raw();
+    loop {
+        let (stream, _) = listener
+            .accept()
+            .await
+            .context("Failed to accept control connection")?;
+        tokio::spawn(handle_connection(stream, context.clone(), owner));
+    }
+}
+
+/// Answer the requests of one client, unless it is neither `owner` nor on the allow-list
+async fn handle_connection(stream: UnixStream, context: Arc<ControlContext>, owner: u32) {
+    let origin = match stream.peer_cred() {
+        Ok(cred) if socket_access().allows(owner, cred.uid(), cred.gid()) => Ok(Source::Socket {
+            pid: cred.pid(),
+            uid: cred.uid(),
+        }),
+        Ok(cred) => Err(format!("uid {} is not allowed on this socket", cred.uid())),
+        Err(_) => Err("peer credentials unavailable".to_string()),
+    };
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    let source = match origin {
+        Ok(source) => source,
+        Err(reason) => {
+            tracing::warn!(reason, "refused control connection");
+            // Answer the first request, so the client reads the refusal instead of a reset
+            let _ = tokio::time::timeout(REFUSAL_WAIT, lines.next_line()).await;
+            let _ = send_response(
+                &mut writer,
+                Err(anyhow::anyhow!("Permission denied: {}", reason)),
+            )
+            .await;
+            return;
+        }
+    };
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let result = match serde_json::from_str::<ControlRequest>(&line) {
+            Ok(ControlRequest::Run {
+                command,
+                terminator,
+                strip_ansi,
+                timeout_secs,
+            }) => {
+                let run = RunRequest {
+                    command,
+                    terminator,
+                    strip_ansi,
+                    timeout: timeout_secs.map(std::time::Duration::from_secs),
+                };
+                if stream_run(run, &context, &source, &mut writer)
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+                continue;
+            }
+            Ok(request) => handle_request(request, &context, &source).await,
+            Err(e) => Err(anyhow::anyhow!("Invalid request: {}", e)),
+        };
+
+        if send_response(&mut writer, result).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Write one response line
+async fn send_response(writer: &mut OwnedWriteHalf, result: Result<Value>) -> Result<()> {
+    let mut response = serde_json::to_vec(&ControlResponse::from_result(result))?;
+    response.push(b'\n');
+    writer.write_all(&response).await?;
+    Ok(())
+}
+
+/// A `run` request
+struct RunRequest {
+    command: String,
+    terminator: Option<Terminator>,
+    strip_ansi: bool,
+    timeout: Option<std::time::Duration>,
+}
+
+/// Queue a command and stream its output to `writer` until the shell draws its next prompt.
+///
+/// **Frames:**
+/// - `{"event":"queued","file":...}` once the command is in the queue
+/// - `{"event":"output","data":...}` for output from the moment the command is written to
+///   the shell, its echo included
+/// - `{"event":"end","exit_status":...,"duration_ms":...,"result":...}` at the next prompt;
+///   `exit_status` is null when the shell integration does not report one, and `result` is
+///   the `CommandResult` with the output cut down to `max_command_output`
+/// - `{"event":"end",...,"timed_out":true}` without a `result` when `timeout_secs` ran out
+///   first
+/// - An error response instead when the command is rejected or cannot be written
+///
+/// Returns an error only when the client has gone away.
+async fn stream_run(
+    run: RunRequest,
+    context: &ControlContext,
+    source: &Source,
+    writer: &mut OwnedWriteHalf,
+) -> Result<()> {
+    if !context.session.arbiter().prompt_marks_seen() {
+        return send_response(
+            writer,
+            Err(anyhow::anyhow!(
+                "run needs shell integration to tell when a command has finished (see typeypipe setup --shell-integration); use enqueue instead"
+            )),
+        )
+        .await;
+    }
+    // Subscribe before queueing so no output or event can be missed
+    let Some(mut output) = context.output.subscribe() else {
+        return send_response(writer, Err(anyhow::anyhow!("Session output is closed"))).await;
+    };
+    let mut events = context.session.watch_queue();
+
+    let enqueue = ControlRequest::Enqueue {
+        command: run.command,
+        terminator: run.terminator,
+    };
+    let queued = match handle_request(enqueue, context, source).await {
+        Ok(queued) => queued,
+        Err(e) => return send_response(writer, Err(e)).await,
+    };
+    let queue_source = match queued["file"].as_str().map(Path::new) {
+        Some(file) => format!(
+            "queue:{}",
+            file.file_name().unwrap_or_default().to_string_lossy()
+        ),
+        None => return send_response(writer, Err(anyhow::anyhow!("Command was not queued"))).await,
+    };
+    send_response(
+        writer,
+        Ok(json!({ "event": "queued", "file": queued["file"] })),
+    )
+    .await?;
+
+    let deadline = run
+        .timeout
+        .map(|timeout| tokio::time::Instant::now() + timeout);
+    let mut started_at: Option<std::time::Instant> = None;
+    let mut result = CommandResult {
+        output: String::new(),
+        success: false,
+        exit_code: None,
+        started_at: chrono::Utc::now(),
+        finished_at: chrono::Utc::now(),
+        stdout_bytes: 0,
+        truncated: false,
+    };
+    let mut tracker = CommandTracker::new();
+    let mut prompts = PromptMarks::new();
+    let mut strip = run.strip_ansi.then(StripAnsi::default);
+    let mut pending = Vec::new();
+    let mut exit_status = None;
+
+    loop {
+        // Events first: the `Writing` event is sent before the command's bytes reach the
+        // shell, so it is seen before any output the command causes
+        tokio::select! {
+            biased;
+            event = events.recv() => match event {
+                Some(QueueEvent::Writing { source }) if source == queue_source => {
+                    started_at = Some(std::time::Instant::now());
+                    result.started_at = chrono::Utc::now();
+                    // The prompt was drawn before the command was written, so the tracker
+                    // has to be told the command line follows
+                    tracker.feed(PROMPT_END_MARK);
+                }
+                Some(QueueEvent::Outcome { source, result, failure })
+                    if source == queue_source && result != "injected" =>
+                {
+                    let error = match failure {
+                        Some(failure) => anyhow::Error::new(failure).context("Command was not run"),
+                        None => anyhow::anyhow!("Command was not run: {}", result),
+                    };
+                    return send_response(writer, Err(error)).await;
+                }
+                Some(_) => {}
+                None => {
+                    return send_response(writer, Err(anyhow::anyhow!("Session has ended"))).await
+                }
+            },
+            chunk = output.recv() => {
+                let chunk = match chunk {
+                    Ok(chunk) => chunk,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => {
+                        return send_response(
+                            writer,
+                            Err(anyhow::anyhow!("Session ended before the command finished")),
+                        )
+                        .await
+                    }
+                };
+                let Some(started_at) = started_at else {
+                    continue;
+                };
+                if let Some(finished) = tracker.feed(&chunk).pop() {
+                    exit_status = finished.exit_status;
+                }
+                match strip.as_mut() {
+                    Some(strip) => strip.apply(&chunk, &mut pending),
+                    None => pending.extend_from_slice(&chunk),
+                }
+                let data = take_text(&mut pending);
+                if !data.is_empty() {
+                    // Stop collecting once past the limit; the overshoot is cut off at the end
+                    result.stdout_bytes += data.len();
+                    if result.output.len() <= context.max_command_output {
+                        result.output.push_str(&data);
+                    }
+                    send_response(writer, Ok(json!({ "event": "output", "data": data }))).await?;
+                }
+                if prompts.feed(&chunk) {
+                    result.success = exit_status.is_none_or(|status| status == 0);
+                    result.exit_code = exit_status;
+                    result.finished_at = chrono::Utc::now();
+                    result.limit_output(context.max_command_output);
+                    return send_response(
+                        writer,
+                        Ok(json!({
+                            "event": "end",
+                            "exit_status": exit_status,
+                            "duration_ms": started_at.elapsed().as_millis() as u64,
+                            "result": result,
+                        })),
+                    )
+                    .await;
+                }
+            },
+            _ = tokio::time::sleep_until(deadline.unwrap_or_else(tokio::time::Instant::now)),
+                if deadline.is_some() =>
+            {
+                return send_response(
+                    writer,
+                    Ok(json!({
+                        "event": "end",
+                        "exit_status": Value::Null,
+                        "duration_ms": started_at.map(|started_at| started_at.elapsed().as_millis() as u64),
+                        "timed_out": true,
+                    })),
+                )
+                .await;
+            }
+        }
+    }
+}
+
+/// The text in `pending`, leaving behind an incomplete UTF-8 character at its end for the
+/// next chunk to finish
+fn take_text(pending: &mut Vec<u8>) -> String {
+    let complete = match std::str::from_utf8(pending) {
+        Err(e) if e.error_len().is_none() => e.valid_up_to(),
+        _ => pending.len(),
+    };
+    let rest = pending.split_off(complete);
+    let text = String::from_utf8_lossy(pending).into_owned();
+    *pending = rest;
+    text
+}
+
+/// Carry out a request from `source`, which the authorizer and the audit log see
+pub(crate) async fn handle_request(
+    request: ControlRequest,
+    context: &ControlContext,
+    source: &Source,
+) -> Result<Value> {
+    let origin = &source.to_string();
+    match request {
+        ControlRequest::Enqueue {
+            command,
+            terminator,
+        } => {
+            let queue_dir = context
+                .queue_dir
+                .as_deref()
+                .ok_or_else(|| anyhow::anyhow!("Session has no queue directory"))?;
+            let message = QueueMessage::Command {
+                command: command.clone(),
+                terminator,
+            };
+            if let Decision::Deny(reason) = context.session.authorize(&message, source) {
+                context
+                    .session
+                    .audit(origin, &command, &format!("rejected: {}", reason));
+                return Err(anyhow::anyhow!("Rejected by authorizer: {}", reason));
+            }
+            let path = enqueue_file(queue_dir, "ctl", &message.encode()?).await?;
+            let filename = path.file_name().unwrap_or_default().to_string_lossy();
+            context
+                .session
+                .audit(origin, &command, &format!("queued: {}", filename));
+            Ok(json!({ "file": path }))
+        }
+        ControlRequest::Write { data, automated } => {
+            let message = QueueMessage::Bytes {
+                data: BASE64.encode(&data),
+            };
+            if let Decision::Deny(reason) = context.session.authorize(&message, source) {
+                context
+                    .session
+                    .audit(origin, &data, &format!("rejected: {}", reason));
+                return Err(anyhow::anyhow!("Rejected by authorizer: {}", reason));
+            }
+            if !automated {
+                context.session.update_user_input();
+            }
+            if context.input.send(data.clone().into_bytes()).is_err() {
+                context
+                    .session
+                    .audit(origin, &data, "failed: session input is closed");
+                return Err(anyhow::anyhow!("Session input is closed"));
+            }
+            context.session.audit(origin, &data, "written");
+            Ok(Value::Null)
+        }
+        ControlRequest::Scrollback { lines, strip_ansi } => {
+            let text = {
+                let scrollback = lock_scrollback(context)?;
+                let count = lines.unwrap_or(scrollback.len());
+                let mut text = scrollback.tail(count).join("\n");
+                let partial = scrollback.partial_line();
+                if !partial.is_empty() {
+                    text.push('\n');
+                    text.push_str(&partial);
+                }
+                text
+            };
+            Ok(json!({ "text": render(text, strip_ansi) }))
+        }
+        ControlRequest::Screen { strip_ansi } => {
+            let text = {
+                let scrollback = lock_scrollback(context)?;
+                let mut visible = scrollback.tail(context.rows.saturating_sub(1) as usize);
+                visible.push(scrollback.partial_line());
+                visible.join("\n")
+            };
+            Ok(json!({ "text": render(text, strip_ansi), "rows": context.rows }))
+        }
+        ControlRequest::Pause => {
+            context.session.hold_queue(true);
+            Ok(json!({ "paused": true }))
+        }
+        ControlRequest::Resume => {
+            context.session.hold_queue(false);
+            Ok(json!({ "paused": false }))
+        }
+        ControlRequest::Stats { recent } => {
+            let queue_depth = match context.queue_dir.as_deref() {
+                Some(queue_dir) => Some(queue_depth(queue_dir).await?),
+                None => None,
+            };
+            let mut recent_commands = context.session.recent_commands();
+            let keep = recent.unwrap_or(DEFAULT_RECENT_COMMANDS);
+            recent_commands.drain(..recent_commands.len().saturating_sub(keep));
+
+            Ok(json!({
+                "started_at": context.started_at,
+                "uptime_secs": (chrono::Utc::now() - context.started_at).num_seconds(),
+                "pid": std::process::id(),
+                "child_pid": context.child_pid,
+                "child_alive": context.child_pid.is_some_and(is_process_alive),
+                "queue_dir": context.queue_dir,
+                "queue_depth": queue_depth,
+                "queue_paused": context.session.queue_paused(),
+                "running_command": context.session.stopwatch().running().map(|(command, elapsed)| json!({
+                    "command": command,
+                    "elapsed_ms": elapsed.as_millis() as u64,
+                })),
+                "progress": context.session.progress().current(),
+                "recent_commands": recent_commands,
+            }))
+        }
+        ControlRequest::Run { .. } => Err(anyhow::anyhow!(
+            "run streams its output and is only answered on the control socket"
+        )),
+    }
+}
+
+/// Number of queue files waiting to be injected
+pub(crate) async fn queue_depth(queue_dir: &Path) -> Result<usize> {
+    let mut entries = tokio::fs::read_dir(queue_dir)
+        .await
+        .context("Failed to read queue directory")?;
+    let mut depth = 0;
+    while let Some(entry) = entries.next_entry().await? {
+        if entry
+            .file_type()
+            .await
+            .is_ok_and(|file_type| file_type.is_file())
+        {
+            depth += 1;
+        }
+    }
+    Ok(depth)
+}
+
+fn lock_scrollback(
+    context: &ControlContext,
+) -> Result<std::sync::MutexGuard<'_, crate::shell::scrollback::Scrollback>> {
+    context
+        .scrollback
+        .lock()
+        .map_err(|_| anyhow::anyhow!("Scrollback is unavailable"))
+}
+
+fn render(text: String, strip: bool) -> String {
+    if strip {
+        strip_ansi(&text)
+    } else {
+        text
+    }
+}
+
+/// Client side of the control socket
+pub struct ControlClient {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl ControlClient {
+    pub async fn connect(socket_path: &Path) -> Result<Self> {
+        let stream = UnixStream::connect(socket_path).await.with_context(|| {
+            format!(
+                "Failed to connect to control socket {}",
+                socket_path.display()
+            )
+        })?;
+        let (reader, writer) = stream.into_split();
+
+        Ok(Self {
+            reader: BufReader::new(reader),
+            writer,
+        })
+    }
+
+    /// Send a request and wait for its response, turning error responses into `ControlError`s
+    pub async fn request(&mut self, request: &ControlRequest) -> Result<Value> {
+        let mut line = serde_json::to_vec(request).context("Failed to serialize request")?;
+        line.push(b'\n');
+        self.writer
+            .write_all(&line)
+            .await
+            .context("Failed to send control request")?;
+
+        let mut response = String::new();
+        if self
+            .reader
+            .read_line(&mut response)
+            .await
+            .context("Failed to read control response")?
+            == 0
+        {
+            return Err(anyhow::anyhow!("Session closed the control connection"));
+        }
+
+        let response: ControlResponse =
+            serde_json::from_str(&response).context("Invalid control response")?;
+        if response.ok {
+            Ok(response.result)
+        } else {
+            Err(ControlError {
+                message: response
+                    .error
+                    .unwrap_or_else(|| "Unknown error".to_string()),
+                code: response.code,
+            }
+            .into())
+        }
+    }
+}
+
+/// An error response from the session. Downcast to it to act on the `code` of a queued
+/// command that was dropped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControlError {
+    pub message: String,
+    pub code: Option<FailureCode>,
+}
+
+impl std::fmt::Display for ControlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ControlError {}
+
+#[cfg(test)]
+mod tests {
+    use super::{handle_connection, take_text, ControlClient, ControlError, ControlRequest};
+    use super::{ControlResponse, SocketAccess};
+    use crate::shell::testing::MockControl;
+    use crate::shell::types::FailureCode;
+    use serde_json::{json, Value};
+    use std::time::Duration;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+    use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+    use tokio::net::{UnixListener, UnixStream};
+
+    /// Send a raw request line on a new connection, returning its response lines and the
+    /// writer that keeps it open
+    async fn send_line(
+        mock: &MockControl,
+        line: &str,
+    ) -> (Lines<BufReader<OwnedReadHalf>>, OwnedWriteHalf) {
+        let (reader, mut writer) = UnixStream::connect(&mock.socket)
+            .await
+            .unwrap()
+            .into_split();
+        writer
+            .write_all(format!("{}\n", line).as_bytes())
+            .await
+            .unwrap();
+        (BufReader::new(reader).lines(), writer)
+    }
+
+    async fn next_frame(lines: &mut Lines<BufReader<OwnedReadHalf>>) -> ControlResponse {
+        let line = tokio::time::timeout(Duration::from_secs(10), lines.next_line())
+            .await
+            .expect("no response within 10s")
+            .unwrap()
+            .expect("connection closed");
+        serde_json::from_str(&line).unwrap()
+    }
+
+    /// Frames of a `run` up to and including its `end` frame, or the error response
+    async fn run_frames(mock: &MockControl, request: Value) -> Vec<ControlResponse> {
+        let (mut lines, _writer) = send_line(mock, &request.to_string()).await;
+        let mut frames = Vec::new();
+        loop {
+            let frame = next_frame(&mut lines).await;
+            let done = !frame.ok || frame.result["event"] == "end";
+            frames.push(frame);
+            if done {
+                return frames;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_requests_round_trip_over_the_socket() {
+        let mut mock = MockControl::start().await.unwrap();
+        let mut client = ControlClient::connect(&mock.socket).await.unwrap();
+
+        let queued = client
+            .request(&ControlRequest::Enqueue {
+                command: "make".to_string(),
+                terminator: None,
+            })
+            .await
+            .unwrap();
+        assert!(queued["file"].as_str().unwrap().contains("ctl-"));
+        let scrollback = ControlRequest::Scrollback {
+            lines: None,
+            strip_ansi: true,
+        };
+        let started = std::time::Instant::now();
+        loop {
+            let text = client.request(&scrollback).await.unwrap()["text"].clone();
+            if text.as_str().unwrap().contains("ran: make") {
+                assert!(text.as_str().unwrap().ends_with("$ "));
+                break;
+            }
+            assert!(started.elapsed() < Duration::from_secs(10));
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        let written = ControlRequest::Write {
+            data: "ls\r".to_string(),
+            automated: true,
+        };
+        assert_eq!(client.request(&written).await.unwrap(), Value::Null);
+        assert_eq!(mock.input().await.unwrap(), b"ls\r");
+
+        let paused = client.request(&ControlRequest::Pause).await.unwrap();
+        assert_eq!(paused, json!({ "paused": true }));
+        let stats = client
+            .request(&ControlRequest::Stats { recent: None })
+            .await
+            .unwrap();
+        assert_eq!(stats["queue_paused"], true);
+        assert!(mock.context.session.queue_paused());
+        assert_eq!(stats["queue_depth"], 0);
+        assert_eq!(stats["pid"], std::process::id());
+        let resumed = client.request(&ControlRequest::Resume).await.unwrap();
+        assert_eq!(resumed, json!({ "paused": false }));
+
+        // A malformed line is answered, and the connection stays usable
+        let (mut lines, mut writer) = send_line(&mock, "{\"verb\":\"reboot\"}").await;
+        let invalid = next_frame(&mut lines).await;
+        assert!(!invalid.ok);
+        assert!(invalid.error.unwrap().starts_with("Invalid request"));
+        writer.write_all(b"{\"verb\":\"resume\"}\n").await.unwrap();
+        assert!(next_frame(&mut lines).await.ok);
+    }
+
+    #[tokio::test]
+    async fn test_run_streams_output_until_the_prompt() {
+        let mock = MockControl::start().await.unwrap();
+
+        let frames = run_frames(
+            &mock,
+            json!({ "verb": "run", "command": "echo hi", "strip_ansi": true }),
+        )
+        .await;
+        assert_eq!(frames[0].result["event"], "queued");
+        let output: String = frames[1..frames.len() - 1]
+            .iter()
+            .map(|frame| {
+                assert_eq!(frame.result["event"], "output");
+                frame.result["data"].as_str().unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(output, "echo hi\nran: echo hi\n$ ");
+        let end = &frames.last().unwrap().result;
+        assert_eq!(end["exit_status"], 0);
+        assert_eq!(end["result"]["success"], true);
+        assert_eq!(end["result"]["exit_code"], 0);
+        assert!(end["result"]["output"]
+            .as_str()
+            .unwrap()
+            .contains("ran: echo hi"));
+
+        let frames = run_frames(&mock, json!({ "verb": "run", "command": "fail 3" })).await;
+        let end = &frames.last().unwrap().result;
+        assert_eq!(end["event"], "end");
+        assert_eq!(end["exit_status"], 3);
+        assert_eq!(end["result"]["success"], false);
+        assert_eq!(end["result"]["exit_code"], 3);
+
+        // The command keeps running; only the stream stops
+        let frames = run_frames(
+            &mock,
+            json!({ "verb": "run", "command": "hang", "timeout_secs": 1 }),
+        )
+        .await;
+        let end = &frames.last().unwrap().result;
+        assert_eq!(end["event"], "end");
+        assert_eq!(end["timed_out"], true);
+        assert!(end.get("result").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_run_reports_why_a_command_was_dropped() {
+        let mock = MockControl::start().await.unwrap();
+
+        let frames = run_frames(&mock, json!({ "verb": "run", "command": "unroutable" })).await;
+        assert_eq!(frames[0].result["event"], "queued");
+        let refused = frames.last().unwrap();
+        assert!(!refused.ok);
+        assert_eq!(refused.code, Some(FailureCode::Unroutable));
+        assert!(refused
+            .error
+            .as_deref()
+            .unwrap()
+            .contains("Unknown target session"));
+    }
+
+    #[tokio::test]
+    async fn test_peer_without_access_is_refused() {
+        let mock = MockControl::start().await.unwrap();
+        let socket = mock.socket.with_file_name("other.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+        // Served as if another user owned the session, so this process is a stranger to it
+        let owner = nix::unistd::getuid().as_raw() + 1;
+        let context = mock.context.clone();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            handle_connection(stream, context, owner).await;
+        });
+
+        let mut client = ControlClient::connect(&socket).await.unwrap();
+        let error = client
+            .request(&ControlRequest::Enqueue {
+                command: "rm -rf /".to_string(),
+                terminator: None,
+            })
+            .await
+            .unwrap_err()
+            .downcast::<ControlError>()
+            .unwrap();
+        assert!(
+            error.message.starts_with("Permission denied: uid"),
+            "{}",
+            error.message
+        );
+        assert_eq!(error.code, None);
+        // Refused before the request was read, so nothing was queued
+        let queue_dir = mock.context.queue_dir.as_deref().unwrap();
+        assert_eq!(std::fs::read_dir(queue_dir).unwrap().count(), 0);
+        assert!(client.request(&ControlRequest::Pause).await.is_err());
+    }
+
+    #[test]
+    fn test_socket_access_allow_list() {
+        let access = SocketAccess::parse("uid:1001,gid:50,user:root").unwrap();
+        assert_eq!(access.uids, vec![1001, 0]);
+        assert_eq!(access.gids, vec![50]);
+
+        assert!(access.allows(1000, 1000, 1000));
+        assert!(access.allows(1000, 1001, 1001));
+        assert!(access.allows(1000, 2000, 50));
+        assert!(!access.allows(1000, 2000, 2000));
+        assert!(!SocketAccess::default().allows(1000, 1001, 50));
+
+        assert!(SocketAccess::parse("1001").is_err());
+        assert!(SocketAccess::parse("uid:alice").is_err());
+    }
+
+    #[test]
+    fn test_streamed_text_keeps_split_characters_whole() {
+        let mut pending = "ok ✓".as_bytes().to_vec();
+        let last = pending.pop().unwrap();
+        assert_eq!(take_text(&mut pending), "ok ");
+        assert_eq!(pending.len(), 2);
+        pending.push(last);
+        assert_eq!(take_text(&mut pending), "✓");
+        assert!(pending.is_empty());
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
+
+/// When writes to the session log and the audit log are forced to disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// Leave flushing to the operating system
+    Never,
+    /// fsync after every entry, so a record survives power loss once it is written
+    Command,
+    /// fsync files with new entries once per interval, bounding what a power loss can take
+    Interval(Duration),
+}
+
+impl FromStr for FsyncPolicy {
+    type Err = anyhow::Error;
+
+    /// Parses `never`, `command` or `interval:<seconds>`
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.split_once(':') {
+            None if value == "never" => Ok(FsyncPolicy::Never),
+            None if value == "command" => Ok(FsyncPolicy::Command),
+            Some(("interval", seconds)) => match seconds.parse::<u64>() {
+                Ok(seconds) if seconds > 0 => {
+                    Ok(FsyncPolicy::Interval(Duration::from_secs(seconds)))
+                }
+                _ => Err(anyhow::anyhow!(
+                    "Invalid fsync interval '{}': expected a positive number of seconds",
+                    seconds
+                )),
+            },
+            _ => Err(anyhow::anyhow!(
+                "Invalid fsync policy '{}': expected never, command or interval:<seconds>",
+                value
+            )),
+        }
+    }
+}
+
+static FSYNC_POLICY: RwLock<FsyncPolicy> = RwLock::new(FsyncPolicy::Command);
+
+/// Files written since the last periodic sync, under `FsyncPolicy::Interval`
+static UNSYNCED: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+
+pub fn set_fsync_policy(policy: FsyncPolicy) {
+    if let Ok(mut current) = FSYNC_POLICY.write() {
+        *current = policy;
+    }
+}
+
+pub fn fsync_policy() -> FsyncPolicy {
+    FSYNC_POLICY
+        .read()
+        .map(|policy| *policy)
+        .unwrap_or(FsyncPolicy::Command)
+}
+
+/// Called after appending to `path`: returns whether the caller must fsync it now. Under an
+/// interval policy the file is remembered for the next periodic sync instead.
+pub fn sync_required(path: &Path) -> bool {
+    match fsync_policy() {
+        FsyncPolicy::Never => false,
+        FsyncPolicy::Command => true,
+        FsyncPolicy::Interval(_) => {
+            if let Ok(mut unsynced) = UNSYNCED.lock() {
+                unsynced.insert(path.to_path_buf());
+            }
+            false
+        }
+    }
+}
+
+/// fsync every file written since the last call
+pub fn sync_unsynced() {
+    let paths = match UNSYNCED.lock() {
+        Ok(mut unsynced) => std::mem::take(&mut *unsynced),
+        Err(_) => return,
+    };
+
+    for path in paths {
+        // Any descriptor of the file flushes its data, so the writer's handle is not needed
+        let result = std::fs::File::open(&path).and_then(|file| file.sync_data());
+        if let Err(e) = result {
+            tracing::warn!(error = %e, path = %path.display(), "failed to sync log");
+        }
+    }
+}
+
+/// Run `sync_unsynced` every `interval` until the task is aborted
+pub async fn sync_periodically(interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let _ = tokio::task::spawn_blocking(sync_unsynced).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FsyncPolicy;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_fsync_policy() {
+        assert_eq!("never".parse::<FsyncPolicy>().unwrap(), FsyncPolicy::Never);
+        assert_eq!(
+            "command".parse::<FsyncPolicy>().unwrap(),
+            FsyncPolicy::Command
+        );
+        assert_eq!(
+            "interval:5".parse::<FsyncPolicy>().unwrap(),
+            FsyncPolicy::Interval(Duration::from_secs(5))
+        );
+        assert!("interval:0".parse::<FsyncPolicy>().is_err());
+        assert!("always".parse::<FsyncPolicy>().is_err());
+    }
+}
@@ -0,0 +1,231 @@
+use std::borrow::Cow;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long automated input waits for its echo to start before it is forgotten, e.g. when a
+/// program has turned echo off
+const ECHO_START_WINDOW: Duration = Duration::from_millis(1000);
+
+/// Most bytes of automated input remembered at once
+const MAX_EXPECTED_BYTES: usize = 64 * 1024;
+
+/// Dim on; `SGR 22` turns dim (and bold) back off
+const DIM_ON: &[u8] = b"\x1b[2m";
+const DIM_OFF: &[u8] = b"\x1b[22m";
+
+/// How the echo of input written by automation (queued commands, control socket `send`,
+/// init commands) is shown on your terminal
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AgentEcho {
+    /// Exactly as the shell echoes it
+    #[default]
+    Show,
+    /// Faint, so it stands apart from what you type
+    Dim,
+    /// Left out, keeping only its line breaks
+    Hide,
+}
+
+impl FromStr for AgentEcho {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "show" => Ok(AgentEcho::Show),
+            "dim" => Ok(AgentEcho::Dim),
+            "hide" => Ok(AgentEcho::Hide),
+            _ => Err(anyhow::anyhow!(
+                "Invalid agent echo '{}': expected show, dim or hide",
+                value
+            )),
+        }
+    }
+}
+
+/// Automated input written to one session's shell, waiting for its `EchoFilter`
+#[derive(Debug, Default)]
+pub struct AgentInput {
+    /// Whether the filter restyles echo at all, so input is only kept when it does
+    tracking: AtomicBool,
+    /// Written since the filter last looked
+    written: Mutex<Vec<u8>>,
+}
+
+impl AgentInput {
+    /// Remember bytes automation is about to write to the shell, so their echo can be told
+    /// apart from output
+    pub fn expect_echo(&self, bytes: &[u8]) {
+        if !self.tracking.load(Ordering::Relaxed) {
+            return;
+        }
+        if let Ok(mut written) = self.written.lock() {
+            if written.len() + bytes.len() <= MAX_EXPECTED_BYTES {
+                written.extend_from_slice(bytes);
+            }
+        }
+    }
+
+    fn take(&self) -> Vec<u8> {
+        self.written
+            .lock()
+            .map(|mut written| std::mem::take(&mut *written))
+            .unwrap_or_default()
+    }
+}
+
+/// Restyles the shell's echo of automated input on its way to the outer terminal.
+///
+/// **Matching:**
+/// - Output is compared byte for byte with the input automation wrote; a line break in the
+///   input matches the `\r\n` the terminal echoes for it
+/// - Output before the echo starts passes through untouched; input whose echo has not started
+///   within a second is forgotten
+/// - Once the echo has started, the first output that differs from the input ends it, so
+///   continuation prompts, completions and redraws are shown as they are
+pub struct EchoFilter {
+    policy: AgentEcho,
+    expected: Vec<u8>,
+    /// When the oldest input still waiting for its echo was written
+    since: Option<Instant>,
+    /// The echo has started and output is being matched against `expected`
+    matching: bool,
+    dimmed: bool,
+}
+
+impl EchoFilter {
+    /// A filter for the echo of the input recorded in `input`
+    pub fn new(policy: AgentEcho, input: &AgentInput) -> Self {
+        input
+            .tracking
+            .store(policy != AgentEcho::Show, Ordering::Relaxed);
+        Self {
+            policy,
+            expected: Vec::new(),
+            since: None,
+            matching: false,
+            dimmed: false,
+        }
+    }
+
+    /// Bytes to write to the outer terminal for `chunk`
+    pub fn process<'a>(&mut self, chunk: &'a [u8], input: &AgentInput) -> Cow<'a, [u8]> {
+        if self.policy == AgentEcho::Show {
+            return Cow::Borrowed(chunk);
+        }
+        self.filter(chunk, &input.take(), Instant::now())
+    }
+
+    fn filter<'a>(&mut self, chunk: &'a [u8], written: &[u8], now: Instant) -> Cow<'a, [u8]> {
+        if !written.is_empty() {
+            if self.expected.is_empty() {
+                self.since = Some(now);
+            }
+            self.expected.extend_from_slice(written);
+        }
+        if !self.matching
+            && self
+                .since
+                .is_some_and(|since| now.duration_since(since) > ECHO_START_WINDOW)
+        {
+            self.forget();
+        }
+        if self.expected.is_empty() {
+            return Cow::Borrowed(chunk);
+        }
+
+        let mut output = Vec::with_capacity(chunk.len() + DIM_ON.len() + DIM_OFF.len());
+        let mut next = 0;
+        for &byte in chunk {
+            match self.expected.get(next).copied() {
+                Some(b'\r' | b'\n') if byte == b'\r' => {
+                    self.matching = true;
+                    self.undim(&mut output);
+                    output.push(byte);
+                }
+                Some(expected @ (b'\r' | b'\n')) if byte == b'\n' => {
+                    next += 1;
+                    // `\r\n` typed as the terminator echoes as a single line break
+                    if expected == b'\r' && self.expected.get(next) == Some(&b'\n') {
+                        next += 1;
+                    }
+                    self.undim(&mut output);
+                    output.push(byte);
+                }
+                Some(expected) if byte == expected => {
+                    next += 1;
+                    self.matching = true;
+                    if self.policy == AgentEcho::Dim {
+                        if !self.dimmed {
+                            output.extend_from_slice(DIM_ON);
+                            self.dimmed = true;
+                        }
+                        output.push(byte);
+                    }
+                }
+                Some(_) if !self.matching => output.push(byte),
+                _ => {
+                    if self.matching {
+                        next = self.expected.len();
+                        self.undim(&mut output);
+                    }
+                    output.push(byte);
+                }
+            }
+        }
+        self.expected.drain(..next.min(self.expected.len()));
+        if self.expected.is_empty() {
+            self.forget();
+        }
+        self.undim(&mut output);
+        Cow::Owned(output)
+    }
+
+    fn undim(&mut self, output: &mut Vec<u8>) {
+        if self.dimmed {
+            output.extend_from_slice(DIM_OFF);
+            self.dimmed = false;
+        }
+    }
+
+    fn forget(&mut self) {
+        self.expected.clear();
+        self.since = None;
+        self.matching = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AgentEcho, AgentInput, EchoFilter};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_echo_of_automated_input_is_restyled() {
+        let now = Instant::now();
+        let mut dim = EchoFilter::new(AgentEcho::Dim, &AgentInput::default());
+        assert_eq!(
+            dim.filter(b"$ ma", b"make\r", now).as_ref(),
+            b"$ \x1b[2mma\x1b[22m"
+        );
+        assert_eq!(
+            dim.filter(b"ke\r\nok\r\n", b"", now).as_ref(),
+            b"\x1b[2mke\x1b[22m\r\nok\r\n"
+        );
+
+        // Continuation prompts end the match; the rest is shown as it is
+        let mut hide = EchoFilter::new(AgentEcho::Hide, &AgentInput::default());
+        assert_eq!(
+            hide.filter(b"echo 1\r\n> ", b"echo 1\necho 2\n", now)
+                .as_ref(),
+            b"\r\n> "
+        );
+        assert_eq!(hide.filter(b"echo 2", b"", now).as_ref(), b"echo 2");
+
+        // Input that never echoes is forgotten
+        let later = now + Duration::from_secs(2);
+        assert_eq!(hide.filter(b"", b"secret\r", now).as_ref(), b"");
+        assert_eq!(hide.filter(b"secret", b"", later).as_ref(), b"secret");
+    }
+}
@@ -0,0 +1,117 @@
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::RwLock;
+
+/// At-rest encryption for the transcript, the input and output logs and the audit log.
+///
+/// These files are append-only and written a line at a time, so each record is encrypted on its
+/// own to an age x25519 recipient and stored as one base64 line. A crash can only lose the
+/// record being written, and the audit log can still fsync every entry.
+///
+/// **Decrypting:**
+/// ```bash
+/// typeypipe decrypt --identity key.txt .tp/myshell.transcript
+/// ```
+static RECIPIENT: RwLock<Option<age::x25519::Recipient>> = RwLock::new(None);
+
+/// Encrypt records written from now on to `recipient` (or stop encrypting with `None`)
+pub fn set_encryption_recipient(recipient: Option<age::x25519::Recipient>) {
+    if let Ok(mut current) = RECIPIENT.write() {
+        *current = recipient;
+    }
+}
+
+/// Parse an `age1...` recipient
+pub fn parse_recipient(value: &str) -> Result<age::x25519::Recipient> {
+    age::x25519::Recipient::from_str(value.trim())
+        .map_err(|e| anyhow::anyhow!("Invalid age recipient '{}': {}", value, e))
+}
+
+/// Prepare a record for writing: encrypted to a single base64 line when a recipient is set,
+/// otherwise returned unchanged
+pub fn seal(record: &[u8]) -> Result<Vec<u8>> {
+    let recipient = RECIPIENT
+        .read()
+        .ok()
+        .and_then(|recipient| recipient.clone());
+    seal_to(recipient.as_ref(), record)
+}
+
+fn seal_to(recipient: Option<&age::x25519::Recipient>, record: &[u8]) -> Result<Vec<u8>> {
+    let Some(recipient) = recipient else {
+        return Ok(record.to_vec());
+    };
+
+    let ciphertext = age::encrypt(recipient, record).context("Failed to encrypt record")?;
+    let mut line = BASE64.encode(ciphertext).into_bytes();
+    line.push(b'\n');
+    Ok(line)
+}
+
+/// Decrypt a file written with encryption enabled, using the first `AGE-SECRET-KEY-` in
+/// `identity_file`
+pub fn decrypt_file(path: &Path, identity_file: &Path) -> Result<Vec<u8>> {
+    let identity = read_identity(identity_file)?;
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+
+    let mut plaintext = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let ciphertext = BASE64
+            .decode(line.trim())
+            .with_context(|| format!("Line {} is not an encrypted record", number + 1))?;
+        let record = age::decrypt(&identity, &ciphertext)
+            .with_context(|| format!("Failed to decrypt line {}", number + 1))?;
+        plaintext.extend(record);
+    }
+
+    Ok(plaintext)
+}
+
+fn read_identity(identity_file: &Path) -> Result<age::x25519::Identity> {
+    let contents = std::fs::read_to_string(identity_file)
+        .with_context(|| format!("Failed to read identity {}", identity_file.display()))?;
+
+    let key = contents
+        .lines()
+        .map(str::trim)
+        .find(|line| line.starts_with("AGE-SECRET-KEY-"))
+        .ok_or_else(|| anyhow::anyhow!("No AGE-SECRET-KEY found in {}", identity_file.display()))?;
+
+    age::x25519::Identity::from_str(key).map_err(|e| anyhow::anyhow!("Invalid identity: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decrypt_file, seal_to};
+    use age::secrecy::ExposeSecret;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_sealed_records_decrypt_in_order() {
+        let temp_dir = TempDir::new().unwrap();
+        let identity = age::x25519::Identity::generate();
+        let identity_file = temp_dir.path().join("key.txt");
+        std::fs::write(&identity_file, identity.to_string().expose_secret()).unwrap();
+
+        let recipient = identity.to_public();
+        let first = seal_to(Some(&recipient), b"first line\n").unwrap();
+        let second = seal_to(Some(&recipient), b"second line\n").unwrap();
+
+        assert!(!first.windows(5).any(|w| w == b"first"));
+        assert_eq!(seal_to(None, b"plain\n").unwrap(), b"plain\n");
+
+        let path = temp_dir.path().join("session.transcript");
+        std::fs::write(&path, [first, second].concat()).unwrap();
+        assert_eq!(
+            decrypt_file(&path, &identity_file).unwrap(),
+            b"first line\nsecond line\n"
+        );
+    }
+}
@@ -0,0 +1,263 @@
+use crate::shell::encryption::seal;
+use anyhow::{Context, Result};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+/// A transformation applied to PTY output on its way to a sink. Output arrives in arbitrary
+/// chunks, so filters keep whatever state they need across calls (an escape sequence or a
+/// line split between two chunks).
+pub trait OutputFilter: Send {
+    /// Transform one chunk, appending the result to `output`
+    fn apply(&mut self, input: &[u8], output: &mut Vec<u8>);
+}
+
+/// Filters applied in order, each to the output of the one before
+#[derive(Default)]
+pub struct FilterChain {
+    filters: Vec<Box<dyn OutputFilter>>,
+}
+
+impl FilterChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, filter: Box<dyn OutputFilter>) {
+        self.filters.push(filter);
+    }
+
+    /// Build the chain a list of `FilterSpec`s describes
+    pub fn from_specs(specs: &[FilterSpec]) -> Self {
+        let mut chain = Self::new();
+        for spec in specs {
+            chain.push(spec.build());
+        }
+        chain
+    }
+
+    pub fn apply(&mut self, chunk: &[u8]) -> Vec<u8> {
+        let mut data = chunk.to_vec();
+        for filter in &mut self.filters {
+            let mut output = Vec::with_capacity(data.len());
+            filter.apply(&data, &mut output);
+            data = output;
+        }
+        data
+    }
+}
+
+/// A built-in filter, as named on the command line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterSpec {
+    /// `strip-ansi`: drop escape sequences and control characters other than newline and tab
+    StripAnsi,
+    /// `timestamp`: start every line with the UTC time its first byte was read
+    Timestamp,
+    /// `prefix:<text>`: start every line with `text`, e.g. the session name
+    Prefix(String),
+}
+
+impl FilterSpec {
+    pub fn build(&self) -> Box<dyn OutputFilter> {
+        match self {
+            FilterSpec::StripAnsi => Box::new(StripAnsi::default()),
+            FilterSpec::Timestamp => Box::new(LineStart::new(|| {
+                chrono::Utc::now()
+                    .format("[%Y-%m-%d %H:%M:%S%.3f UTC] ")
+                    .to_string()
+            })),
+            FilterSpec::Prefix(prefix) => {
+                let prefix = prefix.clone();
+                Box::new(LineStart::new(move || prefix.clone()))
+            }
+        }
+    }
+}
+
+impl FromStr for FilterSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.split_once(':') {
+            None if value == "strip-ansi" => Ok(FilterSpec::StripAnsi),
+            None if value == "timestamp" => Ok(FilterSpec::Timestamp),
+            Some(("prefix", prefix)) => Ok(FilterSpec::Prefix(prefix.to_string())),
+            _ => Err(anyhow::anyhow!(
+                "Invalid output filter '{}': expected strip-ansi, timestamp or prefix:<text>",
+                value
+            )),
+        }
+    }
+}
+
+/// Parse a comma-separated filter list such as `strip-ansi,prefix:[web] `
+pub fn parse_filters(list: &str) -> Result<Vec<FilterSpec>> {
+    list.split(',')
+        .filter(|spec| !spec.is_empty())
+        .map(FilterSpec::from_str)
+        .collect()
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum AnsiState {
+    #[default]
+    Ground,
+    Escape,
+    Csi,
+    /// OSC, DCS, APC and PM strings, up to BEL or ST
+    String,
+    StringEscape,
+    Charset,
+}
+
+/// Streaming counterpart of `ansi::strip_ansi`, for output that arrives in chunks
+#[derive(Debug, Default)]
+pub struct StripAnsi {
+    state: AnsiState,
+}
+
+impl OutputFilter for StripAnsi {
+    fn apply(&mut self, input: &[u8], output: &mut Vec<u8>) {
+        for &byte in input {
+            self.state = match (self.state, byte) {
+                (AnsiState::Ground, 0x1b) => AnsiState::Escape,
+                (AnsiState::Ground, b'\n' | b'\t') => {
+                    output.push(byte);
+                    AnsiState::Ground
+                }
+                (AnsiState::Ground, byte) => {
+                    // Bytes of multi-byte UTF-8 characters are all >= 0x80 and kept
+                    if byte >= 0x20 && byte != 0x7f {
+                        output.push(byte);
+                    }
+                    AnsiState::Ground
+                }
+                (AnsiState::Escape, b'[') => AnsiState::Csi,
+                (AnsiState::Escape, b']' | b'P' | b'_' | b'^') => AnsiState::String,
+                (AnsiState::Escape, b'(' | b')' | b'*' | b'+') => AnsiState::Charset,
+                (AnsiState::Escape | AnsiState::Charset, _) => AnsiState::Ground,
+                (AnsiState::Csi, 0x40..=0x7e) => AnsiState::Ground,
+                (AnsiState::Csi, _) => AnsiState::Csi,
+                (AnsiState::String, 0x07) => AnsiState::Ground,
+                (AnsiState::String, 0x1b) => AnsiState::StringEscape,
+                (AnsiState::String, _) => AnsiState::String,
+                (AnsiState::StringEscape, b'\\') => AnsiState::Ground,
+                (AnsiState::StringEscape, _) => AnsiState::String,
+            };
+        }
+    }
+}
+
+/// Inserts text at the start of every line
+pub struct LineStart<F> {
+    text: F,
+    at_line_start: bool,
+}
+
+impl<F: FnMut() -> String + Send> LineStart<F> {
+    pub fn new(text: F) -> Self {
+        Self {
+            text,
+            at_line_start: true,
+        }
+    }
+}
+
+impl<F: FnMut() -> String + Send> OutputFilter for LineStart<F> {
+    fn apply(&mut self, input: &[u8], output: &mut Vec<u8>) {
+        for &byte in input {
+            if self.at_line_start {
+                output.extend_from_slice((self.text)().as_bytes());
+                self.at_line_start = false;
+            }
+            output.push(byte);
+            if byte == b'\n' {
+                self.at_line_start = true;
+            }
+        }
+    }
+}
+
+/// Appends filtered output to a file one complete line at a time, so several sessions can
+/// share one aggregate log without their lines interleaving mid-line. With `--encrypt-to`
+/// each line is sealed on its own.
+pub struct OutputLog {
+    file: File,
+    filters: FilterChain,
+    pending: Vec<u8>,
+}
+
+impl OutputLog {
+    pub fn create(path: &Path, filters: FilterChain) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .context("Failed to open output log")?;
+        Ok(Self {
+            file,
+            filters,
+            pending: Vec::new(),
+        })
+    }
+
+    pub fn record(&mut self, chunk: &[u8]) -> Result<()> {
+        let filtered = self.filters.apply(chunk);
+        self.pending.extend_from_slice(&filtered);
+
+        if let Some(last_newline) = self.pending.iter().rposition(|&b| b == b'\n') {
+            let lines: Vec<u8> = self.pending.drain(..=last_newline).collect();
+            for line in lines.split_inclusive(|&b| b == b'\n') {
+                self.file
+                    .write_all(&seal(line)?)
+                    .context("Failed to write to output log")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write out a trailing partial line
+    pub fn flush(&mut self) -> Result<()> {
+        if !self.pending.is_empty() {
+            let mut line = std::mem::take(&mut self.pending);
+            line.push(b'\n');
+            self.file
+                .write_all(&seal(&line)?)
+                .context("Failed to write to output log")?;
+        }
+        self.file.flush().context("Failed to flush output log")
+    }
+}
+
+impl Drop for OutputLog {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_filters, FilterChain, FilterSpec};
+
+    #[test]
+    fn test_chain_strips_and_prefixes_across_chunks() {
+        let specs = parse_filters("strip-ansi,prefix:[web] ").unwrap();
+        assert_eq!(
+            specs,
+            vec![
+                FilterSpec::StripAnsi,
+                FilterSpec::Prefix("[web] ".to_string())
+            ]
+        );
+        let mut chain = FilterChain::from_specs(&specs);
+
+        let mut output = chain.apply(b"\x1b[1;3");
+        output.extend(chain.apply(b"2mok\x1b[0m\r\nsecond \x1b]0;ti"));
+        output.extend(chain.apply(b"tle\x07line\n"));
+        assert_eq!(output, b"[web] ok\n[web] second line\n");
+
+        assert!(parse_filters("timestamp,upper").is_err());
+    }
+}
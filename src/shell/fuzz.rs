@@ -0,0 +1,113 @@
+use crate::shell::ansi::strip_ansi;
+use crate::shell::arbiter::{LineTracker, PromptMarks};
+use crate::shell::osc::CommandTracker;
+
+/// Entry points for fuzzers (cargo-fuzz, AFL, honggfuzz) over the parsers that see terminal
+/// streams a chunk at a time.
+///
+/// **Contract:**
+/// - Each function accepts arbitrary bytes and panics only when it finds a bug
+/// - The first byte picks a chunk size (1 to 16); the rest is the stream
+/// - The stream is parsed once whole and once in chunks, and the results must match: a state
+///   machine that loses its place at a read boundary shows up as garbled input in a session
+///
+/// ```ignore
+/// fuzz_target!(|data: &[u8]| typey_pipe::fuzz::command_tracker(data));
+/// ```
+pub fn line_tracker(data: &[u8]) {
+    let (chunk_size, stream) = split_input(data);
+
+    let mut whole = LineTracker::new();
+    whole.observe(stream);
+    let mut chunked = LineTracker::new();
+    for chunk in stream.chunks(chunk_size) {
+        chunked.observe(chunk);
+    }
+
+    assert_eq!(whole.is_pending(), chunked.is_pending());
+}
+
+pub fn command_tracker(data: &[u8]) {
+    let (chunk_size, stream) = split_input(data);
+
+    let summary = |finished: Vec<crate::shell::osc::FinishedCommand>| {
+        finished
+            .into_iter()
+            .map(|command| (command.command, command.exit_status))
+            .collect::<Vec<_>>()
+    };
+    let whole = summary(CommandTracker::new().feed(stream));
+    let mut tracker = CommandTracker::new();
+    let chunked = summary(
+        stream
+            .chunks(chunk_size)
+            .flat_map(|chunk| tracker.feed(chunk))
+            .collect(),
+    );
+
+    assert_eq!(whole, chunked);
+}
+
+pub fn prompt_marks(data: &[u8]) {
+    let (chunk_size, stream) = split_input(data);
+
+    let whole = PromptMarks::new().feed(stream);
+    let mut marks = PromptMarks::new();
+    let chunked = stream.chunks(chunk_size).any(|chunk| marks.feed(chunk));
+
+    assert_eq!(whole, chunked);
+}
+
+/// `strip_ansi` works on whole strings; whatever it is given, nothing that moves the cursor
+/// or changes colours may survive
+pub fn strip_ansi_output(data: &[u8]) {
+    let stripped = strip_ansi(&String::from_utf8_lossy(data));
+    assert!(!stripped
+        .chars()
+        .any(|c| c.is_control() && c != '\n' && c != '\t'));
+}
+
+fn split_input(data: &[u8]) -> (usize, &[u8]) {
+    match data.split_first() {
+        Some((&first, rest)) => (usize::from(first % 16) + 1, rest),
+        None => (1, data),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    /// Pieces of terminal streams, so generated inputs are mostly escape sequences and
+    /// line editing rather than random bytes that never reach the interesting states
+    fn fragment() -> impl Strategy<Value = Vec<u8>> {
+        prop_oneof![
+            "[a-z -]{1,8}".prop_map(String::into_bytes),
+            Just(b"\r\n".to_vec()),
+            prop::sample::select(vec![0x03u8, 0x08, 0x15, 0x17, 0x7f]).prop_map(|b| vec![b]),
+            "[0-9;?]{0,6}[A-Za-z~]".prop_map(|csi| [b"\x1b[", csi.as_bytes()].concat()),
+            "[A-D]".prop_map(|key| [b"\x1bO", key.as_bytes()].concat()),
+            prop::sample::select(vec!["A", "B", "C", "D;0", "D;2", "D"]).prop_flat_map(|mark| {
+                prop::sample::select(vec!["\x07", "\x1b\\"])
+                    .prop_map(move |end| format!("\x1b]133;{}{}", mark, end).into_bytes())
+            }),
+            Just("é".as_bytes().to_vec()),
+            any::<u8>().prop_map(|b| vec![b]),
+        ]
+    }
+
+    fn stream() -> impl Strategy<Value = Vec<u8>> {
+        (any::<u8>(), prop::collection::vec(fragment(), 0..32))
+            .prop_map(|(split, fragments)| [vec![split], fragments.concat()].concat())
+    }
+
+    proptest! {
+        #[test]
+        fn test_parsers_survive_chunk_boundaries(data in stream()) {
+            super::line_tracker(&data);
+            super::command_tracker(&data);
+            super::prompt_marks(&data);
+            super::strip_ansi_output(&data);
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Text labels for the emoji typeypipe prints, for screen readers and fonts without emoji.
+/// Glyphs written with a variation selector come before their bare form so the selector
+/// goes with them.
+const LABELS: &[(&str, &str)] = &[
+    ("⏸️", "[paused]"),
+    ("⏸", "[paused]"),
+    ("▶️", "[resumed]"),
+    ("▶", "[resumed]"),
+    ("➡️", "[routed]"),
+    ("➡", "[routed]"),
+    ("⚠️", "[warning]"),
+    ("⚠", "[warning]"),
+    ("✅", "[ok]"),
+    ("❌", "[error]"),
+    ("🚫", "[rejected]"),
+    ("⏳", "[pending]"),
+    ("⏱️", "[timer]"),
+    ("⏱", "[timer]"),
+    ("🔄", "[processing]"),
+    ("🔒", "[locked]"),
+    ("🔓", "[unlocked]"),
+    ("🔔", "[alert]"),
+    ("🚀", "[start]"),
+    ("📁", "[queue]"),
+    ("📼", "[transcript]"),
+    ("⌨️", "[input]"),
+    ("⌨", "[input]"),
+    ("📊", "[stats]"),
+    ("🎉", "[done]"),
+    ("💥", "[panic]"),
+    ("🚪", "[exit]"),
+];
+
+/// Whether messages, logs and titles use text labels instead of emoji
+static PLAIN_TEXT: AtomicBool = AtomicBool::new(false);
+
+pub fn set_plain_text(plain: bool) {
+    PLAIN_TEXT.store(plain, Ordering::Relaxed);
+}
+
+pub fn plain_text() -> bool {
+    PLAIN_TEXT.load(Ordering::Relaxed)
+}
+
+/// `message` as it should be shown: unchanged normally, or with each emoji replaced by its
+/// text label under `--plain`
+pub fn label(message: &str) -> Cow<'_, str> {
+    if plain_text() {
+        to_plain(message)
+    } else {
+        Cow::Borrowed(message)
+    }
+}
+
+fn to_plain(message: &str) -> Cow<'_, str> {
+    if message.is_ascii() {
+        return Cow::Borrowed(message);
+    }
+    let mut plain = message.to_string();
+    for (glyph, text) in LABELS {
+        if plain.contains(glyph) {
+            plain = plain.replace(glyph, text);
+        }
+    }
+    Cow::Owned(plain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::to_plain;
+
+    #[test]
+    fn test_emoji_become_text_labels() {
+        assert_eq!(
+            to_plain("⏸️ Queue processing paused - user is typing"),
+            "[paused] Queue processing paused - user is typing"
+        );
+        assert_eq!(
+            to_plain("⚠️  Warning: ❌ failed, ⏸ bare"),
+            "[warning]  Warning: [error] failed, [paused] bare"
+        );
+        assert_eq!(to_plain("ls — /srv"), "ls — /srv");
+        assert_eq!(to_plain("plain ascii"), "plain ascii");
+    }
+}
@@ -0,0 +1,514 @@
+use crate::shell::context::SessionContext;
+use crate::shell::pty::{create_pty_session, SharedPtySession};
+use crate::shell::queue::PtyQueueProcessor;
+use crate::shell::scrollback::SharedScrollback;
+use crate::shell::supervisor::supervise;
+use crate::shell::tee::{spawn_blocking_sink, OutputChunk, OutputSubscriptions, OutputTee};
+use crate::shell::transcript::Transcript;
+use crate::shell::types::ShellConfig;
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+
+/// How often a headless session looks for new queue files
+const QUEUE_POLL_INTERVAL_MS: u64 = 250;
+
+/// Chunks an output subscriber may fall behind before it skips ahead
+const OUTPUT_CAPACITY: usize = 1024;
+
+/// How often `wait` and `shutdown` check on the shell and the queue
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Run a shell with its queue engine and nothing attached: no raw mode, no stdin, no stdout
+/// mirroring. Meant for CI jobs and server-side agents that drive a session purely through
+/// its queue directory and read what it prints through the returned handle.
+///
+/// **What runs:**
+/// - The shell in a PTY sized by `config`
+/// - A reader broadcasting PTY output to the scrollback, the transcript when
+///   `transcript_file` is given, and `SessionHandle::subscribe`
+/// - A `PtyQueueProcessor` injecting files placed in `queue_dir` and logging to `log_file`,
+///   with the terminator and authorizer of `context`
+///
+/// Use `wait`, `shutdown` or `kill` on the handle to end the session; dropping it stops the
+/// queue engine and kills the shell.
+pub async fn run_headless(
+    config: ShellConfig,
+    queue_dir: PathBuf,
+    log_file: PathBuf,
+    transcript_file: Option<PathBuf>,
+    context: Arc<SessionContext>,
+) -> Result<SessionHandle> {
+    let session = create_pty_session(config).await?;
+    run_headless_session(session, queue_dir, log_file, transcript_file, context).await
+}
+
+/// `run_headless` over a session that is already running, such as one on a `MockPty`
+pub async fn run_headless_session(
+    session: SharedPtySession,
+    queue_dir: PathBuf,
+    log_file: PathBuf,
+    transcript_file: Option<PathBuf>,
+    context: Arc<SessionContext>,
+) -> Result<SessionHandle> {
+    tokio::fs::create_dir_all(&queue_dir)
+        .await
+        .context("Failed to create queue directory")?;
+    let transcript = transcript_file
+        .as_deref()
+        .map(Transcript::create)
+        .transpose()?;
+
+    let (session_id, scrollback, reader) = {
+        let mut guard = session.lock().await;
+        (
+            guard.session_id().to_string(),
+            guard.scrollback(),
+            guard.clone_pty_reader()?,
+        )
+    };
+
+    let tee = OutputTee::new(OUTPUT_CAPACITY);
+    let output = tee.subscriptions();
+    let mut sinks = {
+        let scrollback = scrollback.clone();
+        vec![spawn_blocking_sink(
+            tee.subscribe(),
+            "scrollback",
+            move |chunk| {
+                if let Ok(mut scrollback) = scrollback.lock() {
+                    scrollback.push(chunk);
+                }
+            },
+        )]
+    };
+    if let Some(mut transcript) = transcript {
+        sinks.push(spawn_blocking_sink(
+            tee.subscribe(),
+            "transcript",
+            move |chunk| {
+                let _ = transcript.record(chunk);
+            },
+        ));
+    }
+    let reader = tee.spawn_reader(reader);
+
+    let processor = Arc::new(
+        PtyQueueProcessor::new(session.clone(), queue_dir.clone(), log_file, context).await?,
+    );
+    let exit_log = processor.clone();
+    let queue = supervise("queue", move || {
+        let processor = processor.clone();
+        async move { processor.start_processing(QUEUE_POLL_INTERVAL_MS).await }
+    });
+
+    // Nothing can be injected once the shell has closed the PTY, so the queue stops with it
+    // and leaves later files for the next session
+    let stop_queue = queue.abort_handle();
+    let reader = tokio::spawn(async move {
+        let end = reader.await;
+        stop_queue.abort();
+        if let Ok(end) = end {
+            let _ = exit_log
+                .log_message(&format!("🚪 Session ending: {}", end))
+                .await;
+        }
+    });
+
+    Ok(SessionHandle {
+        session_id,
+        session,
+        scrollback,
+        output,
+        queue_dir,
+        queue,
+        reader: Some(reader),
+        sinks,
+    })
+}
+
+/// A session started by `run_headless`.
+///
+/// **Ending the session:**
+/// - `wait`: until the shell exits on its own (e.g. after a queued `exit`)
+/// - `shutdown`: let the queue drain, then hang up on the shell, killing it if it outlives `grace`
+/// - `kill`: immediately, dropping anything still queued
+///
+/// Each returns the shell's exit code once its output has been delivered to every subscriber.
+pub struct SessionHandle {
+    session_id: String,
+    session: SharedPtySession,
+    scrollback: SharedScrollback,
+    output: OutputSubscriptions,
+    queue_dir: PathBuf,
+    queue: JoinHandle<Option<Result<()>>>,
+    reader: Option<JoinHandle<()>>,
+    sinks: Vec<JoinHandle<()>>,
+}
+
+impl SessionHandle {
+    pub fn session_id(&self) -> &str {
+        &self.session_id
+    }
+
+    /// The underlying PTY session, for writing input or resizing directly
+    pub fn session(&self) -> SharedPtySession {
+        self.session.clone()
+    }
+
+    /// Directory whose files are injected into the shell
+    pub fn queue_dir(&self) -> &Path {
+        &self.queue_dir
+    }
+
+    pub fn scrollback(&self) -> SharedScrollback {
+        self.scrollback.clone()
+    }
+
+    /// Late subscriptions to the output, and why it ended once it has
+    pub(crate) fn output(&self) -> OutputSubscriptions {
+        self.output.clone()
+    }
+
+    /// Receive output from now on, or `None` once the shell has closed the PTY
+    pub fn subscribe(&self) -> Option<broadcast::Receiver<OutputChunk>> {
+        self.output.subscribe()
+    }
+
+    /// Wait for the shell to exit and return its exit code
+    pub async fn wait(&mut self) -> Result<u32> {
+        loop {
+            if let Some(code) = self.try_wait().await? {
+                return self.finish(code).await;
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Let the queue drain, then send the shell SIGHUP and wait for it to exit; whatever
+    /// remains of `grace` after the queue drained is how long the shell gets before it is
+    /// killed.
+    pub async fn shutdown(&mut self, grace: Duration) -> Result<u32> {
+        let deadline = tokio::time::Instant::now() + grace;
+
+        while !queue_is_empty(&self.queue_dir).await && tokio::time::Instant::now() < deadline {
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+        self.queue.abort();
+
+        if let Some(pid) = self.session.lock().await.child_pid() {
+            use nix::sys::signal::{kill, Signal};
+            use nix::unistd::Pid;
+            let _ = kill(Pid::from_raw(pid as i32), Signal::SIGHUP);
+        }
+
+        loop {
+            if let Some(code) = self.try_wait().await? {
+                return self.finish(code).await;
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return self.kill().await;
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Kill the shell without draining the queue
+    pub async fn kill(&mut self) -> Result<u32> {
+        self.queue.abort();
+        self.session.lock().await.kill()?;
+        self.wait().await
+    }
+
+    async fn try_wait(&self) -> Result<Option<u32>> {
+        self.session.lock().await.try_wait()
+    }
+
+    /// Stop the queue engine and let the reader deliver the shell's last output, and the
+    /// scrollback and transcript take it in
+    async fn finish(&mut self, code: u32) -> Result<u32> {
+        self.queue.abort();
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.await;
+        }
+        for sink in self.sinks.drain(..) {
+            let _ = sink.await;
+        }
+        Ok(code)
+    }
+}
+
+async fn queue_is_empty(queue_dir: &Path) -> bool {
+    match tokio::fs::read_dir(queue_dir).await {
+        Ok(mut entries) => matches!(entries.next_entry().await, Ok(None)),
+        Err(_) => true,
+    }
+}
+
+impl Drop for SessionHandle {
+    fn drop(&mut self) {
+        self.queue.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::run_headless;
+    use crate::shell::audit::{AuditEntry, AuditLog};
+    use crate::shell::context::SessionContext;
+    use crate::shell::queue::{enqueue_file, wait_for_injection};
+    use crate::shell::status::{status_file_path, SessionStatus};
+    use crate::shell::testing::FakeShell;
+    use crate::shell::types::{FailureCode, QueueFailure, QueueMessage, ShellConfig, Terminator};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_headless_session_runs_queued_commands() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = ShellConfig {
+            shell_path: "/bin/sh".to_string(),
+            ..ShellConfig::default()
+        };
+        let mut handle = run_headless(
+            config,
+            temp_dir.path().join("queue"),
+            temp_dir.path().join("queue.log"),
+            Some(temp_dir.path().join("queue.transcript")),
+            Arc::new(SessionContext::new(Terminator::Lf)),
+        )
+        .await
+        .unwrap();
+        let mut output = handle.subscribe().unwrap();
+
+        std::fs::write(handle.queue_dir().join("cmd"), "echo headless-$((40 + 2))").unwrap();
+
+        let mut seen = Vec::new();
+        let found = tokio::time::timeout(Duration::from_secs(10), async {
+            while let Ok(chunk) = output.recv().await {
+                seen.extend_from_slice(&chunk);
+                if String::from_utf8_lossy(&seen).contains("headless-42") {
+                    return true;
+                }
+            }
+            false
+        })
+        .await;
+        assert_eq!(found.ok(), Some(true));
+
+        std::fs::write(handle.queue_dir().join("exit"), "exit 3").unwrap();
+        let code = tokio::time::timeout(Duration::from_secs(10), handle.wait()).await;
+        assert_eq!(code.ok().map(Result::unwrap), Some(3));
+
+        let transcript = std::fs::read_to_string(temp_dir.path().join("queue.transcript")).unwrap();
+        assert!(transcript
+            .lines()
+            .any(|line| line.starts_with('[') && line.ends_with("] headless-42")));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_ends_a_busy_shell() {
+        let mut session = FakeShell::new()
+            .delay(Duration::from_secs(30))
+            .start()
+            .await
+            .unwrap();
+        session.enqueue("cmd", "make").unwrap();
+
+        // A shell busy with a foreground command still exits on SIGHUP, well within the grace
+        let started = std::time::Instant::now();
+        session
+            .handle
+            .shutdown(Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(session
+            .handle
+            .queue_dir()
+            .read_dir()
+            .unwrap()
+            .next()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_targeted_messages_are_routed_to_their_session() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = ShellConfig {
+            shell_path: "/bin/sh".to_string(),
+            ..ShellConfig::default()
+        };
+        let start = |name: &str| {
+            run_headless(
+                config.clone(),
+                temp_dir.path().join(name),
+                temp_dir.path().join(format!("{}.log", name)),
+                None,
+                Arc::new(SessionContext::new(Terminator::Lf)),
+            )
+        };
+        let mut sender = start("sender").await.unwrap();
+        let mut receiver = start("receiver").await.unwrap();
+        let mut output = receiver.subscribe().unwrap();
+        SessionStatus {
+            session_id: receiver.session_id().to_string(),
+            pid: std::process::id(),
+            child_pid: None,
+            queue_dir: Some(receiver.queue_dir().to_path_buf()),
+            log_file: None,
+            started_at: chrono::Utc::now(),
+            last_queue_check: chrono::Utc::now(),
+            queue_paused: false,
+            tp_dir: None,
+            tp_dir_source: None,
+        }
+        .write(&status_file_path(temp_dir.path(), "receiver"))
+        .unwrap();
+
+        let command = QueueMessage::Command {
+            command: "echo routed-$((40 + 2))".to_string(),
+            terminator: None,
+        };
+        let routed = enqueue_file(
+            sender.queue_dir(),
+            "test",
+            &command.encode_for(Some("receiver")).unwrap(),
+        )
+        .await
+        .unwrap();
+        wait_for_injection(&routed, Some(Duration::from_secs(10)))
+            .await
+            .unwrap();
+        let mut seen = Vec::new();
+        let found = tokio::time::timeout(Duration::from_secs(10), async {
+            while let Ok(chunk) = output.recv().await {
+                seen.extend_from_slice(&chunk);
+                if String::from_utf8_lossy(&seen).contains("routed-42") {
+                    return true;
+                }
+            }
+            false
+        })
+        .await;
+        assert_eq!(found.ok(), Some(true));
+
+        // A session nobody runs is reported instead of waiting in a queue nobody reads
+        let unroutable = enqueue_file(
+            sender.queue_dir(),
+            "test",
+            &command.encode_for(Some("missing")).unwrap(),
+        )
+        .await
+        .unwrap();
+        let failure = wait_for_injection(&unroutable, Some(Duration::from_secs(10)))
+            .await
+            .unwrap_err()
+            .downcast::<QueueFailure>()
+            .unwrap();
+        assert_eq!(failure.code, FailureCode::Unroutable);
+
+        sender.kill().await.unwrap();
+        receiver.kill().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_sessions_keep_their_own_logs_and_commands() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = ShellConfig {
+            shell_path: FakeShell::new()
+                .install(temp_dir.path())
+                .unwrap()
+                .display()
+                .to_string(),
+            ..ShellConfig::default()
+        };
+        let start = |name: &str| {
+            let audit_log =
+                AuditLog::open(&temp_dir.path().join(format!("{}.audit", name)), name).unwrap();
+            let context = Arc::new(SessionContext::new(Terminator::Lf).audit_log(Some(audit_log)));
+            let handle = run_headless(
+                config.clone(),
+                temp_dir.path().join(name),
+                temp_dir.path().join(format!("{}.log", name)),
+                None,
+                context.clone(),
+            );
+            async move { (handle.await.unwrap(), context) }
+        };
+        let ((mut first, first_context), (mut second, second_context)) =
+            tokio::join!(start("first"), start("second"));
+
+        let queued = |handle: &super::SessionHandle, command: &str| {
+            let message = QueueMessage::Command {
+                command: command.to_string(),
+                terminator: None,
+            };
+            let queue_dir = handle.queue_dir().to_path_buf();
+            async move {
+                let file = enqueue_file(&queue_dir, "test", &message.encode().unwrap())
+                    .await
+                    .unwrap();
+                wait_for_injection(&file, Some(Duration::from_secs(10))).await
+            }
+        };
+        let (one, two, three) = tokio::join!(
+            queued(&first, "echo first-1"),
+            queued(&second, "echo second-1"),
+            queued(&first, "echo first-2"),
+        );
+        one.unwrap();
+        two.unwrap();
+        three.unwrap();
+        // The queue file is removed just before its outcome is recorded
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while first_context.recent_commands().len() < 2
+                || second_context.recent_commands().is_empty()
+            {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        for (name, context, expected) in [
+            (
+                "first",
+                &first_context,
+                vec!["echo first-1", "echo first-2"],
+            ),
+            ("second", &second_context, vec!["echo second-1"]),
+        ] {
+            let audit =
+                std::fs::read_to_string(temp_dir.path().join(format!("{}.audit", name))).unwrap();
+            let mut audited: Vec<AuditEntry> = audit
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect();
+            audited.sort_by(|a, b| a.command.cmp(&b.command));
+            assert!(audited.iter().all(|entry| entry.session == name));
+            assert_eq!(
+                audited
+                    .iter()
+                    .map(|entry| entry.command.as_str())
+                    .collect::<Vec<_>>(),
+                expected
+            );
+
+            let mut recent: Vec<String> = context
+                .recent_commands()
+                .into_iter()
+                .map(|recent| recent.command)
+                .collect();
+            recent.sort();
+            assert_eq!(recent, expected);
+        }
+
+        first.kill().await.unwrap();
+        second.kill().await.unwrap();
+    }
+}
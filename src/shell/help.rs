@@ -0,0 +1,101 @@
+use crate::shell::stopwatch::format_duration;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// What the help overlay shows about a session besides its key bindings
+#[derive(Debug, Clone, Default)]
+pub struct HelpInfo {
+    pub session: String,
+    /// Files and sockets of the session, labelled, in display order
+    pub paths: Vec<(&'static str, PathBuf)>,
+}
+
+/// What the queue and the command it last injected are doing
+#[derive(Debug, Clone, Default)]
+pub struct HelpStatus {
+    pub queue_state: &'static str,
+    pub pending: Option<usize>,
+    /// The injected command still running, and for how long
+    pub running: Option<(String, Duration)>,
+    /// Fraction of its work the running command reports done
+    pub progress: Option<f64>,
+}
+
+/// F1 opens the help overlay
+pub fn is_help_key(key_event: &crossterm::event::KeyEvent) -> bool {
+    use crossterm::event::{KeyCode, KeyEventKind};
+
+    key_event.kind == KeyEventKind::Press && key_event.code == KeyCode::F(1)
+}
+
+/// Lines of the help screen, each cut to `cols` characters
+pub fn render_help(info: &HelpInfo, status: &HelpStatus, cols: u16) -> Vec<String> {
+    let mut lines = vec![
+        format!("typeypipe help - session {}", info.session),
+        String::new(),
+        "Keys".to_string(),
+        "  F1          Show this help".to_string(),
+        "  F2          Command palette".to_string(),
+        "  Ctrl+]      Lock the queue, or release it".to_string(),
+        "  Ctrl+D      Exit the shell and end the session".to_string(),
+        String::new(),
+        "Queue".to_string(),
+        format!("  State       {}", status.queue_state),
+    ];
+    if let Some(pending) = status.pending {
+        lines.push(format!("  Pending     {} file(s)", pending));
+    }
+    if let Some((command, elapsed)) = &status.running {
+        lines.push(format!(
+            "  Running     {} ({})",
+            command,
+            format_duration(*elapsed)
+        ));
+    }
+    if let Some(progress) = status.progress {
+        lines.push(format!("  Progress    {:.0}%", progress * 100.0));
+    }
+    if !info.paths.is_empty() {
+        lines.push(String::new());
+        lines.push("Paths".to_string());
+        for (name, path) in &info.paths {
+            lines.push(format!("  {:<12}{}", name, path.display()));
+        }
+    }
+    lines.push(String::new());
+    lines.push("Press any key to return".to_string());
+
+    lines
+        .into_iter()
+        .map(|line| line.chars().take(cols as usize).collect())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render_help, HelpInfo, HelpStatus};
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    #[test]
+    fn test_help_lists_state_and_paths() {
+        let info = HelpInfo {
+            session: "web".to_string(),
+            paths: vec![("Log", PathBuf::from("/srv/.tp/web.log"))],
+        };
+        let status = HelpStatus {
+            queue_state: "locked (Ctrl+])",
+            pending: Some(2),
+            running: Some(("make".to_string(), Duration::from_secs(75))),
+            progress: Some(0.425),
+        };
+        let lines = render_help(&info, &status, 30);
+        assert_eq!(lines[0], "typeypipe help - session web");
+        assert!(lines.contains(&"  State       locked (Ctrl+])".to_string()));
+        assert!(lines.contains(&"  Pending     2 file(s)".to_string()));
+        assert!(lines.contains(&"  Running     make (1m15s)".to_string()));
+        assert!(lines.contains(&"  Progress    42%".to_string()));
+        assert!(lines.contains(&"  Log         /srv/.tp/web.log".to_string()));
+        assert!(lines.iter().all(|line| line.chars().count() <= 30));
+    }
+}
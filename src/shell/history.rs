@@ -0,0 +1,289 @@
+use crate::shell::osc::FinishedCommand;
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Searchable record of the commands sessions ran, kept in `.tp/history.sqlite`.
+///
+/// Every session started with `--history` adds a row per queued command when it is injected
+/// (or rejected), and fills in its exit status and timing once the shell reports the command
+/// finished (OSC 133 marks from the shell integration). With `--history-interactive`, commands
+/// typed by the user are recorded as well, with source `interactive`.
+pub struct HistoryDb {
+    connection: Connection,
+}
+
+/// One row of the history database
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub id: i64,
+    pub session: String,
+    pub source: String,
+    pub command: String,
+    pub recorded_at: chrono::DateTime<chrono::Utc>,
+    pub result: String,
+    pub exit_status: Option<i32>,
+    pub started_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub finished_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl HistoryEntry {
+    /// How long the command ran, once the shell has reported it finished
+    pub fn duration(&self) -> Option<std::time::Duration> {
+        (self.finished_at? - self.started_at?).to_std().ok()
+    }
+}
+
+/// Filters for `HistoryDb::query`
+#[derive(Debug, Clone, Default)]
+pub struct HistoryQuery {
+    /// Only commands containing this text
+    pub grep: Option<String>,
+    /// Only commands that exited non-zero or were never run
+    pub failed: bool,
+    pub session: Option<String>,
+    pub limit: usize,
+}
+
+/// Location of the history database inside the `.tp` directory
+pub fn history_db_path(tp_dir: &Path) -> PathBuf {
+    tp_dir.join("history.sqlite")
+}
+
+impl HistoryDb {
+    pub fn open(path: &Path) -> Result<Self> {
+        let connection = Connection::open(path)
+            .with_context(|| format!("Failed to open history database {}", path.display()))?;
+        // Several sessions may share one database
+        connection
+            .busy_timeout(std::time::Duration::from_secs(5))
+            .context("Failed to configure history database")?;
+        connection
+            .execute_batch(
+                "CREATE TABLE IF NOT EXISTS commands (
+                    id INTEGER PRIMARY KEY,
+                    session TEXT NOT NULL,
+                    source TEXT NOT NULL,
+                    command TEXT NOT NULL,
+                    recorded_at TEXT NOT NULL,
+                    result TEXT NOT NULL,
+                    exit_status INTEGER,
+                    started_at TEXT,
+                    finished_at TEXT
+                );
+                CREATE INDEX IF NOT EXISTS commands_recorded_at ON commands (recorded_at);",
+            )
+            .context("Failed to create history schema")?;
+
+        Ok(Self { connection })
+    }
+
+    /// Record a command as it is injected, rejected or fails to be written
+    pub fn record(&self, session: &str, source: &str, command: &str, result: &str) -> Result<()> {
+        self.connection
+            .execute(
+                "INSERT INTO commands (session, source, command, recorded_at, result)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![session, source, command, chrono::Utc::now(), result],
+            )
+            .context("Failed to record command")?;
+        Ok(())
+    }
+
+    /// Attach the outcome of a finished command to the injected row it belongs to. Commands
+    /// that were not injected are inserted as `interactive` when `record_unmatched` is set.
+    pub fn record_finished(
+        &self,
+        session: &str,
+        finished: &FinishedCommand,
+        record_unmatched: bool,
+    ) -> Result<()> {
+        let pending: Option<i64> = self
+            .connection
+            .query_row(
+                "SELECT id FROM commands
+                 WHERE session = ?1 AND command = ?2 AND result = 'injected' AND finished_at IS NULL
+                 ORDER BY id DESC LIMIT 1",
+                params![session, finished.command],
+                |row| row.get(0),
+            )
+            .optional()
+            .context("Failed to look up command")?;
+
+        match pending {
+            Some(id) => {
+                self.connection
+                    .execute(
+                        "UPDATE commands SET exit_status = ?1, started_at = ?2, finished_at = ?3
+                         WHERE id = ?4",
+                        params![
+                            finished.exit_status,
+                            finished.started_at,
+                            finished.finished_at,
+                            id
+                        ],
+                    )
+                    .context("Failed to update command")?;
+            }
+            None if record_unmatched => {
+                self.connection
+                    .execute(
+                        "INSERT INTO commands
+                         (session, source, command, recorded_at, result, exit_status, started_at, finished_at)
+                         VALUES (?1, 'interactive', ?2, ?3, 'typed', ?4, ?3, ?5)",
+                        params![
+                            session,
+                            finished.command,
+                            finished.started_at,
+                            finished.exit_status,
+                            finished.finished_at
+                        ],
+                    )
+                    .context("Failed to record command")?;
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Most recent matching commands, oldest first
+    pub fn query(&self, query: &HistoryQuery) -> Result<Vec<HistoryEntry>> {
+        let mut statement = self
+            .connection
+            .prepare(
+                "SELECT id, session, source, command, recorded_at, result, exit_status, started_at, finished_at
+                 FROM commands
+                 WHERE (?1 IS NULL OR instr(command, ?1) > 0)
+                   AND (?2 IS NULL OR session = ?2)
+                   AND (NOT ?3 OR result <> 'injected' AND result <> 'typed' OR exit_status <> 0)
+                 ORDER BY id DESC LIMIT ?4",
+            )
+            .context("Failed to prepare history query")?;
+
+        let mut entries = statement
+            .query_map(
+                params![query.grep, query.session, query.failed, query.limit as i64],
+                |row| {
+                    Ok(HistoryEntry {
+                        id: row.get(0)?,
+                        session: row.get(1)?,
+                        source: row.get(2)?,
+                        command: row.get(3)?,
+                        recorded_at: row.get(4)?,
+                        result: row.get(5)?,
+                        exit_status: row.get(6)?,
+                        started_at: row.get(7)?,
+                        finished_at: row.get(8)?,
+                    })
+                },
+            )
+            .context("Failed to query history")?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .context("Failed to read history")?;
+
+        entries.reverse();
+        Ok(entries)
+    }
+}
+
+/// The history database as one session writes to it, from its queue processor and output
+/// task
+pub struct SessionHistory {
+    db: Mutex<HistoryDb>,
+    session: String,
+    record_interactive: bool,
+}
+
+impl SessionHistory {
+    /// Start recording `session`'s commands in the database at `path`
+    pub fn open(path: &Path, session: &str, record_interactive: bool) -> Result<Self> {
+        Ok(Self {
+            db: Mutex::new(HistoryDb::open(path)?),
+            session: session.to_string(),
+            record_interactive,
+        })
+    }
+
+    /// Record a queued command
+    pub fn record(&self, source: &str, command: &str, result: &str) {
+        self.write(|db| db.record(&self.session, source, command, result));
+    }
+
+    /// Record the outcome of a command the shell finished
+    pub fn record_finished(&self, finished: &FinishedCommand) {
+        self.write(|db| db.record_finished(&self.session, finished, self.record_interactive));
+    }
+
+    fn write(&self, write: impl FnOnce(&HistoryDb) -> Result<()>) {
+        let Ok(db) = self.db.lock() else {
+            return;
+        };
+        if let Err(e) = write(&db) {
+            tracing::error!(error = %e, "failed to write command history");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HistoryDb, HistoryQuery};
+    use crate::shell::osc::FinishedCommand;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_finished_commands_update_injected_rows() {
+        let temp_dir = TempDir::new().unwrap();
+        let db = HistoryDb::open(&temp_dir.path().join("history.sqlite")).unwrap();
+
+        db.record("dev", "queue:a", "make test", "injected")
+            .unwrap();
+        db.record("dev", "queue:b", "rm -rf /", "rejected: blocked")
+            .unwrap();
+
+        let now = chrono::Utc::now();
+        let finished = |command: &str, exit_status| FinishedCommand {
+            command: command.to_string(),
+            exit_status: Some(exit_status),
+            started_at: now,
+            finished_at: now,
+        };
+        db.record_finished("dev", &finished("make test", 2), false)
+            .unwrap();
+        db.record_finished("dev", &finished("ls", 0), true).unwrap();
+        db.record_finished("dev", &finished("pwd", 0), false)
+            .unwrap();
+
+        let all = db
+            .query(&HistoryQuery {
+                limit: 10,
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[0].exit_status, Some(2));
+        assert_eq!(all[0].duration(), Some(std::time::Duration::ZERO));
+        assert_eq!(all[1].duration(), None);
+        assert_eq!(all[2].source, "interactive");
+
+        let failed = db
+            .query(&HistoryQuery {
+                failed: true,
+                limit: 10,
+                ..Default::default()
+            })
+            .unwrap();
+        let commands: Vec<&str> = failed.iter().map(|e| e.command.as_str()).collect();
+        assert_eq!(commands, ["make test", "rm -rf /"]);
+
+        let grep = db
+            .query(&HistoryQuery {
+                grep: Some("make".to_string()),
+                limit: 10,
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(grep.len(), 1);
+    }
+}
@@ -0,0 +1,146 @@
+use crate::shell::osc::FinishedCommand;
+use anyhow::{Context, Result};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+/// How long a hook may run before the command is rejected
+const HOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What a command hook decided about a queued command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HookOutcome {
+    /// Inject this command (possibly rewritten by the hook)
+    Run(String),
+    /// Drop the command, with the reason reported by the hook
+    Rejected(String),
+}
+
+/// Pass a queued command through the session's command hook, if it has one: an external
+/// program consulted before every queued command is injected.
+///
+/// **Protocol:**
+/// - The hook is run with `sh -c <hook>` and receives the command on stdin
+/// - Exit status 0: stdout is the command to inject (empty stdout keeps the original)
+/// - Any other exit status: the command is rejected and stderr is logged as the reason
+/// - A hook that runs longer than 10 seconds rejects the command
+pub async fn transform_command(hook: Option<&str>, command: &str) -> Result<HookOutcome> {
+    match hook {
+        Some(hook) => run_command_hook(hook, command).await,
+        None => Ok(HookOutcome::Run(command.to_string())),
+    }
+}
+
+async fn run_command_hook(hook: &str, command: &str) -> Result<HookOutcome> {
+    let mut child = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(hook)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .context("Failed to start command hook")?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // A hook that ignores its input may exit before reading it
+        let _ = stdin.write_all(command.as_bytes()).await;
+    }
+
+    let output = match tokio::time::timeout(HOOK_TIMEOUT, child.wait_with_output()).await {
+        Ok(output) => output.context("Failed to run command hook")?,
+        Err(_) => {
+            return Ok(HookOutcome::Rejected(format!(
+                "hook timed out after {}s",
+                HOOK_TIMEOUT.as_secs()
+            )))
+        }
+    };
+
+    if output.status.success() {
+        let rewritten = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if rewritten.is_empty() {
+            Ok(HookOutcome::Run(command.to_string()))
+        } else {
+            Ok(HookOutcome::Run(rewritten))
+        }
+    } else {
+        let reason = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        Ok(HookOutcome::Rejected(if reason.is_empty() {
+            format!("hook exited with {}", output.status)
+        } else {
+            reason
+        }))
+    }
+}
+
+/// Run the post-command hook `hook`, an external program run after each foreground command
+/// the shell finishes.
+///
+/// Commands are discovered from OSC 133 prompt marks (see `osc::CommandTracker`), so the shell
+/// needs the typeypipe shell integration. The hook runs with `sh -c <hook>`, receives the
+/// `FinishedCommand` as JSON on stdin and `TP_COMMAND`, `TP_EXIT_STATUS` and `TP_DURATION_MS`
+/// in its environment. Its output is ignored and it is killed after 10 seconds.
+pub async fn run_post_command_hook(hook: String, finished: FinishedCommand) {
+    if let Err(e) = run_post_command_hook_program(&hook, &finished).await {
+        tracing::warn!(error = %e, "post-command hook failed");
+    }
+}
+
+async fn run_post_command_hook_program(hook: &str, finished: &FinishedCommand) -> Result<()> {
+    let duration_ms = (finished.finished_at - finished.started_at).num_milliseconds();
+    let mut child = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(hook)
+        .env("TP_COMMAND", &finished.command)
+        .env(
+            "TP_EXIT_STATUS",
+            finished
+                .exit_status
+                .map(|status| status.to_string())
+                .unwrap_or_default(),
+        )
+        .env("TP_DURATION_MS", duration_ms.to_string())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .context("Failed to start post-command hook")?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        let payload = serde_json::to_vec(finished).context("Failed to serialize command")?;
+        let _ = stdin.write_all(&payload).await;
+    }
+
+    tokio::time::timeout(HOOK_TIMEOUT, child.wait())
+        .await
+        .map_err(|_| anyhow::anyhow!("timed out after {}s", HOOK_TIMEOUT.as_secs()))?
+        .context("Failed to wait for post-command hook")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run_command_hook, HookOutcome};
+
+    #[tokio::test]
+    async fn test_command_hook_rewrites_and_rejects() {
+        assert_eq!(
+            run_command_hook("sed 's/^rm /echo rm /'", "rm -rf build")
+                .await
+                .unwrap(),
+            HookOutcome::Run("echo rm -rf build".to_string())
+        );
+        assert_eq!(
+            run_command_hook("cat > /dev/null", "ls").await.unwrap(),
+            HookOutcome::Run("ls".to_string())
+        );
+        assert_eq!(
+            run_command_hook("echo 'not allowed' >&2; exit 1", "reboot")
+                .await
+                .unwrap(),
+            HookOutcome::Rejected("not allowed".to_string())
+        );
+    }
+}
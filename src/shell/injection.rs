@@ -0,0 +1,576 @@
+use crate::shell::auth::{Decision, Source};
+use crate::shell::context::{QueueEvent, SessionContext};
+use crate::shell::hooks::HookOutcome;
+use crate::shell::notify::notify;
+use crate::shell::queue::{log_to_file, report_failure, route_to_session};
+use crate::shell::types::{FailureCode, QueueFailure, QueueMessage};
+use std::path::Path;
+
+/// Passes that may fail to write a queue file to the shell before it is dropped and reported
+/// with `FailureCode::WriteFailed`
+pub(crate) const WRITE_PASSES: usize = 3;
+
+/// A queue message that passed every check, ready to be written to the shell
+pub(crate) struct Injection {
+    /// `queue:<file>`, as the audit log and history record it
+    pub source: String,
+    /// How the message reads in logs, the audit log and history
+    pub command: String,
+    pub bytes: Vec<u8>,
+}
+
+/// Take the contents of queue file `path` through the steps every session applies before
+/// writing to its shell, interactive or headless.
+///
+/// **Steps:**
+/// - Decode the message and the session it is addressed to
+/// - Ask the session's `Authorizer`
+/// - Route messages addressed to another session to its queue
+/// - Pass commands through the command hook and add the session's terminator unless they
+///   chose their own
+///
+/// Returns the bytes to write, after which the caller reports back with `injected` or
+/// `write_failed`. `None` means the file was routed or dropped: either way it is recorded,
+/// logged and removed, and dropped files are reported to their producer, since trying again
+/// would fail the same way.
+pub(crate) async fn prepare_injection(
+    context: &SessionContext,
+    path: &Path,
+    contents: &str,
+    log_file: &Path,
+) -> Option<Injection> {
+    let filename = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let source = format!("queue:{}", filename);
+
+    let (target, message) = match QueueMessage::decode_addressed(contents) {
+        Ok(decoded) => decoded,
+        Err(e) => {
+            tracing::warn!(error = %e, "invalid queue message");
+            record_failure(
+                context,
+                path,
+                &source,
+                contents.trim(),
+                FailureCode::InvalidMessage,
+                &format!("{:#}", e),
+            )
+            .await;
+            let _ = log_to_file(
+                log_file,
+                &format!("❌ Invalid queue message in: {}\nError: {:#}", filename, e),
+            )
+            .await;
+            let _ = tokio::fs::remove_file(path).await;
+            return None;
+        }
+    };
+
+    let queue_source = Source::Queue {
+        file: filename.clone(),
+    };
+    if let Decision::Deny(reason) = context.authorize(&message, &queue_source) {
+        reject(
+            context,
+            path,
+            &source,
+            &message.describe(),
+            &reason,
+            FailureCode::RejectedByAuthorizer,
+            log_file,
+        )
+        .await;
+        return None;
+    }
+
+    // Messages addressed to another session are passed on without touching this shell
+    let queue_dir = path.parent().unwrap_or(path);
+    let session = queue_dir
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    if let Some(target) = target.filter(|target| *target != session) {
+        let tp_dir = queue_dir.parent().unwrap_or(queue_dir);
+        let description = message.describe();
+        match route_to_session(tp_dir, &target, &session, &message).await {
+            Ok(routed) => {
+                tracing::info!(target_session = %target, "routed");
+                record_outcome(
+                    context,
+                    &source,
+                    &description,
+                    &format!("routed: {}", target),
+                    None,
+                );
+                let _ = log_to_file(
+                    log_file,
+                    &format!(
+                        "➡️ Routed {} to session {} as {}",
+                        filename,
+                        target,
+                        routed.display()
+                    ),
+                )
+                .await;
+            }
+            Err(e) => {
+                tracing::warn!(error = %e, "unroutable queue message");
+                record_failure(
+                    context,
+                    path,
+                    &source,
+                    &description,
+                    FailureCode::Unroutable,
+                    &format!("{:#}", e),
+                )
+                .await;
+                let _ = log_to_file(
+                    log_file,
+                    &format!("❌ Could not route: {}\nError: {:#}", filename, e),
+                )
+                .await;
+            }
+        }
+        let _ = tokio::fs::remove_file(path).await;
+        return None;
+    }
+
+    // Commands go through the command hook and are submitted; input, keys and bytes are
+    // written as given
+    let (command, bytes) = match message {
+        QueueMessage::Command {
+            command,
+            terminator: chosen,
+        } => match context.transform_command(&command).await {
+            Ok(HookOutcome::Run(transformed)) => {
+                let terminator = chosen.unwrap_or(context.terminator());
+                let bytes = format!("{}{}", transformed, terminator.as_str()).into_bytes();
+                (transformed, bytes)
+            }
+            Ok(HookOutcome::Rejected(reason)) => {
+                reject(
+                    context,
+                    path,
+                    &source,
+                    &command,
+                    &reason,
+                    FailureCode::RejectedByHook,
+                    log_file,
+                )
+                .await;
+                return None;
+            }
+            Err(e) => {
+                let reason = format!("command hook failed: {:#}", e);
+                reject(
+                    context,
+                    path,
+                    &source,
+                    &command,
+                    &reason,
+                    FailureCode::HookFailed,
+                    log_file,
+                )
+                .await;
+                return None;
+            }
+        },
+        other => (
+            other.describe(),
+            other.input_bytes().ok().flatten().unwrap_or_default(),
+        ),
+    };
+
+    Some(Injection {
+        source,
+        command,
+        bytes,
+    })
+}
+
+/// Record an injection the shell accepted and remove its queue file
+pub(crate) async fn injected(context: &SessionContext, path: &Path, injection: &Injection) {
+    let _ = tokio::fs::remove_file(path).await;
+    context.clear_write_failures(&injection.source);
+    record_outcome(
+        context,
+        &injection.source,
+        &injection.command,
+        "injected",
+        None,
+    );
+    tracing::info!("completed");
+}
+
+/// Handle an injection the shell did not accept. Unlike the failures `prepare_injection`
+/// drops, the next attempt may succeed, so the queue file is kept and tried again on the next
+/// pass. Once `WRITE_PASSES` passes have failed it is dropped like them: recorded, reported
+/// to its producer with `FailureCode::WriteFailed` and removed.
+pub(crate) async fn write_failed(
+    context: &SessionContext,
+    path: &Path,
+    injection: &Injection,
+    error: &str,
+    log_file: &Path,
+) {
+    let filename = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let failures = context.count_write_failure(&injection.source);
+    if failures < WRITE_PASSES {
+        let _ = log_to_file(
+            log_file,
+            &format!(
+                "❌ Failed to write {} to the shell, keeping it queued (attempt {} of {})\nError: {}\nCommand was:\n{}",
+                filename, failures, WRITE_PASSES, error, injection.command
+            ),
+        )
+        .await;
+        return;
+    }
+
+    tracing::warn!(error, "dropped after failed writes");
+    context.clear_write_failures(&injection.source);
+    record_failure(
+        context,
+        path,
+        &injection.source,
+        &injection.command,
+        FailureCode::WriteFailed,
+        &format!("{} (after {} attempts)", error, WRITE_PASSES),
+    )
+    .await;
+    let _ = log_to_file(
+        log_file,
+        &format!(
+            "❌ Failed to write {} to the shell {} times, dropping it\nError: {}\nCommand was:\n{}",
+            filename, WRITE_PASSES, error, injection.command
+        ),
+    )
+    .await;
+    let _ = tokio::fs::remove_file(path).await;
+}
+
+/// Record what happened to a queued command in the audit log, the history database and the
+/// recent commands reported by `stats`
+pub(crate) fn record_outcome(
+    context: &SessionContext,
+    source: &str,
+    command: &str,
+    result: &str,
+    failure: Option<QueueFailure>,
+) {
+    context.audit(source, command, result);
+    context.record_history(source, command, result);
+    context.emit_queue_event(QueueEvent::Outcome {
+        source: source.to_string(),
+        result: result.to_string(),
+        failure,
+    });
+    if ["invalid", "rejected", "failed"]
+        .iter()
+        .any(|failure| result.starts_with(failure))
+    {
+        notify("command not injected", &format!("{} ({})", command, result));
+    }
+    context.remember_command(source, command, result);
+}
+
+/// Record a queue file dropped without reaching the shell, and report why to its producer
+/// before the file is removed
+async fn record_failure(
+    context: &SessionContext,
+    path: &Path,
+    source: &str,
+    command: &str,
+    code: FailureCode,
+    error: &str,
+) {
+    let failure = QueueFailure {
+        file: path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        code,
+        error: error.to_string(),
+        timestamp: chrono::Utc::now(),
+    };
+    if let Err(e) = report_failure(path, &failure).await {
+        tracing::warn!(error = %e, "failed to report queue failure");
+    }
+    let outcome = match code {
+        FailureCode::InvalidMessage | FailureCode::Unroutable => "invalid",
+        FailureCode::RejectedByAuthorizer
+        | FailureCode::RejectedByHook
+        | FailureCode::HookFailed => "rejected",
+        FailureCode::WriteFailed => "failed",
+    };
+    record_outcome(
+        context,
+        source,
+        command,
+        &format!("{}: {}", outcome, error),
+        Some(failure),
+    );
+}
+
+/// Drop a queue file whose message the authorizer or the command hook refused
+async fn reject(
+    context: &SessionContext,
+    path: &Path,
+    source: &str,
+    command: &str,
+    reason: &str,
+    code: FailureCode,
+    log_file: &Path,
+) {
+    let filename = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let rejected_by = match code {
+        FailureCode::RejectedByAuthorizer => "authorizer",
+        _ => "command hook",
+    };
+    tracing::warn!(reason, rejected_by, "rejected");
+    record_failure(context, path, source, command, code, reason).await;
+    let _ = log_to_file(
+        log_file,
+        &format!(
+            "🚫 Rejected by {}: {}\nReason: {}\nCommand was:\n{}",
+            rejected_by, filename, reason, command
+        ),
+    )
+    .await;
+    let _ = tokio::fs::remove_file(path).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{prepare_injection, write_failed, Injection, WRITE_PASSES};
+    use crate::shell::auth::{Decision, Source};
+    use crate::shell::context::SessionContext;
+    use crate::shell::queue::wait_for_injection;
+    use crate::shell::status::{status_file_path, SessionStatus};
+    use crate::shell::types::{FailureCode, QueueFailure, QueueMessage, Terminator};
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    /// Put `contents` in the queue of session `build` and take it through `prepare_injection`
+    async fn prepare(
+        context: &SessionContext,
+        temp_dir: &TempDir,
+        contents: &str,
+    ) -> (PathBuf, Option<Injection>) {
+        let queue_dir = temp_dir.path().join("build");
+        std::fs::create_dir_all(&queue_dir).unwrap();
+        let path = queue_dir.join("cmd");
+        std::fs::write(&path, contents).unwrap();
+        let log_file = temp_dir.path().join("build.log");
+        let injection = prepare_injection(context, &path, contents, &log_file).await;
+        (path, injection)
+    }
+
+    /// The failure reported for a queue file `prepare_injection` dropped
+    async fn dropped(path: &Path) -> QueueFailure {
+        assert!(!path.exists());
+        wait_for_injection(path, Some(Duration::from_secs(1)))
+            .await
+            .unwrap_err()
+            .downcast::<QueueFailure>()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_messages_are_decoded_and_terminated() {
+        let temp_dir = TempDir::new().unwrap();
+        let context = SessionContext::new(Terminator::Cr);
+
+        let (_, injection) = prepare(&context, &temp_dir, "make test\n").await;
+        let injection = injection.unwrap();
+        assert_eq!(injection.source, "queue:cmd");
+        assert_eq!(injection.command, "make test");
+        assert_eq!(injection.bytes, b"make test\r");
+
+        let chosen = QueueMessage::Command {
+            command: "1 + 1".to_string(),
+            terminator: Some(Terminator::Lf),
+        };
+        let (_, injection) = prepare(&context, &temp_dir, &chosen.encode().unwrap()).await;
+        assert_eq!(injection.unwrap().bytes, b"1 + 1\n");
+
+        // Input is written as given, without the session's terminator
+        let input = QueueMessage::Input {
+            data: "y".to_string(),
+        };
+        let (_, injection) = prepare(&context, &temp_dir, &input.encode().unwrap()).await;
+        assert_eq!(injection.unwrap().bytes, b"y");
+
+        let keys = QueueMessage::Keys {
+            keys: vec!["C-c".to_string()],
+        };
+        let (_, injection) = prepare(&context, &temp_dir, &keys.encode().unwrap()).await;
+        assert_eq!(injection.unwrap().bytes, b"\x03");
+
+        let (path, injection) = prepare(
+            &context,
+            &temp_dir,
+            r#"{"version":99,"type":"command","command":"make"}"#,
+        )
+        .await;
+        assert!(injection.is_none());
+        assert_eq!(dropped(&path).await.code, FailureCode::InvalidMessage);
+    }
+
+    #[tokio::test]
+    async fn test_authorizer_is_asked_before_the_command_hook() {
+        let temp_dir = TempDir::new().unwrap();
+        let marker = temp_dir.path().join("hook-ran");
+        let context = SessionContext::new(Terminator::Cr)
+            .authorizer(Some(Arc::new(|message: &QueueMessage, source: &Source| {
+                assert_eq!(
+                    source,
+                    &Source::Queue {
+                        file: "cmd".to_string()
+                    }
+                );
+                match message.describe().starts_with("rm ") {
+                    true => Decision::Deny("no deleting".to_string()),
+                    false => Decision::Allow,
+                }
+            })))
+            .hooks(Some(format!("touch {}", marker.display())), None);
+
+        let (path, injection) = prepare(&context, &temp_dir, "rm -rf build").await;
+        assert!(injection.is_none());
+        let failure = dropped(&path).await;
+        assert_eq!(failure.code, FailureCode::RejectedByAuthorizer);
+        assert_eq!(failure.error, "no deleting");
+        assert!(!marker.exists());
+
+        let (_, injection) = prepare(&context, &temp_dir, "make").await;
+        assert_eq!(injection.unwrap().bytes, b"make\r");
+        assert!(marker.exists());
+    }
+
+    #[tokio::test]
+    async fn test_messages_for_other_sessions_are_routed() {
+        let temp_dir = TempDir::new().unwrap();
+        let context = SessionContext::new(Terminator::Cr);
+        let message = QueueMessage::Command {
+            command: "make".to_string(),
+            terminator: None,
+        };
+
+        // Addressed to this session itself
+        let (_, injection) = prepare(
+            &context,
+            &temp_dir,
+            &message.encode_for(Some("build")).unwrap(),
+        )
+        .await;
+        assert_eq!(injection.unwrap().bytes, b"make\r");
+
+        let (path, injection) = prepare(
+            &context,
+            &temp_dir,
+            &message.encode_for(Some("deploy")).unwrap(),
+        )
+        .await;
+        assert!(injection.is_none());
+        assert_eq!(dropped(&path).await.code, FailureCode::Unroutable);
+
+        let deploy_queue = temp_dir.path().join("deploy");
+        std::fs::create_dir_all(&deploy_queue).unwrap();
+        SessionStatus {
+            session_id: "deploy".to_string(),
+            pid: std::process::id(),
+            child_pid: None,
+            queue_dir: Some(deploy_queue.clone()),
+            log_file: None,
+            started_at: chrono::Utc::now(),
+            last_queue_check: chrono::Utc::now(),
+            queue_paused: false,
+            tp_dir: None,
+            tp_dir_source: None,
+        }
+        .write(&status_file_path(temp_dir.path(), "deploy"))
+        .unwrap();
+
+        let (path, injection) = prepare(
+            &context,
+            &temp_dir,
+            &message.encode_for(Some("deploy")).unwrap(),
+        )
+        .await;
+        assert!(injection.is_none());
+        assert!(!path.exists());
+        let routed: Vec<_> = std::fs::read_dir(&deploy_queue)
+            .unwrap()
+            .map(|entry| std::fs::read_to_string(entry.unwrap().path()).unwrap())
+            .collect();
+        assert_eq!(routed, vec![message.encode().unwrap()]);
+    }
+
+    #[tokio::test]
+    async fn test_command_hook_rewrites_or_rejects_commands() {
+        let temp_dir = TempDir::new().unwrap();
+        let context = SessionContext::new(Terminator::Lf).hooks(
+            Some(r#"read -r command; case "$command" in make*) echo "$command -j4";; *) echo "only make" >&2; exit 1;; esac"#.to_string()),
+            None,
+        );
+
+        // The terminator is added after the hook, so the hook never sees or drops it
+        let (_, injection) = prepare(&context, &temp_dir, "make test").await;
+        let injection = injection.unwrap();
+        assert_eq!(injection.command, "make test -j4");
+        assert_eq!(injection.bytes, b"make test -j4\n");
+
+        let (path, injection) = prepare(&context, &temp_dir, "cargo build").await;
+        assert!(injection.is_none());
+        let failure = dropped(&path).await;
+        assert_eq!(failure.code, FailureCode::RejectedByHook);
+        assert_eq!(failure.error, "only make");
+
+        // Only commands pass through the hook
+        let input = QueueMessage::Input {
+            data: "q".to_string(),
+        };
+        let (_, injection) = prepare(&context, &temp_dir, &input.encode().unwrap()).await;
+        assert_eq!(injection.unwrap().bytes, b"q");
+    }
+
+    #[tokio::test]
+    async fn test_unwritable_file_is_dropped_after_a_few_passes() {
+        let temp_dir = TempDir::new().unwrap();
+        let queue_dir = temp_dir.path().join("build");
+        let log_file = temp_dir.path().join("build.log");
+        std::fs::create_dir_all(&queue_dir).unwrap();
+        let path = queue_dir.join("cmd");
+        std::fs::write(&path, "make").unwrap();
+        let context = SessionContext::new(Terminator::Cr);
+
+        let injection = prepare_injection(&context, &path, "make", &log_file)
+            .await
+            .unwrap();
+        for _ in 1..WRITE_PASSES {
+            write_failed(&context, &path, &injection, "would block", &log_file).await;
+            assert!(path.exists());
+        }
+        write_failed(&context, &path, &injection, "would block", &log_file).await;
+
+        assert!(!path.exists());
+        let failure = wait_for_injection(&path, Some(Duration::from_secs(1)))
+            .await
+            .unwrap_err()
+            .downcast::<QueueFailure>()
+            .unwrap();
+        assert_eq!(failure.code, FailureCode::WriteFailed);
+        assert_eq!(failure.file, "cmd");
+    }
+}
@@ -0,0 +1,129 @@
+use crate::shell::encryption::seal;
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// One write to the shell's PTY in the input log.
+///
+/// The input log records every byte sent to the shell, whoever sent it, with enough timing to
+/// replay a session exactly. Keystrokes can include passwords typed at prompts, so the log is
+/// opt-in, kept apart from the transcript and readable only by its owner.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputEvent {
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    /// Milliseconds since the log was opened
+    pub elapsed_ms: u64,
+    /// `keyboard`, `paste`, `stdin`, `control` or `queue:<file>`
+    pub source: String,
+    /// The bytes written, when they are valid UTF-8
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+    /// The bytes written, base64-encoded, when they are not
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_base64: Option<String>,
+}
+
+/// The input log of one session, fed by every path that writes to its PTY
+pub struct InputLog {
+    file: Mutex<File>,
+    opened_at: Instant,
+}
+
+impl InputLog {
+    /// Start recording input to a new file at `path`, readable and writable only by its owner
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut options = std::fs::OpenOptions::new();
+        options.create(true).write(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let file = options
+            .open(path)
+            .with_context(|| format!("Failed to open input log {}", path.display()))?;
+        // An existing file keeps its mode when opened, so tighten it explicitly
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(std::fs::Permissions::from_mode(0o600))
+                .with_context(|| format!("Failed to restrict access to {}", path.display()))?;
+        }
+
+        Ok(Self {
+            file: Mutex::new(file),
+            opened_at: Instant::now(),
+        })
+    }
+
+    /// Append `bytes`, just written to the shell by `source`
+    pub fn record(&self, source: &str, bytes: &[u8]) {
+        let Ok(mut file) = self.file.lock() else {
+            return;
+        };
+
+        let (data, data_base64) = match std::str::from_utf8(bytes) {
+            Ok(text) => (Some(text.to_string()), None),
+            Err(_) => (None, Some(BASE64.encode(bytes))),
+        };
+        let event = InputEvent {
+            timestamp: chrono::Utc::now(),
+            elapsed_ms: self.opened_at.elapsed().as_millis() as u64,
+            source: source.to_string(),
+            data,
+            data_base64,
+        };
+        if let Err(e) = write_event(&mut file, &event) {
+            tracing::error!(error = %e, "failed to write input event");
+        }
+    }
+}
+
+fn write_event(file: &mut File, event: &InputEvent) -> Result<()> {
+    let mut line = serde_json::to_vec(event).context("Failed to serialize input event")?;
+    line.push(b'\n');
+    let line = seal(&line)?;
+    file.write_all(&line).context("Failed to write input event")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InputEvent, InputLog, BASE64};
+    use base64::Engine;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_input_is_logged_privately_with_timing() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("session.input");
+
+        let input_log = InputLog::open(&path).unwrap();
+        input_log.record("keyboard", b"l");
+        input_log.record("control", b"\xff\x03");
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let events: Vec<InputEvent> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let [typed, control] = &events[..] else {
+            panic!("expected two events, got {:?}", events);
+        };
+        assert_eq!(typed.data.as_deref(), Some("l"));
+        assert_eq!(control.data, None);
+        let control_bytes = BASE64
+            .decode(control.data_base64.as_deref().unwrap())
+            .unwrap();
+        assert_eq!(control_bytes, b"\xff\x03");
+        assert!(typed.elapsed_ms <= control.elapsed_ms);
+    }
+}
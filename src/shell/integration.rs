@@ -0,0 +1,183 @@
+use anyhow::{Context, Result};
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// First line of every snippet, used to avoid appending the same integration twice
+const MARKER: &str = "# typeypipe shell integration";
+
+/// Shells that `typeypipe setup --shell-integration` has snippets for.
+///
+/// **Each snippet adds:**
+/// - OSC 133 prompt marks (prompt start, command start, command finished with exit status)
+/// - OSC 7 working directory reports after every command
+/// - A `tps <command...>` function that enqueues a command into the session the shell runs in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntegrationShell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl FromStr for IntegrationShell {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "bash" => Ok(IntegrationShell::Bash),
+            "zsh" => Ok(IntegrationShell::Zsh),
+            "fish" => Ok(IntegrationShell::Fish),
+            _ => Err(anyhow::anyhow!(
+                "Unsupported shell '{}': expected bash, zsh or fish",
+                value
+            )),
+        }
+    }
+}
+
+impl IntegrationShell {
+    /// The rc snippet for this shell
+    pub fn snippet(self) -> &'static str {
+        match self {
+            IntegrationShell::Bash => BASH_SNIPPET,
+            IntegrationShell::Zsh => ZSH_SNIPPET,
+            IntegrationShell::Fish => FISH_SNIPPET,
+        }
+    }
+
+    /// The rc file the snippet is appended to
+    pub fn rc_file(self) -> Result<PathBuf> {
+        let home = std::env::var_os("HOME")
+            .map(PathBuf::from)
+            .ok_or_else(|| anyhow::anyhow!("HOME is not set"))?;
+
+        Ok(match self {
+            IntegrationShell::Bash => home.join(".bashrc"),
+            IntegrationShell::Zsh => std::env::var_os("ZDOTDIR")
+                .map(PathBuf::from)
+                .unwrap_or(home)
+                .join(".zshrc"),
+            IntegrationShell::Fish => std::env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| home.join(".config"))
+                .join("fish")
+                .join("config.fish"),
+        })
+    }
+
+    /// Append the snippet to the shell's rc file unless it is already there.
+    /// Returns the rc file and whether it was changed.
+    pub fn install(self) -> Result<(PathBuf, bool)> {
+        let rc_file = self.rc_file()?;
+        let existing = std::fs::read_to_string(&rc_file).unwrap_or_default();
+        if existing.contains(MARKER) {
+            return Ok((rc_file, false));
+        }
+
+        if let Some(parent) = rc_file.parent() {
+            std::fs::create_dir_all(parent).context("Failed to create rc file directory")?;
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&rc_file)
+            .with_context(|| format!("Failed to open {}", rc_file.display()))?;
+
+        let separator = if existing.is_empty() || existing.ends_with('\n') {
+            "\n"
+        } else {
+            "\n\n"
+        };
+        write!(file, "{}{}", separator, self.snippet())
+            .with_context(|| format!("Failed to write {}", rc_file.display()))?;
+
+        Ok((rc_file, true))
+    }
+}
+
+const BASH_SNIPPET: &str = r#"# typeypipe shell integration
+__tp_prompt_command() {
+    local ret=$?
+    printf '\e]133;D;%s\a' "$ret"
+    printf '\e]7;file://%s%s\a' "$HOSTNAME" "$PWD"
+    return $ret
+}
+PROMPT_COMMAND="__tp_prompt_command${PROMPT_COMMAND:+;$PROMPT_COMMAND}"
+PS1="\[\e]133;A\a\]$PS1\[\e]133;B\a\]"
+PS0="${PS0}\e]133;C\a"
+
+tps() {
+    if [ -z "${TP_QUEUE_DIR:-}" ]; then
+        echo "tps: not running inside a typeypipe session" >&2
+        return 1
+    fi
+    local name="tps-$(date +%Y%m%d%H%M%S)-$$-$RANDOM"
+    local staging="$(dirname "$TP_QUEUE_DIR")/.$name.tmp"
+    printf '%s\n' "$*" > "$staging" && mv "$staging" "$TP_QUEUE_DIR/$name"
+}
+"#;
+
+const ZSH_SNIPPET: &str = r#"# typeypipe shell integration
+autoload -Uz add-zsh-hook
+__tp_precmd() {
+    local ret=$?
+    printf '\e]133;D;%s\a' "$ret"
+    printf '\e]7;file://%s%s\a' "$HOST" "$PWD"
+    printf '\e]133;A\a'
+}
+__tp_preexec() {
+    printf '\e]133;C\a'
+}
+add-zsh-hook precmd __tp_precmd
+add-zsh-hook preexec __tp_preexec
+PS1="$PS1%{$(printf '\e]133;B\a')%}"
+
+tps() {
+    if [[ -z "${TP_QUEUE_DIR:-}" ]]; then
+        echo "tps: not running inside a typeypipe session" >&2
+        return 1
+    fi
+    local name="tps-$(date +%Y%m%d%H%M%S)-$$-$RANDOM"
+    local staging="${TP_QUEUE_DIR:h}/.$name.tmp"
+    printf '%s\n' "$*" > "$staging" && mv "$staging" "$TP_QUEUE_DIR/$name"
+}
+"#;
+
+const FISH_SNIPPET: &str = r#"# typeypipe shell integration
+function __tp_prompt --on-event fish_prompt
+    printf '\e]7;file://%s%s\a' (hostname) $PWD
+    printf '\e]133;A\a'
+end
+function __tp_preexec --on-event fish_preexec
+    printf '\e]133;C\a'
+end
+function __tp_postexec --on-event fish_postexec
+    printf '\e]133;D;%s\a' $status
+end
+
+function tps
+    if not set -q TP_QUEUE_DIR
+        echo "tps: not running inside a typeypipe session" >&2
+        return 1
+    end
+    set -l name tps-(date +%Y%m%d%H%M%S)-$fish_pid-(random)
+    set -l staging (dirname $TP_QUEUE_DIR)/.$name.tmp
+    printf '%s\n' "$argv" > $staging; and mv $staging $TP_QUEUE_DIR/$name
+end
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::{IntegrationShell, MARKER};
+
+    #[test]
+    fn test_snippets_start_with_marker() {
+        for shell in ["bash", "zsh", "fish"] {
+            let shell: IntegrationShell = shell.parse().unwrap();
+            assert!(shell.snippet().starts_with(MARKER));
+            assert!(shell.snippet().contains("133;C"));
+            assert!(shell.snippet().contains("tps"));
+        }
+        assert!("tcsh".parse::<IntegrationShell>().is_err());
+    }
+}
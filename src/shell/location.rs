@@ -0,0 +1,126 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// Where the `.tp` directory was found, recorded in the session status
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TpDirSource {
+    /// `--tp-dir`
+    Flag,
+    /// `TP_DIR` environment variable
+    Env,
+    /// `.tp` at the root of the enclosing git repository
+    GitRoot,
+    /// `$XDG_STATE_HOME/typeypipe` (or `~/.local/state/typeypipe`)
+    StateDir,
+    /// `.tp` in the current directory, when nothing else applies
+    CurrentDir,
+}
+
+impl TpDirSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TpDirSource::Flag => "flag",
+            TpDirSource::Env => "env",
+            TpDirSource::GitRoot => "git-root",
+            TpDirSource::StateDir => "state-dir",
+            TpDirSource::CurrentDir => "current-dir",
+        }
+    }
+}
+
+/// The directory holding queues, logs, sockets and status files.
+///
+/// **Resolution order:**
+/// - `--tp-dir <DIR>`
+/// - `TP_DIR` (exported into every session, so commands run inside one find it)
+/// - `.tp` at the root of the git repository containing the current directory, so running
+///   from a subdirectory does not scatter `.tp` folders
+/// - `$XDG_STATE_HOME/typeypipe`, falling back to `~/.local/state/typeypipe`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TpDir {
+    pub path: PathBuf,
+    pub source: TpDirSource,
+}
+
+/// Locate the `.tp` directory for this process
+pub fn resolve_tp_dir(explicit: Option<&Path>) -> Result<TpDir> {
+    let cwd = std::env::current_dir().context("Failed to read current directory")?;
+    let state_home = std::env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/state")));
+
+    Ok(resolve(
+        explicit,
+        std::env::var_os("TP_DIR")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .as_deref(),
+        &cwd,
+        state_home.as_deref(),
+    ))
+}
+
+fn resolve(
+    explicit: Option<&Path>,
+    env: Option<&Path>,
+    cwd: &Path,
+    state_home: Option<&Path>,
+) -> TpDir {
+    let (path, source) = if let Some(dir) = explicit {
+        (dir.to_path_buf(), TpDirSource::Flag)
+    } else if let Some(dir) = env {
+        (dir.to_path_buf(), TpDirSource::Env)
+    } else if let Some(root) = cwd.ancestors().find(|dir| dir.join(".git").exists()) {
+        (root.join(".tp"), TpDirSource::GitRoot)
+    } else if let Some(state_home) = state_home {
+        (state_home.join("typeypipe"), TpDirSource::StateDir)
+    } else {
+        (cwd.join(".tp"), TpDirSource::CurrentDir)
+    };
+
+    // Sessions hand this path to their shell, so make it independent of the current directory
+    let path = if path.is_relative() {
+        cwd.join(path)
+    } else {
+        path
+    };
+    TpDir { path, source }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{resolve, TpDirSource};
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_resolution_order() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = temp_dir.path().join("repo");
+        let subdir = repo.join("src/shell");
+        std::fs::create_dir_all(repo.join(".git")).unwrap();
+        std::fs::create_dir_all(&subdir).unwrap();
+        let state = Some(Path::new("/state"));
+
+        let flag = resolve(
+            Some(Path::new("tp")),
+            Some(Path::new("/env")),
+            &subdir,
+            state,
+        );
+        assert_eq!(flag.path, subdir.join("tp"));
+        assert_eq!(flag.source, TpDirSource::Flag);
+
+        let env = resolve(None, Some(Path::new("/env")), &subdir, state);
+        assert_eq!(env.path, Path::new("/env"));
+
+        let git = resolve(None, None, &subdir, state);
+        assert_eq!(git.path, repo.join(".tp"));
+        assert_eq!(git.source, TpDirSource::GitRoot);
+
+        let outside = resolve(None, None, temp_dir.path(), state);
+        assert_eq!(outside.path, Path::new("/state/typeypipe"));
+        assert_eq!(outside.source, TpDirSource::StateDir);
+    }
+}
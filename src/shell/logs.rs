@@ -0,0 +1,346 @@
+use crate::shell::ansi::strip_ansi;
+use crate::shell::schedule::parse_duration;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How often `--follow` checks the files for new entries
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Which of a session's files to show
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogSource {
+    /// The queue log (`<session>.log`)
+    Log,
+    /// The output transcript (`<session>.transcript`)
+    Transcript,
+    All,
+}
+
+impl std::str::FromStr for LogSource {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "log" => Ok(Self::Log),
+            "transcript" => Ok(Self::Transcript),
+            "all" => Ok(Self::All),
+            other => Err(anyhow!(
+                "Unknown log source '{}' (expected log, transcript or all)",
+                other
+            )),
+        }
+    }
+}
+
+/// What `typeypipe logs` prints
+#[derive(Debug, Clone)]
+pub struct LogsOptions {
+    pub sessions: Vec<String>,
+    pub source: LogSource,
+    /// Only entries at or after this time
+    pub since: Option<DateTime<Utc>>,
+    /// Only the last this many entries of the existing files
+    pub lines: Option<usize>,
+    /// Keep colors; other escape sequences are removed either way
+    pub ansi: bool,
+    pub follow: bool,
+}
+
+/// One timestamped entry; queue log messages can span several lines
+#[derive(Debug, Clone, PartialEq)]
+struct Entry {
+    time: Option<DateTime<Utc>>,
+    text: String,
+}
+
+/// A log or transcript file being read, remembering how far it has been read for `--follow`
+struct LogFile {
+    path: PathBuf,
+    /// Shown before each entry when more than one file is shown
+    prefix: String,
+    offset: u64,
+    /// A line still waiting for its newline
+    partial: String,
+    /// Whether the hint about lines without a timestamp has been printed
+    warned_encrypted: bool,
+}
+
+impl LogFile {
+    /// Entries added since the last read. A file shorter than what was already read has been
+    /// rotated or recreated by a new session, so it is read again from the start.
+    fn read_new(&mut self) -> Result<Vec<Entry>> {
+        let mut file = match std::fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to open {}", self.path.display()))
+            }
+        };
+        let len = file.metadata()?.len();
+        if len < self.offset {
+            self.offset = 0;
+            self.partial.clear();
+        }
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)
+            .with_context(|| format!("Failed to read {}", self.path.display()))?;
+        self.offset += bytes.len() as u64;
+
+        self.partial.push_str(&String::from_utf8_lossy(&bytes));
+        let complete = match self.partial.rfind('\n') {
+            Some(newline) => {
+                let rest = self.partial.split_off(newline + 1);
+                std::mem::replace(&mut self.partial, rest)
+            }
+            None => return Ok(Vec::new()),
+        };
+
+        let entries = parse_entries(&complete);
+        if !self.warned_encrypted && entries.iter().any(|entry| entry.time.is_none()) {
+            self.warned_encrypted = true;
+            eprintln!(
+                "{} has lines without a timestamp; if it was written with --encrypt-to, read it with `typeypipe decrypt`",
+                self.path.display()
+            );
+        }
+        Ok(entries)
+    }
+}
+
+/// Split file contents into entries: a line starting with a `[... UTC]` timestamp begins an
+/// entry and the lines after it, up to the next timestamp, continue it
+fn parse_entries(text: &str) -> Vec<Entry> {
+    let mut entries: Vec<Entry> = Vec::new();
+    for line in text.lines() {
+        match split_timestamp(line) {
+            Some((time, rest)) => entries.push(Entry {
+                time: Some(time),
+                text: rest.to_string(),
+            }),
+            None => match entries.last_mut() {
+                Some(entry) if entry.time.is_some() => {
+                    entry.text.push('\n');
+                    entry.text.push_str(line);
+                }
+                _ => entries.push(Entry {
+                    time: None,
+                    text: line.to_string(),
+                }),
+            },
+        }
+    }
+    entries
+}
+
+/// The timestamp at the start of a log line (`[2025-03-01 10:07:12 UTC] `) or transcript line
+/// (`[2025-03-01 10:07:12.345 UTC] `) and the rest of the line
+pub(crate) fn split_timestamp(line: &str) -> Option<(DateTime<Utc>, &str)> {
+    let inner = line.strip_prefix('[')?;
+    let (stamp, rest) = inner.split_once("] ")?;
+    let stamp = stamp.strip_suffix(" UTC")?;
+    let time = NaiveDateTime::parse_from_str(stamp, "%Y-%m-%d %H:%M:%S%.f").ok()?;
+    Some((Utc.from_utc_datetime(&time), rest))
+}
+
+/// `--since` as a time: a duration back from `now` (`90s`, `10m`, `2h`) or a local date and
+/// time (`2025-03-01 10:00`, `2025-03-01T10:00:00`, `2025-03-01`)
+pub fn parse_since(value: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>> {
+    if let Some(duration) = parse_duration(value) {
+        return Ok(now - chrono::Duration::from_std(duration)?);
+    }
+    let value = value.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    let formats = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M:%S"];
+    let naive = formats
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .or_else(|| {
+            chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+        .ok_or_else(|| {
+            anyhow!(
+                "Invalid --since '{}' (use a duration like 10m or a time like 2025-03-01 10:00)",
+                value
+            )
+        })?;
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .map(|time| time.with_timezone(&Utc))
+        .ok_or_else(|| anyhow!("Invalid --since '{}': no such local time", value))
+}
+
+/// Text safe to print: a redrawn line keeps only what was drawn last, and escape sequences are
+/// removed, except colors when `keep_colors` is set (reset at the end so they cannot leak)
+fn render(text: &str, keep_colors: bool) -> String {
+    text.split('\n')
+        .map(|line| {
+            let line = line.trim_end_matches('\r');
+            let line = line.rsplit('\r').next().unwrap_or_default();
+            if keep_colors {
+                keep_sgr(line)
+            } else {
+                strip_ansi(line)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// `strip_ansi`, except that SGR sequences (`ESC [ ... m`) are kept
+fn keep_sgr(line: &str) -> String {
+    let mut output = String::with_capacity(line.len());
+    let mut colored = false;
+    let mut rest = line;
+    while let Some(start) = rest.find("\x1b[") {
+        output.push_str(&strip_ansi(&rest[..start]));
+        let params = &rest[start + 2..];
+        match params.find(|c: char| ('\x40'..='\x7e').contains(&c)) {
+            Some(end) if params[end..].starts_with('m') => {
+                output.push_str(&rest[start..start + 2 + end + 1]);
+                colored = true;
+                rest = &params[end + 1..];
+            }
+            Some(end) => rest = &params[end + 1..],
+            None => rest = "",
+        }
+    }
+    output.push_str(&strip_ansi(rest));
+    if colored {
+        output.push_str("\x1b[0m");
+    }
+    output
+}
+
+fn print_entry(file: &LogFile, entry: &Entry, keep_colors: bool) {
+    let time = entry
+        .time
+        .map(|time| format!("{} ", time.format("%Y-%m-%d %H:%M:%S")))
+        .unwrap_or_default();
+    let mut lines = render(&entry.text, keep_colors);
+    if !file.prefix.is_empty() || !time.is_empty() {
+        let indent = " ".repeat(file.prefix.chars().count() + time.chars().count());
+        lines = lines.replace('\n', &format!("\n{}", indent));
+    }
+    println!("{}{}{}", file.prefix, time, lines);
+}
+
+/// Show sessions' queue logs and transcripts without raw escape sequences. Existing entries
+/// are merged in time order; with `follow`, new entries are printed as they are written until
+/// interrupted.
+pub async fn run_logs(tp_dir: &Path, options: &LogsOptions) -> Result<()> {
+    let mut kinds = Vec::new();
+    if options.source != LogSource::Transcript {
+        kinds.push(("log", "log"));
+    }
+    if options.source != LogSource::Log {
+        kinds.push(("out", "transcript"));
+    }
+
+    let mut files = Vec::new();
+    for session in &options.sessions {
+        for (tag, extension) in &kinds {
+            let mut prefix = String::new();
+            if options.sessions.len() > 1 {
+                prefix.push_str(&format!("{} ", session));
+            }
+            if kinds.len() > 1 {
+                prefix.push_str(&format!("{} ", tag));
+            }
+            files.push(LogFile {
+                path: tp_dir.join(format!("{}.{}", session, extension)),
+                prefix,
+                offset: 0,
+                partial: String::new(),
+                warned_encrypted: false,
+            });
+        }
+    }
+    if !options.follow && !files.iter().any(|file| file.path.exists()) {
+        return Err(anyhow!(
+            "No log or transcript found for {} in {}",
+            options.sessions.join(", "),
+            tp_dir.display()
+        ));
+    }
+
+    let wanted = |entry: &Entry| match (options.since, entry.time) {
+        (Some(since), Some(time)) => time >= since,
+        (Some(_), None) => false,
+        (None, _) => true,
+    };
+
+    // Stable sort keeps each file's order for entries with the same time
+    let mut backlog = Vec::new();
+    for (index, file) in files.iter_mut().enumerate() {
+        for entry in file.read_new()? {
+            if wanted(&entry) {
+                backlog.push((index, entry));
+            }
+        }
+    }
+    backlog.sort_by_key(|(_, entry)| entry.time);
+    let skip = options
+        .lines
+        .map_or(0, |lines| backlog.len().saturating_sub(lines));
+    for (index, entry) in backlog.iter().skip(skip) {
+        print_entry(&files[*index], entry, options.ansi);
+    }
+
+    if !options.follow {
+        return Ok(());
+    }
+    loop {
+        tokio::time::sleep(FOLLOW_INTERVAL).await;
+        for file in files.iter_mut() {
+            for entry in file.read_new()? {
+                if wanted(&entry) {
+                    print_entry(file, &entry, options.ansi);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_entries, parse_since, render, Entry};
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn test_entries_are_parsed_filtered_and_rendered() {
+        let text = "[2025-03-01 10:07:12 UTC] 🔄 Processing: build.txt\nmake all\n\
+                    [2025-03-01 10:07:13.250 UTC] \x1b[1;32mok\x1b[0m\x1b[K\r\n";
+        let entries = parse_entries(text);
+        assert_eq!(
+            entries[0],
+            Entry {
+                time: Some(Utc.with_ymd_and_hms(2025, 3, 1, 10, 7, 12).unwrap()),
+                text: "🔄 Processing: build.txt\nmake all".to_string(),
+            }
+        );
+        assert_eq!(render(&entries[1].text, false), "ok");
+        assert_eq!(render(&entries[1].text, true), "\x1b[1;32mok\x1b[0m\x1b[0m");
+        assert_eq!(render("50%\r100%", false), "100%");
+        assert_eq!(parse_entries("YWdlLWVuY3J5cHRpb24=\n")[0].time, None);
+
+        let now = Utc.with_ymd_and_hms(2025, 3, 1, 10, 30, 0).unwrap();
+        assert_eq!(
+            parse_since("10m", now).unwrap(),
+            Utc.with_ymd_and_hms(2025, 3, 1, 10, 20, 0).unwrap()
+        );
+        assert_eq!(
+            parse_since("2025-03-01T09:00:00Z", now).unwrap(),
+            Utc.with_ymd_and_hms(2025, 3, 1, 9, 0, 0).unwrap()
+        );
+        assert!(parse_since("yesterday", now).is_err());
+    }
+}
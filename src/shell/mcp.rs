@@ -0,0 +1,348 @@
+use crate::shell::control::{ControlClient, ControlRequest};
+use crate::shell::queue::wait_for_injection;
+use crate::shell::rpc::error_response;
+use crate::shell::types::QueueMessage;
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+/// MCP protocol revision used when the client does not ask for a specific one
+const DEFAULT_PROTOCOL_VERSION: &str = "2024-11-05";
+
+/// How long `run_command` waits for the queue to pick up its command
+const INJECTION_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// The MCP (Model Context Protocol) server exposes a running session as tools that LLM agents
+/// can call directly instead of writing files into `.tp/`.
+///
+/// **Transport:**
+/// - Speaks newline-delimited JSON-RPC 2.0 on stdin/stdout, as MCP clients expect when they
+///   launch a server process (`typeypipe mcp <name>`)
+/// - Every tool call is forwarded to the session's control socket
+///
+/// **Tools:**
+/// - `run_command`: queue a command, wait for it to be injected, and return the screen afterwards
+/// - `send_keys`: write raw text (including control characters) straight to the shell
+/// - `read_screen`: the currently visible output, without ANSI escapes
+/// - `read_scrollback`: the last N lines of output, without ANSI escapes
+pub struct McpServer {
+    socket_path: PathBuf,
+}
+
+impl McpServer {
+    pub fn new(socket_path: PathBuf) -> Self {
+        Self { socket_path }
+    }
+
+    /// Serve requests from stdin until it is closed
+    pub async fn serve_stdio(&self) -> Result<()> {
+        let mut lines = BufReader::new(tokio::io::stdin()).lines();
+        let mut stdout = tokio::io::stdout();
+
+        while let Some(line) = lines.next_line().await.context("Failed to read stdin")? {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let response = match serde_json::from_str::<Value>(&line) {
+                Ok(message) => self.handle_message(message).await,
+                Err(e) => Some(error_response(Value::Null, -32700, &e.to_string())),
+            };
+
+            if let Some(response) = response {
+                let mut bytes = serde_json::to_vec(&response)?;
+                bytes.push(b'\n');
+                stdout.write_all(&bytes).await?;
+                stdout.flush().await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Handle one JSON-RPC message, returning the response for requests (notifications get none)
+    pub async fn handle_message(&self, message: Value) -> Option<Value> {
+        let id = message.get("id").cloned()?;
+        let method = message.get("method").and_then(Value::as_str).unwrap_or("");
+        let params = message.get("params").cloned().unwrap_or(Value::Null);
+
+        let result = match method {
+            "initialize" => Ok(json!({
+                "protocolVersion": params
+                    .get("protocolVersion")
+                    .and_then(Value::as_str)
+                    .unwrap_or(DEFAULT_PROTOCOL_VERSION),
+                "capabilities": { "tools": {} },
+                "serverInfo": { "name": "typeypipe", "version": env!("CARGO_PKG_VERSION") },
+            })),
+            "ping" => Ok(json!({})),
+            "tools/list" => Ok(json!({ "tools": tool_definitions() })),
+            "tools/call" => Ok(self.call_tool(&params).await),
+            _ => Err((-32601, format!("Method not found: {}", method))),
+        };
+
+        Some(match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => error_response(id, code, &message),
+        })
+    }
+
+    async fn call_tool(&self, params: &Value) -> Value {
+        let name = params.get("name").and_then(Value::as_str).unwrap_or("");
+        let arguments = params.get("arguments").cloned().unwrap_or(Value::Null);
+
+        match self.run_tool(name, &arguments).await {
+            Ok(text) => json!({ "content": [{ "type": "text", "text": text }], "isError": false }),
+            Err(e) => json!({
+                "content": [{ "type": "text", "text": format!("{:#}", e) }],
+                "isError": true,
+            }),
+        }
+    }
+
+    async fn run_tool(&self, name: &str, arguments: &Value) -> Result<String> {
+        let mut client = ControlClient::connect(&self.socket_path).await?;
+
+        match name {
+            "run_command" => {
+                let command = string_argument(arguments, "command")?;
+                let wait_ms = arguments
+                    .get("wait_ms")
+                    .and_then(Value::as_u64)
+                    .unwrap_or(1000);
+
+                let queued = client
+                    .request(&ControlRequest::try_from(QueueMessage::Command {
+                        command,
+                        terminator: None,
+                    })?)
+                    .await?;
+                if let Some(file) = queued.get("file").and_then(Value::as_str) {
+                    wait_for_injection(Path::new(file), Some(INJECTION_TIMEOUT)).await?;
+                }
+                tokio::time::sleep(Duration::from_millis(wait_ms)).await;
+
+                screen_text(&mut client).await
+            }
+            "send_keys" => {
+                let data = string_argument(arguments, "text")?;
+                client
+                    .request(&ControlRequest::try_from(QueueMessage::Input { data })?)
+                    .await?;
+                Ok("Sent".to_string())
+            }
+            "read_screen" => screen_text(&mut client).await,
+            "read_scrollback" => {
+                let lines = arguments
+                    .get("lines")
+                    .and_then(Value::as_u64)
+                    .map(|lines| lines as usize);
+                let result = client
+                    .request(&ControlRequest::Scrollback {
+                        lines,
+                        strip_ansi: true,
+                    })
+                    .await?;
+                Ok(text_field(&result))
+            }
+            _ => Err(anyhow::anyhow!("Unknown tool: {}", name)),
+        }
+    }
+}
+
+async fn screen_text(client: &mut ControlClient) -> Result<String> {
+    let result = client
+        .request(&ControlRequest::Screen { strip_ansi: true })
+        .await?;
+    Ok(text_field(&result))
+}
+
+fn string_argument(arguments: &Value, name: &str) -> Result<String> {
+    arguments
+        .get(name)
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| anyhow::anyhow!("Missing string argument '{}'", name))
+}
+
+fn text_field(result: &Value) -> String {
+    result
+        .get("text")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn tool_definitions() -> Value {
+    json!([
+        {
+            "name": "run_command",
+            "description": "Queue a shell command in the typeypipe session, wait for it to be typed into the shell, then return the visible screen.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "command": { "type": "string", "description": "Command line to run" },
+                    "wait_ms": { "type": "integer", "description": "Milliseconds to wait for output after the command is sent (default 1000)" }
+                },
+                "required": ["command"]
+            }
+        },
+        {
+            "name": "send_keys",
+            "description": "Write text directly to the shell without queueing. Control characters such as \\u0003 (Ctrl+C) and \\r (Enter) are sent as-is.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "text": { "type": "string", "description": "Text to write to the shell" }
+                },
+                "required": ["text"]
+            }
+        },
+        {
+            "name": "read_screen",
+            "description": "Return the output currently visible in the session's terminal window.",
+            "inputSchema": { "type": "object", "properties": {} }
+        },
+        {
+            "name": "read_scrollback",
+            "description": "Return recent output lines from the session's scrollback.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "lines": { "type": "integer", "description": "Number of lines to return (default: all)" }
+                }
+            }
+        }
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::McpServer;
+    use crate::shell::testing::MockControl;
+    use serde_json::{json, Value};
+
+    /// The text of a `tools/call` result and whether it is an error
+    fn tool_text(response: &Value) -> (&str, bool) {
+        let result = &response["result"];
+        (
+            result["content"][0]["text"].as_str().unwrap(),
+            result["isError"].as_bool().unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_initialize_and_tools_list() {
+        let server = McpServer::new("/nonexistent.sock".into());
+
+        let response = server
+            .handle_message(json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "initialize",
+                "params": { "protocolVersion": "2025-03-26" },
+            }))
+            .await
+            .unwrap();
+        assert_eq!(response["id"], 1);
+        assert_eq!(response["result"]["protocolVersion"], "2025-03-26");
+        assert_eq!(response["result"]["serverInfo"]["name"], "typeypipe");
+        let defaulted = server
+            .handle_message(json!({ "jsonrpc": "2.0", "id": 2, "method": "initialize" }))
+            .await
+            .unwrap();
+        assert_eq!(defaulted["result"]["protocolVersion"], "2024-11-05");
+
+        let response = server
+            .handle_message(json!({ "jsonrpc": "2.0", "id": "list", "method": "tools/list" }))
+            .await
+            .unwrap();
+        assert_eq!(response["id"], "list");
+        let names: Vec<&str> = response["result"]["tools"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|tool| tool["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            ["run_command", "send_keys", "read_screen", "read_scrollback"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unknown_methods_and_notifications() {
+        let server = McpServer::new("/nonexistent.sock".into());
+
+        let response = server
+            .handle_message(json!({ "jsonrpc": "2.0", "id": 7, "method": "resources/list" }))
+            .await
+            .unwrap();
+        assert_eq!(response["id"], 7);
+        assert_eq!(response["error"]["code"], -32601);
+        assert!(response.get("result").is_none());
+
+        // Notifications are never answered, whatever their method
+        for method in ["notifications/initialized", "tools/list", "unknown"] {
+            let notification = json!({ "jsonrpc": "2.0", "method": method });
+            assert_eq!(server.handle_message(notification).await, None);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tools_are_called_through_the_control_socket() {
+        let mut mock = MockControl::start().await.unwrap();
+        let server = McpServer::new(mock.socket.clone());
+        let call = |name: &str, arguments: Value| {
+            json!({
+                "jsonrpc": "2.0",
+                "id": 3,
+                "method": "tools/call",
+                "params": { "name": name, "arguments": arguments },
+            })
+        };
+
+        let response = server
+            .handle_message(call(
+                "run_command",
+                json!({ "command": "make", "wait_ms": 200 }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response["id"], 3);
+        let (screen, is_error) = tool_text(&response);
+        assert!(!is_error);
+        assert!(screen.contains("ran: make"), "{}", screen);
+        assert!(!screen.contains('\x1b'));
+
+        let response = server
+            .handle_message(call("read_scrollback", json!({ "lines": 2 })))
+            .await
+            .unwrap();
+        assert_eq!(tool_text(&response), ("$ make\nran: make\n$ ", false));
+
+        let response = server
+            .handle_message(call("send_keys", json!({ "text": "\u{3}" })))
+            .await
+            .unwrap();
+        assert_eq!(tool_text(&response), ("Sent", false));
+        assert_eq!(mock.input().await.unwrap(), b"\x03");
+
+        // Tool failures are results the agent can read, not protocol errors
+        let response = server
+            .handle_message(call("send_keys", json!({})))
+            .await
+            .unwrap();
+        assert_eq!(
+            tool_text(&response),
+            ("Missing string argument 'text'", true)
+        );
+        let response = server
+            .handle_message(call("format_disk", json!({})))
+            .await
+            .unwrap();
+        assert_eq!(tool_text(&response), ("Unknown tool: format_disk", true));
+    }
+}
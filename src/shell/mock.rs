@@ -0,0 +1,307 @@
+use portable_pty::{Child, ChildKiller, ExitStatus, MasterPty, PtySize};
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+/// An in-memory PTY with a scripted shell behind it, for testing automation without spawning
+/// real shells. Pass it to `SessionBuilder::mock` and drive the session as usual.
+///
+/// **Behaviour:**
+/// - Prints `prompt` at startup and after every line it reads
+/// - Echoes input like a terminal, `\r` or `\n` ending a line
+/// - Answers a line set up with `respond` with its output, `\n` printed as `\r\n`
+/// - `exit` or `exit <n>` ends the session with code 0 or `n`; killing it gives code 1
+/// - Every byte written is kept for `input`
+#[derive(Debug, Clone, Default)]
+pub struct MockPty {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    state: Mutex<MockState>,
+    changed: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    prompt: String,
+    responses: Vec<(String, String)>,
+    no_echo: bool,
+    /// Output not yet read from the PTY
+    output: VecDeque<u8>,
+    /// Input since the last line ended
+    line: Vec<u8>,
+    after_cr: bool,
+    input: Vec<u8>,
+    exit_code: Option<u32>,
+    size: Option<PtySize>,
+}
+
+impl MockState {
+    fn print(&mut self, text: &str) {
+        self.output
+            .extend(text.replace("\r\n", "\n").replace('\n', "\r\n").bytes());
+    }
+
+    fn receive(&mut self, byte: u8) {
+        if self.exit_code.is_some() {
+            return;
+        }
+        self.input.push(byte);
+        let after_cr = std::mem::replace(&mut self.after_cr, byte == b'\r');
+        match byte {
+            b'\n' if after_cr => {}
+            b'\r' | b'\n' => {
+                if !self.no_echo {
+                    self.output.extend(b"\r\n");
+                }
+                let line = String::from_utf8_lossy(&std::mem::take(&mut self.line)).into_owned();
+                self.run(line.trim());
+            }
+            byte => {
+                if !self.no_echo {
+                    self.output.push_back(byte);
+                }
+                self.line.push(byte);
+            }
+        }
+    }
+
+    fn run(&mut self, line: &str) {
+        if line == "exit" {
+            self.exit_code = Some(0);
+            return;
+        }
+        if let Some(code) = line
+            .strip_prefix("exit ")
+            .and_then(|code| code.parse().ok())
+        {
+            self.exit_code = Some(code);
+            return;
+        }
+        if let Some((_, output)) = self.responses.iter().find(|(input, _)| input == line) {
+            let output = output.clone();
+            self.print(&output);
+        }
+        let prompt = self.prompt.clone();
+        self.print(&prompt);
+    }
+}
+
+impl MockPty {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn prompt(self, prompt: &str) -> Self {
+        self.state().prompt = prompt.to_string();
+        self
+    }
+
+    /// Print `output` whenever the line `input` is entered
+    pub fn respond(self, input: &str, output: &str) -> Self {
+        self.state()
+            .responses
+            .push((input.to_string(), output.to_string()));
+        self
+    }
+
+    /// Do not echo input, like a terminal at a password prompt
+    pub fn no_echo(self) -> Self {
+        self.state().no_echo = true;
+        self
+    }
+
+    /// Every byte written to the PTY so far
+    pub fn input(&self) -> Vec<u8> {
+        self.state().input.clone()
+    }
+
+    /// The size last set by the session, if it has started
+    pub fn size(&self) -> Option<(u16, u16)> {
+        self.state().size.map(|size| (size.cols, size.rows))
+    }
+
+    /// The PTY's parent side and the scripted shell as a child process, printing the prompt
+    pub(crate) fn start(
+        &self,
+        cols: u16,
+        rows: u16,
+    ) -> (Box<dyn MasterPty + Send>, Box<dyn Child + Send + Sync>) {
+        let mut state = self.state();
+        state.size = Some(PtySize {
+            rows,
+            cols,
+            ..PtySize::default()
+        });
+        let prompt = state.prompt.clone();
+        state.print(&prompt);
+        drop(state);
+        self.shared.changed.notify_all();
+        (Box::new(self.clone()), Box::new(self.clone()))
+    }
+
+    fn state(&self) -> MutexGuard<'_, MockState> {
+        self.shared
+            .state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl MasterPty for MockPty {
+    fn resize(&self, size: PtySize) -> Result<(), anyhow::Error> {
+        self.state().size = Some(size);
+        Ok(())
+    }
+
+    fn get_size(&self) -> Result<PtySize, anyhow::Error> {
+        Ok(self.state().size.unwrap_or_default())
+    }
+
+    fn try_clone_reader(&self) -> Result<Box<dyn Read + Send>, anyhow::Error> {
+        Ok(Box::new(self.clone()))
+    }
+
+    fn take_writer(&self) -> Result<Box<dyn Write + Send>, anyhow::Error> {
+        Ok(Box::new(self.clone()))
+    }
+
+    #[cfg(unix)]
+    fn process_group_leader(&self) -> Option<nix::libc::pid_t> {
+        None
+    }
+
+    #[cfg(unix)]
+    fn as_raw_fd(&self) -> Option<portable_pty::unix::RawFd> {
+        None
+    }
+}
+
+/// Reads block until the scripted shell prints something, and return 0 once it has exited
+impl Read for MockPty {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut state = self.state();
+        while state.output.is_empty() && state.exit_code.is_none() {
+            state = self
+                .shared
+                .changed
+                .wait(state)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        let count = buf.len().min(state.output.len());
+        for (slot, byte) in buf.iter_mut().zip(state.output.drain(..count)) {
+            *slot = byte;
+        }
+        Ok(count)
+    }
+}
+
+impl Write for MockPty {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut state = self.state();
+        for &byte in buf {
+            state.receive(byte);
+        }
+        drop(state);
+        self.shared.changed.notify_all();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl ChildKiller for MockPty {
+    fn kill(&mut self) -> std::io::Result<()> {
+        self.state().exit_code.get_or_insert(1);
+        self.shared.changed.notify_all();
+        Ok(())
+    }
+
+    fn clone_killer(&self) -> Box<dyn ChildKiller + Send + Sync> {
+        Box::new(self.clone())
+    }
+}
+
+impl Child for MockPty {
+    fn try_wait(&mut self) -> std::io::Result<Option<ExitStatus>> {
+        Ok(self.state().exit_code.map(ExitStatus::with_exit_code))
+    }
+
+    fn wait(&mut self) -> std::io::Result<ExitStatus> {
+        let mut state = self.state();
+        loop {
+            if let Some(code) = state.exit_code {
+                return Ok(ExitStatus::with_exit_code(code));
+            }
+            state = self
+                .shared
+                .changed
+                .wait(state)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+    }
+
+    fn process_id(&self) -> Option<u32> {
+        None
+    }
+
+    #[cfg(windows)]
+    fn as_raw_handle(&self) -> Option<std::os::windows::io::RawHandle> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MockPty;
+    use crate::api::{OutputEvent, SessionBuilder};
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_mock_pty_answers_queued_commands() {
+        let temp_dir = TempDir::new().unwrap();
+        let pty = MockPty::new()
+            .prompt("mock$ ")
+            .respond("make", "compiling\nbuilt\n");
+        let mut session = SessionBuilder::new(temp_dir.path().join("mock"))
+            .size(100, 30)
+            .mock(pty.clone())
+            .spawn()
+            .await
+            .unwrap();
+        let mut events = session.events().unwrap();
+
+        session.queue().command("make").await.unwrap();
+        let mut seen = Vec::new();
+        let found = tokio::time::timeout(Duration::from_secs(10), async {
+            while let Some(event) = events.next().await {
+                if let OutputEvent::Output(bytes) = event {
+                    seen.extend_from_slice(&bytes);
+                }
+                if String::from_utf8_lossy(&seen).contains("built\r\nmock$ ") {
+                    return true;
+                }
+            }
+            false
+        })
+        .await;
+        assert_eq!(found.ok(), Some(true));
+        assert!(String::from_utf8_lossy(&seen).contains("make\r\ncompiling\r\n"));
+        assert_eq!(pty.size(), Some((100, 30)));
+
+        session.queue().command("exit 5").await.unwrap();
+        let code = tokio::time::timeout(Duration::from_secs(10), session.wait()).await;
+        assert_eq!(code.ok().map(Result::unwrap), Some(5));
+        let mut last = None;
+        while let Some(event) = events.next().await {
+            last = Some(event);
+        }
+        assert_eq!(last, Some(OutputEvent::ChildExited));
+        assert_eq!(pty.input(), b"make\nexit 5\n");
+    }
+}
@@ -1,14 +1,79 @@
+pub mod alerts;
+pub mod ansi;
+pub mod arbiter;
+pub mod audit;
+pub mod auth;
+pub mod clock;
+pub mod config;
+pub mod context;
+pub mod control;
+pub mod durability;
+pub mod echo;
+pub mod encryption;
+pub mod filter;
+pub mod fuzz;
+pub mod glyphs;
+pub mod headless;
+pub mod help;
+pub mod history;
+pub mod hooks;
+pub mod injection;
+pub mod input_log;
+pub mod integration;
+pub use crate::keys;
+pub mod location;
+pub mod logs;
+pub mod mcp;
+pub mod mock;
+pub mod notify;
+pub mod osc;
+pub mod overlay;
+pub mod palette;
+pub mod paste;
+pub mod play;
+pub mod progress;
 pub mod pty;
 pub mod queue;
+pub mod rotation;
+pub mod rpc;
+pub mod sandbox;
+pub mod schedule;
+pub mod scrollback;
+pub mod status;
+pub mod stopwatch;
+pub mod supervisor;
+pub mod systemd;
+pub mod tee;
 pub mod terminal;
+pub mod terminfo;
+#[cfg(test)]
+pub(crate) mod testing;
+pub mod title;
+pub mod transcript;
 pub mod types;
+pub mod watch;
 
-// Re-export commonly used items
-pub use pty::{
-    create_pty_session, create_pty_session_manager, pty_manager_execute_and_wait,
-    pty_manager_write_line, PtySession, PtySessionManager, SharedPtySession,
-    SharedPtySessionManager,
-};
-pub use queue::PtyQueueProcessor;
+// Re-exported for the command line
+pub use alerts::BellPolicy;
+pub use audit::AuditLog;
+pub use control::{set_socket_access, ControlClient, ControlRequest, SocketAccess};
+pub use durability::{set_fsync_policy, FsyncPolicy};
+pub use echo::AgentEcho;
+pub use encryption::{decrypt_file, set_encryption_recipient};
+pub use filter::parse_filters;
+pub use glyphs::set_plain_text;
+pub use history::{HistoryDb, HistoryQuery, SessionHistory};
+pub use input_log::InputLog;
+pub use mcp::McpServer;
+pub use notify::{set_notifier, NotifyMethod};
+pub use pty::create_pty_session;
+pub use queue::enqueue_file;
+pub use rotation::{set_rotation_policy, RotationPolicy};
+pub use schedule::ScheduledCommand;
+pub use status::check_health;
+pub use supervisor::{install_panic_hook, set_panic_policy, PanicPolicy};
 pub use terminal::setup_interactive_pty;
-pub use types::{CommandResult, ShellConfig};
+pub use types::{
+    InteractiveOptions, NestedPolicy, QueueMessage, SessionTarget, ShellConfig, Terminator,
+};
+pub use watch::WatchRule;
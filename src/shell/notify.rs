@@ -0,0 +1,212 @@
+use crate::shell::filter::{OutputFilter, StripAnsi};
+use std::collections::HashMap;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// The same notification is not repeated more often than this, so a log line that matches
+/// on every build does not bury the desktop in popups
+const NOTIFY_COOLDOWN: Duration = Duration::from_secs(10);
+
+/// Longest notification body sent; desktops truncate long ones anyway
+const MAX_BODY_CHARS: usize = 200;
+
+/// Longest output line kept while waiting for its newline
+const MAX_LINE_BYTES: usize = 4096;
+
+/// How desktop notifications reach the user
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotifyMethod {
+    /// `OSC 9` written to the outer terminal (iTerm2, WezTerm, Windows Terminal, kitty)
+    Osc9,
+    /// `OSC 777;notify` written to the outer terminal (VTE-based terminals, foot, urxvt)
+    Osc777,
+    /// Run `notify-send`, for terminals that understand neither sequence
+    NotifySend,
+}
+
+impl FromStr for NotifyMethod {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "osc9" => Ok(NotifyMethod::Osc9),
+            "osc777" => Ok(NotifyMethod::Osc777),
+            "notify-send" => Ok(NotifyMethod::NotifySend),
+            _ => Err(anyhow::anyhow!(
+                "Invalid notification method '{}': expected osc9, osc777 or notify-send",
+                value
+            )),
+        }
+    }
+}
+
+/// Desktop notifications for things a user not watching the terminal should hear about.
+///
+/// **Triggers:**
+/// - A queued command that could not be injected (invalid, rejected by the hook, or failed)
+/// - A line of shell output containing one of the `--notify-on` texts (see `OutputWatcher`)
+static NOTIFIER: RwLock<Option<Notifier>> = RwLock::new(None);
+
+#[derive(Debug, Clone)]
+struct Notifier {
+    method: NotifyMethod,
+    /// Queue name of the session, shown in every notification
+    session: String,
+}
+
+/// When each notification title was last sent
+static LAST_SENT: Mutex<Option<HashMap<String, Instant>>> = Mutex::new(None);
+
+/// Send notifications for `session` through `method`, or stop sending them
+pub fn set_notifier(method: Option<NotifyMethod>, session: &str) {
+    if let Ok(mut current) = NOTIFIER.write() {
+        *current = method.map(|method| Notifier {
+            method,
+            session: session.to_string(),
+        });
+    }
+}
+
+pub fn notifier_enabled() -> bool {
+    NOTIFIER.read().is_ok_and(|notifier| notifier.is_some())
+}
+
+/// Send a notification titled with the session and `event`, unless the same event was
+/// notified within the cooldown
+pub fn notify(event: &str, body: &str) {
+    let Some(Notifier { method, session }) = NOTIFIER.read().ok().and_then(|n| n.clone()) else {
+        return;
+    };
+    if let Ok(mut last_sent) = LAST_SENT.lock() {
+        let last_sent = last_sent.get_or_insert_with(HashMap::new);
+        let now = Instant::now();
+        if last_sent
+            .get(event)
+            .is_some_and(|at| now.duration_since(*at) < NOTIFY_COOLDOWN)
+        {
+            return;
+        }
+        last_sent.insert(event.to_string(), now);
+    }
+
+    let title = sanitize(&format!("{}: {}", session, event));
+    let body = sanitize(body);
+    match escape_sequence(method, &title, &body) {
+        Some(sequence) => {
+            let mut stdout = std::io::stdout().lock();
+            let _ = stdout.write_all(sequence.as_bytes());
+            let _ = stdout.flush();
+        }
+        None => {
+            let spawned = std::process::Command::new("notify-send")
+                .arg("--app-name=typeypipe")
+                .arg(&title)
+                .arg(&body)
+                .stdout(std::process::Stdio::null())
+                .stderr(std::process::Stdio::null())
+                .spawn();
+            match spawned {
+                // Reap it in the background so it does not linger as a zombie
+                Ok(mut child) => {
+                    std::thread::spawn(move || child.wait());
+                }
+                Err(e) => tracing::warn!(error = %e, "failed to run notify-send"),
+            }
+        }
+    }
+}
+
+/// The terminal escape sequence for an OSC notification, or `None` for `notify-send`
+fn escape_sequence(method: NotifyMethod, title: &str, body: &str) -> Option<String> {
+    match method {
+        NotifyMethod::Osc9 => Some(format!("\x1b]9;{}: {}\x07", title, body)),
+        // Fields are separated by `;`, so none may appear inside them
+        NotifyMethod::Osc777 => Some(format!(
+            "\x1b]777;notify;{};{}\x07",
+            title.replace(';', ","),
+            body.replace(';', ",")
+        )),
+        NotifyMethod::NotifySend => None,
+    }
+}
+
+/// Drop control characters, which could end the escape sequence early, and cap the length
+fn sanitize(text: &str) -> String {
+    let text: String = text.chars().filter(|c| !c.is_control()).collect();
+    match text.char_indices().nth(MAX_BODY_CHARS) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text,
+    }
+}
+
+/// Looks for lines of shell output containing any of a set of texts, with escape sequences
+/// removed first so colouring inside a word does not hide it
+pub struct OutputWatcher {
+    patterns: Vec<String>,
+    strip: StripAnsi,
+    line: Vec<u8>,
+}
+
+impl OutputWatcher {
+    pub fn new(patterns: Vec<String>) -> Self {
+        Self {
+            patterns,
+            strip: StripAnsi::default(),
+            line: Vec::new(),
+        }
+    }
+
+    /// Feed a chunk of output and return each completed line that matched, with its pattern
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<(String, String)> {
+        let mut text = Vec::with_capacity(chunk.len());
+        self.strip.apply(chunk, &mut text);
+
+        let mut matches = Vec::new();
+        for byte in text {
+            if byte != b'\n' {
+                if self.line.len() < MAX_LINE_BYTES {
+                    self.line.push(byte);
+                }
+                continue;
+            }
+            let line = String::from_utf8_lossy(&self.line).trim().to_string();
+            self.line.clear();
+            if let Some(pattern) = self.patterns.iter().find(|p| line.contains(p.as_str())) {
+                matches.push((pattern.clone(), line));
+            }
+        }
+        matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape_sequence, sanitize, NotifyMethod, OutputWatcher};
+
+    #[test]
+    fn test_watcher_matches_lines_and_sequences_are_safe() {
+        let mut watcher = OutputWatcher::new(vec!["error[".to_string(), "FAILED".to_string()]);
+        assert!(watcher.feed(b"\x1b[1;31merr").is_empty());
+        let matches = watcher.feed(b"or\x1b[0m[E0382]: borrow\r\nok\ntest a ... FAIL");
+        assert_eq!(
+            matches,
+            vec![("error[".to_string(), "error[E0382]: borrow".to_string())]
+        );
+        assert_eq!(watcher.feed(b"ED\n").len(), 1);
+
+        let body = sanitize("bad\x07; rm\x1b]");
+        assert_eq!(
+            escape_sequence(NotifyMethod::Osc777, "typeypipe; web", &body).unwrap(),
+            "\x1b]777;notify;typeypipe, web;bad, rm]\x07"
+        );
+        assert_eq!(
+            escape_sequence(NotifyMethod::Osc9, "web", "done").unwrap(),
+            "\x1b]9;web: done\x07"
+        );
+        assert!(escape_sequence(NotifyMethod::NotifySend, "web", "done").is_none());
+        assert_eq!(sanitize(&"x".repeat(300)).chars().count(), 201);
+        assert!("growl".parse::<NotifyMethod>().is_err());
+    }
+}
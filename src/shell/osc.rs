@@ -0,0 +1,182 @@
+use crate::shell::ansi::strip_ansi;
+use serde::{Deserialize, Serialize};
+
+/// Longest OSC payload kept; anything longer is not a prompt mark and is discarded
+const MAX_OSC_LEN: usize = 4096;
+
+/// Longest command line captured between the prompt and command-start marks
+const MAX_COMMAND_LEN: usize = 64 * 1024;
+
+/// A foreground command seen in the shell output
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FinishedCommand {
+    /// Command line as echoed by the shell, without escape sequences
+    pub command: String,
+    pub exit_status: Option<i32>,
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    pub finished_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug)]
+enum State {
+    Ground,
+    Escape,
+    Osc(Vec<u8>),
+    OscEscape(Vec<u8>),
+}
+
+#[derive(Debug)]
+struct RunningCommand {
+    command: String,
+    started_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Follows OSC 133 prompt marks in PTY output to discover the commands the shell runs.
+///
+/// **Marks (emitted by `typeypipe setup --shell-integration`):**
+/// - `133;A`: prompt starts
+/// - `133;B`: prompt ends; the echoed command line follows
+/// - `133;C`: the command line was submitted and the command starts
+/// - `133;D;<status>`: the command finished with an exit status
+///
+/// Output may be fed in arbitrary chunks; marks split across chunks are still recognised.
+#[derive(Debug)]
+pub struct CommandTracker {
+    state: State,
+    capturing: bool,
+    command_line: Vec<u8>,
+    running: Option<RunningCommand>,
+}
+
+impl Default for CommandTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CommandTracker {
+    pub fn new() -> Self {
+        Self {
+            state: State::Ground,
+            capturing: false,
+            command_line: Vec::new(),
+            running: None,
+        }
+    }
+
+    /// Feed a chunk of output, returning every command that finished within it
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<FinishedCommand> {
+        let mut finished = Vec::new();
+
+        for &byte in bytes {
+            self.state = match std::mem::replace(&mut self.state, State::Ground) {
+                State::Ground if byte == 0x1b => State::Escape,
+                State::Ground => {
+                    self.capture(&[byte]);
+                    State::Ground
+                }
+                State::Escape if byte == b']' => State::Osc(Vec::new()),
+                State::Escape => {
+                    self.capture(&[0x1b, byte]);
+                    State::Ground
+                }
+                State::Osc(payload) if byte == 0x07 => {
+                    finished.extend(self.handle_osc(&payload));
+                    State::Ground
+                }
+                State::Osc(payload) if byte == 0x1b => State::OscEscape(payload),
+                State::Osc(mut payload) => {
+                    if payload.len() >= MAX_OSC_LEN {
+                        State::Ground
+                    } else {
+                        payload.push(byte);
+                        State::Osc(payload)
+                    }
+                }
+                State::OscEscape(payload) if byte == b'\\' => {
+                    finished.extend(self.handle_osc(&payload));
+                    State::Ground
+                }
+                State::OscEscape(mut payload) => {
+                    payload.extend_from_slice(&[0x1b, byte]);
+                    State::Osc(payload)
+                }
+            };
+        }
+
+        finished
+    }
+
+    /// The command started by the last `133;C` mark, until it finishes
+    pub fn running_command(&self) -> Option<&str> {
+        self.running
+            .as_ref()
+            .map(|running| running.command.as_str())
+    }
+
+    fn capture(&mut self, bytes: &[u8]) {
+        if self.capturing && self.command_line.len() < MAX_COMMAND_LEN {
+            self.command_line.extend_from_slice(bytes);
+        }
+    }
+
+    fn handle_osc(&mut self, payload: &[u8]) -> Option<FinishedCommand> {
+        let payload = std::str::from_utf8(payload).ok()?;
+        let mark = payload.strip_prefix("133;")?;
+        let mut fields = mark.split(';');
+
+        match fields.next()? {
+            "A" => {
+                self.capturing = false;
+                None
+            }
+            "B" => {
+                self.capturing = true;
+                self.command_line.clear();
+                None
+            }
+            "C" => {
+                self.capturing = false;
+                let command = strip_ansi(&String::from_utf8_lossy(&self.command_line))
+                    .trim()
+                    .to_string();
+                self.command_line.clear();
+                self.running = (!command.is_empty()).then(|| RunningCommand {
+                    command,
+                    started_at: chrono::Utc::now(),
+                });
+                None
+            }
+            "D" => {
+                let running = self.running.take()?;
+                Some(FinishedCommand {
+                    command: running.command,
+                    exit_status: fields.next().and_then(|status| status.parse().ok()),
+                    started_at: running.started_at,
+                    finished_at: chrono::Utc::now(),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CommandTracker;
+
+    #[test]
+    fn test_tracker_reports_finished_commands() {
+        let mut tracker = CommandTracker::new();
+        let output = b"\x1b]133;D;0\x07\x1b]133;A\x07$ \x1b]133;B\x07ls \x1b[1m-la\x1b[0m\r\n\x1b]133;C\x07file\r\n\x1b]133;D;2\x1b\\";
+
+        // Split mid-sequence to exercise chunk boundaries
+        let (first, second) = output.split_at(40);
+        assert!(tracker.feed(first).is_empty());
+        let finished = tracker.feed(second);
+
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].command, "ls -la");
+        assert_eq!(finished[0].exit_status, Some(2));
+    }
+}
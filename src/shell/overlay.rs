@@ -0,0 +1,202 @@
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Most shell output held back while the overlay is shown; anything beyond it is dropped and
+/// the screen is redrawn when the overlay closes
+const MAX_HELD_BYTES: usize = 4 * 1024 * 1024;
+
+/// Sequences that switch the terminal to and from the alternate screen, longest first
+const ALT_SCREEN_ON: &[&[u8]] = &[b"\x1b[?1049h", b"\x1b[?1047h", b"\x1b[?47h"];
+const ALT_SCREEN_OFF: &[&[u8]] = &[b"\x1b[?1049l", b"\x1b[?1047l", b"\x1b[?47l"];
+/// Sequences that turn bracketed paste on and off
+const PASTE_ON: &[&[u8]] = &[b"\x1b[?2004h"];
+const PASTE_OFF: &[&[u8]] = &[b"\x1b[?2004l"];
+const LONGEST_SWITCH: usize = 8;
+
+/// Output held while the overlay covers the screen; `None` when it is not shown. Terminal
+/// writes go through this lock so the overlay and shell output never interleave.
+static OVERLAY: Mutex<Option<HeldOutput>> = Mutex::new(None);
+
+/// Whether the program in the shell has switched to the alternate screen
+static INNER_ALT_SCREEN: AtomicBool = AtomicBool::new(false);
+
+/// Whether the program in the shell has turned bracketed paste on
+static INNER_BRACKETED_PASTE: AtomicBool = AtomicBool::new(false);
+
+/// Keep bracketed paste on in the outer terminal, whatever the shell asks for, so pastes can
+/// be told apart from typing
+static GUARD_PASTES: AtomicBool = AtomicBool::new(false);
+
+#[derive(Default)]
+struct HeldOutput {
+    bytes: Vec<u8>,
+    overflowed: bool,
+    /// The overlay was drawn over a full-screen program rather than on a screen of its own
+    over_alt_screen: bool,
+}
+
+/// Whether pastes should reach the shell wrapped in bracketed paste marks
+pub fn inner_bracketed_paste() -> bool {
+    INNER_BRACKETED_PASTE.load(Ordering::Relaxed)
+}
+
+/// Turn bracketed paste on in the outer terminal and keep it on until `guard_pastes(false)`
+pub fn guard_pastes(on: bool) {
+    GUARD_PASTES.store(on, Ordering::Relaxed);
+    let restore = on || inner_bracketed_paste();
+    let mut stdout = std::io::stdout().lock();
+    let _ = stdout.write_all(if restore {
+        b"\x1b[?2004h".as_slice()
+    } else {
+        b"\x1b[?2004l".as_slice()
+    });
+    let _ = stdout.flush();
+}
+
+pub fn overlay_shown() -> bool {
+    OVERLAY.lock().is_ok_and(|overlay| overlay.is_some())
+}
+
+/// Cover the screen with `lines` and hold shell output back until `close_overlay`, or
+/// redraw the overlay with new lines when it is already shown.
+///
+/// **Screens:**
+/// - Normally the overlay goes on the outer terminal's alternate screen, so leaving it puts
+///   the shell's screen back exactly as it was
+/// - When a full-screen program already uses the alternate screen, the overlay is drawn over
+///   it, and the program has to redraw once it closes
+pub fn show_overlay(lines: &[String]) {
+    let Ok(mut overlay) = OVERLAY.lock() else {
+        return;
+    };
+    let over_alt_screen = INNER_ALT_SCREEN.load(Ordering::Relaxed);
+
+    let mut screen = String::from(match overlay.as_ref() {
+        Some(_) => "\x1b[H\x1b[2J",
+        None if over_alt_screen => "\x1b7\x1b[H\x1b[2J",
+        None => "\x1b[?1049h\x1b[H\x1b[2J",
+    });
+    screen.push_str(&lines.join("\r\n"));
+
+    let mut stdout = std::io::stdout().lock();
+    let _ = stdout.write_all(screen.as_bytes());
+    let _ = stdout.flush();
+    if overlay.is_none() {
+        *overlay = Some(HeldOutput {
+            over_alt_screen,
+            ..HeldOutput::default()
+        });
+    }
+}
+
+/// Close the overlay and write the output held while it was shown. Returns whether the
+/// program in the shell needs to redraw the screen.
+pub fn close_overlay() -> bool {
+    let Ok(mut overlay) = OVERLAY.lock() else {
+        return false;
+    };
+    let Some(held) = overlay.take() else {
+        return false;
+    };
+
+    let mut stdout = std::io::stdout().lock();
+    let _ = stdout.write_all(if held.over_alt_screen {
+        b"\x1b[H\x1b[2J\x1b8".as_slice()
+    } else {
+        b"\x1b[?1049l".as_slice()
+    });
+    let _ = stdout.write_all(&held.bytes);
+    let _ = stdout.flush();
+    held.over_alt_screen || held.overflowed
+}
+
+/// Writes shell output to the outer terminal, holding it back while an overlay is shown
+/// and following the program's switches to and from the alternate screen and bracketed paste.
+/// While pastes are guarded, bracketed paste is turned back on whenever the program turns it
+/// off.
+#[derive(Default)]
+pub struct TerminalWriter {
+    /// End of the previous chunk, in case a switch is split across chunks
+    tail: Vec<u8>,
+}
+
+impl TerminalWriter {
+    pub fn write(&mut self, chunk: &[u8]) {
+        let paste_off = self.track_modes(chunk);
+        let restored;
+        let chunk = if paste_off && GUARD_PASTES.load(Ordering::Relaxed) {
+            restored = [chunk, b"\x1b[?2004h".as_slice()].concat();
+            restored.as_slice()
+        } else {
+            chunk
+        };
+
+        let Ok(mut overlay) = OVERLAY.lock() else {
+            return;
+        };
+        match overlay.as_mut() {
+            Some(held) if held.bytes.len() + chunk.len() <= MAX_HELD_BYTES => {
+                held.bytes.extend_from_slice(chunk)
+            }
+            Some(held) => held.overflowed = true,
+            None => {
+                let mut stdout = std::io::stdout().lock();
+                let _ = stdout.write_all(chunk);
+                let _ = stdout.flush();
+            }
+        }
+    }
+
+    /// Follow the mode switches in `chunk`. Returns whether it left bracketed paste off.
+    fn track_modes(&mut self, chunk: &[u8]) -> bool {
+        let mut window = std::mem::take(&mut self.tail);
+        window.extend_from_slice(chunk);
+
+        let last = |switches: &[&[u8]]| {
+            switches
+                .iter()
+                .filter_map(|switch| {
+                    window
+                        .windows(switch.len())
+                        .rposition(|bytes| bytes == *switch)
+                })
+                .max()
+        };
+        let track = |mode: &AtomicBool, on: Option<usize>, off: Option<usize>| match (on, off) {
+            (Some(on), Some(off)) => mode.store(on > off, Ordering::Relaxed),
+            (Some(_), None) => mode.store(true, Ordering::Relaxed),
+            (None, Some(_)) => mode.store(false, Ordering::Relaxed),
+            (None, None) => {}
+        };
+        track(&INNER_ALT_SCREEN, last(ALT_SCREEN_ON), last(ALT_SCREEN_OFF));
+        let paste_switch = (last(PASTE_ON), last(PASTE_OFF));
+        track(&INNER_BRACKETED_PASTE, paste_switch.0, paste_switch.1);
+
+        let keep = window.len().min(LONGEST_SWITCH - 1);
+        self.tail = window.split_off(window.len() - keep);
+        paste_switch.1.is_some() && !inner_bracketed_paste()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{inner_bracketed_paste, TerminalWriter, INNER_ALT_SCREEN};
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn test_mode_switches_are_tracked_across_chunks() {
+        let mut writer = TerminalWriter::default();
+        writer.track_modes(b"vim\x1b[?10");
+        writer.track_modes(b"49h\x1b[H");
+        assert!(INNER_ALT_SCREEN.load(Ordering::Relaxed));
+        writer.track_modes(b"\x1b[?1049l\x1b[?1049h\x1b[?1049l$ ");
+        assert!(!INNER_ALT_SCREEN.load(Ordering::Relaxed));
+
+        assert!(!writer.track_modes(b"\x1b[?2004h$ "));
+        assert!(inner_bracketed_paste());
+        assert!(!writer.track_modes(b"ls\r\n\x1b[?20"));
+        assert!(writer.track_modes(b"04l"));
+        assert!(!inner_bracketed_paste());
+    }
+}
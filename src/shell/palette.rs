@@ -0,0 +1,239 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+
+/// Recent queued commands offered for re-running
+const RECENT_COMMANDS_SHOWN: usize = 10;
+
+/// Something the command palette can do
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PaletteAction {
+    /// Lock or release the queue, like Ctrl+]
+    ToggleTakeover,
+    /// Hold queue processing or resume it, like the `pause`/`resume` control requests
+    ToggleHold,
+    /// Save the visible screen to a file next to the session log
+    DumpScreen,
+    ShowHelp,
+    /// Queue a command again
+    Rerun(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct PaletteItem {
+    pub label: String,
+    pub action: PaletteAction,
+}
+
+/// What the session should do after a key press in the palette
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PaletteOutcome {
+    /// Still open; draw it again
+    Redraw,
+    Close,
+    Run(PaletteAction),
+}
+
+/// F2 opens the command palette
+pub fn is_palette_key(key_event: &KeyEvent) -> bool {
+    key_event.kind == KeyEventKind::Press && key_event.code == KeyCode::F(2)
+}
+
+/// The palette's entries: session actions, labelled for the current state, then the most
+/// recent distinct queued commands, newest first
+pub fn palette_items(locked: bool, held: bool, recent: &[String]) -> Vec<PaletteItem> {
+    let item = |label: &str, action| PaletteItem {
+        label: label.to_string(),
+        action,
+    };
+    let mut items = vec![
+        item(
+            if locked {
+                "Release the queue lock (Ctrl+])"
+            } else {
+                "Lock the queue (Ctrl+])"
+            },
+            PaletteAction::ToggleTakeover,
+        ),
+        item(
+            if held {
+                "Resume queue processing"
+            } else {
+                "Hold queue processing"
+            },
+            PaletteAction::ToggleHold,
+        ),
+        item("Save the screen to a file", PaletteAction::DumpScreen),
+        item("Show help (F1)", PaletteAction::ShowHelp),
+    ];
+
+    let actions = items.len();
+    let mut seen = std::collections::HashSet::new();
+    for command in recent.iter().rev() {
+        if items.len() - actions >= RECENT_COMMANDS_SHOWN {
+            break;
+        }
+        if seen.insert(command.as_str()) {
+            items.push(PaletteItem {
+                label: format!("Re-run: {}", command),
+                action: PaletteAction::Rerun(command.clone()),
+            });
+        }
+    }
+    items
+}
+
+/// A filterable list of actions, drawn in an overlay.
+///
+/// **Keys:**
+/// - Typing filters the list by fuzzy match; Backspace removes a character
+/// - Up/Down (or Ctrl+P/Ctrl+N) move the selection, Enter runs it
+/// - Esc or Ctrl+C closes the palette
+pub struct Palette {
+    items: Vec<PaletteItem>,
+    query: String,
+    selected: usize,
+}
+
+impl Palette {
+    pub fn new(items: Vec<PaletteItem>) -> Self {
+        Self {
+            items,
+            query: String::new(),
+            selected: 0,
+        }
+    }
+
+    /// Items matching the query, best match first
+    fn matches(&self) -> Vec<&PaletteItem> {
+        let mut scored: Vec<(usize, &PaletteItem)> = self
+            .items
+            .iter()
+            .filter_map(|item| Some((fuzzy_score(&self.query, &item.label)?, item)))
+            .collect();
+        scored.sort_by_key(|(score, _)| *score);
+        scored.into_iter().map(|(_, item)| item).collect()
+    }
+
+    pub fn handle_key(&mut self, key_event: &KeyEvent) -> PaletteOutcome {
+        let control = key_event.modifiers.contains(KeyModifiers::CONTROL);
+        let count = self.matches().len();
+        match key_event.code {
+            KeyCode::Esc => return PaletteOutcome::Close,
+            KeyCode::Char('c') if control => return PaletteOutcome::Close,
+            KeyCode::Enter => {
+                return match self.matches().get(self.selected) {
+                    Some(item) => PaletteOutcome::Run(item.action.clone()),
+                    None => PaletteOutcome::Close,
+                }
+            }
+            KeyCode::Up => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Char('p') if control => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down => self.selected = (self.selected + 1).min(count.saturating_sub(1)),
+            KeyCode::Char('n') if control => {
+                self.selected = (self.selected + 1).min(count.saturating_sub(1))
+            }
+            KeyCode::Backspace => {
+                self.query.pop();
+                self.selected = 0;
+            }
+            KeyCode::Char(c) if !control => {
+                self.query.push(c);
+                self.selected = 0;
+            }
+            _ => {}
+        }
+        PaletteOutcome::Redraw
+    }
+
+    /// Lines of the palette, fitted to the terminal
+    pub fn render(&self, cols: u16, rows: u16) -> Vec<String> {
+        let mut lines = vec![
+            "typeypipe - type to filter, Up/Down to choose, Enter to run, Esc to close".to_string(),
+            format!("> {}", self.query),
+            String::new(),
+        ];
+        let matches = self.matches();
+        if matches.is_empty() {
+            lines.push("  (no matches)".to_string());
+        }
+        // Scroll so the selection stays on screen
+        let room = (rows as usize).saturating_sub(lines.len()).max(1);
+        let first = self.selected.saturating_sub(room - 1);
+        for (index, item) in matches.iter().enumerate().skip(first).take(room) {
+            let marker = if index == self.selected { "> " } else { "  " };
+            lines.push(format!("{}{}", marker, item.label));
+        }
+
+        lines
+            .into_iter()
+            .map(|line| line.chars().take(cols as usize).collect())
+            .collect()
+    }
+}
+
+/// How well `query` matches `label` as a case-insensitive subsequence, lower is better, or
+/// `None` when it does not match. Matches starting earlier and with fewer gaps rank first.
+fn fuzzy_score(query: &str, label: &str) -> Option<usize> {
+    let label: Vec<char> = label.to_lowercase().chars().collect();
+    let mut position = 0;
+    let mut score = 0;
+    let mut previous: Option<usize> = None;
+    for wanted in query.to_lowercase().chars() {
+        let found = position + label[position..].iter().position(|&c| c == wanted)?;
+        score += match previous {
+            Some(previous) => found - previous - 1,
+            None => found,
+        };
+        previous = Some(found);
+        position = found + 1;
+    }
+    Some(score)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{palette_items, Palette, PaletteAction, PaletteOutcome};
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+    #[test]
+    fn test_palette_filters_and_runs_selection() {
+        let recent = vec![
+            "make test".to_string(),
+            "cargo build".to_string(),
+            "make test".to_string(),
+        ];
+        let items = palette_items(false, false, &recent);
+        assert_eq!(items.len(), 6);
+        assert_eq!(
+            items[4].action,
+            PaletteAction::Rerun("make test".to_string())
+        );
+
+        let key = |code| KeyEvent::new(code, KeyModifiers::NONE);
+        let mut palette = Palette::new(items);
+        for c in "mkt".chars() {
+            assert_eq!(
+                palette.handle_key(&key(KeyCode::Char(c))),
+                PaletteOutcome::Redraw
+            );
+        }
+        let lines = palette.render(80, 24);
+        assert_eq!(lines[1], "> mkt");
+        assert_eq!(lines[3], "> Re-run: make test");
+
+        palette.handle_key(&key(KeyCode::Backspace));
+        palette.handle_key(&key(KeyCode::Backspace));
+        palette.handle_key(&key(KeyCode::Backspace));
+        palette.handle_key(&key(KeyCode::Char('h')));
+        palette.handle_key(&key(KeyCode::Char('o')));
+        palette.handle_key(&key(KeyCode::Char('l')));
+        palette.handle_key(&key(KeyCode::Char('d')));
+        assert_eq!(
+            palette.handle_key(&key(KeyCode::Enter)),
+            PaletteOutcome::Run(PaletteAction::ToggleHold)
+        );
+        assert_eq!(
+            palette.handle_key(&key(KeyCode::Esc)),
+            PaletteOutcome::Close
+        );
+    }
+}
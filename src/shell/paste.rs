@@ -0,0 +1,111 @@
+use crossterm::event::{KeyCode, KeyEvent};
+
+/// Lines of a held paste shown in the confirmation
+const PREVIEW_LINES: usize = 3;
+
+/// Marks around a paste for a program that has turned bracketed paste on
+const PASTE_START: &str = "\x1b[200~";
+const PASTE_END: &str = "\x1b[201~";
+
+/// What to do with a paste held by `--paste-guard`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasteChoice {
+    /// Send it to the shell as it is
+    Paste,
+    /// Queue it as a command, to be injected like any other
+    Queue,
+    Discard,
+}
+
+impl PasteChoice {
+    /// `p` pastes, `q` queues and any other key discards
+    pub fn from_key(key_event: &KeyEvent) -> Self {
+        match key_event.code {
+            KeyCode::Char('p' | 'P') => PasteChoice::Paste,
+            KeyCode::Char('q' | 'Q') => PasteChoice::Queue,
+            _ => PasteChoice::Discard,
+        }
+    }
+}
+
+/// Bytes to write to the PTY for pasted `text`, wrapped in paste marks when the program in
+/// the shell asked for them. The marks are removed from the text so it cannot end the paste
+/// early.
+pub fn paste_input(text: &str, bracketed: bool) -> Vec<u8> {
+    if !bracketed {
+        return text.as_bytes().to_vec();
+    }
+    let text = text.replace(PASTE_START, "").replace(PASTE_END, "");
+    format!("{}{}{}", PASTE_START, text, PASTE_END).into_bytes()
+}
+
+/// Pasted `text` as a queued command, with the terminal's `\r` line breaks turned into `\n`
+pub fn paste_command(text: &str) -> String {
+    text.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+/// The overlay asking what to do with a paste over `limit` bytes, each line cut to `cols`
+/// characters
+pub fn render_paste_prompt(text: &str, limit: u64, cols: u16) -> Vec<String> {
+    let command = paste_command(text);
+    let line_count = command.lines().count();
+    let mut lines = vec![
+        format!(
+            "typeypipe - large paste: {} line(s), {} (--paste-guard is {})",
+            line_count,
+            format_size(text.len() as u64),
+            format_size(limit)
+        ),
+        String::new(),
+    ];
+    for line in command.lines().take(PREVIEW_LINES) {
+        lines.push(format!("  {}", line));
+    }
+    if line_count > PREVIEW_LINES {
+        lines.push(format!("  ... {} more line(s)", line_count - PREVIEW_LINES));
+    }
+    lines.extend([
+        String::new(),
+        "  p          Paste it into the shell".to_string(),
+        "  q          Queue it as a command".to_string(),
+        "  Other key  Discard it".to_string(),
+    ]);
+    lines
+        .into_iter()
+        .map(|line| line.chars().take(cols as usize).collect())
+        .collect()
+}
+
+fn format_size(bytes: u64) -> String {
+    match bytes {
+        bytes if bytes >= 1024 * 1024 => format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0)),
+        bytes if bytes >= 1024 => format!("{:.1} KB", bytes as f64 / 1024.0),
+        bytes => format!("{} bytes", bytes),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{paste_command, paste_input, render_paste_prompt};
+
+    #[test]
+    fn test_large_paste_is_described_and_wrapped() {
+        let text = "one\rtwo\rthree\rfour\r";
+        let prompt = render_paste_prompt(text, 16, 80);
+        assert_eq!(
+            prompt[0],
+            "typeypipe - large paste: 4 line(s), 19 bytes (--paste-guard is 16 bytes)"
+        );
+        assert_eq!(
+            prompt[2..6],
+            ["  one", "  two", "  three", "  ... 1 more line(s)"]
+        );
+        assert_eq!(paste_command(text), "one\ntwo\nthree\nfour\n");
+
+        assert_eq!(paste_input("ls\r", false), b"ls\r");
+        assert_eq!(
+            paste_input("a\x1b[201~b", true),
+            b"\x1b[200~ab\x1b[201~".to_vec()
+        );
+    }
+}
@@ -0,0 +1,228 @@
+use crate::shell::logs::split_timestamp;
+use anyhow::{anyhow, Context, Result};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
+use std::io::{IsTerminal, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// Longest wait between checks for keys while playing
+const KEY_POLL: Duration = Duration::from_millis(50);
+
+/// Playback speeds reached with `+` and `-`
+const MIN_SPEED: f64 = 0.125;
+const MAX_SPEED: f64 = 64.0;
+
+/// What `typeypipe play` replays and how
+#[derive(Debug, Clone)]
+pub struct PlayOptions {
+    /// A `--record` transcript or an asciicast v2 recording
+    pub path: PathBuf,
+    /// Multiplier for the recorded timing
+    pub speed: f64,
+    /// Pauses longer than this are shortened to it
+    pub idle_limit: Option<Duration>,
+}
+
+/// Output to write at a point in the recording
+#[derive(Debug, Clone, PartialEq)]
+struct Frame {
+    at: Duration,
+    data: String,
+}
+
+/// Frames of a recording, detected by its first line: asciicast files start with a JSON
+/// header, transcripts with a timestamp
+fn parse_recording(text: &str) -> Result<Vec<Frame>> {
+    match text.lines().next() {
+        Some(first) if first.trim_start().starts_with('{') => parse_cast(text),
+        Some(first) if split_timestamp(first).is_some() => parse_transcript(text),
+        Some(_) => Err(anyhow!(
+            "Not a transcript or asciicast recording; if it was written with --encrypt-to, decrypt it first with `typeypipe decrypt`"
+        )),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Each `[... UTC]` line of a transcript as a frame, timed from the first one
+fn parse_transcript(text: &str) -> Result<Vec<Frame>> {
+    let mut frames = Vec::new();
+    let mut start = None;
+    for line in text.split_inclusive('\n') {
+        let (time, rest) = split_timestamp(line)
+            .ok_or_else(|| anyhow!("Transcript line without a timestamp: {}", line.trim_end()))?;
+        let start = *start.get_or_insert(time);
+        frames.push(Frame {
+            at: (time - start).to_std().unwrap_or_default(),
+            data: rest.to_string(),
+        });
+    }
+    Ok(frames)
+}
+
+/// The output events (`[time, "o", data]`) of an asciicast v2 recording
+fn parse_cast(text: &str) -> Result<Vec<Frame>> {
+    let mut lines = text.lines();
+    let header: serde_json::Value = serde_json::from_str(lines.next().unwrap_or_default())
+        .context("Invalid asciicast header")?;
+    if header["version"] != 2 {
+        return Err(anyhow!(
+            "Unsupported asciicast version {} (only version 2 can be played)",
+            header["version"]
+        ));
+    }
+
+    let mut frames = Vec::new();
+    for (index, line) in lines.enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event: (f64, String, String) = serde_json::from_str(line)
+            .with_context(|| format!("Invalid asciicast event on line {}", index + 2))?;
+        if event.1 == "o" {
+            frames.push(Frame {
+                at: Duration::from_secs_f64(event.0.max(0.0)),
+                data: event.2,
+            });
+        }
+    }
+    Ok(frames)
+}
+
+/// Shorten every pause between frames to at most `limit`
+fn limit_idle(frames: &mut [Frame], limit: Duration) {
+    let mut removed = Duration::ZERO;
+    let mut previous = Duration::ZERO;
+    for frame in frames.iter_mut() {
+        let gap = frame.at.saturating_sub(previous);
+        previous = frame.at;
+        removed += gap.saturating_sub(limit);
+        frame.at -= removed;
+    }
+}
+
+/// Leaves raw mode when playback ends, however it ends
+struct RawMode;
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = disable_raw_mode();
+    }
+}
+
+/// Replay a recording in the terminal with its original timing.
+///
+/// **Keys** (when stdin is a terminal):
+/// - Space: pause or resume
+/// - `+` / `-`: double or halve the speed
+/// - `.`: while paused, show the next frame
+/// - `q`, Esc or Ctrl+C: stop
+pub fn play(options: &PlayOptions) -> Result<()> {
+    let text = std::fs::read_to_string(&options.path)
+        .with_context(|| format!("Failed to read {}", options.path.display()))?;
+    let mut frames = parse_recording(&text)
+        .with_context(|| format!("Cannot play {}", options.path.display()))?;
+    if let Some(limit) = options.idle_limit {
+        limit_idle(&mut frames, limit);
+    }
+
+    let raw_mode = if std::io::stdin().is_terminal() {
+        enable_raw_mode().context("Failed to enable raw mode")?;
+        Some(RawMode)
+    } else {
+        None
+    };
+    let mut stdout = std::io::stdout().lock();
+    let mut speed = options.speed;
+    let mut paused = false;
+    let mut position = Duration::ZERO;
+    let mut last_tick = Instant::now();
+    let mut next = 0;
+
+    while next < frames.len() {
+        let now = Instant::now();
+        if !paused {
+            position += now.duration_since(last_tick).mul_f64(speed);
+        }
+        last_tick = now;
+
+        while next < frames.len() && frames[next].at <= position {
+            stdout.write_all(frames[next].data.as_bytes())?;
+            next += 1;
+        }
+        stdout.flush()?;
+        let Some(frame) = frames.get(next) else {
+            break;
+        };
+
+        let wait = if paused {
+            KEY_POLL
+        } else {
+            frame
+                .at
+                .saturating_sub(position)
+                .div_f64(speed)
+                .min(KEY_POLL)
+        };
+        if raw_mode.is_none() {
+            std::thread::sleep(wait);
+            continue;
+        }
+        if !event::poll(wait).context("Failed to poll for keys")? {
+            continue;
+        }
+        let Event::Key(key) = event::read().context("Failed to read key")? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => break,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => break,
+            KeyCode::Char(' ') => paused = !paused,
+            KeyCode::Char('+' | '=') => speed = (speed * 2.0).min(MAX_SPEED),
+            KeyCode::Char('-') => speed = (speed / 2.0).max(MIN_SPEED),
+            KeyCode::Char('.') if paused => position = frame.at,
+            _ => {}
+        }
+    }
+
+    // Leave the terminal's colors and cursor as they were before playback
+    stdout.write_all(b"\x1b[0m\x1b[?25h\r\n")?;
+    stdout.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{limit_idle, parse_recording, Frame};
+    use std::time::Duration;
+
+    #[test]
+    fn test_recordings_are_parsed_into_timed_frames() {
+        let transcript = "[2025-03-01 10:07:12.000 UTC] $ make\r\n\
+                          [2025-03-01 10:07:12.500 UTC] ok\r\n\
+                          [2025-03-01 10:07:42.500 UTC] $ \n";
+        let mut frames = parse_recording(transcript).unwrap();
+        assert_eq!(
+            frames[1],
+            Frame {
+                at: Duration::from_millis(500),
+                data: "ok\r\n".to_string(),
+            }
+        );
+        limit_idle(&mut frames, Duration::from_secs(2));
+        assert_eq!(frames[2].at, Duration::from_millis(2500));
+
+        let cast = "{\"version\": 2, \"width\": 80, \"height\": 24}\n\
+                    [0.25, \"o\", \"hello\"]\n[0.5, \"i\", \"x\"]\n[1.0, \"o\", \"\\u001b[0m\"]\n";
+        let frames = parse_recording(cast).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[1].at, Duration::from_secs(1));
+        assert_eq!(frames[1].data, "\x1b[0m");
+
+        assert!(parse_recording("YWdlLWVuY3J5cHRpb24=\n").is_err());
+        assert!(parse_recording("{\"version\": 1, \"stdout\": []}").is_err());
+    }
+}
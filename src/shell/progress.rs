@@ -0,0 +1,207 @@
+use crate::shell::arbiter::PromptMarks;
+use crate::shell::filter::{OutputFilter, StripAnsi};
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Progress not updated for this long is no longer reported; without shell integration
+/// there is no prompt to say the command has finished
+const PROGRESS_STALE_AFTER: Duration = Duration::from_secs(30);
+
+/// Longest line examined; progress lines are short, and log lines this long rarely matter
+const MAX_LINE_BYTES: usize = 1024;
+
+/// Shortest `[####    ]` bar taken for a progress bar rather than a bracketed word
+const MIN_BAR_WIDTH: usize = 10;
+
+/// Progress of the running command as last printed, normalised to a fraction
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Progress {
+    /// Between 0 and 1
+    pub fraction: f64,
+    /// The output line it was read from, without escape sequences
+    pub line: String,
+}
+
+/// The progress a session's `ProgressTracker` last read
+#[derive(Debug, Default)]
+pub struct LatestProgress {
+    progress: Mutex<Option<(Progress, Instant)>>,
+}
+
+impl LatestProgress {
+    /// The most recent progress, unless the command has finished or gone quiet
+    pub fn current(&self) -> Option<Progress> {
+        let progress = self.progress.lock().ok()?;
+        let (progress, updated_at) = progress.as_ref()?;
+        (updated_at.elapsed() < PROGRESS_STALE_AFTER).then(|| progress.clone())
+    }
+
+    fn set(&self, progress: Option<Progress>) {
+        if let Ok(mut latest) = self.progress.lock() {
+            *latest = progress.map(|progress| (progress, Instant::now()));
+        }
+    }
+}
+
+/// Reads progress from shell output line by line, where a carriage return also ends a line so
+/// redrawn progress bars are seen on every redraw.
+///
+/// **Recognised, first match wins:**
+/// - Percentages: `42%`, `99.5%`
+/// - Counters: `3/10`, `[ 12/250]` (not dates or paths such as `12/25/2024` or `a/b`)
+/// - Bars: `[#####     ]`, `[=====>    ]`, `|█████     |`
+#[derive(Default)]
+pub struct ProgressTracker {
+    strip: StripAnsi,
+    line: Vec<u8>,
+    prompts: PromptMarks,
+}
+
+impl ProgressTracker {
+    /// Read a chunk of output, keeping what it reports in `latest`
+    pub fn feed(&mut self, chunk: &[u8], latest: &LatestProgress) {
+        if self.prompts.feed(chunk) {
+            self.line.clear();
+            latest.set(None);
+        }
+
+        let mut text = Vec::with_capacity(chunk.len());
+        for (index, piece) in chunk.split(|&byte| byte == b'\r').enumerate() {
+            if index > 0 {
+                self.end_line(latest);
+            }
+            text.clear();
+            self.strip.apply(piece, &mut text);
+            for &byte in &text {
+                if byte == b'\n' {
+                    self.end_line(latest);
+                } else if self.line.len() < MAX_LINE_BYTES {
+                    self.line.push(byte);
+                }
+            }
+        }
+        // A bar being redrawn may not have its line ended yet
+        check(&self.line, latest);
+    }
+
+    fn end_line(&mut self, latest: &LatestProgress) {
+        let line = std::mem::take(&mut self.line);
+        check(&line, latest);
+    }
+}
+
+fn check(line: &[u8], latest: &LatestProgress) {
+    let line = String::from_utf8_lossy(line);
+    if let Some(fraction) = parse_progress(&line) {
+        latest.set(Some(Progress {
+            fraction,
+            line: line.trim().to_string(),
+        }));
+    }
+}
+
+/// The progress a line reports, as a fraction between 0 and 1
+pub fn parse_progress(line: &str) -> Option<f64> {
+    percentage(line)
+        .or_else(|| counter(line))
+        .or_else(|| bar(line))
+}
+
+fn percentage(line: &str) -> Option<f64> {
+    let chars: Vec<char> = line.chars().collect();
+    (0..chars.len())
+        .rev()
+        .filter(|&i| chars[i] == '%')
+        .find_map(|end| {
+            let start = (0..end)
+                .rev()
+                .take_while(|&i| chars[i].is_ascii_digit() || chars[i] == '.')
+                .last()?;
+            let value: f64 = chars[start..end].iter().collect::<String>().parse().ok()?;
+            (0.0..=100.0).contains(&value).then_some(value / 100.0)
+        })
+}
+
+fn counter(line: &str) -> Option<f64> {
+    let chars: Vec<char> = line.chars().collect();
+    let is_part = |c: char| c.is_alphanumeric() || c == '/' || c == '.' || c == '-';
+    (0..chars.len())
+        .rev()
+        .filter(|&i| chars[i] == '/')
+        .find_map(|slash| {
+            let start = (0..slash)
+                .rev()
+                .take_while(|&i| chars[i].is_ascii_digit())
+                .last()?;
+            let end = (slash + 1..chars.len())
+                .take_while(|&i| chars[i].is_ascii_digit())
+                .last()?
+                + 1;
+            if start > 0 && is_part(chars[start - 1]) || end < chars.len() && is_part(chars[end]) {
+                return None;
+            }
+            let done: u64 = chars[start..slash]
+                .iter()
+                .collect::<String>()
+                .parse()
+                .ok()?;
+            let total: u64 = chars[slash + 1..end]
+                .iter()
+                .collect::<String>()
+                .parse()
+                .ok()?;
+            (total > 0 && done <= total).then(|| done as f64 / total as f64)
+        })
+}
+
+fn bar(line: &str) -> Option<f64> {
+    let is_fill = |c: char| matches!(c, '#' | '=' | '>' | '█' | '▓');
+    let is_empty = |c: char| matches!(c, ' ' | '-' | '.' | '░' | '▒' | '·');
+
+    let chars: Vec<char> = line.chars().collect();
+    (0..chars.len()).find_map(|open| {
+        let close = match chars[open] {
+            '[' => ']',
+            '|' => '|',
+            _ => return None,
+        };
+        let inner: Vec<char> = chars[open + 1..]
+            .iter()
+            .copied()
+            .take_while(|&c| c != close)
+            .collect();
+        let closed = chars.get(open + 1 + inner.len()) == Some(&close);
+        let filled = inner.iter().filter(|&&c| is_fill(c)).count();
+        (closed
+            && inner.len() >= MIN_BAR_WIDTH
+            && filled > 0
+            && inner.iter().all(|&c| is_fill(c) || is_empty(c)))
+        .then(|| filled as f64 / inner.len() as f64)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_progress;
+
+    #[test]
+    fn test_progress_is_read_from_common_formats() {
+        assert_eq!(parse_progress("Downloading foo.tar.gz  42%"), Some(0.42));
+        assert_eq!(
+            parse_progress("Receiving objects: 99.5% (199/200)"),
+            Some(0.995)
+        );
+        assert_eq!(
+            parse_progress("   Compiling serde v1.0 [ 12/48]"),
+            Some(0.25)
+        );
+        assert_eq!(parse_progress("[#####     ] eta 3s"), Some(0.5));
+        assert_eq!(parse_progress("|██████████          |"), Some(0.5));
+
+        assert_eq!(parse_progress("released 12/25/2024"), None);
+        assert_eq!(parse_progress("see src/3/4 and a/b"), None);
+        assert_eq!(parse_progress("[INFO] starting"), None);
+        assert_eq!(parse_progress("load 150%"), None);
+    }
+}
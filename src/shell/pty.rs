@@ -1,7 +1,8 @@
-use crate::shell::types::{CommandResult, ShellConfig};
+use crate::shell::scrollback::{create_scrollback, SharedScrollback};
+use crate::shell::types::{SessionTarget, ShellConfig};
 use anyhow::{Context, Result};
 use portable_pty::{native_pty_system, Child, CommandBuilder, MasterPty, PtySize};
-use std::io::{Read, Write};
+use std::io::Write;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use uuid::Uuid;
@@ -25,6 +26,7 @@ pub struct PtySession {
     pty_parent: Box<dyn MasterPty + Send>,
     pty_writer: Option<Box<dyn Write + Send>>,
     child: Box<dyn Child + Send + Sync>,
+    scrollback: SharedScrollback,
 }
 
 impl std::fmt::Debug for PtySession {
@@ -37,14 +39,13 @@ impl std::fmt::Debug for PtySession {
             .field("pty_parent", &"<pty_parent>")
             .field("pty_writer", &"<pty_writer>")
             .field("child", &"<child>")
+            .field("scrollback", &"<scrollback>")
             .finish()
     }
 }
 
 impl PtySession {
     pub async fn new(config: ShellConfig) -> Result<Self> {
-        let session_id = format!("tp-{}", &Uuid::new_v4().to_string()[..8]);
-
         let pty_system = native_pty_system();
         let pty_pair = pty_system
             .openpty(PtySize {
@@ -55,16 +56,24 @@ impl PtySession {
             })
             .context("Failed to create PTY pair")?;
 
-        let mut cmd = CommandBuilder::new(&config.shell_path);
-        cmd.env("TERM", "xterm-256color");
+        let cmd = build_shell_command(&config);
 
         let child = pty_pair
             .slave
             .spawn_command(cmd)
             .context("Failed to spawn shell in PTY")?;
 
-        let writer = pty_pair
-            .master
+        Self::from_parts(config, pty_pair.master, child)
+    }
+
+    /// A session over a PTY and shell created elsewhere, such as a `MockPty`
+    pub fn from_parts(
+        config: ShellConfig,
+        pty_parent: Box<dyn MasterPty + Send>,
+        child: Box<dyn Child + Send + Sync>,
+    ) -> Result<Self> {
+        let session_id = format!("tp-{}", &Uuid::new_v4().to_string()[..8]);
+        let writer = pty_parent
             .take_writer()
             .context("Failed to get PTY writer")?;
 
@@ -73,16 +82,17 @@ impl PtySession {
             shell_path: config.shell_path,
             cols: config.cols,
             rows: config.rows,
-            pty_parent: pty_pair.master,
+            pty_parent,
             pty_writer: Some(writer),
             child,
+            scrollback: create_scrollback(config.scroll_buffer_size),
         })
     }
 
-    pub fn send_input(&mut self, input: &str) -> Result<()> {
+    pub fn send_bytes(&mut self, bytes: &[u8]) -> Result<()> {
         if let Some(writer) = &mut self.pty_writer {
             writer
-                .write_all(input.as_bytes())
+                .write_all(bytes)
                 .context("Failed to write input to PTY parent")?;
             writer.flush().context("Failed to flush PTY writer")?;
             Ok(())
@@ -91,31 +101,30 @@ impl PtySession {
         }
     }
 
-    /// Get currently available output from PTY buffer
-    pub fn get_available_output(&mut self) -> Result<String> {
-        let mut buffer = [0u8; 4096];
-        let mut reader = self
-            .pty_parent
-            .try_clone_reader()
-            .context("Failed to get PTY reader")?;
-        match reader
-            .read(&mut buffer)
-            .context("Failed to read from PTY parent")
-        {
-            Ok(bytes_read) => {
-                let output = String::from_utf8_lossy(&buffer[..bytes_read]);
-                Ok(output.to_string())
-            }
-            Err(_) => Ok("No output available".to_string()),
-        }
-    }
-
     pub fn session_id(&self) -> &str {
         &self.session_id
     }
 
-    pub fn is_alive(&mut self) -> bool {
-        self.child.try_wait().is_ok()
+    /// Current terminal size as (rows, cols)
+    pub fn size(&self) -> (u16, u16) {
+        (self.rows, self.cols)
+    }
+
+    /// Process ID of the wrapped shell, when the platform reports one
+    pub fn child_pid(&self) -> Option<u32> {
+        self.child.process_id()
+    }
+
+    /// The shell's exit code once it has exited, without blocking
+    pub fn try_wait(&mut self) -> Result<Option<u32>> {
+        self.child
+            .try_wait()
+            .map(|status| status.map(|status| status.exit_code()))
+            .context("Failed to check shell status")
+    }
+
+    pub fn kill(&mut self) -> Result<()> {
+        self.child.kill().context("Failed to kill shell")
     }
 
     pub fn resize(&mut self, rows: u16, cols: u16) -> Result<()> {
@@ -126,7 +135,17 @@ impl PtySession {
             pixel_height: 0,
         };
 
-        self.pty_parent.resize(size).context("Failed to resize PTY")
+        self.pty_parent
+            .resize(size)
+            .context("Failed to resize PTY")?;
+        self.rows = rows;
+        self.cols = cols;
+        Ok(())
+    }
+
+    /// Shared handle to the output history of this session
+    pub fn scrollback(&self) -> SharedScrollback {
+        self.scrollback.clone()
     }
 
     /// Take the PTY writer for external use
@@ -142,6 +161,46 @@ impl PtySession {
     }
 }
 
+/// Build the command that starts the shell for `config.target`
+fn build_shell_command(config: &ShellConfig) -> CommandBuilder {
+    match &config.target {
+        SessionTarget::Local => {
+            let mut cmd = CommandBuilder::new(&config.shell_path);
+            cmd.env("TERM", &config.term);
+            match &config.colorterm {
+                Some(colorterm) => cmd.env("COLORTERM", colorterm),
+                None => cmd.env_remove("COLORTERM"),
+            }
+            for (key, value) in &config.env {
+                cmd.env(key, value);
+            }
+            if let Some(cwd) = &config.cwd {
+                cmd.cwd(cwd);
+            }
+            cmd
+        }
+        SessionTarget::Container { engine, container } => {
+            // The container does not inherit our environment, so it is passed explicitly
+            let mut cmd = CommandBuilder::new(engine);
+            cmd.args(["exec", "-it", "-e"]);
+            cmd.arg(format!("TERM={}", config.term));
+            if let Some(colorterm) = &config.colorterm {
+                cmd.args(["-e", &format!("COLORTERM={}", colorterm)]);
+            }
+            for (key, value) in &config.env {
+                cmd.args(["-e", &format!("{}={}", key, value)]);
+            }
+            if let Some(cwd) = &config.cwd {
+                cmd.arg("-w");
+                cmd.arg(cwd);
+            }
+            cmd.arg(container);
+            cmd.arg(&config.shell_path);
+            cmd
+        }
+    }
+}
+
 impl Drop for PtySession {
     fn drop(&mut self) {
         let _ = self.child.kill();
@@ -169,7 +228,7 @@ impl Drop for PtySession {
 /// - Borrow checker conflicts when multiple components need mutable access
 ///
 /// **With this wrapper:** We get:
-/// - Safe concurrent access to PTY operations (send_input, get_available_output, resize)
+/// - Safe concurrent access to PTY operations (send_bytes, resize)
 /// - Coordinated access prevents conflicts between user input and queue injection
 /// - Clean separation between interactive and automated PTY usage
 pub type SharedPtySession = Arc<Mutex<PtySession>>;
@@ -179,113 +238,3 @@ pub async fn create_pty_session(config: ShellConfig) -> Result<SharedPtySession>
     let session = PtySession::new(config).await?;
     Ok(Arc::new(Mutex::new(session)))
 }
-
-/// The PtySessionManager serves as a higher-level wrapper around the core PtySession,
-/// providing async-friendly interfaces and additional functionality:
-///
-/// **Core Responsibilities:**
-/// - **Async Interface**: Wraps synchronous PtySession methods with async/await support
-/// - **Session Management**: Maintains session metadata (ID, dimensions) for external reference
-/// - **Input Processing**: Provides raw input via `send_input` (callers can add newlines as needed)
-/// - **Output Handling**: Offers convenient method to read PTY output as strings
-/// - **Queue Command Processing**: Processes commands from external queue with proper timing and output capture
-/// - **Session Lifecycle**: Handles session resizing
-///
-/// **Architecture:**
-/// - Wraps a `SharedPtySession` (Arc<Mutex<PtySession>>) for thread-safe access
-/// - Maintains cached session metadata to avoid locking the inner session for basic queries
-/// - Provides async methods that properly handle mutex locking and PTY operations
-///
-/// **Usage Pattern:**
-/// ```rust,no_run,ignore
-/// let config = ShellConfig::default();
-/// let manager = PtySessionManager::new(config).await?;
-///
-/// // Send commands
-/// manager.send_input(&format!("{}\n", "ls -la")).await?;
-///
-/// // Read output
-/// let output = manager.get_available_output().await?;
-///
-/// // Process queue commands with timing and output capture
-/// let result = manager.process_queue_command("echo hello").await?;
-/// ```
-#[derive(Debug)]
-pub struct PtySessionManager {
-    inner_session: SharedPtySession,
-    session_id: String,
-}
-
-impl PtySessionManager {
-    pub async fn new(config: ShellConfig) -> Result<Self> {
-        let inner_session = create_pty_session(config.clone()).await?;
-        let session_id = {
-            let guard = inner_session.lock().await;
-            guard.session_id().to_string()
-        };
-
-        Ok(Self {
-            inner_session,
-            session_id,
-        })
-    }
-
-    pub async fn send_input(&self, input: &str) -> Result<()> {
-        let mut session_guard = self.inner_session.lock().await;
-        session_guard.send_input(input)
-    }
-
-    pub async fn get_available_output(&self) -> Result<String> {
-        let mut session_guard = self.inner_session.lock().await;
-        session_guard.get_available_output()
-    }
-
-    pub async fn process_queue_command(&self, command: &str) -> Result<CommandResult> {
-        self.send_input(&format!("{}\n", command))
-            .await
-            .context("Failed to send queue command to terminal")?;
-
-        // Wait a bit for the command to process
-        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
-
-        let output = self
-            .get_available_output()
-            .await
-            .unwrap_or_else(|_| "Command executed".to_string());
-
-        Ok(CommandResult {
-            output,
-            success: true,
-        })
-    }
-
-    pub fn session_id(&self) -> &str {
-        &self.session_id
-    }
-
-    pub async fn resize(&self, cols: u16, rows: u16) -> Result<()> {
-        let mut session_guard = self.inner_session.lock().await;
-        session_guard.resize(rows, cols)
-    }
-}
-
-pub type SharedPtySessionManager = Arc<Mutex<PtySessionManager>>;
-
-pub async fn create_pty_session_manager(config: ShellConfig) -> Result<SharedPtySessionManager> {
-    let session = PtySessionManager::new(config).await?;
-    Ok(Arc::new(Mutex::new(session)))
-}
-
-pub async fn pty_manager_write_line(session: &SharedPtySessionManager, text: &str) -> Result<()> {
-    let session_guard = session.lock().await;
-    session_guard.send_input(&format!("{}\n", text)).await
-}
-
-pub async fn pty_manager_execute_and_wait(
-    session: &SharedPtySessionManager,
-    command: &str,
-    _timeout_ms: u64,
-) -> Result<CommandResult> {
-    let session_guard = session.lock().await;
-    session_guard.process_queue_command(command).await
-}
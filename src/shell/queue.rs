@@ -1,8 +1,16 @@
+use crate::shell::context::SessionContext;
+use crate::shell::durability::sync_required;
+use crate::shell::glyphs::label;
+use crate::shell::injection::{injected, prepare_injection, write_failed};
 use crate::shell::pty::SharedPtySession;
-use crate::shell::types::CommandResult;
+use crate::shell::rotation::{rotate_if_needed, rotation_policy};
+use crate::shell::status::{is_process_alive, status_file_path, SessionStatus};
+use crate::shell::types::{CommandResult, QueueFailure, QueueMessage};
 use anyhow::{Context, Result};
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
 /// The PtyQueueProcessor enables external applications to send commands to a running shell
 /// session through a file-based queue system, providing programmatic control over interactive
@@ -11,6 +19,7 @@ use std::path::PathBuf;
 /// **Core Responsibilities:**
 /// - **File-Based Queue Processing**: Monitors a directory for command files and processes them chronologically
 /// - **Command Injection**: Safely injects commands from queue files into the PTY session
+/// - **Shared Checks**: Applies the authorizer, routing and command hook of interactive sessions
 /// - **Logging & Audit Trail**: Logs processed commands with timestamps
 /// - **Error Handling**: Graceful handling of malformed commands, file I/O errors, and PTY communication issues
 /// - **File Cleanup**: Automatic removal of processed command files to prevent reprocessing
@@ -23,10 +32,19 @@ use std::path::PathBuf;
 ///
 /// **Queue File Format:**
 /// - All files placed in the queue directory are processed
-/// - File contents are sent exactly as stored to the PTY (including or excluding newlines)
+/// - Contents are decoded as a `QueueMessage`; anything that is not an encoded message is a
+///   plain command, with surrounding whitespace and newlines trimmed
 /// - Files are processed by modification time (oldest first)
 /// - Files are automatically removed after successful processing
 ///
+/// **Pipeline** (see `injection::prepare_injection`):
+/// - Decode: undecodable files are dropped and reported as `InvalidMessage`
+/// - Authorize: the session's `Authorizer` may refuse the message
+/// - Route: messages addressed to another session are moved to its queue instead
+/// - Hook: commands pass through the command hook, which may rewrite or reject them
+/// - Terminate: commands get the session's terminator unless the message chose its own;
+///   input, keys and bytes are written as given
+///
 /// External applications can send commands by creating temporary files and atomically
 /// moving them to the queue directory:
 /// ```bash
@@ -37,6 +55,7 @@ pub struct PtyQueueProcessor {
     session: SharedPtySession,
     queue_dir: PathBuf,
     log_file: PathBuf,
+    context: Arc<SessionContext>,
 }
 
 impl PtyQueueProcessor {
@@ -44,14 +63,19 @@ impl PtyQueueProcessor {
         session: SharedPtySession,
         queue_dir: PathBuf,
         log_file: PathBuf,
+        context: Arc<SessionContext>,
     ) -> Result<Self> {
         Ok(Self {
             session,
             queue_dir,
             log_file,
+            context,
         })
     }
 
+    /// Inject every file in the queue, returning whether each was written to the shell. The
+    /// command's own output and exit code are only known at the next prompt, so they are
+    /// left empty here; a control socket `run` waits for them.
     pub async fn process_queue(&self) -> Result<HashMap<String, CommandResult>> {
         use tokio::fs;
 
@@ -61,9 +85,18 @@ impl PtyQueueProcessor {
             .await
             .context("Failed to read queue directory")?;
 
+        // Oldest first; files written within the same clock tick keep their name order
+        let mut paths = Vec::new();
         while let Some(entry) = entries.next_entry().await? {
-            let path = entry.path();
+            let modified = entry
+                .metadata()
+                .await
+                .and_then(|metadata| metadata.modified());
+            paths.push((modified.ok(), entry.path()));
+        }
+        paths.sort();
 
+        for (_, path) in paths {
             let filename = path
                 .file_name()
                 .and_then(|n| n.to_str())
@@ -71,57 +104,55 @@ impl PtyQueueProcessor {
                 .to_string();
 
             match fs::read_to_string(&path).await {
-                Ok(command) => {
-                    let command = command.trim();
+                Ok(contents) => {
+                    let started_at = chrono::Utc::now();
+                    let Some(injection) =
+                        prepare_injection(&self.context, &path, &contents, &self.log_file).await
+                    else {
+                        continue;
+                    };
                     let _ = self
                         .log_message(&format!(
                             "🔄 Processing queue file: {} -> {}",
-                            filename, command
+                            filename, injection.command
                         ))
                         .await;
 
-                    let result: Result<CommandResult> = {
-                        let mut session_guard = self.session.lock().await;
-                        let command_with_newline = format!("{}\n", command);
-                        session_guard.send_input(&command_with_newline)?;
-
-                        Ok(CommandResult {
-                            output: "Command sent to shell".to_string(),
-                            success: true,
-                        })
-                    };
-
-                    match result {
-                        Ok(cmd_result) => {
-                            results.insert(filename.clone(), cmd_result);
-
-                            // Remove the processed file
-                            if let Err(e) = fs::remove_file(&path).await {
-                                let _ = self
-                                    .log_message(&format!(
-                                        "⚠️  Warning: Failed to remove queue file {}: {}",
-                                        filename, e
-                                    ))
-                                    .await;
-                            } else {
-                                let _ = self
-                                    .log_message(&format!("✅ Completed and removed: {}", filename))
-                                    .await;
-                            }
-                        }
-                        Err(e) => {
+                    self.context
+                        .record_input(&injection.source, &injection.bytes);
+                    let written = self.session.lock().await.send_bytes(&injection.bytes);
+                    let output = match written {
+                        Ok(()) => {
+                            injected(&self.context, &path, &injection).await;
                             let _ = self
-                                .log_message(&format!("❌ Error processing {}: {}", filename, e))
+                                .log_message(&format!("✅ Completed and removed: {}", filename))
                                 .await;
-                            results.insert(
-                                filename,
-                                CommandResult {
-                                    output: format!("Error: {}", e),
-                                    success: false,
-                                },
-                            );
+                            Ok("Command sent to shell".to_string())
                         }
-                    }
+                        Err(e) => {
+                            write_failed(
+                                &self.context,
+                                &path,
+                                &injection,
+                                &format!("{:#}", e),
+                                &self.log_file,
+                            )
+                            .await;
+                            Err(format!("Error: {}", e))
+                        }
+                    };
+                    results.insert(
+                        filename,
+                        CommandResult {
+                            success: output.is_ok(),
+                            output: output.unwrap_or_else(|error| error),
+                            exit_code: None,
+                            started_at,
+                            finished_at: chrono::Utc::now(),
+                            stdout_bytes: 0,
+                            truncated: false,
+                        },
+                    );
                 }
                 Err(e) => {
                     let _ = self
@@ -168,22 +199,166 @@ impl PtyQueueProcessor {
         }
     }
 
-    async fn log_message(&self, message: &str) -> Result<()> {
-        use tokio::io::AsyncWriteExt;
-        let timestamp = chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC");
-        let log_entry = format!("[{}] {}\n", timestamp, message);
+    pub(crate) async fn log_message(&self, message: &str) -> Result<()> {
+        log_to_file(&self.log_file, message).await
+    }
+}
+
+/// Append a timestamped line to a session log, which lives next to the session's queue
+/// directory inside the .tp directory
+pub(crate) async fn log_to_file(log_file: &Path, message: &str) -> Result<()> {
+    use tokio::io::AsyncWriteExt;
+    let timestamp = chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC");
+    let log_entry = format!("[{}] {}\n", timestamp, label(message));
 
-        let mut file = tokio::fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&self.log_file)
-            .await
-            .context("Failed to open log file")?;
+    let _ = rotate_if_needed(log_file, &rotation_policy());
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_file)
+        .await
+        .context("Failed to open log file")?;
+
+    file.write_all(log_entry.as_bytes())
+        .await
+        .context("Failed to write to log file")?;
+    file.flush().await.context("Failed to flush log file")?;
+    if sync_required(log_file) {
+        file.sync_data().await.context("Failed to sync log file")?;
+    }
+    Ok(())
+}
+
+/// Add a file containing `contents` to `queue_dir` the same way external producers should:
+/// write it next to the queue directory first, then atomically move it in.
+pub async fn enqueue_file(queue_dir: &Path, prefix: &str, contents: &str) -> Result<PathBuf> {
+    let filename = format!(
+        "{}-{}-{}",
+        prefix,
+        chrono::Utc::now().format("%Y%m%d%H%M%S%3f"),
+        &uuid::Uuid::new_v4().to_string()[..8]
+    );
+    let staging_dir = queue_dir.parent().unwrap_or(queue_dir);
+    let staging_path = staging_dir.join(format!(".{}.tmp", filename));
+    let queue_path = queue_dir.join(&filename);
+
+    tokio::fs::write(&staging_path, contents)
+        .await
+        .context("Failed to write queue file")?;
+    tokio::fs::rename(&staging_path, &queue_path)
+        .await
+        .context("Failed to move file into queue directory")?;
 
-        file.write_all(log_entry.as_bytes())
+    Ok(queue_path)
+}
+
+/// Hand `message` to the running session named `target` in `tp_dir`, as a file in its queue
+/// named after the session it came from. Fails when no such session is running, so a
+/// misspelled target is reported instead of waiting in a queue nobody reads.
+pub async fn route_to_session(
+    tp_dir: &Path,
+    target: &str,
+    from: &str,
+    message: &QueueMessage,
+) -> Result<PathBuf> {
+    let status = SessionStatus::read(&status_file_path(tp_dir, target))
+        .map_err(|_| anyhow::anyhow!("Unknown target session '{}'", target))?;
+    if !is_process_alive(status.pid) {
+        return Err(anyhow::anyhow!(
+            "Target session '{}' is not running",
+            target
+        ));
+    }
+    let queue_dir = status
+        .queue_dir
+        .ok_or_else(|| anyhow::anyhow!("Target session '{}' has no queue directory", target))?;
+
+    enqueue_file(&queue_dir, &format!("from-{}", from), &message.encode()?).await
+}
+
+/// Where the failure of `queue_file` is reported: `<queue dir>.failed/<file>.json`, outside
+/// the queue so the report is never injected itself
+pub fn failure_path(queue_file: &Path) -> Option<PathBuf> {
+    let queue_dir = queue_file.parent()?;
+    let name = queue_dir.file_name()?.to_string_lossy();
+    let file = queue_file.file_name()?.to_string_lossy();
+    Some(
+        queue_dir
+            .with_file_name(format!("{}.failed", name))
+            .join(format!("{}.json", file)),
+    )
+}
+
+/// Leave `failure` for the producer of `queue_file`. Written before the queue file is
+/// removed, so a producer waiting for the file to disappear finds the report.
+pub async fn report_failure(queue_file: &Path, failure: &QueueFailure) -> Result<()> {
+    let path = failure_path(queue_file)
+        .ok_or_else(|| anyhow::anyhow!("Queue file {} has no queue", queue_file.display()))?;
+    let dir = path.parent().unwrap_or(&path);
+    tokio::fs::create_dir_all(dir)
+        .await
+        .context("Failed to create failure directory")?;
+    let staging_path = dir.join(format!(".{}.tmp", failure.file));
+    tokio::fs::write(&staging_path, serde_json::to_vec(failure)?)
+        .await
+        .context("Failed to write failure report")?;
+    tokio::fs::rename(&staging_path, &path)
+        .await
+        .context("Failed to move failure report into place")
+}
+
+/// The failure reported for `queue_file`, removing the report so it is only seen once
+pub async fn take_failure(queue_file: &Path) -> Option<QueueFailure> {
+    let path = failure_path(queue_file)?;
+    let contents = tokio::fs::read(&path).await.ok()?;
+    let _ = tokio::fs::remove_file(&path).await;
+    serde_json::from_slice(&contents).ok()
+}
+
+/// Wait until the session has picked up `queue_file`: queue files are removed once their
+/// contents have been written to the shell. Waits forever when `timeout` is `None`.
+///
+/// Returns the `QueueFailure` as the error when the message was dropped instead.
+pub async fn wait_for_injection(queue_file: &Path, timeout: Option<Duration>) -> Result<()> {
+    let deadline = timeout.map(|timeout| tokio::time::Instant::now() + timeout);
+
+    while queue_file.exists() {
+        if deadline.is_some_and(|deadline| tokio::time::Instant::now() >= deadline) {
+            return Err(anyhow::anyhow!(
+                "Command is still queued (the session may be paused while a user is typing)"
+            ));
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    match take_failure(queue_file).await {
+        Some(failure) => Err(failure.into()),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::wait_for_injection;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_wait_for_injection_times_out_in_virtual_time() {
+        let temp_dir = TempDir::new().unwrap();
+        let queue_file = temp_dir.path().join("queue").join("cmd");
+        std::fs::create_dir_all(queue_file.parent().unwrap()).unwrap();
+        std::fs::write(&queue_file, "make").unwrap();
+
+        let started = tokio::time::Instant::now();
+        let real_start = std::time::Instant::now();
+        let error = wait_for_injection(&queue_file, Some(Duration::from_secs(600)))
             .await
-            .context("Failed to write to log file")?;
-        file.flush().await.context("Failed to flush log file")?;
-        Ok(())
+            .unwrap_err();
+
+        assert!(error.to_string().contains("still queued"), "{}", error);
+        assert!(started.elapsed() >= Duration::from_secs(600));
+        assert!(real_start.elapsed() < Duration::from_secs(5));
     }
 }
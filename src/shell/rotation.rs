@@ -0,0 +1,170 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime};
+
+/// Size and age limits for the files typeypipe appends to under `.tp/` (queue log, transcript).
+///
+/// When a file exceeds either limit it is renamed to `<file>.1`, older rotations shift up by
+/// one (`<file>.1` -> `<file>.2`, ...) and anything beyond `retain` rotations is deleted.
+/// Writers then continue with a fresh file at the original path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RotationPolicy {
+    /// Rotate once the file reaches this many bytes
+    pub max_bytes: Option<u64>,
+    /// Rotate once the file was created longer ago than this
+    pub max_age: Option<Duration>,
+    /// Number of rotated files to keep
+    pub retain: usize,
+}
+
+impl RotationPolicy {
+    pub const DISABLED: RotationPolicy = RotationPolicy {
+        max_bytes: None,
+        max_age: None,
+        retain: 5,
+    };
+
+    pub fn is_enabled(&self) -> bool {
+        self.max_bytes.is_some() || self.max_age.is_some()
+    }
+
+    /// Whether a file with the given size and creation time is due for rotation
+    pub fn should_rotate(&self, size: u64, created: Option<SystemTime>) -> bool {
+        if self.max_bytes.is_some_and(|max| size >= max) {
+            return true;
+        }
+
+        match (self.max_age, created) {
+            (Some(max_age), Some(created)) => SystemTime::now()
+                .duration_since(created)
+                .is_ok_and(|age| age >= max_age),
+            _ => false,
+        }
+    }
+}
+
+impl Default for RotationPolicy {
+    fn default() -> Self {
+        Self::DISABLED
+    }
+}
+
+/// Global rotation policy shared by every log writer
+static ROTATION_POLICY: RwLock<RotationPolicy> = RwLock::new(RotationPolicy::DISABLED);
+
+pub fn set_rotation_policy(policy: RotationPolicy) {
+    if let Ok(mut current) = ROTATION_POLICY.write() {
+        *current = policy;
+    }
+}
+
+pub fn rotation_policy() -> RotationPolicy {
+    ROTATION_POLICY
+        .read()
+        .map(|policy| *policy)
+        .unwrap_or(RotationPolicy::DISABLED)
+}
+
+/// Path of the `index`th rotation of `path` (`<file>.1` is the most recent)
+pub fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+/// Rotate `path` if it exceeds the limits of `policy`. Returns true when a rotation happened.
+pub fn rotate_if_needed(path: &Path, policy: &RotationPolicy) -> Result<bool> {
+    if !policy.is_enabled() {
+        return Ok(false);
+    }
+
+    let metadata = match std::fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return Ok(false), // Nothing to rotate yet
+    };
+
+    if !policy.should_rotate(metadata.len(), metadata.created().ok()) {
+        return Ok(false);
+    }
+
+    rotate(path, policy.retain)?;
+    Ok(true)
+}
+
+/// Shift existing rotations up by one and move `path` to `<file>.1`
+pub fn rotate(path: &Path, retain: usize) -> Result<()> {
+    if retain == 0 {
+        return std::fs::remove_file(path).context("Failed to remove log file during rotation");
+    }
+
+    let _ = std::fs::remove_file(rotated_path(path, retain));
+    for index in (1..retain).rev() {
+        let from = rotated_path(path, index);
+        if from.exists() {
+            std::fs::rename(&from, rotated_path(path, index + 1))
+                .context("Failed to shift rotated log file")?;
+        }
+    }
+
+    std::fs::rename(path, rotated_path(path, 1)).context("Failed to rotate log file")
+}
+
+/// Parse a size such as `512`, `64K`, `10M` or `1G` into bytes
+pub fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let (digits, multiplier) = match value.char_indices().last()? {
+        (i, 'k' | 'K') => (&value[..i], 1024),
+        (i, 'm' | 'M') => (&value[..i], 1024 * 1024),
+        (i, 'g' | 'G') => (&value[..i], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+    digits.trim().parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_size, rotate, rotated_path, RotationPolicy};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse_size_suffixes() {
+        assert_eq!(parse_size("512"), Some(512));
+        assert_eq!(parse_size("64K"), Some(64 * 1024));
+        assert_eq!(parse_size("10m"), Some(10 * 1024 * 1024));
+        assert_eq!(parse_size("1G"), Some(1024 * 1024 * 1024));
+        assert_eq!(parse_size("lots"), None);
+    }
+
+    #[test]
+    fn test_should_rotate_on_size() {
+        let policy = RotationPolicy {
+            max_bytes: Some(100),
+            ..RotationPolicy::DISABLED
+        };
+        assert!(!policy.should_rotate(99, None));
+        assert!(policy.should_rotate(100, None));
+    }
+
+    #[test]
+    fn test_rotate_keeps_retention_count() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("queue.log");
+
+        for generation in 0..4 {
+            std::fs::write(&path, format!("generation {}", generation)).unwrap();
+            rotate(&path, 2).unwrap();
+        }
+
+        assert!(!path.exists());
+        assert_eq!(
+            std::fs::read_to_string(rotated_path(&path, 1)).unwrap(),
+            "generation 3"
+        );
+        assert_eq!(
+            std::fs::read_to_string(rotated_path(&path, 2)).unwrap(),
+            "generation 2"
+        );
+        assert!(!rotated_path(&path, 3).exists());
+    }
+}
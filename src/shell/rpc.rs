@@ -0,0 +1,227 @@
+use crate::shell::auth::Source;
+use crate::shell::control::{handle_request, ControlContext, ControlRequest};
+use anyhow::Result;
+use serde_json::{json, Map, Value};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// JSON-RPC 2.0 control protocol for embedding a session in another program (editor plugins).
+///
+/// In `--control-stdio` mode typeypipe does not touch the terminal: stdin/stdout carry
+/// newline-delimited JSON-RPC instead, and the host renders shell output from `output`
+/// notifications.
+///
+/// **Methods:**
+/// - `enqueue {command}`: add a command to the queue
+/// - `write {data, automated?}`: write text straight to the shell
+/// - `pause` / `resume`: hold or release queue processing
+/// - `snapshot {strip_ansi?}`: the visible screen plus the session scrollback
+/// - `subscribe_output` / `unsubscribe_output`: start or stop `output {data}` notifications
+///
+/// All control socket verbs are also accepted as methods, with their fields as params.
+pub async fn serve_json_rpc<R, W>(reader: R, writer: W, context: Arc<ControlContext>) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    // Responses and output notifications share one writer
+    let (outgoing_tx, outgoing_rx) = mpsc::unbounded_channel::<Value>();
+    let writer_task = tokio::spawn(write_messages(writer, outgoing_rx));
+
+    let mut lines = BufReader::new(reader).lines();
+    let mut subscription: Option<JoinHandle<()>> = None;
+
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let message: Value = match serde_json::from_str(&line) {
+            Ok(message) => message,
+            Err(e) => {
+                let _ = outgoing_tx.send(error_response(Value::Null, -32700, &e.to_string()));
+                continue;
+            }
+        };
+
+        let id = message.get("id").cloned();
+        let method = message
+            .get("method")
+            .and_then(Value::as_str)
+            .unwrap_or("")
+            .to_string();
+        let params = message.get("params").cloned().unwrap_or(Value::Null);
+
+        let result = match method.as_str() {
+            "subscribe_output" => {
+                if subscription.is_none() {
+                    subscription = Some(tokio::spawn(forward_output(
+                        context.clone(),
+                        outgoing_tx.clone(),
+                    )));
+                }
+                Ok(Value::Null)
+            }
+            "unsubscribe_output" => {
+                if let Some(subscription) = subscription.take() {
+                    subscription.abort();
+                }
+                Ok(Value::Null)
+            }
+            "snapshot" => snapshot(&params, &context).await,
+            _ => match control_request(&method, params) {
+                Ok(request) => handle_request(request, &context, &Source::Stdio)
+                    .await
+                    .map_err(|e| (-32000, format!("{:#}", e))),
+                Err(e) => Err(e),
+            },
+        };
+
+        // Notifications (no id) get no response
+        if let Some(id) = id {
+            let response = match result {
+                Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                Err((code, message)) => error_response(id, code, &message),
+            };
+            let _ = outgoing_tx.send(response);
+        }
+    }
+
+    if let Some(subscription) = subscription {
+        subscription.abort();
+    }
+    drop(outgoing_tx);
+    let _ = writer_task.await;
+    Ok(())
+}
+
+/// Turn a method and its params into the equivalent control socket request
+fn control_request(method: &str, params: Value) -> Result<ControlRequest, (i64, String)> {
+    let mut fields = match params {
+        Value::Object(fields) => fields,
+        Value::Null => Map::new(),
+        _ => return Err((-32602, "Params must be an object".to_string())),
+    };
+    fields.insert("verb".to_string(), Value::String(method.to_string()));
+
+    serde_json::from_value(Value::Object(fields)).map_err(|e| {
+        if e.to_string().contains("unknown variant") {
+            (-32601, format!("Method not found: {}", method))
+        } else {
+            (-32602, format!("Invalid params: {}", e))
+        }
+    })
+}
+
+async fn snapshot(params: &Value, context: &ControlContext) -> Result<Value, (i64, String)> {
+    let strip_ansi = params
+        .get("strip_ansi")
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+    let lines = params
+        .get("lines")
+        .and_then(Value::as_u64)
+        .map(|lines| lines as usize);
+
+    let screen = handle_request(
+        ControlRequest::Screen { strip_ansi },
+        context,
+        &Source::Stdio,
+    )
+    .await;
+    let scrollback = handle_request(
+        ControlRequest::Scrollback { lines, strip_ansi },
+        context,
+        &Source::Stdio,
+    )
+    .await;
+
+    match (screen, scrollback) {
+        (Ok(screen), Ok(scrollback)) => Ok(json!({
+            "screen": screen["text"],
+            "scrollback": scrollback["text"],
+        })),
+        (Err(e), _) | (_, Err(e)) => Err((-32000, format!("{:#}", e))),
+    }
+}
+
+async fn forward_output(context: Arc<ControlContext>, outgoing: mpsc::UnboundedSender<Value>) {
+    use tokio::sync::broadcast::error::RecvError;
+
+    let Some(mut output) = context.output.subscribe() else {
+        return;
+    };
+    loop {
+        match output.recv().await {
+            Ok(bytes) => {
+                let notification = json!({
+                    "jsonrpc": "2.0",
+                    "method": "output",
+                    "params": { "data": String::from_utf8_lossy(&bytes) },
+                });
+                if outgoing.send(notification).is_err() {
+                    break;
+                }
+            }
+            Err(RecvError::Lagged(skipped)) => {
+                let notification = json!({
+                    "jsonrpc": "2.0",
+                    "method": "output_lagged",
+                    "params": { "skipped_chunks": skipped },
+                });
+                if outgoing.send(notification).is_err() {
+                    break;
+                }
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
+
+async fn write_messages<W>(mut writer: W, mut outgoing: mpsc::UnboundedReceiver<Value>)
+where
+    W: AsyncWrite + Unpin,
+{
+    while let Some(message) = outgoing.recv().await {
+        let Ok(mut bytes) = serde_json::to_vec(&message) else {
+            continue;
+        };
+        bytes.push(b'\n');
+        if writer.write_all(&bytes).await.is_err() || writer.flush().await.is_err() {
+            break;
+        }
+    }
+}
+
+pub(crate) fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::control_request;
+    use crate::shell::control::ControlRequest;
+    use serde_json::json;
+
+    #[test]
+    fn test_methods_map_to_control_requests() {
+        assert!(matches!(
+            control_request("enqueue", json!({ "command": "ls" })),
+            Ok(ControlRequest::Enqueue { command, .. }) if command == "ls"
+        ));
+        assert!(matches!(
+            control_request("pause", json!(null)),
+            Ok(ControlRequest::Pause)
+        ));
+        assert!(matches!(
+            control_request("launch", json!({})),
+            Err((-32601, _))
+        ));
+        assert!(matches!(
+            control_request("write", json!({})),
+            Err((-32602, _))
+        ));
+    }
+}
@@ -0,0 +1,101 @@
+use anyhow::{Context, Result};
+use std::future::Future;
+use std::path::PathBuf;
+
+/// Hardening mode: the session's own threads may only write beneath a fixed set of directories
+/// (the `.tp` directory and wherever the audit log lives), enforced by Linux Landlock.
+///
+/// Landlock restricts a thread and everything it creates afterwards, so the session is moved onto
+/// a fresh runtime started from a restricted thread. The wrapped shell is spawned before that and
+/// keeps full access; processes typeypipe starts later (hooks, notifications) are restricted.
+///
+/// **Allowed writes:**
+/// - Anything beneath the given directories (create, remove, rename, truncate)
+/// - Writing to existing files under `/dev` (the controlling terminal, `/dev/null`)
+/// - File descriptors that were already open before the restriction
+pub async fn run_sandboxed<F, Fut>(writable: Vec<PathBuf>, task: F) -> Result<()>
+where
+    F: FnOnce() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<()>>,
+{
+    let thread = std::thread::Builder::new()
+        .name("typeypipe-sandbox".to_string())
+        .spawn(move || -> Result<()> {
+            restrict_writes(&writable)?;
+            let runtime = tokio::runtime::Runtime::new().context("Failed to start runtime")?;
+            runtime.block_on(task())
+        })
+        .context("Failed to start sandbox thread")?;
+
+    tokio::task::spawn_blocking(move || thread.join())
+        .await
+        .context("Sandbox thread join failed")?
+        .map_err(|_| anyhow::anyhow!("Sandboxed session panicked"))?
+}
+
+/// Restrict filesystem writes for the current thread and any thread or process it creates
+#[cfg(target_os = "linux")]
+fn restrict_writes(writable: &[PathBuf]) -> Result<()> {
+    use landlock::{
+        path_beneath_rules, AccessFs, Ruleset, RulesetAttr, RulesetCreatedAttr, RulesetStatus, ABI,
+    };
+
+    let abi = ABI::V3;
+    let status = Ruleset::default()
+        .handle_access(AccessFs::from_write(abi))
+        .and_then(|ruleset| ruleset.create())
+        .and_then(|ruleset| {
+            ruleset.add_rules(path_beneath_rules(writable, AccessFs::from_write(abi)))
+        })
+        .and_then(|ruleset| ruleset.add_rules(path_beneath_rules(["/dev"], AccessFs::WriteFile)))
+        .and_then(|ruleset| ruleset.restrict_self())
+        .context("Failed to apply Landlock ruleset")?;
+
+    match status.ruleset {
+        RulesetStatus::FullyEnforced => Ok(()),
+        RulesetStatus::PartiallyEnforced => {
+            tracing::warn!("landlock sandbox only partially enforced by this kernel");
+            Ok(())
+        }
+        RulesetStatus::NotEnforced => Err(anyhow::anyhow!(
+            "Landlock is not supported or not enabled in this kernel"
+        )),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn restrict_writes(_writable: &[PathBuf]) -> Result<()> {
+    Err(anyhow::anyhow!("--sandbox requires Linux (Landlock)"))
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::restrict_writes;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_writes_outside_allowed_directories_are_denied() {
+        let allowed = TempDir::new().unwrap();
+        let denied = TempDir::new().unwrap();
+        let allowed_path = allowed.path().to_path_buf();
+        let denied_path = denied.path().to_path_buf();
+
+        let result = std::thread::spawn(move || {
+            if restrict_writes(std::slice::from_ref(&allowed_path)).is_err() {
+                eprintln!("Landlock unavailable, skipping");
+                return None;
+            }
+            Some((
+                std::fs::write(allowed_path.join("ok"), "ok").is_ok(),
+                std::fs::write(denied_path.join("blocked"), "no").is_ok(),
+            ))
+        })
+        .join()
+        .unwrap();
+
+        if let Some((allowed_write, denied_write)) = result {
+            assert!(allowed_write);
+            assert!(!denied_write);
+        }
+    }
+}
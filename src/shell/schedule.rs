@@ -0,0 +1,268 @@
+use crate::shell::queue::enqueue_file;
+use crate::shell::types::QueueMessage;
+use anyhow::Result;
+use chrono::{Datelike, Duration as ChronoDuration, Local, NaiveDateTime, Timelike};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// How far ahead to look for a cron expression's next match before giving up on it
+/// (e.g. `0 0 31 2 *` never matches)
+const CRON_SEARCH_LIMIT_DAYS: i64 = 366 * 5;
+
+/// When a recurring command runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Schedule {
+    /// `@every <duration>`, counted from session start
+    Every(Duration),
+    /// A five-field cron expression in local time
+    Cron(CronExpr),
+}
+
+impl FromStr for Schedule {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        if let Some(interval) = value.strip_prefix("@every") {
+            return parse_duration(interval.trim())
+                .filter(|interval| !interval.is_zero())
+                .map(Schedule::Every)
+                .ok_or_else(|| anyhow::anyhow!("Invalid interval '{}'", interval.trim()));
+        }
+        let expression = match value {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            _ => value,
+        };
+        expression.parse().map(Schedule::Cron)
+    }
+}
+
+/// A command queued on a schedule, as given to `--schedule` in the form `SCHEDULE=COMMAND`.
+///
+/// **Schedules:**
+/// - `@every <duration>` with an `s`, `m`, `h` or `d` suffix, e.g. `@every 5m`
+/// - Five cron fields (minute hour day-of-month month day-of-week) supporting `*`, lists,
+///   ranges and steps, e.g. `*/15 9-17 * * 1-5`
+/// - `@hourly`, `@daily`, `@weekly` and `@monthly`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScheduledCommand {
+    pub schedule: Schedule,
+    pub command: String,
+}
+
+impl FromStr for ScheduledCommand {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.split_once('=') {
+            Some((schedule, command)) if !command.trim().is_empty() => Ok(ScheduledCommand {
+                schedule: schedule.parse()?,
+                command: command.to_string(),
+            }),
+            _ => Err(anyhow::anyhow!(
+                "Invalid schedule '{}': expected SCHEDULE=COMMAND, e.g. '@every 5m=make status'",
+                value
+            )),
+        }
+    }
+}
+
+/// Minute, hour, day, month and weekday sets of a cron expression, one bit per value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronExpr {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Cron runs a job when either day field matches if both are restricted
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl FromStr for CronExpr {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = value.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(anyhow::anyhow!(
+                "Invalid cron expression '{}': expected five fields",
+                value
+            ));
+        };
+        let field = |text: &str, min: u32, max: u32| {
+            parse_field(text, min, max)
+                .ok_or_else(|| anyhow::anyhow!("Invalid cron field '{}' in '{}'", text, value))
+        };
+
+        // Sunday may be written as 0 or 7
+        let mut weekday_bits = field(weekdays, 0, 7)?;
+        if weekday_bits & (1 << 7) != 0 {
+            weekday_bits = (weekday_bits | 1) & !(1 << 7);
+        }
+
+        Ok(CronExpr {
+            minutes: field(minutes, 0, 59)?,
+            hours: field(hours, 0, 23)?,
+            days: field(days, 1, 31)?,
+            months: field(months, 1, 12)?,
+            weekdays: weekday_bits,
+            days_restricted: days != "*",
+            weekdays_restricted: weekdays != "*",
+        })
+    }
+}
+
+impl CronExpr {
+    fn matches_day(&self, time: &NaiveDateTime) -> bool {
+        let day = self.days & (1 << time.day()) != 0;
+        let weekday = self.weekdays & (1 << time.weekday().num_days_from_sunday()) != 0;
+        let day_matches = match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => day || weekday,
+            (true, false) => day,
+            (false, true) => weekday,
+            (false, false) => true,
+        };
+        day_matches && self.months & (1 << time.month()) != 0
+    }
+
+    /// The first whole minute after `after` that matches
+    pub fn next_after(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        let mut time = after.with_second(0)?.with_nanosecond(0)? + ChronoDuration::minutes(1);
+        let limit = after + ChronoDuration::days(CRON_SEARCH_LIMIT_DAYS);
+
+        while time <= limit {
+            if !self.matches_day(&time) {
+                time = time.date().succ_opt()?.and_hms_opt(0, 0, 0)?;
+            } else if self.hours & (1 << time.hour()) == 0 {
+                time = time.with_minute(0)? + ChronoDuration::hours(1);
+            } else if self.minutes & (1 << time.minute()) == 0 {
+                time += ChronoDuration::minutes(1);
+            } else {
+                return Some(time);
+            }
+        }
+        None
+    }
+}
+
+/// Parse one cron field: `*`, `5`, `1-5`, `*/15`, `0-30/10` or a comma-separated list of them
+fn parse_field(text: &str, min: u32, max: u32) -> Option<u64> {
+    let mut bits = 0u64;
+    for part in text.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|&s| s > 0)?),
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (start.parse().ok()?, end.parse().ok()?),
+                None => {
+                    let value = range.parse().ok()?;
+                    // `5/10` means every 10th value starting at 5
+                    (value, if part.contains('/') { max } else { value })
+                }
+            },
+        };
+        if start < min || end > max || start > end {
+            return None;
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Some(bits)
+}
+
+/// Parse a duration such as `30`, `45s`, `5m`, `2h` or `1d`; a bare number is seconds
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let (digits, multiplier) = match value.char_indices().last()? {
+        (i, 's') => (&value[..i], 1),
+        (i, 'm') => (&value[..i], 60),
+        (i, 'h') => (&value[..i], 60 * 60),
+        (i, 'd') => (&value[..i], 24 * 60 * 60),
+        _ => (value, 1),
+    };
+    let seconds = digits.trim().parse::<u64>().ok()?.checked_mul(multiplier)?;
+    Some(Duration::from_secs(seconds))
+}
+
+/// Queue `scheduled.command` into `queue_dir` each time its schedule comes round, until the
+/// task is aborted. Commands go through the normal queue, so they wait out typing pauses
+/// like any other message; a run is skipped while the previous one is still queued.
+pub async fn run_schedule(scheduled: ScheduledCommand, queue_dir: PathBuf) -> Result<()> {
+    let message = QueueMessage::Command {
+        command: scheduled.command.clone(),
+        terminator: None,
+    }
+    .encode()?;
+    let mut pending: Option<PathBuf> = None;
+
+    loop {
+        let wait = match &scheduled.schedule {
+            Schedule::Every(interval) => *interval,
+            Schedule::Cron(expr) => {
+                let now = Local::now().naive_local();
+                let Some(next) = expr.next_after(now) else {
+                    return Err(anyhow::anyhow!(
+                        "Schedule for '{}' never matches",
+                        scheduled.command
+                    ));
+                };
+                (next - now).to_std().unwrap_or_default()
+            }
+        };
+        tokio::time::sleep(wait).await;
+
+        if !pending.as_deref().is_some_and(Path::exists) {
+            pending = Some(enqueue_file(&queue_dir, "schedule", &message).await?);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CronExpr, Schedule, ScheduledCommand};
+    use chrono::NaiveDate;
+    use std::time::Duration;
+
+    #[test]
+    fn test_schedules_parse_and_find_next_run() {
+        let every: ScheduledCommand = "@every 5m=make status".parse().unwrap();
+        assert_eq!(every.schedule, Schedule::Every(Duration::from_secs(300)));
+        assert_eq!(every.command, "make status");
+        assert!("@every 0s=x".parse::<ScheduledCommand>().is_err());
+        assert!("* * *=x".parse::<ScheduledCommand>().is_err());
+        assert!("61 * * * *=x".parse::<ScheduledCommand>().is_err());
+
+        // Saturday 2025-03-01 10:07
+        let at = |d, h, m| {
+            NaiveDate::from_ymd_opt(2025, 3, d)
+                .unwrap()
+                .and_hms_opt(h, m, 0)
+                .unwrap()
+        };
+        let now = at(1, 10, 7);
+
+        let quarter: CronExpr = "*/15 * * * *".parse().unwrap();
+        assert_eq!(quarter.next_after(now), Some(at(1, 10, 15)));
+
+        // Weekdays only, so the next run is Monday morning
+        let weekday: CronExpr = "30 9-17 * * 1-5".parse().unwrap();
+        assert_eq!(weekday.next_after(now), Some(at(3, 9, 30)));
+
+        // Day of month or Sunday (written as 7), whichever comes first
+        let either: CronExpr = "0 0 15 * 7".parse().unwrap();
+        assert_eq!(either.next_after(now), Some(at(2, 0, 0)));
+
+        let never: CronExpr = "0 0 31 2 *".parse().unwrap();
+        assert_eq!(never.next_after(now), None);
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// Bounded, line-oriented history of everything the wrapped shell has written to its PTY.
+///
+/// The scrollback is the foundation for features that need to look back at output after it
+/// has been forwarded to the terminal (dumping, searching, copying).
+///
+/// **Storage:**
+/// - Output is split on `\n`; a trailing `\r` is removed so lines read naturally
+/// - Bytes are kept undecoded until a line completes, so multi-byte characters split across
+///   PTY reads are not mangled
+/// - ANSI escape sequences are kept as-is
+///
+/// **Trim Strategy:**
+/// - At most `max_lines` completed lines are kept; the oldest lines are dropped first
+/// - The byte size of all retained lines is tracked incrementally so memory use can be
+///   reported without walking the buffer
+/// - A `max_lines` of 0 disables the scrollback entirely
+#[derive(Debug, Default)]
+pub struct Scrollback {
+    lines: VecDeque<String>,
+    partial: Vec<u8>,
+    max_lines: usize,
+    line_bytes: usize,
+    trimmed_lines: u64,
+}
+
+impl Scrollback {
+    pub fn new(max_lines: usize) -> Self {
+        Self {
+            max_lines,
+            ..Self::default()
+        }
+    }
+
+    /// Append a chunk of PTY output
+    pub fn push(&mut self, bytes: &[u8]) {
+        if self.max_lines == 0 {
+            return;
+        }
+
+        for chunk in bytes.split_inclusive(|&b| b == b'\n') {
+            match chunk.split_last() {
+                Some((b'\n', line)) => {
+                    self.partial.extend_from_slice(line);
+                    self.complete_line();
+                }
+                _ => self.partial.extend_from_slice(chunk),
+            }
+        }
+    }
+
+    fn complete_line(&mut self) {
+        let mut bytes = std::mem::take(&mut self.partial);
+        if bytes.last() == Some(&b'\r') {
+            bytes.pop();
+        }
+        let line = String::from_utf8_lossy(&bytes).into_owned();
+
+        self.line_bytes += line.len();
+        self.lines.push_back(line);
+
+        while self.lines.len() > self.max_lines {
+            if let Some(dropped) = self.lines.pop_front() {
+                self.line_bytes -= dropped.len();
+                self.trimmed_lines += 1;
+            }
+        }
+    }
+
+    /// Completed lines, oldest first
+    #[cfg(test)]
+    pub fn lines(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().map(String::as_str)
+    }
+
+    /// The last `count` completed lines, oldest first
+    pub fn tail(&self, count: usize) -> Vec<String> {
+        let skip = self.lines.len().saturating_sub(count);
+        self.lines.iter().skip(skip).cloned().collect()
+    }
+
+    /// Output received since the last newline
+    pub fn partial_line(&self) -> String {
+        String::from_utf8_lossy(&self.partial).into_owned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// Approximate heap usage of the retained output in bytes
+    #[cfg(test)]
+    pub fn memory_usage(&self) -> usize {
+        self.line_bytes + self.partial.len()
+    }
+
+    /// Number of lines dropped so far to stay within `max_lines`
+    #[cfg(test)]
+    pub fn trimmed_lines(&self) -> u64 {
+        self.trimmed_lines
+    }
+}
+
+/// Scrollback shared between the PTY output task (writer) and anything reading history.
+///
+/// Uses `std::sync::Mutex` because the output task runs on a blocking thread and the
+/// critical sections never await.
+pub type SharedScrollback = Arc<Mutex<Scrollback>>;
+
+pub fn create_scrollback(max_lines: usize) -> SharedScrollback {
+    Arc::new(Mutex::new(Scrollback::new(max_lines)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Scrollback;
+
+    #[test]
+    fn test_scrollback_joins_lines_across_reads() {
+        let mut scrollback = Scrollback::new(10);
+        scrollback.push(b"hel");
+        scrollback.push(b"lo\r\nwor");
+        scrollback.push(b"ld\r\n$ ");
+
+        assert_eq!(
+            scrollback.lines().collect::<Vec<_>>(),
+            vec!["hello", "world"]
+        );
+        assert_eq!(scrollback.partial_line(), "$ ");
+        assert_eq!(scrollback.memory_usage(), 12);
+    }
+
+    #[test]
+    fn test_scrollback_trims_oldest_lines() {
+        let mut scrollback = Scrollback::new(2);
+        scrollback.push(b"one\ntwo\nthree\n");
+
+        assert_eq!(scrollback.tail(10), vec!["two", "three"]);
+        assert_eq!(scrollback.trimmed_lines(), 1);
+        assert_eq!(scrollback.memory_usage(), 8);
+    }
+
+    #[test]
+    fn test_scrollback_keeps_split_utf8_intact() {
+        let mut scrollback = Scrollback::new(10);
+        let bytes = "✅ done\n".as_bytes();
+        scrollback.push(&bytes[..1]);
+        scrollback.push(&bytes[1..]);
+
+        assert_eq!(scrollback.tail(1), vec!["✅ done"]);
+    }
+
+    #[test]
+    fn test_scrollback_disabled_with_zero_size() {
+        let mut scrollback = Scrollback::new(0);
+        scrollback.push(b"ignored\n");
+
+        assert_eq!(scrollback.len(), 0);
+        assert!(scrollback.partial_line().is_empty());
+    }
+}
@@ -0,0 +1,253 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How stale the queue heartbeat may get before the session is considered stuck
+pub const HEARTBEAT_STALE_AFTER: Duration = Duration::from_secs(15);
+
+/// Snapshot of a running session, written next to its log file as `<name>.status.json`.
+///
+/// The running session refreshes this file periodically so that other processes (the `health`
+/// subcommand, supervisors, dashboards) can inspect it without talking to the session directly.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionStatus {
+    pub session_id: String,
+    /// PID of the typeypipe process itself
+    pub pid: u32,
+    /// PID of the wrapped shell
+    pub child_pid: Option<u32>,
+    pub queue_dir: Option<PathBuf>,
+    pub log_file: Option<PathBuf>,
+    pub started_at: DateTime<Utc>,
+    /// Last time the queue processing loop ran
+    pub last_queue_check: DateTime<Utc>,
+    pub queue_paused: bool,
+    /// Directory holding this session's files and how it was chosen (see `location::TpDir`)
+    #[serde(default)]
+    pub tp_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub tp_dir_source: Option<String>,
+}
+
+impl SessionStatus {
+    /// Atomically replace the status file so readers never observe a partial write
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut temp_name = path.as_os_str().to_owned();
+        temp_name.push(".tmp");
+        let temp_path = PathBuf::from(temp_name);
+
+        let json = serde_json::to_vec_pretty(self).context("Failed to serialize session status")?;
+        std::fs::write(&temp_path, json).context("Failed to write session status")?;
+        std::fs::rename(&temp_path, path).context("Failed to replace session status file")
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        let contents = std::fs::read(path)
+            .with_context(|| format!("Failed to read session status {}", path.display()))?;
+        serde_json::from_slice(&contents).context("Failed to parse session status")
+    }
+}
+
+/// Location of the status file for a session named `name` inside the `.tp` directory
+pub fn status_file_path(tp_dir: &Path, name: &str) -> PathBuf {
+    tp_dir.join(format!("{}.status.json", name))
+}
+
+/// Result of a single health check
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthCheck {
+    pub name: &'static str,
+    pub ok: bool,
+    pub detail: String,
+}
+
+/// Result of checking a session's health
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthReport {
+    pub session: String,
+    pub healthy: bool,
+    pub checks: Vec<HealthCheck>,
+}
+
+/// Check that a session's processes are alive, its queue loop is running, and its log is writable
+pub fn check_health(tp_dir: &Path, name: &str) -> HealthReport {
+    let mut checks = Vec::new();
+
+    match SessionStatus::read(&status_file_path(tp_dir, name)) {
+        Ok(status) => {
+            checks.push(process_check("typeypipe process", Some(status.pid)));
+            checks.push(process_check("shell process", status.child_pid));
+
+            let age = Utc::now()
+                .signed_duration_since(status.last_queue_check)
+                .to_std()
+                .unwrap_or_default();
+            checks.push(HealthCheck {
+                name: "queue processing",
+                ok: age <= HEARTBEAT_STALE_AFTER,
+                detail: format!(
+                    "last queue check {}s ago{}",
+                    age.as_secs(),
+                    if status.queue_paused { " (paused)" } else { "" }
+                ),
+            });
+
+            if let Some(log_file) = status.log_file {
+                let writable = std::fs::OpenOptions::new().append(true).open(&log_file);
+                checks.push(HealthCheck {
+                    name: "log file",
+                    ok: writable.is_ok(),
+                    detail: match writable {
+                        Ok(_) => format!("{} is writable", log_file.display()),
+                        Err(e) => format!("{}: {}", log_file.display(), e),
+                    },
+                });
+            }
+        }
+        Err(e) => checks.push(HealthCheck {
+            name: "session status",
+            ok: false,
+            detail: format!("{:#}", e),
+        }),
+    }
+
+    HealthReport {
+        session: name.to_string(),
+        healthy: checks.iter().all(|check| check.ok),
+        checks,
+    }
+}
+
+fn process_check(name: &'static str, pid: Option<u32>) -> HealthCheck {
+    match pid {
+        Some(pid) => {
+            let alive = is_process_alive(pid);
+            HealthCheck {
+                name,
+                ok: alive,
+                detail: format!(
+                    "pid {} {}",
+                    pid,
+                    if alive { "running" } else { "not running" }
+                ),
+            }
+        }
+        None => HealthCheck {
+            name,
+            ok: false,
+            detail: "pid unknown".to_string(),
+        },
+    }
+}
+
+pub fn is_process_alive(pid: u32) -> bool {
+    use nix::sys::signal::kill;
+    use nix::unistd::Pid;
+
+    match kill(Pid::from_raw(pid as i32), None) {
+        Ok(()) => true,
+        // The process exists but belongs to someone else
+        Err(nix::errno::Errno::EPERM) => true,
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// Status of a session whose processes are this test process, with a fresh heartbeat and
+    /// a writable log
+    fn healthy_status(tp_dir: &Path) -> SessionStatus {
+        let log_file = tp_dir.join("test.log");
+        std::fs::write(&log_file, "").unwrap();
+        SessionStatus {
+            session_id: "test".to_string(),
+            pid: std::process::id(),
+            child_pid: Some(std::process::id()),
+            queue_dir: Some(tp_dir.join("test")),
+            log_file: Some(log_file),
+            started_at: Utc::now(),
+            last_queue_check: Utc::now(),
+            queue_paused: false,
+            tp_dir: Some(tp_dir.to_path_buf()),
+            tp_dir_source: None,
+        }
+    }
+
+    fn check(tp_dir: &Path, status: &SessionStatus) -> HealthReport {
+        status.write(&status_file_path(tp_dir, "test")).unwrap();
+        check_health(tp_dir, "test")
+    }
+
+    fn failed(report: &HealthReport) -> Vec<&'static str> {
+        report
+            .checks
+            .iter()
+            .filter(|check| !check.ok)
+            .map(|check| check.name)
+            .collect()
+    }
+
+    #[test]
+    fn test_healthy_session() {
+        let temp_dir = TempDir::new().unwrap();
+        let report = check(temp_dir.path(), &healthy_status(temp_dir.path()));
+        assert!(report.healthy, "{:?}", report.checks);
+        assert_eq!(report.checks.len(), 4);
+    }
+
+    #[test]
+    fn test_dead_shell_is_unhealthy() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let dead_pid = child.id();
+        child.wait().unwrap();
+
+        let mut status = healthy_status(temp_dir.path());
+        status.child_pid = Some(dead_pid);
+        let report = check(temp_dir.path(), &status);
+        assert!(!report.healthy);
+        assert_eq!(failed(&report), vec!["shell process"]);
+
+        status.child_pid = None;
+        let report = check(temp_dir.path(), &status);
+        assert_eq!(failed(&report), vec!["shell process"]);
+    }
+
+    #[test]
+    fn test_stuck_queue_is_unhealthy() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut status = healthy_status(temp_dir.path());
+        status.last_queue_check =
+            Utc::now() - chrono::Duration::from_std(HEARTBEAT_STALE_AFTER * 2).unwrap();
+        status.queue_paused = true;
+
+        let report = check(temp_dir.path(), &status);
+        assert!(!report.healthy);
+        assert_eq!(failed(&report), vec!["queue processing"]);
+        assert!(report.checks[2].detail.ends_with("(paused)"));
+    }
+
+    #[test]
+    fn test_unwritable_log_is_unhealthy() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut status = healthy_status(temp_dir.path());
+        status.log_file = Some(temp_dir.path().join("missing").join("test.log"));
+
+        let report = check(temp_dir.path(), &status);
+        assert!(!report.healthy);
+        assert_eq!(failed(&report), vec!["log file"]);
+    }
+
+    #[test]
+    fn test_missing_status_is_unhealthy() {
+        let temp_dir = TempDir::new().unwrap();
+        let report = check_health(temp_dir.path(), "test");
+        assert!(!report.healthy);
+        assert_eq!(failed(&report), vec!["session status"]);
+    }
+}
@@ -0,0 +1,72 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// An injected command waiting for the shell's next prompt
+#[derive(Debug, Clone)]
+pub struct InFlight {
+    pub source: String,
+    pub command: String,
+    pub injected_at: Instant,
+}
+
+/// Times the queued command a session's shell is running. The queue injects one command at
+/// a time, each at a prompt, so the next prompt marks the end of the last one injected.
+#[derive(Debug, Default)]
+pub struct Stopwatch {
+    in_flight: Mutex<Option<InFlight>>,
+}
+
+impl Stopwatch {
+    /// Start timing a command as it is injected
+    pub fn start(&self, source: &str, command: &str) {
+        if let Ok(mut in_flight) = self.in_flight.lock() {
+            *in_flight = Some(InFlight {
+                source: source.to_string(),
+                command: command.to_string(),
+                injected_at: Instant::now(),
+            });
+        }
+    }
+
+    /// The command still running and how long ago it was injected
+    pub fn running(&self) -> Option<(String, Duration)> {
+        let in_flight = self.in_flight.lock().ok()?;
+        let in_flight = in_flight.as_ref()?;
+        Some((in_flight.command.clone(), in_flight.injected_at.elapsed()))
+    }
+
+    /// Stop timing at a fresh prompt, returning the command that finished and how long it took
+    pub fn stop(&self) -> Option<(InFlight, Duration)> {
+        let in_flight = self.in_flight.lock().ok()?.take()?;
+        let elapsed = in_flight.injected_at.elapsed();
+        Some((in_flight, elapsed))
+    }
+}
+
+/// A duration as people read it: `850ms`, `4.2s`, `3m07s` or `2h05m`
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds < 1 {
+        format!("{}ms", duration.as_millis())
+    } else if seconds < 60 {
+        format!("{:.1}s", duration.as_secs_f64())
+    } else if seconds < 60 * 60 {
+        format!("{}m{:02}s", seconds / 60, seconds % 60)
+    } else {
+        format!("{}h{:02}m", seconds / 3600, seconds / 60 % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_duration;
+    use std::time::Duration;
+
+    #[test]
+    fn test_durations_are_formatted_by_magnitude() {
+        assert_eq!(format_duration(Duration::from_millis(850)), "850ms");
+        assert_eq!(format_duration(Duration::from_millis(4_240)), "4.2s");
+        assert_eq!(format_duration(Duration::from_secs(187)), "3m07s");
+        assert_eq!(format_duration(Duration::from_secs(7_500)), "2h05m");
+    }
+}
@@ -0,0 +1,268 @@
+use crate::shell::glyphs::label;
+use std::any::Any;
+use std::future::Future;
+use std::io::Write;
+use std::ops::ControlFlow;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::RwLock;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// Panics a task may recover from before it is stopped for good
+const MAX_RESTARTS: u32 = 5;
+
+/// Wait before restarting a task, doubled after each panic
+const RESTART_DELAY: Duration = Duration::from_millis(250);
+
+/// What happens to a session task that panics
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PanicPolicy {
+    /// Start the task again; output sinks and the input loop drop what they were handling
+    /// and carry on with the next chunk or key
+    #[default]
+    Restart,
+    /// Stop the task and let the rest of the session run without it
+    Stop,
+    /// Restore the terminal and exit with status 101
+    Exit,
+}
+
+impl FromStr for PanicPolicy {
+    type Err = anyhow::Error;
+
+    /// Parses `restart`, `stop` or `exit`
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "restart" => Ok(PanicPolicy::Restart),
+            "stop" => Ok(PanicPolicy::Stop),
+            "exit" => Ok(PanicPolicy::Exit),
+            _ => Err(anyhow::anyhow!(
+                "Invalid panic policy '{}': expected restart, stop or exit",
+                value
+            )),
+        }
+    }
+}
+
+static PANIC_POLICY: RwLock<PanicPolicy> = RwLock::new(PanicPolicy::Restart);
+
+/// Session log that panics are written to instead of the terminal
+static PANIC_LOG: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+pub fn set_panic_policy(policy: PanicPolicy) {
+    if let Ok(mut current) = PANIC_POLICY.write() {
+        *current = policy;
+    }
+}
+
+pub fn panic_policy() -> PanicPolicy {
+    PANIC_POLICY
+        .read()
+        .map(|policy| *policy)
+        .unwrap_or_default()
+}
+
+/// Write panics to `log_file` from now on, or to stderr again when `None`
+pub(crate) fn set_panic_log(log_file: Option<PathBuf>) {
+    if let Ok(mut current) = PANIC_LOG.write() {
+        *current = log_file;
+    }
+}
+
+/// Report panics in the session log while one is open, where they cannot scribble over the
+/// shell's screen, and on stderr otherwise
+pub fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let log_file = PANIC_LOG.read().ok().and_then(|log_file| log_file.clone());
+        let Some(log_file) = log_file else {
+            default_hook(info);
+            return;
+        };
+        let thread = std::thread::current();
+        let location = info
+            .location()
+            .map(|location| location.to_string())
+            .unwrap_or_default();
+        let message = panic_message(info.payload());
+        tracing::error!(thread = thread.name(), location, message, "panic");
+        append_log(
+            &log_file,
+            &format!(
+                "💥 Panic in thread '{}' at {}: {}",
+                thread.name().unwrap_or("<unnamed>"),
+                location,
+                message
+            ),
+        );
+    }));
+}
+
+/// Keeps one session task alive through panics, following the panic policy.
+///
+/// **Usage:**
+/// - Blocking loops run each iteration through `run`, so a panic loses only the chunk or
+///   key being handled and the task keeps its state
+/// - Async tasks are started with `supervise`, which spawns them again from scratch
+pub struct Supervisor {
+    name: &'static str,
+    panics: u32,
+}
+
+impl Supervisor {
+    pub fn new(name: &'static str) -> Self {
+        Self { name, panics: 0 }
+    }
+
+    /// Run `step`: its result, `Continue(None)` if it panicked and the task carries on
+    /// without it, or `Break` if the task should stop
+    pub fn run<T>(&mut self, step: impl FnOnce() -> T) -> ControlFlow<(), Option<T>> {
+        match catch_unwind(AssertUnwindSafe(step)) {
+            Ok(value) => ControlFlow::Continue(Some(value)),
+            Err(payload) if self.recover(payload.as_ref()) => ControlFlow::Continue(None),
+            Err(_) => ControlFlow::Break(()),
+        }
+    }
+
+    /// Log a panic and decide what happens next: true to carry on, false to stop the task.
+    /// Exits the process under `PanicPolicy::Exit`.
+    fn recover(&mut self, payload: &(dyn Any + Send)) -> bool {
+        self.panics += 1;
+        let message = panic_message(payload);
+        tracing::error!(
+            task = self.name,
+            panics = self.panics,
+            message,
+            "task panicked"
+        );
+        let policy = panic_policy();
+        let restart = policy == PanicPolicy::Restart && self.panics <= MAX_RESTARTS;
+        let action = match policy {
+            PanicPolicy::Exit => "exiting".to_string(),
+            _ if restart => format!("restarting ({} of {})", self.panics, MAX_RESTARTS),
+            _ => "stopped".to_string(),
+        };
+        log_panic(&format!(
+            "💥 {} panicked: {}; {}",
+            self.name, message, action
+        ));
+        if policy == PanicPolicy::Exit {
+            let _ = crossterm::terminal::disable_raw_mode();
+            std::process::exit(101);
+        }
+        restart
+    }
+
+    fn restart_delay(&self) -> Duration {
+        RESTART_DELAY * 2u32.pow(self.panics.saturating_sub(1).min(5))
+    }
+}
+
+/// Spawn the task `start` makes and make it again each time it panics, until the panic
+/// policy stops it. The result is the task's own, or `None` when it was stopped.
+pub fn supervise<F, T>(
+    name: &'static str,
+    mut start: impl FnMut() -> F + Send + 'static,
+) -> JoinHandle<Option<T>>
+where
+    F: Future<Output = T> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut supervisor = Supervisor::new(name);
+        loop {
+            // Aborting the supervisor must take the running task down with it
+            let error = match AbortOnDrop(tokio::spawn(start())).await {
+                Ok(value) => return Some(value),
+                Err(error) => error,
+            };
+            let Ok(payload) = error.try_into_panic() else {
+                return None;
+            };
+            if !supervisor.recover(payload.as_ref()) {
+                return None;
+            }
+            tokio::time::sleep(supervisor.restart_delay()).await;
+        }
+    })
+}
+
+struct AbortOnDrop<T>(JoinHandle<T>);
+
+impl<T> Future for AbortOnDrop<T> {
+    type Output = Result<T, tokio::task::JoinError>;
+
+    fn poll(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Self::Output> {
+        std::pin::Pin::new(&mut self.0).poll(cx)
+    }
+}
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// The text a panic was raised with
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "<non-string panic payload>".to_string())
+}
+
+fn log_panic(message: &str) {
+    let log_file = PANIC_LOG.read().ok().and_then(|log_file| log_file.clone());
+    match log_file {
+        Some(log_file) => append_log(&log_file, message),
+        None => eprintln!("{}", label(message)),
+    }
+}
+
+/// Append a line to the session log. Panics are rare enough to skip rotation and fsync.
+fn append_log(log_file: &Path, message: &str) {
+    let timestamp = chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC");
+    if let Ok(mut file) = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_file)
+    {
+        let _ = writeln!(file, "[{}] {}", timestamp, label(message));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Supervisor;
+    use std::ops::ControlFlow;
+
+    #[test]
+    fn test_panicking_step_is_skipped_until_the_restart_limit() {
+        let mut supervisor = Supervisor::new("test");
+        let mut handled = Vec::new();
+        let mut stopped_at = None;
+        for chunk in 0..20 {
+            let step = supervisor.run(|| {
+                if chunk % 3 == 1 {
+                    panic!("bad escape sequence in chunk {}", chunk);
+                }
+                handled.push(chunk);
+                chunk
+            });
+            if step.is_break() {
+                stopped_at = Some(chunk);
+                break;
+            }
+        }
+        // Five panics are recovered from, the sixth stops the task
+        assert_eq!(stopped_at, Some(16));
+        assert_eq!(handled, [0, 2, 3, 5, 6, 8, 9, 11, 12, 14, 15]);
+        assert_eq!(supervisor.run(|| 42), ControlFlow::Continue(Some(42)));
+    }
+}
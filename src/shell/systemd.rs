@@ -0,0 +1,66 @@
+use anyhow::{Context, Result};
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+
+/// Minimal implementation of the systemd notification protocol (`sd_notify(3)`).
+///
+/// When typeypipe runs as a `Type=notify` service, systemd passes the path of a datagram
+/// socket in `$NOTIFY_SOCKET`. Writing `READY=1` tells systemd that startup has finished,
+/// `WATCHDOG=1` resets the service watchdog and `STOPPING=1` announces shutdown.
+/// Outside of systemd `$NOTIFY_SOCKET` is unset and every call is a no-op.
+pub fn notify(state: &str) -> Result<bool> {
+    let Some(socket_path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return Ok(false);
+    };
+
+    let socket = UnixDatagram::unbound().context("Failed to create notify socket")?;
+    let socket_path = socket_path.to_string_lossy();
+
+    match socket_path.strip_prefix('@') {
+        Some(abstract_name) => send_abstract(&socket, abstract_name, state)?,
+        None => {
+            socket
+                .send_to(state.as_bytes(), socket_path.as_ref())
+                .context("Failed to send systemd notification")?;
+        }
+    }
+
+    Ok(true)
+}
+
+#[cfg(target_os = "linux")]
+fn send_abstract(socket: &UnixDatagram, name: &str, state: &str) -> Result<()> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::SocketAddr;
+
+    let addr = SocketAddr::from_abstract_name(name.as_bytes())
+        .context("Invalid abstract NOTIFY_SOCKET address")?;
+    socket
+        .send_to_addr(state.as_bytes(), &addr)
+        .context("Failed to send systemd notification")?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send_abstract(_socket: &UnixDatagram, _name: &str, _state: &str) -> Result<()> {
+    Err(anyhow::anyhow!(
+        "Abstract NOTIFY_SOCKET addresses are only supported on Linux"
+    ))
+}
+
+/// Interval at which `WATCHDOG=1` should be sent, if systemd enabled the watchdog for this process.
+///
+/// Returns half of `$WATCHDOG_USEC` as recommended by `sd_watchdog_enabled(3)`.
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    if usec == 0 {
+        return None;
+    }
+    Some(Duration::from_micros(usec / 2))
+}
@@ -0,0 +1,216 @@
+use crate::shell::supervisor::Supervisor;
+use bytes::{Bytes, BytesMut};
+use std::io::Read;
+use std::ops::ControlFlow;
+use std::sync::{Arc, OnceLock};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::task::JoinHandle;
+
+/// A chunk of PTY output, shared by every consumer without copying
+pub type OutputChunk = Bytes;
+
+/// Most bytes taken from the PTY in one read
+const READ_SIZE: usize = 1024;
+
+/// Chunks are cut from buffers this large, so small reads do not each allocate
+const READ_BUFFER_CAPACITY: usize = 64 * 1024;
+
+/// Fan-out of PTY output: one task reads the PTY and broadcasts each chunk to every sink
+/// (attached terminal, transcript, scrollback, command tracker, control clients).
+///
+/// **Lifecycle:**
+/// - Subscribe every sink before calling `spawn_reader`, so none of them misses the first output
+/// - When the shell closes the PTY (EOF, or EIO on Linux) or reading fails, the reader records
+///   why in `ReaderEnd`, drops the only strong sender and every receiver sees `Closed` after
+///   draining what is left, letting sinks flush and exit
+/// - Late subscribers (control clients) go through `OutputSubscriptions`, which does not keep the
+///   channel open
+/// - A sink that falls more than `capacity` chunks behind skips the oldest chunks and logs a warning
+pub struct OutputTee {
+    sender: broadcast::Sender<OutputChunk>,
+    ended: Arc<OnceLock<ReaderEnd>>,
+}
+
+/// Handle for subscribing to a running `OutputTee` at any time
+#[derive(Clone)]
+pub struct OutputSubscriptions {
+    sender: broadcast::WeakSender<OutputChunk>,
+    ended: Arc<OnceLock<ReaderEnd>>,
+}
+
+/// Why the reader stopped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReaderEnd {
+    /// The shell closed its side of the PTY, normally by exiting
+    ChildExited,
+    /// Reading the PTY failed for another reason
+    Failed(String),
+}
+
+impl std::fmt::Display for ReaderEnd {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReaderEnd::ChildExited => write!(f, "the shell closed the PTY"),
+            ReaderEnd::Failed(error) => write!(f, "reading the PTY failed: {}", error),
+        }
+    }
+}
+
+/// Linux reports reads from a PTY whose child side has closed as EIO instead of EOF
+fn is_pty_closed(error: &std::io::Error) -> bool {
+    error.raw_os_error() == Some(nix::libc::EIO)
+}
+
+impl OutputTee {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self {
+            sender,
+            ended: Arc::default(),
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<OutputChunk> {
+        self.sender.subscribe()
+    }
+
+    pub fn subscriptions(&self) -> OutputSubscriptions {
+        OutputSubscriptions {
+            sender: self.sender.downgrade(),
+            ended: self.ended.clone(),
+        }
+    }
+
+    /// Read the PTY until the shell closes it or reading fails, broadcasting every chunk
+    pub fn spawn_reader(self, mut reader: Box<dyn Read + Send>) -> JoinHandle<ReaderEnd> {
+        tokio::task::spawn_blocking(move || {
+            let mut buffer = BytesMut::with_capacity(READ_BUFFER_CAPACITY);
+
+            let mut supervisor = Supervisor::new("reader");
+
+            let end = loop {
+                // Reuses the rest of the current allocation, or a new one once it is used up
+                buffer.resize(READ_SIZE, 0);
+                match supervisor.run(|| reader.read(&mut buffer)) {
+                    ControlFlow::Continue(Some(Ok(0))) => break ReaderEnd::ChildExited,
+                    ControlFlow::Continue(Some(Ok(n))) => {
+                        // No receivers is not an error: nobody has subscribed yet
+                        buffer.truncate(n);
+                        let _ = self.sender.send(buffer.split().freeze());
+                    }
+                    ControlFlow::Continue(Some(Err(e)))
+                        if e.kind() == std::io::ErrorKind::Interrupted => {}
+                    ControlFlow::Continue(Some(Err(e))) if is_pty_closed(&e) => {
+                        break ReaderEnd::ChildExited
+                    }
+                    ControlFlow::Continue(Some(Err(e))) => {
+                        tracing::warn!(error = %e, "reading the PTY failed");
+                        break ReaderEnd::Failed(e.to_string());
+                    }
+                    ControlFlow::Continue(None) => {}
+                    ControlFlow::Break(()) => {
+                        break ReaderEnd::Failed("the PTY reader panicked".to_string())
+                    }
+                }
+            };
+            // Recorded before the sender is dropped, so a receiver seeing `Closed` can read it
+            let _ = self.ended.set(end.clone());
+            end
+        })
+    }
+}
+
+impl OutputSubscriptions {
+    /// Receive output from now on, or `None` once the PTY has closed
+    pub fn subscribe(&self) -> Option<broadcast::Receiver<OutputChunk>> {
+        self.sender.upgrade().map(|sender| sender.subscribe())
+    }
+
+    /// Why the reader stopped, once it has
+    pub fn reader_end(&self) -> Option<ReaderEnd> {
+        self.ended.get().cloned()
+    }
+}
+
+/// Feed every chunk to `sink` on a blocking thread until the tee's reader finishes. A chunk
+/// the sink panics on is dropped, as the panic policy allows, and the sink keeps its state.
+pub fn spawn_blocking_sink(
+    mut receiver: broadcast::Receiver<OutputChunk>,
+    name: &'static str,
+    mut sink: impl FnMut(&[u8]) + Send + 'static,
+) -> JoinHandle<()> {
+    let mut supervisor = Supervisor::new(name);
+    tokio::task::spawn_blocking(move || loop {
+        match receiver.blocking_recv() {
+            Ok(chunk) => {
+                if supervisor.run(|| sink(&chunk)).is_break() {
+                    break;
+                }
+            }
+            Err(RecvError::Lagged(skipped)) => {
+                tracing::warn!(sink = name, skipped, "output sink fell behind")
+            }
+            Err(RecvError::Closed) => break,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{spawn_blocking_sink, OutputTee, ReaderEnd};
+    use std::io::Read;
+    use std::sync::{Arc, Mutex};
+
+    #[tokio::test]
+    async fn test_every_sink_receives_all_output() {
+        let tee = OutputTee::new(16);
+        let first = Arc::new(Mutex::new(Vec::new()));
+        let second = Arc::new(Mutex::new(Vec::new()));
+
+        let sinks = [first.clone(), second.clone()].map(|collected| {
+            spawn_blocking_sink(tee.subscribe(), "test", move |chunk| {
+                collected.lock().unwrap().extend_from_slice(chunk)
+            })
+        });
+        let subscriptions = tee.subscriptions();
+        let (mut first_raw, mut second_raw) = (tee.subscribe(), tee.subscribe());
+
+        let end = tee.spawn_reader(Box::new(&b"hello world"[..])).await;
+        assert_eq!(end.unwrap(), ReaderEnd::ChildExited);
+        for sink in sinks {
+            sink.await.unwrap();
+        }
+
+        assert_eq!(*first.lock().unwrap(), b"hello world");
+        assert_eq!(*second.lock().unwrap(), b"hello world");
+        assert!(subscriptions.subscribe().is_none());
+        // Every receiver gets the same buffer, not a copy
+        let (first_chunk, second_chunk) = (first_raw.recv().await, second_raw.recv().await);
+        assert_eq!(
+            first_chunk.unwrap().as_ptr(),
+            second_chunk.unwrap().as_ptr()
+        );
+    }
+
+    /// What a Linux PTY returns once the shell has exited
+    struct ClosedPty;
+
+    impl Read for ClosedPty {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::from_raw_os_error(nix::libc::EIO))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_eio_ends_the_reader_as_a_child_exit() {
+        let tee = OutputTee::new(16);
+        let subscriptions = tee.subscriptions();
+        let mut receiver = tee.subscribe();
+
+        let end = tee.spawn_reader(Box::new(ClosedPty)).await.unwrap();
+
+        assert_eq!(end, ReaderEnd::ChildExited);
+        assert_eq!(subscriptions.reader_end(), Some(ReaderEnd::ChildExited));
+        assert!(receiver.recv().await.is_err());
+    }
+}
@@ -1,33 +1,132 @@
+use crate::shell::alerts::AlertMonitor;
+use crate::shell::ansi::strip_ansi;
+use crate::shell::arbiter::{Arbiter, PromptMarks};
+use crate::shell::context::{QueueEvent, SessionContext};
+use crate::shell::control::{self, ControlContext};
+use crate::shell::durability::{fsync_policy, sync_periodically, sync_unsynced, FsyncPolicy};
+use crate::shell::echo::EchoFilter;
+use crate::shell::filter::{FilterChain, OutputLog};
+use crate::shell::glyphs::label;
+use crate::shell::help::{is_help_key, render_help, HelpInfo, HelpStatus};
+use crate::shell::injection::{injected, prepare_injection, write_failed, Injection};
+use crate::shell::notify::{notifier_enabled, notify, OutputWatcher};
+use crate::shell::osc::CommandTracker;
+use crate::shell::overlay::{
+    close_overlay, guard_pastes, inner_bracketed_paste, overlay_shown, show_overlay, TerminalWriter,
+};
+use crate::shell::palette::{
+    is_palette_key, palette_items, Palette, PaletteAction, PaletteOutcome,
+};
+use crate::shell::paste::{paste_command, paste_input, render_paste_prompt, PasteChoice};
+use crate::shell::progress::ProgressTracker;
 use crate::shell::pty::SharedPtySession;
+use crate::shell::queue::{enqueue_file, log_to_file};
+use crate::shell::rpc;
+use crate::shell::schedule::run_schedule;
+use crate::shell::scrollback::SharedScrollback;
+use crate::shell::status::{SessionStatus, HEARTBEAT_STALE_AFTER};
+use crate::shell::stopwatch::format_duration;
+use crate::shell::supervisor::{set_panic_log, supervise, Supervisor};
+use crate::shell::systemd;
+use crate::shell::tee::{spawn_blocking_sink, OutputChunk, OutputTee};
+use crate::shell::title::TitleRewriter;
+use crate::shell::transcript::Transcript;
+use crate::shell::types::{InteractiveOptions, QueueMessage};
+use crate::shell::watch::run_watch;
 use anyhow::{Context, Result};
 use std::io::Write;
-use std::path::PathBuf;
-use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::sync::{broadcast, mpsc};
+use tracing::Instrument;
 
-/// Global atomic variables to track user typing state
-static LAST_USER_INPUT_TIME: AtomicU64 = AtomicU64::new(0);
-static USER_IS_TYPING: AtomicBool = AtomicBool::new(false);
-static INPUT_TIMEOUT_MS: AtomicU64 = AtomicU64::new(30_000); // Default 30 seconds
+/// Without shell integration, a pause in output this long is taken for a prompt
+const PROMPT_QUIET: std::time::Duration = std::time::Duration::from_millis(500);
 
-/// Global state for tracking pause/resume logging
-static QUEUE_PAUSED_LOGGED: AtomicBool = AtomicBool::new(false);
+/// How long to wait for a shell that prints nothing at all before giving up on its prompt
+const PROMPT_WAIT_LIMIT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// How often the session status file is refreshed
+const STATUS_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Chunks of PTY output a sink may fall behind before it starts skipping output
+const OUTPUT_TEE_CAPACITY: usize = 1024;
+
+/// How long sinks get to drain the last output once the shell has exited
+const SINK_DRAIN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Whether a session is attached to this process's terminal
+static ATTACHED: AtomicBool = AtomicBool::new(false);
+
+/// Marks the terminal as attached until dropped
+struct Attached;
+
+impl Attached {
+    fn claim() -> Result<Self> {
+        if ATTACHED.swap(true, Ordering::SeqCst) {
+            return Err(anyhow::anyhow!(
+                "Another interactive session is already attached to this terminal"
+            ));
+        }
+        Ok(Self)
+    }
+}
+
+impl Drop for Attached {
+    fn drop(&mut self) {
+        ATTACHED.store(false, Ordering::SeqCst);
+    }
+}
 
 /// Setup interactive mode with PTY session using proper terminal bridge
+///
+/// The session's state lives in `context`, but the session owns the terminal: raw mode, the
+/// overlays, its title and the panic log. A process therefore attaches at most one of these at
+/// a time and a second call fails; headless sessions are not limited.
 pub async fn setup_interactive_pty(
     session: SharedPtySession,
-    queue_dir: Option<PathBuf>,
-    log_file: Option<PathBuf>,
-    input_timeout_secs: u64,
+    context: Arc<SessionContext>,
+    options: InteractiveOptions,
 ) -> Result<()> {
-    set_input_timeout(input_timeout_secs);
+    let _attached = Attached::claim()?;
+    let InteractiveOptions {
+        queue_dir,
+        log_file,
+        transcript_file,
+        output_file,
+        output_filters,
+        watch_rules,
+        watch_root,
+        schedules,
+        notify_patterns,
+        bell,
+        activity_quiet,
+        title_template,
+        suppress_inner_title,
+        agent_echo,
+        paste_guard,
+        max_command_output,
+        status_file,
+        control_socket,
+        control_stdio,
+        input_timeout_secs,
+        tp_dir,
+        init_commands,
+    } = options;
+
+    context.set_input_timeout(input_timeout_secs);
+    // Panics go to the session log rather than over the shell's screen
+    set_panic_log(log_file.clone());
     use crossterm::{
-        event::{self, Event, KeyCode, KeyModifiers},
+        event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
         terminal::{disable_raw_mode, enable_raw_mode},
     };
-    use std::io::{self, Read, Write};
+    use std::io::Write;
 
-    let (mut pty_reader, mut pty_writer) = {
+    let (pty_reader, pty_writer, scrollback) = {
         let mut session_guard = session.lock().await;
         let reader = session_guard.clone_pty_reader()?;
 
@@ -35,115 +134,500 @@ pub async fn setup_interactive_pty(
             .take_pty_writer()
             .ok_or_else(|| anyhow::anyhow!("PTY writer not available"))?;
 
-        (reader, pty_writer_main)
+        (
+            reader,
+            SharedWriter::new(pty_writer_main),
+            session_guard.scrollback(),
+        )
     };
 
-    let raw_mode_enabled = match enable_raw_mode() {
-        Ok(()) => true,
-        Err(_) => false,
-    };
+    context.queue_checked();
+    let queue_dir_enabled = queue_dir.is_some() && log_file.is_some();
 
-    let pty_output_task = tokio::task::spawn_blocking(move || {
-        let mut buffer = [0u8; 1024];
-        let mut stdout = io::stdout();
+    // File watches and schedules feed the queue like any other producer
+    let mut trigger_tasks = Vec::new();
+    if let Some(queue_dir) = &queue_dir {
+        let root = match watch_root {
+            Some(root) => root,
+            None => std::env::current_dir().context("Failed to read current directory")?,
+        };
+        for rule in watch_rules {
+            let (root, queue_dir) = (root.clone(), queue_dir.clone());
+            trigger_tasks.push(
+                supervise("watch", move || {
+                    run_watch(root.clone(), rule.clone(), queue_dir.clone())
+                })
+                .abort_handle(),
+            );
+        }
+        for scheduled in schedules {
+            let queue_dir = queue_dir.clone();
+            trigger_tasks.push(
+                supervise("schedule", move || {
+                    run_schedule(scheduled.clone(), queue_dir.clone())
+                })
+                .abort_handle(),
+            );
+        }
+    }
 
-        loop {
-            match pty_reader.read(&mut buffer) {
-                Ok(0) => break, // EOF
-                Ok(n) => {
-                    stdout.write_all(&buffer[..n]).unwrap();
-                    stdout.flush().unwrap();
+    let status_task = match status_file.clone() {
+        Some(status_file) => {
+            let status = {
+                let session_guard = session.lock().await;
+                SessionStatus {
+                    session_id: session_guard.session_id().to_string(),
+                    pid: std::process::id(),
+                    child_pid: session_guard.child_pid(),
+                    queue_dir: queue_dir.clone(),
+                    log_file: log_file.clone(),
+                    started_at: chrono::Utc::now(),
+                    last_queue_check: chrono::Utc::now(),
+                    queue_paused: false,
+                    tp_dir: tp_dir.as_ref().map(|tp_dir| tp_dir.path.clone()),
+                    tp_dir_source: tp_dir
+                        .as_ref()
+                        .map(|tp_dir| tp_dir.source.as_str().to_string()),
                 }
-                Err(_) => break, // Error reading from PTY
-            }
+            };
+            let context = context.clone();
+            Some(supervise("status", move || {
+                publish_status(status_file.clone(), status.clone(), context.clone())
+            }))
         }
+        None => None,
+    };
+
+    let session_name = queue_dir
+        .as_deref()
+        .and_then(Path::file_name)
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "typeypipe".to_string());
+    let help_info = HelpInfo {
+        session: session_name.clone(),
+        paths: [
+            ("Queue", queue_dir.as_ref()),
+            ("Log", log_file.as_ref()),
+            ("Socket", control_socket.as_ref()),
+            ("Transcript", transcript_file.as_ref()),
+            ("Output", output_file.as_ref()),
+        ]
+        .into_iter()
+        .filter_map(|(name, path)| Some((name, path?.clone())))
+        .collect(),
+    };
+
+    // Bytes written through the control socket are forwarded to the PTY by the input task
+    let (control_input_tx, mut control_input_rx) = mpsc::unbounded_channel::<Vec<u8>>();
+    // PTY output is read once and fanned out to every sink through the tee
+    let tee = OutputTee::new(OUTPUT_TEE_CAPACITY);
+
+    let (rows, child_pid) = {
+        let session_guard = session.lock().await;
+        (session_guard.size().0, session_guard.child_pid())
+    };
+    // Queue processing waits for the init commands, which are written like control input
+    let init_input = (!init_commands.is_empty()).then(|| {
+        context.set_init_running(true);
+        control_input_tx.clone()
+    });
+    let control_context = Arc::new(ControlContext {
+        session: context.clone(),
+        queue_dir: queue_dir.clone(),
+        scrollback: scrollback.clone(),
+        input: control_input_tx,
+        output: tee.subscriptions(),
+        rows,
+        started_at: chrono::Utc::now(),
+        child_pid,
+        max_command_output,
+    });
+
+    let control_task = control_socket.clone().map(|socket_path| {
+        let control_context = control_context.clone();
+        supervise("control", move || {
+            control::serve(socket_path.clone(), control_context.clone())
+        })
     });
 
-    // Create appropriate input handler based on raw mode availability with integrated queue monitoring
-    let input_task = if raw_mode_enabled {
+    // In control-stdio mode stdin/stdout carry JSON-RPC and the session ends when stdin closes
+    let rpc_task = control_stdio.then(|| {
+        tokio::spawn(rpc::serve_json_rpc(
+            tokio::io::stdin(),
+            tokio::io::stdout(),
+            control_context.clone(),
+        ))
+    });
+
+    let transcript = transcript_file
+        .as_deref()
+        .map(Transcript::create)
+        .transpose()?;
+    let output_log = output_file
+        .as_deref()
+        .map(|path| OutputLog::create(path, FilterChain::from_specs(&output_filters)))
+        .transpose()?;
+
+    let raw_mode_enabled = !control_stdio && enable_raw_mode().is_ok();
+
+    let command_tracker = context.tracks_finished_commands().then(CommandTracker::new);
+
+    let screen_scrollback = scrollback.clone();
+
+    // Every sink subscribes before the reader starts so none misses the first output
+    let mut sinks = vec![spawn_blocking_sink(
+        tee.subscribe(),
+        "scrollback",
+        move |chunk| {
+            if let Ok(mut scrollback) = scrollback.lock() {
+                scrollback.push(chunk);
+            }
+        },
+    )];
+    if let Some(mut transcript) = transcript {
+        sinks.push(spawn_blocking_sink(
+            tee.subscribe(),
+            "transcript",
+            move |chunk| {
+                let _ = transcript.record(chunk);
+            },
+        ));
+    }
+    if let Some(mut output_log) = output_log {
+        sinks.push(spawn_blocking_sink(
+            tee.subscribe(),
+            "output-log",
+            move |chunk| {
+                let _ = output_log.record(chunk);
+            },
+        ));
+    }
+    if !notify_patterns.is_empty() && notifier_enabled() {
+        let mut watcher = OutputWatcher::new(notify_patterns);
+        sinks.push(spawn_blocking_sink(
+            tee.subscribe(),
+            "notify",
+            move |chunk| {
+                for (pattern, line) in watcher.feed(chunk) {
+                    notify(&format!("output matched '{}'", pattern), &line);
+                }
+            },
+        ));
+    }
+    if let Some(mut tracker) = command_tracker {
+        let context = context.clone();
+        sinks.push(spawn_blocking_sink(
+            tee.subscribe(),
+            "commands",
+            move |chunk| {
+                for finished in tracker.feed(chunk) {
+                    context.command_finished(finished);
+                }
+            },
+        ));
+    }
+    let mut progress = ProgressTracker::default();
+    let progress_context = context.clone();
+    sinks.push(spawn_blocking_sink(
+        tee.subscribe(),
+        "progress",
+        move |chunk| progress.feed(chunk, progress_context.progress()),
+    ));
+    if queue_dir_enabled {
+        // A fresh prompt means any half-typed line was discarded and the last injected
+        // command has finished, and echo of user input counts as interaction
+        let log_file = log_file.clone();
+        let context = context.clone();
+        sinks.push(spawn_blocking_sink(
+            tee.subscribe(),
+            "interaction",
+            move |chunk| {
+                if context.arbiter().observe_output(chunk) {
+                    if let Some(log_file) = log_file.clone() {
+                        record_command_duration(&context, log_file);
+                    }
+                }
+                context.observe_echo();
+            },
+        ));
+    }
+    if !control_stdio {
+        let mut alerts = AlertMonitor::new(&session_name, bell, activity_quiet);
+        let mut titles = (title_template.is_some() || suppress_inner_title).then(|| {
+            TitleRewriter::new(
+                title_template,
+                suppress_inner_title,
+                &session_name,
+                child_pid,
+            )
+        });
+        let mut echo = EchoFilter::new(agent_echo, context.agent_input());
+        let mut terminal = TerminalWriter::default();
+        let context = context.clone();
+        sinks.push(spawn_blocking_sink(
+            tee.subscribe(),
+            "terminal",
+            move |chunk| {
+                let chunk = echo.process(chunk, context.agent_input());
+                let chunk = match titles.as_mut() {
+                    Some(titles) => titles.process(&chunk),
+                    None => chunk,
+                };
+                terminal.write(&alerts.process(&chunk, !context.is_user_typing()));
+            },
+        ));
+    }
+
+    if let Some(input) = init_input {
+        trigger_tasks.push(
+            tokio::spawn(run_init_commands(
+                init_commands,
+                tee.subscribe(),
+                input,
+                log_file.clone(),
+                context.clone(),
+            ))
+            .abort_handle(),
+        );
+    }
+
+    let mut pty_output_task = tee.spawn_reader(pty_reader);
+    let session_log = log_file.clone();
+
+    // The queue has its own task, so keys (a takeover among them) are still read while a
+    // command waits for the PTY to accept it
+    if let (Some(queue_dir), Some(log_file)) = (queue_dir.clone(), log_file.clone()) {
+        let context = context.clone();
+        let pty_writer = pty_writer.clone();
+        trigger_tasks.push(
+            supervise("queue", move || {
+                process_queue_periodically(
+                    context.clone(),
+                    queue_dir.clone(),
+                    log_file.clone(),
+                    pty_writer.clone(),
+                )
+            })
+            .abort_handle(),
+        );
+    }
+    let mut pty_writer: Box<dyn Write + Send> = Box::new(pty_writer);
+
+    // Create appropriate input handler based on raw mode availability
+    let input_task = if control_stdio {
+        // Headless: all input arrives through JSON-RPC
+        let context = context.clone();
+        tokio::spawn(async move {
+            while !context.output_ended() {
+                write_control_input(&mut control_input_rx, &context, &mut pty_writer)?;
+
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            }
+            Ok(())
+        })
+    } else if raw_mode_enabled {
         // Raw mode: character-by-character input with queue monitoring
+        let session = session.clone();
+        let context = context.clone();
         tokio::task::spawn_blocking(move || -> Result<()> {
             let rt = tokio::runtime::Handle::current();
-            let mut last_queue_check = std::time::Instant::now();
+            let mut palette: Option<Palette> = None;
+            // A paste over --paste-guard, waiting for the user to choose what to do with it
+            let mut held_paste: Option<String> = None;
+            if paste_guard.is_some() {
+                guard_pastes(true);
+            }
+            let screen_file = log_file
+                .as_ref()
+                .map(|log_file| log_file.with_extension("screen.txt"));
+            let help_lines = |rt: &tokio::runtime::Handle| {
+                let pending = match queue_dir.as_deref() {
+                    Some(queue_dir) => rt.block_on(control::queue_depth(queue_dir)).ok(),
+                    None => None,
+                };
+                let status = HelpStatus {
+                    queue_state: context.queue_state(),
+                    pending,
+                    running: context.stopwatch().running(),
+                    progress: context
+                        .progress()
+                        .current()
+                        .map(|progress| progress.fraction),
+                };
+                render_help(&help_info, &status, terminal_size().0)
+            };
 
-            loop {
-                if last_queue_check.elapsed() >= std::time::Duration::from_secs(1) {
-                    if let (Some(ref queue_dir), Some(ref log_file)) =
-                        (queue_dir.as_ref(), log_file.as_ref())
-                    {
-                        rt.block_on(async {
-                            let _ =
-                                process_next_queue_command(queue_dir, log_file, &mut pty_writer)
-                                    .await;
-                        });
-                    }
-                    last_queue_check = std::time::Instant::now();
-                }
+            let mut supervisor = Supervisor::new("input");
+            while !context.output_ended() {
+                // A panic loses the key being handled; the writer and overlays survive it
+                let step = supervisor.run(|| -> Result<()> {
+                    write_control_input(&mut control_input_rx, &context, &mut pty_writer)?;
 
-                if event::poll(std::time::Duration::from_millis(100))
-                    .context("Failed to poll for events")?
-                {
-                    let crossterm_event = event::read().context("Failed to read event")?;
-                    match &crossterm_event {
-                        Event::Key(key_event) => {
-                            update_user_input();
+                    if event::poll(std::time::Duration::from_millis(100))
+                        .context("Failed to poll for events")?
+                    {
+                        let crossterm_event =
+                            control_key_name(event::read().context("Failed to read event")?);
+                        match &crossterm_event {
+                            // The next key decides what happens to a held paste
+                            Event::Key(key_event) if held_paste.is_some() => {
+                                if key_event.kind != KeyEventKind::Press {
+                                    return Ok(());
+                                }
+                                let Some(text) = held_paste.take() else {
+                                    return Ok(());
+                                };
+                                if close_overlay() {
+                                    rt.block_on(redraw_shell(&session));
+                                }
+                                match PasteChoice::from_key(key_event) {
+                                    PasteChoice::Paste => {
+                                        write_paste(&text, &context, &mut pty_writer)?
+                                    }
+                                    PasteChoice::Queue => {
+                                        rt.block_on(queue_paste(&text, queue_dir.as_deref()))
+                                    }
+                                    PasteChoice::Discard => print_notice("Paste discarded"),
+                                }
+                            }
+                            // The palette takes every key while it is open
+                            Event::Key(key_event) if palette.is_some() => {
+                                if key_event.kind != KeyEventKind::Press {
+                                    return Ok(());
+                                }
+                                let Some(open) = palette.as_mut() else {
+                                    return Ok(());
+                                };
+                                match open.handle_key(key_event) {
+                                    PaletteOutcome::Redraw => {
+                                        let (cols, rows) = terminal_size();
+                                        show_overlay(&open.render(cols, rows));
+                                    }
+                                    PaletteOutcome::Run(PaletteAction::ShowHelp) => {
+                                        palette = None;
+                                        show_overlay(&help_lines(&rt));
+                                    }
+                                    outcome => {
+                                        palette = None;
+                                        if close_overlay() {
+                                            rt.block_on(redraw_shell(&session));
+                                        }
+                                        if let PaletteOutcome::Run(action) = outcome {
+                                            rt.block_on(run_palette_action(
+                                                action,
+                                                &context,
+                                                queue_dir.as_deref(),
+                                                screen_file.as_deref(),
+                                                &screen_scrollback,
+                                                rows,
+                                            ));
+                                        }
+                                    }
+                                }
+                            }
+                            // Any key closes the help overlay without reaching the shell
+                            Event::Key(key_event) if overlay_shown() => {
+                                let closed = key_event.kind == KeyEventKind::Press;
+                                if closed && close_overlay() {
+                                    rt.block_on(redraw_shell(&session));
+                                }
+                            }
+                            Event::Key(key_event) if is_help_key(key_event) => {
+                                show_overlay(&help_lines(&rt));
+                            }
+                            Event::Key(key_event) if is_palette_key(key_event) => {
+                                let recent: Vec<String> = context
+                                    .recent_commands()
+                                    .into_iter()
+                                    .map(|recent| recent.command)
+                                    .collect();
+                                let open = Palette::new(palette_items(
+                                    context.taken_over(),
+                                    context.queue_held(),
+                                    &recent,
+                                ));
+                                let (cols, rows) = terminal_size();
+                                show_overlay(&open.render(cols, rows));
+                                palette = Some(open);
+                            }
+                            Event::Key(key_event) if is_takeover_key(key_event) => {
+                                toggle_takeover(&context);
+                            }
+                            Event::Key(key_event) => {
+                                context.update_user_input();
 
-                            if let Ok(terminput_event) =
-                                terminput_crossterm::to_terminput(crossterm_event.clone())
-                            {
-                                let mut buffer = [0u8; 16];
-                                if let Ok(bytes_written) =
-                                    terminput_event.encode(&mut buffer, terminput::Encoding::Xterm)
+                                if let Ok(terminput_event) =
+                                    terminput_crossterm::to_terminput(crossterm_event.clone())
                                 {
-                                    pty_writer
-                                        .write_all(&buffer[..bytes_written])
-                                        .context("Failed to write to PTY")?;
-                                    pty_writer.flush().context("Failed to flush PTY writer")?;
-                                } else {
-                                    if let KeyCode::Char(c) = key_event.code {
-                                        let bytes = if key_event
-                                            .modifiers
-                                            .contains(KeyModifiers::CONTROL)
-                                        {
-                                            vec![c as u8 & 0x1f]
-                                        } else {
-                                            vec![c as u8]
-                                        };
+                                    let mut buffer = [0u8; 16];
+                                    if let Ok(bytes_written) = terminput_event
+                                        .encode(&mut buffer, terminput::Encoding::Xterm)
+                                    {
+                                        let bytes = &buffer[..bytes_written];
+                                        context.arbiter().observe_input(bytes);
+                                        context.record_input("keyboard", bytes);
                                         pty_writer
-                                            .write_all(&bytes)
+                                            .write_all(&buffer[..bytes_written])
                                             .context("Failed to write to PTY")?;
                                         pty_writer.flush().context("Failed to flush PTY writer")?;
+                                    } else {
+                                        if let KeyCode::Char(c) = key_event.code {
+                                            let bytes = if key_event
+                                                .modifiers
+                                                .contains(KeyModifiers::CONTROL)
+                                            {
+                                                vec![c as u8 & 0x1f]
+                                            } else {
+                                                vec![c as u8]
+                                            };
+                                            context.arbiter().observe_input(&bytes);
+                                            context.record_input("keyboard", &bytes);
+                                            pty_writer
+                                                .write_all(&bytes)
+                                                .context("Failed to write to PTY")?;
+                                            pty_writer
+                                                .flush()
+                                                .context("Failed to flush PTY writer")?;
+                                        }
                                     }
                                 }
                             }
-                        }
-                        _ => {
-                            // Ignore other events
+                            Event::Paste(_) if overlay_shown() => {}
+                            Event::Paste(text)
+                                if paste_guard.is_some_and(|limit| text.len() as u64 > limit) =>
+                            {
+                                let limit = paste_guard.unwrap_or_default();
+                                show_overlay(&render_paste_prompt(text, limit, terminal_size().0));
+                                held_paste = Some(text.clone());
+                            }
+                            Event::Paste(text) => write_paste(text, &context, &mut pty_writer)?,
+                            _ => {
+                                // Ignore other events
+                            }
                         }
                     }
+                    Ok(())
+                });
+                match step {
+                    ControlFlow::Continue(Some(result)) => result?,
+                    ControlFlow::Continue(None) => {}
+                    ControlFlow::Break(()) => break,
                 }
             }
+            Ok(())
         })
     } else {
         // Line mode: fallback for non-interactive environments with queue monitoring
+        let context = context.clone();
         tokio::spawn(async move {
             use tokio::io::{AsyncBufReadExt, BufReader};
             let stdin = tokio::io::stdin();
             let mut reader = BufReader::new(stdin);
             let mut line = String::new();
-            let mut last_queue_check = std::time::Instant::now();
             let mut eof_warned = false;
 
-            loop {
-                if last_queue_check.elapsed() >= std::time::Duration::from_secs(1) {
-                    if let (Some(ref queue_dir), Some(ref log_file)) =
-                        (queue_dir.as_ref(), log_file.as_ref())
-                    {
-                        let _ =
-                            process_next_queue_command(queue_dir, log_file, &mut pty_writer).await;
-                    }
-                    last_queue_check = std::time::Instant::now();
-                }
+            while !context.output_ended() {
+                write_control_input(&mut control_input_rx, &context, &mut pty_writer)?;
 
                 line.clear();
                 match tokio::time::timeout(
@@ -161,7 +645,8 @@ pub async fn setup_interactive_pty(
                         tokio::time::sleep(std::time::Duration::from_millis(500)).await;
                     }
                     Ok(Ok(_)) => {
-                        update_user_input();
+                        context.update_user_input();
+                        context.record_input("stdin", line.as_bytes());
                         pty_writer
                             .write_all(line.as_bytes())
                             .context("Failed to write line to PTY")?;
@@ -175,107 +660,521 @@ pub async fn setup_interactive_pty(
         })
     };
 
+    let sync_task = match fsync_policy() {
+        FsyncPolicy::Interval(interval) => Some(tokio::spawn(sync_periodically(interval))),
+        _ => None,
+    };
+
+    let watchdog_task = systemd::watchdog_interval().map(|interval| {
+        let context = context.clone();
+        supervise("watchdog", move || {
+            feed_watchdog(interval, queue_dir_enabled, context.clone())
+        })
+    });
+    let _ = systemd::notify("READY=1");
+
     // Wait for any task to complete or Ctrl+C
+    let mut output_ended = false;
     let result = tokio::select! {
         _ = tokio::signal::ctrl_c() => {
             Ok(())
         }
-        result = pty_output_task => {
-            result.context("PTY output task failed")?;
+        result = &mut pty_output_task => {
+            let end = result.context("PTY output task failed")?;
+            context.end_output();
+            tracing::info!(%end, "PTY output ended");
+            if let Some(log_file) = &session_log {
+                let _ = log_to_file(log_file, &format!("🚪 Session ending: {}", end)).await;
+            }
+            output_ended = true;
             Ok(())
         }
         result = input_task => {
             result.context("Input task join failed")??;
             Ok(())
         }
+        result = async {
+            match rpc_task {
+                Some(rpc_task) => rpc_task.await,
+                None => std::future::pending().await,
+            }
+        } => {
+            result.context("JSON-RPC task join failed")??;
+            Ok(())
+        }
     };
 
+    // Stops the input loops and the queue
+    context.end_output();
+    // After Ctrl+C or the end of input the shell is still running: hang up on it so the
+    // reader sees the end of its output
+    if !output_ended {
+        let _ = session.lock().await.kill();
+        let _ = tokio::time::timeout(SINK_DRAIN_TIMEOUT, &mut pty_output_task).await;
+    }
+
+    // Once the reader has stopped, sinks finish on their own: let them print the last output
+    // and flush the transcript and output log
+    let _ = tokio::time::timeout(SINK_DRAIN_TIMEOUT, async {
+        for sink in sinks {
+            let _ = sink.await;
+        }
+    })
+    .await;
+
+    // Leave the overlay's screen if the session ended while it was shown
+    close_overlay();
+
     // Restore terminal mode only if we enabled it
     if raw_mode_enabled {
+        if paste_guard.is_some() {
+            guard_pastes(false);
+        }
         disable_raw_mode().context("Failed to disable raw mode")?;
     }
 
+    let _ = systemd::notify("STOPPING=1");
+    for trigger_task in trigger_tasks {
+        trigger_task.abort();
+    }
+    if let Some(watchdog_task) = watchdog_task {
+        watchdog_task.abort();
+    }
+    if let Some(status_task) = status_task {
+        status_task.abort();
+    }
+    if let Some(sync_task) = sync_task {
+        sync_task.abort();
+        sync_unsynced();
+    }
+    if let Some(control_task) = control_task {
+        control_task.abort();
+    }
+    if let Some(control_socket) = control_socket {
+        let _ = std::fs::remove_file(control_socket);
+    }
+    if let Some(status_file) = status_file {
+        let _ = std::fs::remove_file(status_file);
+    }
+    set_panic_log(None);
+
     result
 }
 
-pub fn set_input_timeout(timeout_secs: u64) {
-    INPUT_TIMEOUT_MS.store(timeout_secs * 1000, Ordering::Relaxed);
+/// Write any input received through the control socket to the PTY
+fn write_control_input(
+    control_input: &mut mpsc::UnboundedReceiver<Vec<u8>>,
+    context: &SessionContext,
+    pty_writer: &mut Box<dyn Write + Send>,
+) -> Result<()> {
+    while let Ok(bytes) = control_input.try_recv() {
+        context.arbiter().observe_input(&bytes);
+        context.agent_input().expect_echo(&bytes);
+        context.record_input("control", &bytes);
+        pty_writer
+            .write_all(&bytes)
+            .context("Failed to write control input to PTY")?;
+        pty_writer.flush().context("Failed to flush PTY writer")?;
+    }
+    Ok(())
 }
 
-fn current_time_ms() -> u64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_millis() as u64
+/// Write each init command to the shell at a prompt: the first once the shell has started,
+/// the rest as the one before finishes. Queue processing is held until the last one is done.
+async fn run_init_commands(
+    commands: Vec<String>,
+    mut output: broadcast::Receiver<OutputChunk>,
+    input: mpsc::UnboundedSender<Vec<u8>>,
+    log_file: Option<PathBuf>,
+    context: Arc<SessionContext>,
+) -> Result<()> {
+    let mut prompts = PromptMarks::new();
+    wait_for_prompt(&mut output, &mut prompts, context.arbiter()).await;
+    for command in commands {
+        if let Some(log_file) = &log_file {
+            let _ = log_to_file(log_file, &format!("🚀 Init command: {}", command)).await;
+        }
+        context.audit("init", &command, "injected");
+        let bytes = format!("{}{}", command, context.terminator().as_str()).into_bytes();
+        if input.send(bytes).is_err() {
+            break;
+        }
+        wait_for_prompt(&mut output, &mut prompts, context.arbiter()).await;
+    }
+    context.set_init_running(false);
+    Ok(())
+}
+
+/// Wait for the shell's next prompt. With shell integration that is its prompt mark, however
+/// long the command takes; without, the first pause in output, or `PROMPT_WAIT_LIMIT` if the
+/// shell prints nothing at all.
+async fn wait_for_prompt(
+    output: &mut broadcast::Receiver<OutputChunk>,
+    prompts: &mut PromptMarks,
+    arbiter: &Arbiter,
+) {
+    let mut seen_output = false;
+    loop {
+        let wait = if seen_output {
+            PROMPT_QUIET
+        } else {
+            PROMPT_WAIT_LIMIT
+        };
+        match tokio::time::timeout(wait, output.recv()).await {
+            Ok(Ok(chunk)) => {
+                if prompts.feed(&chunk) {
+                    return;
+                }
+                seen_output = true;
+            }
+            Ok(Err(broadcast::error::RecvError::Lagged(_))) => seen_output = true,
+            Ok(Err(broadcast::error::RecvError::Closed)) => return,
+            Err(_) if seen_output && arbiter.prompt_marks_seen() => {}
+            Err(_) => return,
+        }
+    }
 }
 
-fn update_user_input() {
-    let now = current_time_ms();
-    LAST_USER_INPUT_TIME.store(now, Ordering::Relaxed);
-    USER_IS_TYPING.store(true, Ordering::Relaxed);
+/// Ping the systemd watchdog for as long as the queue loop keeps its heartbeat fresh, so a
+/// stuck session is restarted by systemd
+async fn feed_watchdog(
+    interval: std::time::Duration,
+    check_heartbeat: bool,
+    context: Arc<SessionContext>,
+) {
+    let mut ticker = tokio::time::interval(interval);
+
+    loop {
+        ticker.tick().await;
+
+        if !check_heartbeat || context.since_queue_check() <= HEARTBEAT_STALE_AFTER {
+            let _ = systemd::notify("WATCHDOG=1");
+        }
+    }
 }
 
-fn is_user_typing() -> bool {
-    let now = current_time_ms();
-    let last_input = LAST_USER_INPUT_TIME.load(Ordering::Relaxed);
-    let timeout_ms = INPUT_TIMEOUT_MS.load(Ordering::Relaxed);
-    let time_since_input = now.saturating_sub(last_input);
+/// Keep the session status file up to date until the task is aborted
+async fn publish_status(
+    status_file: PathBuf,
+    mut status: SessionStatus,
+    context: Arc<SessionContext>,
+) {
+    let mut interval = tokio::time::interval(STATUS_INTERVAL);
 
-    if time_since_input > timeout_ms {
-        USER_IS_TYPING.store(false, Ordering::Relaxed);
-        false
+    loop {
+        interval.tick().await;
+
+        let last_check_ms = context.last_queue_check_ms() as i64;
+        if let Some(last_check) = chrono::DateTime::from_timestamp_millis(last_check_ms) {
+            status.last_queue_check = last_check;
+        }
+        status.queue_paused = context.queue_paused();
+
+        let _ = status.write(&status_file);
+    }
+}
+
+/// Ctrl+] takes over the session
+fn is_takeover_key(key_event: &crossterm::event::KeyEvent) -> bool {
+    use crossterm::event::{KeyCode, KeyEventKind, KeyModifiers};
+
+    key_event.kind == KeyEventKind::Press
+        && key_event.modifiers.contains(KeyModifiers::CONTROL)
+        && key_event.code == KeyCode::Char(']')
+}
+
+/// Name the control bytes 0x1c-0x1f after the keys that produce them, Ctrl+\, Ctrl+],
+/// Ctrl+^ and Ctrl+_. crossterm reports them as Ctrl+4 to Ctrl+7, which some terminals also
+/// send them for.
+fn control_key_name(event: crossterm::event::Event) -> crossterm::event::Event {
+    use crossterm::event::{Event, KeyCode, KeyModifiers};
+
+    match event {
+        Event::Key(mut key_event) if key_event.modifiers == KeyModifiers::CONTROL => {
+            key_event.code = match key_event.code {
+                KeyCode::Char('4') => KeyCode::Char('\\'),
+                KeyCode::Char('5') => KeyCode::Char(']'),
+                KeyCode::Char('6') => KeyCode::Char('^'),
+                KeyCode::Char('7') => KeyCode::Char('_'),
+                code => code,
+            };
+            Event::Key(key_event)
+        }
+        event => event,
+    }
+}
+
+/// Lock the queue for a human taking control mid-automation, or release it again. The lock
+/// is shown in the terminal title and announced in the terminal while it changes.
+fn toggle_takeover(context: &SessionContext) {
+    let locked = context.toggle_takeover();
+    let (title, message) = if locked {
+        (
+            "🔒 typeypipe: queue locked",
+            "🔒 Queue locked - press Ctrl+] again to release it",
+        )
     } else {
-        true
+        ("typeypipe", "🔓 Queue released")
+    };
+
+    let mut stdout = std::io::stdout();
+    let _ = write!(
+        stdout,
+        "\x1b]2;{}\x07\r\n[typeypipe] {}\r\n",
+        label(title),
+        label(message)
+    );
+    let _ = stdout.flush();
+}
+
+/// Size of the outer terminal, assuming 80x24 when it does not report one
+fn terminal_size() -> (u16, u16) {
+    match crossterm::terminal::size() {
+        Ok((cols, rows)) if cols > 0 && rows > 0 => (cols, rows),
+        _ => (80, 24),
     }
 }
 
-/// Log files are placed next to the queue directories inside the .tp directory
-async fn log_to_file(log_file: &PathBuf, message: &str) -> Result<()> {
-    use tokio::io::AsyncWriteExt;
-    let timestamp = chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC");
-    let log_entry = format!("[{}] {}\n", timestamp, message);
-
-    let mut file = tokio::fs::OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(log_file)
-        .await
-        .context("Failed to open log file")?;
-
-    file.write_all(log_entry.as_bytes())
-        .await
-        .context("Failed to write to log file")?;
-    file.flush().await.context("Failed to flush log file")?;
-    Ok(())
+/// Carry out an action chosen in the command palette and say what happened
+async fn run_palette_action(
+    action: PaletteAction,
+    context: &SessionContext,
+    queue_dir: Option<&Path>,
+    screen_file: Option<&Path>,
+    scrollback: &SharedScrollback,
+    rows: u16,
+) {
+    let message = match action {
+        PaletteAction::ToggleTakeover => return toggle_takeover(context),
+        // Shown in the overlay by the caller
+        PaletteAction::ShowHelp => return,
+        PaletteAction::ToggleHold => {
+            let held = !context.queue_held();
+            context.hold_queue(held);
+            if held {
+                "⏸️ Queue held - choose it again in the palette (F2) to resume".to_string()
+            } else {
+                "▶️ Queue resumed".to_string()
+            }
+        }
+        PaletteAction::DumpScreen => match screen_file {
+            Some(screen_file) => match save_screen(scrollback, rows, screen_file) {
+                Ok(()) => format!("Screen saved to {}", screen_file.display()),
+                Err(e) => format!("❌ {:#}", e),
+            },
+            None => "❌ This session has no log directory to save the screen in".to_string(),
+        },
+        PaletteAction::Rerun(command) => match queue_dir {
+            Some(queue_dir) => {
+                let queued = async {
+                    let message = QueueMessage::Command {
+                        command: command.clone(),
+                        terminator: None,
+                    };
+                    enqueue_file(queue_dir, "palette", &message.encode()?).await
+                };
+                match queued.await {
+                    Ok(_) => format!("Queued: {}", command),
+                    Err(e) => format!("❌ Could not queue {}: {:#}", command, e),
+                }
+            }
+            None => "❌ This session has no queue".to_string(),
+        },
+    };
+
+    print_notice(&message);
+}
+
+/// Write a pasted `text` to the shell as typed input
+fn write_paste(
+    text: &str,
+    context: &SessionContext,
+    pty_writer: &mut Box<dyn Write + Send>,
+) -> Result<()> {
+    context.update_user_input();
+    let bytes = paste_input(text, inner_bracketed_paste());
+    context.arbiter().observe_input(&bytes);
+    context.record_input("paste", &bytes);
+    pty_writer
+        .write_all(&bytes)
+        .context("Failed to write paste to PTY")?;
+    pty_writer.flush().context("Failed to flush PTY writer")
+}
+
+/// Queue a held paste as a command instead of pasting it
+async fn queue_paste(text: &str, queue_dir: Option<&Path>) {
+    let Some(queue_dir) = queue_dir else {
+        return print_notice("❌ This session has no queue; paste discarded");
+    };
+    let message = QueueMessage::Command {
+        command: paste_command(text),
+        terminator: None,
+    };
+    let queued = match message.encode() {
+        Ok(encoded) => enqueue_file(queue_dir, "paste", &encoded).await,
+        Err(e) => Err(e),
+    };
+    match queued {
+        Ok(_) => print_notice("Paste queued as a command"),
+        Err(e) => print_notice(&format!("❌ Could not queue the paste: {:#}", e)),
+    }
+}
+
+/// Show a message from typeypipe on its own line below the shell's output
+fn print_notice(message: &str) {
+    let mut stdout = std::io::stdout();
+    let _ = write!(stdout, "\r\n[typeypipe] {}\r\n", label(message));
+    let _ = stdout.flush();
+}
+
+/// Save the visible screen, without escape sequences, to `screen_file`
+fn save_screen(scrollback: &SharedScrollback, rows: u16, screen_file: &Path) -> Result<()> {
+    let text = {
+        let scrollback = scrollback
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Scrollback is unavailable"))?;
+        let mut visible = scrollback.tail(rows.saturating_sub(1) as usize);
+        visible.push(scrollback.partial_line());
+        visible.join("\n")
+    };
+    std::fs::write(screen_file, strip_ansi(&text) + "\n").context("Failed to save the screen")
+}
+
+/// Make the program in the shell redraw its screen by resizing the PTY away and back, which
+/// sends it SIGWINCH
+async fn redraw_shell(session: &SharedPtySession) {
+    let mut session_guard = session.lock().await;
+    let (rows, cols) = session_guard.size();
+    let _ = session_guard.resize(rows.saturating_sub(1).max(1), cols);
+    let _ = session_guard.resize(rows, cols);
+}
+
+/// The PTY writer, shared by the input loop and the queue task. Each write holds the lock for
+/// the whole buffer, so a keystroke never lands in the middle of a queued command.
+#[derive(Clone)]
+struct SharedWriter(Arc<std::sync::Mutex<Box<dyn Write + Send>>>);
+
+impl SharedWriter {
+    fn new(writer: Box<dyn Write + Send>) -> Self {
+        Self(Arc::new(std::sync::Mutex::new(writer)))
+    }
+
+    fn lock(&self) -> std::io::Result<std::sync::MutexGuard<'_, Box<dyn Write + Send>>> {
+        self.0
+            .lock()
+            .map_err(|_| std::io::Error::other("PTY writer is unavailable"))
+    }
+}
+
+impl Write for SharedWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.lock()?.write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        self.lock()?.write_all(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.lock()?.flush()
+    }
+}
+
+/// Inject the oldest queued command once a second for as long as the shell is running
+async fn process_queue_periodically(
+    context: Arc<SessionContext>,
+    queue_dir: PathBuf,
+    log_file: PathBuf,
+    pty_writer: SharedWriter,
+) {
+    let mut pty_writer: Box<dyn Write + Send> = Box::new(pty_writer);
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(1));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    while !context.output_ended() {
+        interval.tick().await;
+        let _ = process_next_queue_command(&context, &queue_dir, &log_file, &mut pty_writer).await;
+    }
 }
 
 /// Process the next queue command if one exists by injecting the command into the interactive shell
 async fn process_next_queue_command(
+    context: &SessionContext,
     queue_dir: &PathBuf,
-    log_file: &PathBuf,
+    log_file: &Path,
     pty_writer: &mut Box<dyn Write + Send>,
 ) -> Result<()> {
     use tokio::fs;
-    use tokio::io::AsyncWriteExt;
 
-    if is_user_typing() {
-        if !QUEUE_PAUSED_LOGGED.load(Ordering::Relaxed) {
+    context.queue_checked();
+
+    // A command written now would be lost with the shell; it waits for the next session
+    if context.output_ended() {
+        return Ok(());
+    }
+
+    if context.taken_over() {
+        if !context.swap_takeover_logged(true) {
+            tracing::info!("queue locked by takeover");
+            let _ = log_to_file(log_file, "🔒 Queue locked - user took over the session").await;
+        }
+        return Ok(());
+    } else if context.swap_takeover_logged(false) {
+        tracing::info!("queue unlocked");
+        let _ = log_to_file(log_file, "🔓 Queue unlocked - user released the session").await;
+    }
+
+    if context.init_running() {
+        return Ok(());
+    }
+
+    if context.queue_held() {
+        if !context.swap_held_logged(true) {
+            tracing::info!("queue held");
+            let _ = log_to_file(log_file, "⏸️ Queue processing held by control request").await;
+        }
+        return Ok(());
+    } else if context.swap_held_logged(false) {
+        tracing::info!("queue released");
+        let _ = log_to_file(log_file, "▶️ Queue processing released by control request").await;
+    }
+
+    if context.is_user_typing() {
+        if !context.swap_paused_logged(true) {
+            tracing::info!("queue paused");
             let _ = log_to_file(log_file, "⏸️ Queue processing paused - user is typing").await;
-            QUEUE_PAUSED_LOGGED.store(true, Ordering::Relaxed);
         }
         return Ok(()); // Skip processing while user is typing
-    } else {
-        if QUEUE_PAUSED_LOGGED.load(Ordering::Relaxed) {
+    } else if context.swap_paused_logged(false) {
+        tracing::info!("queue resumed");
+        let _ = log_to_file(
+            log_file,
+            "▶️ Queue processing resumed - user input timeout expired",
+        )
+        .await;
+    }
+
+    // Injecting now would splice the command into the line being typed
+    if context.arbiter().line_pending() {
+        if !context.swap_line_pending_logged(true) {
+            tracing::info!("queue waiting for typed line");
             let _ = log_to_file(
                 log_file,
-                "▶️ Queue processing resumed - user input timeout expired",
+                "⏸️ Queue processing held - a typed line has not been submitted",
             )
             .await;
-            QUEUE_PAUSED_LOGGED.store(false, Ordering::Relaxed);
         }
+        return Ok(());
+    } else if context.swap_line_pending_logged(false) {
+        tracing::info!("typed line submitted");
+        let _ = log_to_file(
+            log_file,
+            "▶️ Queue processing resumed - typed line submitted",
+        )
+        .await;
     }
 
-    // Read and sort queue directory entries by modification time (oldest first)
+    // Read and sort queue directory entries by modification time (oldest first), then by
+    // name for files written within the same timestamp tick
     let mut file_entries = Vec::new();
     let mut entries = match fs::read_dir(queue_dir).await {
         Ok(entries) => entries,
@@ -294,180 +1193,186 @@ async fn process_next_queue_command(
         }
     }
 
-    file_entries.sort_by(|a, b| a.1.cmp(&b.1));
+    file_entries.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
 
     // Process only the oldest file (one message per tick)
-    if let Some((path, _)) = file_entries.first() {
+    if let Some((path, modified)) = file_entries.into_iter().next() {
         let filename = path
             .file_name()
             .and_then(|n| n.to_str())
             .unwrap_or("unknown")
             .to_string();
 
-        if let Ok(command) = fs::read_to_string(&path).await {
-            let command = command.trim();
+        let span = tracing::info_span!("queue_message", file = %filename);
+        inject_queue_file(context, &path, &filename, modified, log_file, pty_writer)
+            .instrument(span)
+            .await;
+    }
 
-            let log_entry = {
-                let timestamp = chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC");
-                format!("[{}] 🔄 Processing: {}\n{}\n", timestamp, filename, command)
-            };
+    Ok(())
+}
 
-            let mut file = tokio::fs::OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(log_file)
-                .await
-                .unwrap_or_else(|_| panic!("Failed to open log file"));
-
-            file.write_all(log_entry.as_bytes()).await.ok();
-            file.flush().await.ok();
-
-            let command_with_newline = format!("{}\r", command);
-            let mut _success = false;
-
-            // Try up to 50 times for recoverable errors
-            for attempt in 0..50 {
-                let write_result = pty_writer.write_all(command_with_newline.as_bytes());
-
-                match write_result {
-                    Err(e) => match e.kind() {
-                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::Interrupted => {
-                            if attempt == 49 {
-                                // Final attempt failed - log and remove file
-                                let retry_log_entry = {
-                                    let timestamp =
-                                        chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC");
-                                    format!("[{}] ❌ Gave up after 50 retries for: {} ({})\nCommand was:\n{}\n", 
-                                                timestamp, filename, e.kind(), command)
-                                };
+/// Number of attempts made for PTY writes and flushes that fail with a recoverable error
+const PTY_WRITE_ATTEMPTS: usize = 50;
 
-                                let mut file = tokio::fs::OpenOptions::new()
-                                    .create(true)
-                                    .append(true)
-                                    .open(log_file)
-                                    .await
-                                    .unwrap_or_else(|_| panic!("Failed to open log file"));
-
-                                file.write_all(retry_log_entry.as_bytes()).await.ok();
-                                file.flush().await.ok();
-                                let _ = fs::remove_file(&path).await; // Remove failed file
-                                break;
-                            }
-                            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
-                            continue;
-                        }
-                        _ => {
-                            // Non-recoverable error - log and remove file
-                            let error_log_entry = {
-                                let timestamp = chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC");
-                                format!("[{}] ❌ Failed to inject command from: {}\nError: {}\nCommand was:\n{}\n", 
-                                            timestamp, filename, e, command)
-                            };
-
-                            let mut file = tokio::fs::OpenOptions::new()
-                                .create(true)
-                                .append(true)
-                                .open(log_file)
-                                .await
-                                .unwrap_or_else(|_| panic!("Failed to open log file"));
-
-                            file.write_all(error_log_entry.as_bytes()).await.ok();
-                            file.flush().await.ok();
-                            let _ = fs::remove_file(&path).await;
-                            break;
-                        }
-                    },
-                    Ok(()) => {
-                        for flush_attempt in 0..50 {
-                            match pty_writer.flush() {
-                                Err(e) => match e.kind() {
-                                    std::io::ErrorKind::WouldBlock
-                                    | std::io::ErrorKind::Interrupted => {
-                                        if flush_attempt == 49 {
-                                            let retry_log_entry = {
-                                                let timestamp = chrono::Utc::now()
-                                                    .format("%Y-%m-%d %H:%M:%S UTC");
-                                                format!("[{}] ❌ Gave up after 50 flush retries for: {} ({})\nCommand was:\n{}\n", 
-                                                            timestamp, filename, e.kind(), command)
-                                            };
+/// Why a PTY write or flush was abandoned
+enum PtyWriteError {
+    /// Every attempt failed with a recoverable error (WouldBlock/Interrupted)
+    GaveUp(std::io::ErrorKind),
+    /// A non-recoverable error occurred
+    Failed(std::io::Error),
+    /// The user took over the session while the write was being retried
+    TakenOver,
+}
 
-                                            let mut file = tokio::fs::OpenOptions::new()
-                                                .create(true)
-                                                .append(true)
-                                                .open(log_file)
-                                                .await
-                                                .unwrap_or_else(|_| {
-                                                    panic!("Failed to open log file")
-                                                });
-
-                                            file.write_all(retry_log_entry.as_bytes()).await.ok();
-                                            file.flush().await.ok();
-                                            let _ = fs::remove_file(&path).await; // Remove failed file
-                                            break;
-                                        }
-                                        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
-                                        continue;
-                                    }
-                                    _ => {
-                                        let error_log_entry = {
-                                            let timestamp =
-                                                chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC");
-                                            format!("[{}] ❌ Failed to flush PTY writer for: {}\nError: {}\nCommand was:\n{}\n", 
-                                                        timestamp, filename, e, command)
-                                        };
-
-                                        let mut file = tokio::fs::OpenOptions::new()
-                                            .create(true)
-                                            .append(true)
-                                            .open(log_file)
-                                            .await
-                                            .unwrap_or_else(|_| panic!("Failed to open log file"));
-
-                                        file.write_all(error_log_entry.as_bytes()).await.ok();
-                                        file.flush().await.ok();
-                                        let _ = fs::remove_file(&path).await;
-                                        break;
-                                    }
-                                },
-                                Ok(()) => {
-                                    // Both write and flush succeeded - remove the processed file
-                                    let _ = fs::remove_file(&path).await;
-                                    _success = true;
-                                    break;
-                                }
-                            }
-                        }
-                        break; // Exit write retry loop
+/// Run a PTY write operation, retrying once a second while it fails with a recoverable error
+/// and the user has not taken over the session
+async fn retry_pty_write(
+    context: &SessionContext,
+    mut operation: impl FnMut() -> std::io::Result<()>,
+) -> std::result::Result<(), PtyWriteError> {
+    for attempt in 0..PTY_WRITE_ATTEMPTS {
+        match operation() {
+            Ok(()) => return Ok(()),
+            Err(e) => match e.kind() {
+                std::io::ErrorKind::WouldBlock | std::io::ErrorKind::Interrupted => {
+                    if attempt == PTY_WRITE_ATTEMPTS - 1 {
+                        return Err(PtyWriteError::GaveUp(e.kind()));
+                    }
+                    if context.taken_over() {
+                        return Err(PtyWriteError::TakenOver);
                     }
+                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
                 }
+                _ => return Err(PtyWriteError::Failed(e)),
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Inject the contents of a single queue file into the shell and remove the file once it
+/// has been handled (successfully or not)
+async fn inject_queue_file(
+    context: &SessionContext,
+    path: &Path,
+    filename: &str,
+    queued_at: SystemTime,
+    log_file: &Path,
+    pty_writer: &mut Box<dyn Write + Send>,
+) {
+    let Ok(contents) = tokio::fs::read_to_string(path).await else {
+        return;
+    };
+
+    let queue_wait_ms = SystemTime::now()
+        .duration_since(queued_at)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    tracing::info!(queue_wait_ms, bytes = contents.len(), "enqueued");
+
+    let Some(injection) = prepare_injection(context, path, &contents, log_file).await else {
+        return;
+    };
+    let Injection {
+        source,
+        command,
+        bytes,
+    } = &injection;
+
+    let _ = log_to_file(
+        log_file,
+        &format!("🔄 Processing: {}\n{}", filename, command),
+    )
+    .await;
+
+    // Started before writing so a quick command's prompt cannot arrive first. Without shell
+    // integration no prompt would ever stop it.
+    if context.arbiter().prompt_marks_seen() {
+        context.stopwatch().start(source, command);
+    }
+    context.emit_queue_event(QueueEvent::Writing {
+        source: source.clone(),
+    });
+    context.agent_input().expect_echo(bytes);
+    context.record_input(source, bytes);
+    let written = match retry_pty_write(context, || pty_writer.write_all(bytes)).await {
+        Ok(()) => {
+            tracing::info!("injected");
+            // The command has reached the PTY, so a takeover only stops the flush
+            match retry_pty_write(context, || pty_writer.flush()).await {
+                Err(PtyWriteError::TakenOver) => Ok(()),
+                flushed => flushed,
             }
         }
+        Err(e) => Err(e),
+    };
+    match written {
+        Ok(()) => injected(context, path, &injection).await,
+        Err(PtyWriteError::GaveUp(kind)) => {
+            tracing::warn!(error = %kind, "gave up writing to PTY");
+            context.stopwatch().stop();
+            let error = format!("gave up after {} retries ({})", PTY_WRITE_ATTEMPTS, kind);
+            write_failed(context, path, &injection, &error, log_file).await;
+        }
+        Err(PtyWriteError::Failed(e)) => {
+            tracing::error!(error = %e, "failed to write to PTY");
+            context.stopwatch().stop();
+            write_failed(context, path, &injection, &e.to_string(), log_file).await;
+        }
+        Err(PtyWriteError::TakenOver) => {
+            tracing::info!("write abandoned for takeover");
+            context.stopwatch().stop();
+            let _ = log_to_file(
+                log_file,
+                &format!(
+                    "🔒 Stopped writing {} - user took over the session; it stays queued",
+                    filename
+                ),
+            )
+            .await;
+        }
     }
+}
 
-    Ok(())
+/// Stop the command timer at a fresh prompt, and note how long the command took in the
+/// recent commands and the session log
+fn record_command_duration(context: &SessionContext, log_file: PathBuf) {
+    let Some((finished, duration)) = context.stopwatch().stop() else {
+        return;
+    };
+    context.record_duration(&finished.source, &finished.command, duration);
+    tokio::spawn(async move {
+        let message = format!(
+            "⏱️ Finished in {}: {}",
+            format_duration(duration),
+            finished.command
+        );
+        let _ = log_to_file(&log_file, &message).await;
+    });
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::shell::pty::{create_pty_session, PtySessionManager};
+    use crate::shell::pty::{create_pty_session, PtySession};
     use crate::shell::types::ShellConfig;
     use tempfile::TempDir;
 
     #[tokio::test]
-    async fn test_pty_session_manager_creation() {
+    async fn test_pty_session_creation() {
         let config = ShellConfig::default();
-        let result = PtySessionManager::new(config).await;
+        let result = PtySession::new(config).await;
 
         match result {
             Ok(session) => {
                 assert!(!session.session_id().is_empty());
                 eprintln!(
-                    "✅ Successfully created PTY session manager: {}",
+                    "✅ Successfully created PTY session: {}",
                     session.session_id()
                 );
             }
             Err(e) => {
-                eprintln!("❌ PTY session manager creation failed: {}", e);
+                eprintln!("❌ PTY session creation failed: {}", e);
                 // This might fail in CI environments without proper terminal setup
             }
         }
@@ -519,4 +1424,122 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_takeover_key_is_ctrl_close_bracket_only() {
+        use super::{control_key_name, is_takeover_key};
+        use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+
+        let ctrl = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL);
+        assert!(is_takeover_key(&ctrl(']')));
+        assert!(!is_takeover_key(&ctrl('5')));
+        assert!(!is_takeover_key(&KeyEvent::new(
+            KeyCode::Char(']'),
+            KeyModifiers::NONE
+        )));
+
+        // The 0x1d byte terminals send for Ctrl+] is decoded by crossterm as Ctrl+5
+        let Event::Key(decoded) = control_key_name(Event::Key(ctrl('5'))) else {
+            unreachable!();
+        };
+        assert_eq!(decoded, ctrl(']'));
+    }
+
+    #[tokio::test]
+    async fn test_pty_write_retries_stop_at_takeover() {
+        use super::{retry_pty_write, PtyWriteError};
+        use crate::shell::context::SessionContext;
+        use crate::shell::types::Terminator;
+
+        let context = SessionContext::new(Terminator::Cr);
+        context.toggle_takeover();
+        let mut attempts = 0;
+        let result = retry_pty_write(&context, || {
+            attempts += 1;
+            Err(std::io::ErrorKind::WouldBlock.into())
+        })
+        .await;
+
+        assert!(matches!(result, Err(PtyWriteError::TakenOver)));
+        assert_eq!(attempts, 1);
+    }
+
+    #[tokio::test]
+    async fn test_takeover_stops_an_injection_stuck_in_retries() {
+        use super::{process_next_queue_command, SharedWriter};
+        use crate::shell::context::SessionContext;
+        use crate::shell::types::Terminator;
+        use std::io::Write;
+        use std::sync::Arc;
+
+        /// A PTY whose buffer never drains
+        struct Full;
+        impl Write for Full {
+            fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::ErrorKind::WouldBlock.into())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let queue_dir = temp_dir.path().join("queue");
+        let log_file = temp_dir.path().join("session.log");
+        std::fs::create_dir_all(&queue_dir).unwrap();
+        std::fs::write(queue_dir.join("cmd"), "make").unwrap();
+        let context = Arc::new(SessionContext::new(Terminator::Cr));
+
+        let injection = tokio::spawn({
+            let (context, queue_dir, log_file) =
+                (context.clone(), queue_dir.clone(), log_file.clone());
+            async move {
+                let mut pty_writer: Box<dyn Write + Send> =
+                    Box::new(SharedWriter::new(Box::new(Full)));
+                process_next_queue_command(&context, &queue_dir, &log_file, &mut pty_writer).await
+            }
+        });
+        // Ctrl+] arrives while the write is being retried
+        tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
+        assert!(!injection.is_finished());
+        context.toggle_takeover();
+
+        tokio::time::timeout(std::time::Duration::from_secs(5), injection)
+            .await
+            .expect("the takeover ends the retries")
+            .unwrap()
+            .unwrap();
+        assert!(queue_dir.join("cmd").exists());
+        let log = std::fs::read_to_string(&log_file).unwrap();
+        assert!(log.contains("user took over the session"), "{}", log);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pty_write_gives_up_in_virtual_time() {
+        use super::{retry_pty_write, PtyWriteError, PTY_WRITE_ATTEMPTS};
+        use crate::shell::context::SessionContext;
+        use crate::shell::types::Terminator;
+
+        // Fifty seconds of retries pass without waiting for them
+        let started = tokio::time::Instant::now();
+        let real_start = std::time::Instant::now();
+        let context = SessionContext::new(Terminator::Cr);
+        let mut attempts = 0;
+        let result = retry_pty_write(&context, || {
+            attempts += 1;
+            Err(std::io::ErrorKind::WouldBlock.into())
+        })
+        .await;
+
+        assert!(matches!(
+            result,
+            Err(PtyWriteError::GaveUp(std::io::ErrorKind::WouldBlock))
+        ));
+        assert_eq!(attempts, PTY_WRITE_ATTEMPTS);
+        assert_eq!(
+            started.elapsed(),
+            std::time::Duration::from_secs(PTY_WRITE_ATTEMPTS as u64 - 1)
+        );
+        assert!(real_start.elapsed() < std::time::Duration::from_secs(5));
+    }
 }
@@ -0,0 +1,197 @@
+use crate::shell::types::{SessionTarget, ShellConfig};
+use std::io::IsTerminal;
+use std::path::PathBuf;
+
+/// `TERM` given to the shell unless `--term` says otherwise
+pub const DEFAULT_TERM: &str = "xterm-256color";
+
+/// Value of `--term` and `--colorterm` that passes the outer terminal's value through
+const INHERIT: &str = "inherit";
+
+/// `TERM` for the shell from a `--term` value: the outer terminal's with `inherit`, falling
+/// back to `DEFAULT_TERM` when it has none
+pub fn resolve_term(value: &str) -> String {
+    match value {
+        INHERIT => std::env::var("TERM")
+            .ok()
+            .filter(|term| !term.is_empty())
+            .unwrap_or_else(|| DEFAULT_TERM.to_string()),
+        term => term.to_string(),
+    }
+}
+
+/// `COLORTERM` for the shell from a `--colorterm` value: the outer terminal's with `inherit`,
+/// unset with `none`
+pub fn resolve_colorterm(value: &str) -> Option<String> {
+    match value {
+        INHERIT => std::env::var("COLORTERM")
+            .ok()
+            .filter(|colorterm| !colorterm.is_empty()),
+        "none" => None,
+        colorterm => Some(colorterm.to_string()),
+    }
+}
+
+/// Problems with the terminal the shell is told it runs in, worth a warning at startup.
+///
+/// **Checks:**
+/// - The shell is promised more colors than the outer terminal reports, so programs would
+///   send escape sequences it cannot show. Skipped when stdout is not a terminal.
+/// - No terminfo entry exists for a local shell's `TERM`, so curses programs fall back to a
+///   dumb terminal or refuse to start. A container has its own terminfo and is not checked.
+pub fn term_warnings(config: &ShellConfig) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if std::io::stdout().is_terminal() {
+        let outer_term = std::env::var("TERM").ok();
+        let outer_colorterm = std::env::var("COLORTERM").ok();
+        warnings.extend(color_mismatch(
+            outer_term.as_deref(),
+            outer_colorterm.as_deref(),
+            &config.term,
+            config.colorterm.as_deref(),
+        ));
+    }
+    if config.target == SessionTarget::Local && terminfo_missing(&config.term) {
+        warnings.push(format!(
+            "No terminfo entry for TERM={} was found; full-screen programs in the shell may not work (see --term)",
+            config.term
+        ));
+    }
+    warnings
+}
+
+/// Colors a terminal with this `TERM` and `COLORTERM` claims to show
+fn colors(term: Option<&str>, colorterm: Option<&str>) -> u32 {
+    if matches!(colorterm, Some("truecolor" | "24bit")) {
+        return 1 << 24;
+    }
+    match term {
+        None | Some("" | "dumb") => 0,
+        Some(term) if term.ends_with("-direct") => 1 << 24,
+        Some(term) if term.contains("256color") => 256,
+        Some(term) if term.contains("16color") => 16,
+        Some(_) => 8,
+    }
+}
+
+fn describe_colors(colors: u32) -> String {
+    match colors {
+        0 => "no colors".to_string(),
+        n if n >= 1 << 24 => "24-bit color".to_string(),
+        n => format!("{} colors", n),
+    }
+}
+
+fn describe_term(term: Option<&str>, colorterm: Option<&str>) -> String {
+    let mut described = format!("TERM={}", term.unwrap_or("<unset>"));
+    if let Some(colorterm) = colorterm {
+        described.push_str(&format!(", COLORTERM={}", colorterm));
+    }
+    described
+}
+
+/// A warning when the shell is told about more colors than the outer terminal has
+fn color_mismatch(
+    outer_term: Option<&str>,
+    outer_colorterm: Option<&str>,
+    term: &str,
+    colorterm: Option<&str>,
+) -> Option<String> {
+    let outer = colors(outer_term, outer_colorterm);
+    let advertised = colors(Some(term), colorterm);
+    (advertised > outer).then(|| {
+        format!(
+            "The shell is told the terminal has {} ({}) but this terminal reports {} ({}); use --term inherit or --colorterm to match",
+            describe_colors(advertised),
+            describe_term(Some(term), colorterm),
+            describe_colors(outer),
+            describe_term(outer_term, outer_colorterm)
+        )
+    })
+}
+
+/// Directories ncurses searches for terminfo entries, in its order
+fn terminfo_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Some(dir) = std::env::var_os("TERMINFO") {
+        dirs.push(PathBuf::from(dir));
+    }
+    if let Some(home) = std::env::var_os("HOME") {
+        dirs.push(PathBuf::from(home).join(".terminfo"));
+    }
+    if let Ok(list) = std::env::var("TERMINFO_DIRS") {
+        dirs.extend(list.split(':').map(|dir| {
+            PathBuf::from(if dir.is_empty() {
+                "/usr/share/terminfo"
+            } else {
+                dir
+            })
+        }));
+    }
+    dirs.extend(
+        [
+            "/etc/terminfo",
+            "/lib/terminfo",
+            "/usr/share/terminfo",
+            "/usr/lib/terminfo",
+        ]
+        .map(PathBuf::from),
+    );
+    dirs
+}
+
+/// Whether this system keeps terminfo entries but none for `term`. Entries live under the
+/// term's first letter, or its hex code on macOS.
+fn terminfo_missing(term: &str) -> bool {
+    let Some(first) = term.chars().next() else {
+        return false;
+    };
+    let dirs: Vec<PathBuf> = terminfo_dirs()
+        .into_iter()
+        .filter(|dir| dir.is_dir())
+        .collect();
+    if dirs.is_empty() {
+        return false;
+    }
+    let subdirs = [first.to_string(), format!("{:x}", first as u32)];
+    !dirs.iter().any(|dir| {
+        subdirs
+            .iter()
+            .any(|subdir| dir.join(subdir).join(term).is_file())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::color_mismatch;
+
+    #[test]
+    fn test_warns_only_when_the_shell_is_promised_more_colors() {
+        assert!(color_mismatch(Some("xterm-256color"), None, "xterm-256color", None).is_none());
+        assert!(color_mismatch(
+            Some("xterm-kitty"),
+            Some("truecolor"),
+            "xterm-256color",
+            Some("truecolor")
+        )
+        .is_none());
+        assert!(color_mismatch(Some("xterm-256color"), None, "xterm", None).is_none());
+
+        let warning = color_mismatch(Some("linux"), None, "xterm-256color", None).unwrap();
+        assert!(
+            warning.contains("256 colors (TERM=xterm-256color)"),
+            "{}",
+            warning
+        );
+        assert!(warning.contains("8 colors (TERM=linux)"), "{}", warning);
+        let warning = color_mismatch(
+            Some("xterm-256color"),
+            None,
+            "xterm-256color",
+            Some("truecolor"),
+        )
+        .unwrap();
+        assert!(warning.contains("24-bit color"), "{}", warning);
+        assert!(color_mismatch(None, None, "dumb", None).is_none());
+    }
+}
@@ -0,0 +1,358 @@
+use crate::shell::context::{QueueEvent, SessionContext};
+use crate::shell::control::{self, ControlContext};
+use crate::shell::headless::{run_headless, SessionHandle};
+use crate::shell::scrollback::create_scrollback;
+use crate::shell::tee::{spawn_blocking_sink, OutputTee};
+use crate::shell::types::{FailureCode, QueueFailure, QueueMessage, ShellConfig, Terminator};
+use anyhow::{Context, Result};
+use std::io::Read;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc as std_mpsc, Arc};
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::mpsc;
+
+/// A deterministic stand-in for a real shell, so tests do not depend on the bash or zsh
+/// installed on the machine and the user's rc files.
+///
+/// **Behaviour:**
+/// - Prints `prompt` wrapped in OSC 133 `A`/`B` marks, like a shell with integration installed
+/// - Reads one line at a time (the PTY echoes it) and answers `ran: <line>` after `delay`,
+///   between `C` and `D;<status>` marks
+/// - `fail <n>` finishes with status `n`; `exit <n>` exits the shell with code `n`
+/// - Written out as a POSIX sh script, so no extra binary has to be built for tests
+pub struct FakeShell {
+    prompt: String,
+    delay: Duration,
+}
+
+impl Default for FakeShell {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FakeShell {
+    pub fn new() -> Self {
+        Self {
+            prompt: "$ ".to_string(),
+            delay: Duration::ZERO,
+        }
+    }
+
+    pub fn prompt(mut self, prompt: &str) -> Self {
+        self.prompt = prompt.to_string();
+        self
+    }
+
+    /// How long every command takes before it prints its output
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Write the script into `dir` and return its path
+    pub fn install(&self, dir: &Path) -> Result<PathBuf> {
+        let path = dir.join("fake-shell");
+        std::fs::write(&path, self.script()).context("Failed to write fake shell")?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))
+            .context("Failed to make fake shell executable")?;
+        Ok(path)
+    }
+
+    /// Run the fake shell headless in a fresh temporary `.tp` directory
+    pub async fn start(&self) -> Result<FakeSession> {
+        let dir = TempDir::new().context("Failed to create temporary directory")?;
+        let config = ShellConfig {
+            shell_path: self.install(dir.path())?.display().to_string(),
+            ..ShellConfig::default()
+        };
+        let handle = run_headless(
+            config,
+            dir.path().join("fake"),
+            dir.path().join("fake.log"),
+            None,
+            Arc::new(SessionContext::new(Terminator::Lf)),
+        )
+        .await?;
+        let output = handle
+            .subscribe()
+            .ok_or_else(|| anyhow::anyhow!("Fake shell closed its PTY immediately"))?;
+
+        Ok(FakeSession {
+            handle,
+            output,
+            seen: Vec::new(),
+            _dir: dir,
+        })
+    }
+
+    fn script(&self) -> String {
+        let prompt = self.prompt.replace('\'', r"'\''");
+        let delay = format!("{}.{:03}", self.delay.as_secs(), self.delay.subsec_millis());
+        format!(
+            r#"#!/bin/sh
+PROMPT='{prompt}'
+prompt() {{ printf '\033]133;A\007%s\033]133;B\007' "$PROMPT"; }}
+prompt
+while IFS= read -r line; do
+    printf '\033]133;C\007'
+    status=0
+    case "$line" in
+        exit) exit 0 ;;
+        "exit "*) exit "${{line#exit }}" ;;
+        "fail "*) status="${{line#fail }}" ;;
+    esac
+    [ "{delay}" = "0.000" ] || sleep {delay}
+    printf 'ran: %s\n' "$line"
+    printf '\033]133;D;%s\007' "$status"
+    prompt
+done
+"#
+        )
+    }
+}
+
+/// A running `FakeShell` and everything it has printed so far
+pub struct FakeSession {
+    pub handle: SessionHandle,
+    output: broadcast::Receiver<crate::shell::tee::OutputChunk>,
+    seen: Vec<u8>,
+    _dir: TempDir,
+}
+
+impl FakeSession {
+    /// Drop a file into the session's queue
+    pub fn enqueue(&self, name: &str, contents: &str) -> Result<()> {
+        std::fs::write(self.handle.queue_dir().join(name), contents)
+            .context("Failed to write queue file")
+    }
+
+    /// All output received so far
+    pub fn output(&self) -> &[u8] {
+        &self.seen
+    }
+
+    /// Collect output until `needle` has appeared, failing after `timeout`
+    pub async fn wait_for(&mut self, needle: &str, timeout: Duration) -> Result<()> {
+        let deadline = tokio::time::Instant::now() + timeout;
+
+        while !String::from_utf8_lossy(&self.seen).contains(needle) {
+            match tokio::time::timeout_at(deadline, self.output.recv()).await {
+                Ok(Ok(chunk)) => self.seen.extend_from_slice(&chunk),
+                Ok(Err(RecvError::Lagged(_))) => continue,
+                Ok(Err(RecvError::Closed)) => {
+                    return Err(anyhow::anyhow!("Shell exited before printing '{}'", needle))
+                }
+                Err(_) => {
+                    return Err(anyhow::anyhow!(
+                        "Timed out waiting for '{}'; output was:\n{}",
+                        needle,
+                        String::from_utf8_lossy(&self.seen)
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Prompt drawn by `MockControl`'s shell, with the shell integration marks
+pub const MOCK_PROMPT: &str = "\x1b]133;A\x07$ \x1b]133;B\x07";
+
+/// A control socket in front of a scripted session, for testing clients of the socket
+/// without a PTY.
+///
+/// **Behaviour:**
+/// - Queued commands are taken from the queue and answered at once, like a shell with the
+///   integration installed: `ran: <command>` and status 0
+/// - `fail <n>` finishes with status `n`, `hang` prints nothing and never finishes
+/// - `unroutable` is dropped with `FailureCode::Unroutable` instead of being written
+/// - Bytes sent with `write` are kept for `input`
+pub struct MockControl {
+    pub socket: PathBuf,
+    pub context: Arc<ControlContext>,
+    input: mpsc::UnboundedReceiver<Vec<u8>>,
+    _shell: std_mpsc::Sender<Vec<u8>>,
+    _dir: TempDir,
+}
+
+impl MockControl {
+    /// Serve the control socket of a fresh session that has drawn its first prompt
+    pub async fn start() -> Result<Self> {
+        let dir = TempDir::new().context("Failed to create temporary directory")?;
+        let queue_dir = dir.path().join("mock");
+        std::fs::create_dir_all(&queue_dir).context("Failed to create queue directory")?;
+
+        let session = Arc::new(SessionContext::new(Terminator::Lf));
+        let (shell, output) = std_mpsc::channel();
+        let tee = OutputTee::new(64);
+        let subscriptions = tee.subscriptions();
+        let scrollback = create_scrollback(100);
+        {
+            let scrollback = scrollback.clone();
+            spawn_blocking_sink(tee.subscribe(), "scrollback", move |chunk| {
+                if let Ok(mut scrollback) = scrollback.lock() {
+                    scrollback.push(chunk);
+                }
+            });
+        }
+        tee.spawn_reader(Box::new(ChannelReader {
+            output,
+            pending: Vec::new(),
+        }));
+        session.arbiter().observe_output(MOCK_PROMPT.as_bytes());
+        let _ = shell.send(MOCK_PROMPT.as_bytes().to_vec());
+
+        let (input_sender, input) = mpsc::unbounded_channel();
+        let context = Arc::new(ControlContext {
+            session: session.clone(),
+            queue_dir: Some(queue_dir.clone()),
+            scrollback,
+            input: input_sender,
+            output: subscriptions,
+            rows: 24,
+            started_at: chrono::Utc::now(),
+            child_pid: None,
+            max_command_output: 1024,
+        });
+        tokio::spawn(answer_queue(queue_dir, session, shell.clone()));
+
+        let socket = dir.path().join("mock.sock");
+        tokio::spawn(control::serve(socket.clone(), context.clone()));
+        while !socket.exists() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        Ok(Self {
+            socket,
+            context,
+            input,
+            _shell: shell,
+            _dir: dir,
+        })
+    }
+
+    /// The next bytes sent to the shell with `write`, if any arrive within a second
+    pub async fn input(&mut self) -> Option<Vec<u8>> {
+        tokio::time::timeout(Duration::from_secs(1), self.input.recv())
+            .await
+            .ok()
+            .flatten()
+    }
+}
+
+/// Answer every queued command the way `MockControl` describes
+async fn answer_queue(
+    queue_dir: PathBuf,
+    session: Arc<SessionContext>,
+    shell: std_mpsc::Sender<Vec<u8>>,
+) {
+    loop {
+        let entries = std::fs::read_dir(&queue_dir)
+            .into_iter()
+            .flatten()
+            .flatten();
+        for entry in entries {
+            let file = entry.file_name().to_string_lossy().into_owned();
+            let Ok(contents) = std::fs::read_to_string(entry.path()) else {
+                continue;
+            };
+            let _ = std::fs::remove_file(entry.path());
+            let Ok(QueueMessage::Command { command, .. }) = QueueMessage::decode(&contents) else {
+                continue;
+            };
+            let source = format!("queue:{}", file);
+
+            if command == "unroutable" {
+                session.emit_queue_event(QueueEvent::Outcome {
+                    source,
+                    result: "failed: unroutable".to_string(),
+                    failure: Some(QueueFailure {
+                        file,
+                        code: FailureCode::Unroutable,
+                        error: "Unknown target session".to_string(),
+                        timestamp: chrono::Utc::now(),
+                    }),
+                });
+                continue;
+            }
+            session.emit_queue_event(QueueEvent::Writing { source });
+            let reply = if command == "hang" {
+                String::new()
+            } else if let Some(status) = command.strip_prefix("fail ") {
+                format!("\x1b]133;D;{}\x07{}", status, MOCK_PROMPT)
+            } else {
+                format!("ran: {}\r\n\x1b]133;D;0\x07{}", command, MOCK_PROMPT)
+            };
+            let _ = shell.send(format!("{}\r\n\x1b]133;C\x07{}", command, reply).into_bytes());
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+/// The PTY side of `MockControl`: reads block until the scripted shell prints something
+struct ChannelReader {
+    output: std_mpsc::Receiver<Vec<u8>>,
+    pending: Vec<u8>,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        if self.pending.is_empty() {
+            match self.output.recv() {
+                Ok(chunk) => self.pending = chunk,
+                Err(_) => return Ok(0),
+            }
+        }
+        let count = self.pending.len().min(buffer.len());
+        buffer[..count].copy_from_slice(&self.pending[..count]);
+        self.pending.drain(..count);
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FakeShell;
+    use crate::shell::osc::CommandTracker;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_queue_order_and_prompt_marks() {
+        let mut session = FakeShell::new().prompt("fake> ").start().await.unwrap();
+
+        // Written before the processor's next pass, so all three are pending at once
+        session.enqueue("1-first", "echo one").unwrap();
+        session.enqueue("2-second", "fail 2").unwrap();
+        session.enqueue("3-third", "echo three").unwrap();
+        session
+            .wait_for("ran: echo three\r\n\x1b]133;D;0", Duration::from_secs(10))
+            .await
+            .unwrap();
+
+        let output = String::from_utf8_lossy(session.output()).into_owned();
+        assert!(output.contains("\x1b]133;B\x07echo one"));
+        assert!(output.contains("fake> "));
+        let positions: Vec<usize> = ["ran: echo one", "ran: fail 2", "ran: echo three"]
+            .iter()
+            .map(|line| output.find(line).unwrap())
+            .collect();
+        assert!(positions.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let statuses: Vec<Option<i32>> = CommandTracker::new()
+            .feed(session.output())
+            .iter()
+            .map(|finished| finished.exit_status)
+            .collect();
+        assert_eq!(statuses, vec![Some(0), Some(2), Some(0)]);
+
+        // Nothing else is coming, so waiting must time out instead of hanging
+        assert!(session
+            .wait_for("never printed", Duration::from_millis(200))
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,241 @@
+use crate::shell::osc::CommandTracker;
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
+/// Longest OSC sequence held back while looking for its terminator; longer ones are passed
+/// through unexamined
+const MAX_OSC_LEN: usize = 4096;
+
+#[derive(Debug)]
+enum State {
+    Ground,
+    Escape,
+    /// Raw bytes of an OSC sequence so far, starting with `ESC ]`
+    Osc(Vec<u8>),
+    OscEscape(Vec<u8>),
+}
+
+/// Rewrites the window title the inner shell sets on its way to the outer terminal.
+///
+/// **Behaviour:**
+/// - With a template, OSC 0/1/2 title changes are replaced by the rendered template, which is
+///   also re-rendered when the working directory or the running command changes
+/// - With `suppress`, the inner title is dropped; `{title}` then stays empty
+/// - Every other escape sequence is passed through unchanged
+///
+/// **Placeholders:**
+/// - `{session}`: queue name of the session
+/// - `{title}`: the title the inner shell last set
+/// - `{cwd}`: the shell's working directory, from OSC 7 reports or `/proc/<pid>/cwd`
+/// - `{last_command}`: the running or most recent command (needs shell integration)
+pub struct TitleRewriter {
+    template: Option<String>,
+    suppress: bool,
+    session: String,
+    child_pid: Option<u32>,
+    state: State,
+    title: String,
+    reported_cwd: Option<String>,
+    last_command: String,
+    commands: CommandTracker,
+    shown: Option<String>,
+}
+
+impl TitleRewriter {
+    pub fn new(
+        template: Option<String>,
+        suppress: bool,
+        session: &str,
+        child_pid: Option<u32>,
+    ) -> Self {
+        Self {
+            template,
+            suppress,
+            session: session.to_string(),
+            child_pid,
+            state: State::Ground,
+            title: String::new(),
+            reported_cwd: None,
+            last_command: String::new(),
+            commands: CommandTracker::new(),
+            shown: None,
+        }
+    }
+
+    /// Bytes to write to the outer terminal for `chunk`
+    pub fn process<'a>(&mut self, chunk: &'a [u8]) -> Cow<'a, [u8]> {
+        let mut output = Vec::with_capacity(chunk.len());
+        let mut title_changed = self.shown.is_none();
+
+        for &byte in chunk {
+            self.state = match std::mem::replace(&mut self.state, State::Ground) {
+                State::Ground if byte == 0x1b => State::Escape,
+                State::Ground => {
+                    output.push(byte);
+                    State::Ground
+                }
+                State::Escape if byte == b']' => State::Osc(b"\x1b]".to_vec()),
+                State::Escape if byte == 0x1b => {
+                    output.push(0x1b);
+                    State::Escape
+                }
+                State::Escape => {
+                    output.extend_from_slice(&[0x1b, byte]);
+                    State::Ground
+                }
+                State::Osc(mut raw) if byte == 0x07 => {
+                    raw.push(byte);
+                    title_changed |= self.handle_osc(raw, 1, &mut output);
+                    State::Ground
+                }
+                State::Osc(mut raw) if byte == 0x1b => {
+                    raw.push(byte);
+                    State::OscEscape(raw)
+                }
+                State::OscEscape(mut raw) if byte == b'\\' => {
+                    raw.push(byte);
+                    title_changed |= self.handle_osc(raw, 2, &mut output);
+                    State::Ground
+                }
+                State::Osc(mut raw) | State::OscEscape(mut raw) => {
+                    raw.push(byte);
+                    if raw.len() >= MAX_OSC_LEN {
+                        output.extend_from_slice(&raw);
+                        State::Ground
+                    } else {
+                        State::Osc(raw)
+                    }
+                }
+            };
+        }
+
+        self.commands.feed(chunk);
+        if let Some(command) = self.commands.running_command() {
+            if command != self.last_command {
+                self.last_command = command.to_string();
+                title_changed = true;
+            }
+        }
+
+        if title_changed && matches!(self.state, State::Ground) {
+            if let Some(rendered) = self.render() {
+                if self.shown.as_ref() != Some(&rendered) {
+                    output.extend_from_slice(format!("\x1b]2;{}\x07", rendered).as_bytes());
+                    self.shown = Some(rendered);
+                }
+            }
+        }
+
+        if output == chunk {
+            Cow::Borrowed(chunk)
+        } else {
+            Cow::Owned(output)
+        }
+    }
+
+    /// Handle a complete OSC sequence ending in a terminator of `terminator_len` bytes,
+    /// returning whether the title needs rendering again
+    fn handle_osc(&mut self, raw: Vec<u8>, terminator_len: usize, output: &mut Vec<u8>) -> bool {
+        let payload = String::from_utf8_lossy(&raw[2..raw.len() - terminator_len]).into_owned();
+        let (code, value) = payload.split_once(';').unwrap_or((&payload, ""));
+
+        match code {
+            "0" | "1" | "2" => {
+                if self.template.is_none() && !self.suppress {
+                    output.extend_from_slice(&raw);
+                    return false;
+                }
+                if !self.suppress {
+                    self.title = value.to_string();
+                }
+                true
+            }
+            "7" => {
+                output.extend_from_slice(&raw);
+                self.reported_cwd = cwd_from_url(value);
+                true
+            }
+            _ => {
+                output.extend_from_slice(&raw);
+                false
+            }
+        }
+    }
+
+    fn render(&self) -> Option<String> {
+        let template = self.template.as_ref()?;
+        let rendered = template
+            .replace("{session}", &self.session)
+            .replace("{title}", &self.title)
+            .replace("{cwd}", &self.cwd())
+            .replace("{last_command}", &self.last_command);
+        // Control characters would end the title sequence early
+        Some(rendered.chars().filter(|c| !c.is_control()).collect())
+    }
+
+    fn cwd(&self) -> String {
+        let cwd = self.reported_cwd.clone().or_else(|| {
+            let pid = self.child_pid?;
+            let path: PathBuf = std::fs::read_link(format!("/proc/{}/cwd", pid)).ok()?;
+            Some(path.display().to_string())
+        });
+        let Some(cwd) = cwd else {
+            return String::new();
+        };
+        match std::env::var("HOME") {
+            Ok(home) if !home.is_empty() && Path::new(&cwd).starts_with(&home) => {
+                format!("~{}", &cwd[home.len()..])
+            }
+            _ => cwd,
+        }
+    }
+}
+
+/// The path of an OSC 7 `file://host/path` report, with percent-escapes decoded
+fn cwd_from_url(url: &str) -> Option<String> {
+    let rest = url.strip_prefix("file://")?;
+    let path = &rest[rest.find('/')?..];
+
+    let mut decoded = Vec::with_capacity(path.len());
+    let mut bytes = path.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte == b'%' {
+            let hex: String = bytes.by_ref().take(2).map(char::from).collect();
+            decoded.push(u8::from_str_radix(&hex, 16).ok()?);
+        } else {
+            decoded.push(byte);
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TitleRewriter;
+
+    #[test]
+    fn test_inner_titles_are_templated_or_suppressed() {
+        let mut rewriter = TitleRewriter::new(
+            Some("{session} — {cwd} — {title}".to_string()),
+            false,
+            "web",
+            None,
+        );
+        let output = rewriter.process(b"\x1b]7;file://host/srv/my%20app\x07\x1b]2;vi");
+        assert_eq!(&*output, b"\x1b]7;file://host/srv/my%20app\x07");
+        let output = rewriter.process(b"m\x1b\\ok\x1b[0m");
+        assert_eq!(
+            &*output,
+            "ok\x1b[0m\x1b]2;web — /srv/my app — vim\x07".as_bytes()
+        );
+
+        let mut suppressed = TitleRewriter::new(None, true, "web", None);
+        assert_eq!(
+            &*suppressed.process(b"a\x1b]0;user@host\x07b\x1b]133;A\x07"),
+            b"ab\x1b]133;A\x07"
+        );
+
+        let mut passthrough = TitleRewriter::new(None, false, "web", None);
+        assert_eq!(&*passthrough.process(b"\x1b]0;t\x07"), b"\x1b]0;t\x07");
+    }
+}
@@ -0,0 +1,132 @@
+use crate::shell::encryption::seal;
+use crate::shell::rotation::{rotate, rotation_policy};
+use anyhow::{Context, Result};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Records everything the wrapped shell writes to its PTY into a timestamped transcript file.
+///
+/// The transcript is fed from the PTY output task itself rather than from the attached terminal,
+/// so sessions without an interactive terminal (daemons, CI jobs) still produce a reviewable record.
+///
+/// **Format:**
+/// - Output is split into lines and each line is prefixed with the UTC time it was read
+/// - Partial lines are held back until their newline arrives (or the transcript is flushed)
+/// - Bytes are written as received, including ANSI escape sequences
+/// - The file is rotated according to the global `RotationPolicy`
+/// - With an encryption recipient set, each line is stored encrypted (see `encryption`)
+pub struct Transcript {
+    path: PathBuf,
+    file: File,
+    pending: Vec<u8>,
+    bytes_written: u64,
+    opened_at: SystemTime,
+}
+
+impl Transcript {
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = Self::open(path)?;
+        let bytes_written = file.metadata().map(|m| m.len()).unwrap_or(0);
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            pending: Vec::new(),
+            bytes_written,
+            opened_at: SystemTime::now(),
+        })
+    }
+
+    fn open(path: &Path) -> Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .context("Failed to open transcript file")
+    }
+
+    /// Append a chunk of PTY output, writing out every completed line
+    pub fn record(&mut self, bytes: &[u8]) -> Result<()> {
+        self.pending.extend_from_slice(bytes);
+
+        while let Some(newline) = self.pending.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=newline).collect();
+            self.write_line(&line)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write out any partial line that is still waiting for its newline
+    pub fn flush(&mut self) -> Result<()> {
+        if !self.pending.is_empty() {
+            let mut line = std::mem::take(&mut self.pending);
+            line.push(b'\n');
+            self.write_line(&line)?;
+        }
+        self.file.flush().context("Failed to flush transcript file")
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> Result<()> {
+        let timestamp = chrono::Utc::now().format("%Y-%m-%d %H:%M:%S%.3f UTC");
+        let mut record = format!("[{}] ", timestamp).into_bytes();
+        record.extend_from_slice(line);
+        let record = seal(&record)?;
+
+        self.file
+            .write_all(&record)
+            .context("Failed to write to transcript file")?;
+        self.bytes_written += record.len() as u64;
+
+        self.rotate_if_needed()
+    }
+
+    fn rotate_if_needed(&mut self) -> Result<()> {
+        let policy = rotation_policy();
+        if !policy.should_rotate(self.bytes_written, Some(self.opened_at)) {
+            return Ok(());
+        }
+
+        self.file
+            .flush()
+            .context("Failed to flush transcript file")?;
+        rotate(&self.path, policy.retain)?;
+        self.file = Self::open(&self.path)?;
+        self.bytes_written = 0;
+        self.opened_at = SystemTime::now();
+        Ok(())
+    }
+}
+
+impl Drop for Transcript {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Transcript;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_transcript_timestamps_complete_lines() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("session.transcript");
+
+        {
+            let mut transcript = Transcript::create(&path).unwrap();
+            transcript.record(b"first li").unwrap();
+            transcript.record(b"ne\nsecond line\npartial").unwrap();
+        }
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with('[') && lines[0].ends_with("] first line"));
+        assert!(lines[1].ends_with("] second line"));
+        assert!(lines[2].ends_with("] partial"));
+    }
+}
@@ -1,26 +1,185 @@
-use serde::{Deserialize, Serialize};
+use crate::shell::alerts::BellPolicy;
+use crate::shell::echo::AgentEcho;
+use crate::shell::filter::FilterSpec;
+use crate::shell::location::TpDir;
+use crate::shell::schedule::ScheduledCommand;
+use crate::shell::terminfo::DEFAULT_TERM;
+use crate::shell::watch::WatchRule;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+pub use crate::messages::{
+    CommandResult, FailureCode, QueueFailure, QueueMessage, Terminator, QUEUE_MESSAGE_VERSION,
+};
+
+/// Where the wrapped shell runs
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum SessionTarget {
+    /// Spawn the shell directly on this machine
+    #[default]
+    Local,
+    /// Run the shell inside an existing container via `<engine> exec -it <container>`
+    Container { engine: String, container: String },
+}
+
+impl FromStr for SessionTarget {
+    type Err = anyhow::Error;
+
+    /// Parses `local`, `docker:<container>` or `podman:<container>`
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.split_once(':') {
+            None if value == "local" => Ok(SessionTarget::Local),
+            Some((engine @ ("docker" | "podman"), container)) if !container.is_empty() => {
+                Ok(SessionTarget::Container {
+                    engine: engine.to_string(),
+                    container: container.to_string(),
+                })
+            }
+            _ => Err(anyhow::anyhow!(
+                "Invalid target '{}': expected local, docker:<container> or podman:<container>",
+                value
+            )),
+        }
+    }
+}
+
+/// What to do when typeypipe is started from a shell that already runs inside a session
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NestedPolicy {
+    /// Exit with an error instead of wrapping a second shell
+    #[default]
+    Refuse,
+    /// Start the inner session anyway, after a warning
+    Warn,
+    /// Start no shell; queue each line of stdin into the outer session instead
+    Route,
+}
+
+impl FromStr for NestedPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "refuse" => Ok(NestedPolicy::Refuse),
+            "warn" => Ok(NestedPolicy::Warn),
+            "route" => Ok(NestedPolicy::Route),
+            _ => Err(anyhow::anyhow!(
+                "Invalid nested policy '{}': expected refuse, warn or route",
+                value
+            )),
+        }
+    }
+}
 
 /// Configuration for shell creation
 #[derive(Debug, Clone)]
 pub struct ShellConfig {
     pub shell_path: String,
+    pub target: SessionTarget,
     pub cols: u16,
     pub rows: u16,
+    /// Number of output lines kept in the session scrollback (0 disables it)
+    pub scroll_buffer_size: usize,
+    /// Extra environment variables set in the shell, e.g. `TP_QUEUE_DIR`
+    pub env: Vec<(String, String)>,
+    /// Directory the shell starts in; the home directory when `None`
+    pub cwd: Option<PathBuf>,
+    /// `TERM` the shell is told it runs under
+    pub term: String,
+    /// `COLORTERM` for the shell, unset when `None`
+    pub colorterm: Option<String>,
 }
 
 impl Default for ShellConfig {
     fn default() -> Self {
         Self {
             shell_path: std::env::var("SHELL").unwrap_or_else(|_| "/bin/bash".to_string()),
+            target: SessionTarget::Local,
             cols: 80,
             rows: 24,
+            scroll_buffer_size: 10_000,
+            env: Vec::new(),
+            cwd: None,
+            term: DEFAULT_TERM.to_string(),
+            colorterm: std::env::var("COLORTERM").ok(),
         }
     }
 }
 
-/// Command execution result
-#[derive(Debug, Serialize, Deserialize)]
-pub struct CommandResult {
-    pub output: String,
-    pub success: bool,
-}
\ No newline at end of file
+/// Files and timing used by an interactive session on top of its PTY
+#[derive(Debug, Clone)]
+pub struct InteractiveOptions {
+    pub queue_dir: Option<PathBuf>,
+    pub log_file: Option<PathBuf>,
+    /// Record all PTY output here when set
+    pub transcript_file: Option<PathBuf>,
+    /// Append PTY output, passed through `output_filters`, to this file when set
+    pub output_file: Option<PathBuf>,
+    pub output_filters: Vec<FilterSpec>,
+    /// Queue a command when files matching a pattern change
+    pub watch_rules: Vec<WatchRule>,
+    /// Directory `watch_rules` patterns are relative to, the configured `--cwd`; typeypipe's
+    /// own working directory when unset
+    pub watch_root: Option<PathBuf>,
+    /// Queue commands on a recurring schedule
+    pub schedules: Vec<ScheduledCommand>,
+    /// Send a desktop notification for output lines containing any of these
+    pub notify_patterns: Vec<String>,
+    /// Forward or mute bells from the shell
+    pub bell: BellPolicy,
+    /// Alert on output after this long without any
+    pub activity_quiet: Option<Duration>,
+    /// Template for the outer terminal title, see `title::TitleRewriter`
+    pub title_template: Option<String>,
+    /// Drop title changes from the inner shell
+    pub suppress_inner_title: bool,
+    /// How the echo of automated input is shown, see `echo::EchoFilter`
+    pub agent_echo: AgentEcho,
+    /// Ask before sending a paste larger than this many bytes to the shell
+    pub paste_guard: Option<u64>,
+    /// Largest `CommandResult::output` returned by a control socket `run`; longer output is
+    /// cut with a marker
+    pub max_command_output: usize,
+    /// Periodically publish a `SessionStatus` snapshot here when set
+    pub status_file: Option<PathBuf>,
+    /// Listen for control requests on this Unix socket when set
+    pub control_socket: Option<PathBuf>,
+    /// Leave the terminal alone and speak JSON-RPC on stdin/stdout instead
+    pub control_stdio: bool,
+    /// Seconds to wait after user input before resuming queue processing
+    pub input_timeout_secs: u64,
+    /// Where the session's files live, recorded in the status file
+    pub tp_dir: Option<TpDir>,
+    /// Written to the shell at its first prompt, one at a time, before queue processing starts
+    pub init_commands: Vec<String>,
+}
+
+impl Default for InteractiveOptions {
+    fn default() -> Self {
+        Self {
+            queue_dir: None,
+            log_file: None,
+            transcript_file: None,
+            output_file: None,
+            output_filters: Vec::new(),
+            watch_rules: Vec::new(),
+            watch_root: None,
+            schedules: Vec::new(),
+            notify_patterns: Vec::new(),
+            bell: BellPolicy::default(),
+            activity_quiet: None,
+            title_template: None,
+            suppress_inner_title: false,
+            agent_echo: AgentEcho::default(),
+            paste_guard: None,
+            max_command_output: 64 * 1024,
+            status_file: None,
+            control_socket: None,
+            control_stdio: false,
+            input_timeout_secs: 30,
+            tp_dir: None,
+            init_commands: Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use crate::shell::queue::enqueue_file;
+use crate::shell::types::QueueMessage;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+/// How often watched paths are scanned for changes
+const WATCH_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Quiet period after the last change before the command is queued, unless a rule sets its own
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Queue `command` whenever a file matching `pattern` changes, as given to `--watch`
+/// in the form `PATTERN=COMMAND`.
+///
+/// **Patterns:**
+/// - Relative to the directory typeypipe was started in, or absolute
+/// - `*` and `?` match within one path component, `**` matches any number of components
+/// - Hidden directories (`.git`, `.tp`) are only entered when the pattern names them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchRule {
+    pub pattern: String,
+    pub command: String,
+    pub debounce: Duration,
+}
+
+impl FromStr for WatchRule {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.split_once('=') {
+            Some((pattern, command)) if !pattern.is_empty() && !command.trim().is_empty() => {
+                Ok(WatchRule {
+                    pattern: pattern.to_string(),
+                    command: command.to_string(),
+                    debounce: DEFAULT_DEBOUNCE,
+                })
+            }
+            _ => Err(anyhow::anyhow!(
+                "Invalid watch rule '{}': expected PATTERN=COMMAND, e.g. 'src/**=cargo test'",
+                value
+            )),
+        }
+    }
+}
+
+/// Scan the files matching `rule` until the task is aborted, queueing its command into
+/// `queue_dir` once changes have settled for the rule's debounce period.
+///
+/// While a queued command is still waiting in the queue, further changes do not queue
+/// it again; the pending run will see them.
+pub async fn run_watch(root: PathBuf, rule: WatchRule, queue_dir: PathBuf) -> Result<()> {
+    let message = QueueMessage::Command {
+        command: rule.command.clone(),
+        terminator: None,
+    }
+    .encode()?;
+
+    let mut snapshot = scan(&root, &rule.pattern);
+    let mut changed_at: Option<tokio::time::Instant> = None;
+    let mut pending: Option<PathBuf> = None;
+
+    loop {
+        tokio::time::sleep(WATCH_POLL_INTERVAL).await;
+
+        let current = scan(&root, &rule.pattern);
+        if current != snapshot {
+            snapshot = current;
+            changed_at = Some(tokio::time::Instant::now());
+        }
+
+        let settled = changed_at.is_some_and(|at| at.elapsed() >= rule.debounce);
+        if settled {
+            changed_at = None;
+            if !pending.as_deref().is_some_and(Path::exists) {
+                pending = Some(enqueue_file(&queue_dir, "watch", &message).await?);
+            }
+        }
+    }
+}
+
+/// Modification time and size of every file under `root` matching `pattern`
+fn scan(root: &Path, pattern: &str) -> HashMap<PathBuf, (Option<SystemTime>, u64)> {
+    let segments: Vec<&str> = pattern.split('/').filter(|s| !s.is_empty()).collect();
+    let literal = segments
+        .iter()
+        .take_while(|segment| !segment.contains(['*', '?']))
+        .count();
+
+    let mut base = if pattern.starts_with('/') {
+        PathBuf::from("/")
+    } else {
+        root.to_path_buf()
+    };
+    base.extend(&segments[..literal]);
+
+    let mut files = HashMap::new();
+    collect(&base, &segments[literal..], &mut files);
+    files
+}
+
+fn collect(path: &Path, pattern: &[&str], files: &mut HashMap<PathBuf, (Option<SystemTime>, u64)>) {
+    let Ok(metadata) = std::fs::metadata(path) else {
+        return;
+    };
+    if metadata.is_file() {
+        if pattern.is_empty() || pattern == ["**"] {
+            files.insert(
+                path.to_path_buf(),
+                (metadata.modified().ok(), metadata.len()),
+            );
+        }
+        return;
+    }
+    let Some((&segment, rest)) = pattern.split_first() else {
+        return;
+    };
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        let entry_path = entry.path();
+        if segment == "**" {
+            // `**` may match nothing, so the rest of the pattern applies here too
+            if name.starts_with('.') && !rest.first().is_some_and(|next| next.starts_with('.')) {
+                continue;
+            }
+            if rest
+                .first()
+                .is_some_and(|next| matches_component(next, &name))
+            {
+                collect(&entry_path, &rest[1..], files);
+            }
+            if entry_path.is_dir() || rest.is_empty() {
+                collect(&entry_path, pattern, files);
+            }
+        } else if matches_component(segment, &name) {
+            collect(&entry_path, rest, files);
+        }
+    }
+}
+
+/// Match one path component against a pattern using `*` and `?`
+fn matches_component(pattern: &str, name: &str) -> bool {
+    if name.starts_with('.') && !pattern.starts_with('.') {
+        return false;
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    // Position in the pattern after the last `*`, and the name position it was tried at
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((after, tried)) => {
+                    p = after;
+                    n = tried + 1;
+                    star = Some((after, tried + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run_watch, scan, WatchRule};
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_changes_queue_the_command_once_settled() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("project");
+        let queue_dir = temp_dir.path().join("queue");
+        std::fs::create_dir_all(root.join("src/shell")).unwrap();
+        std::fs::create_dir_all(root.join("src/.cache")).unwrap();
+        std::fs::create_dir_all(&queue_dir).unwrap();
+        for file in [
+            "src/main.rs",
+            "src/shell/mod.rs",
+            "src/.cache/x.rs",
+            "notes.md",
+        ] {
+            std::fs::write(root.join(file), "").unwrap();
+        }
+
+        let watched: Vec<_> = scan(&root, "src/**/*.rs").into_keys().collect();
+        assert_eq!(watched.len(), 2);
+        assert!(watched.contains(&root.join("src/shell/mod.rs")));
+        assert_eq!(scan(&root, "*.md").len(), 1);
+
+        let mut rule: WatchRule = "src/**=cargo test".parse().unwrap();
+        rule.debounce = Duration::from_millis(300);
+        assert!("src/**".parse::<WatchRule>().is_err());
+        let task = tokio::spawn(run_watch(root.clone(), rule, queue_dir.clone()));
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        // A burst of edits queues the command a single time
+        for _ in 0..3 {
+            std::fs::write(root.join("src/main.rs"), "fn main() {}").unwrap();
+            std::fs::write(root.join("src/shell/new.rs"), "").unwrap();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        std::fs::write(root.join("notes.md"), "unwatched").unwrap();
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+        task.abort();
+
+        let queued: Vec<_> = std::fs::read_dir(&queue_dir).unwrap().flatten().collect();
+        assert_eq!(queued.len(), 1);
+        let contents = std::fs::read_to_string(queued[0].path()).unwrap();
+        assert!(contents.contains("cargo test"));
+    }
+}
@@ -0,0 +1,86 @@
+/* Generated by cbindgen from typey-pipe-ffi/src/lib.rs; do not edit. */
+
+#ifndef TYPEYPIPE_H
+#define TYPEYPIPE_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// A running session, owned by the host between `tp_session_create` and
+// `tp_session_shutdown`
+typedef struct TpSession TpSession;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// The message of the last call that failed on this thread, or NULL. Valid until the next
+// failing call on the same thread.
+const char *tp_last_error(void);
+
+// Start `shell` (NULL for `$SHELL`) in a `cols` x `rows` PTY, injecting the files queued
+// in `queue_dir`. Returns NULL on failure; see `tp_last_error`.
+//
+// # Safety
+// `queue_dir` and `shell` must be NULL or point to NUL-terminated strings.
+struct TpSession *tp_session_create(const char *queue_dir,
+                                    const char *shell,
+                                    uint16_t cols,
+                                    uint16_t rows);
+
+// Queue `command`, to be submitted once the queue reaches it. Returns 0, or -1 on failure.
+//
+// # Safety
+// `session` must come from `tp_session_create` and `command` must be a NUL-terminated
+// string.
+int tp_session_enqueue(struct TpSession *session, const char *command);
+
+// Write `len` bytes to the shell right away, as if typed. Returns 0, or -1 on failure.
+//
+// # Safety
+// `session` must come from `tp_session_create` and `data` must point to `len` bytes.
+int tp_session_write(struct TpSession *session, const uint8_t *data, size_t len);
+
+// Copy up to `capacity` bytes of the shell's output, escape sequences included, into
+// `buffer` and their number into `*len`, waiting up to `timeout_ms` for some to arrive.
+// Returns 1 while the shell is running (`*len` is 0 when the wait ran out), 0 with `*len`
+// set to 0 once the shell has closed the PTY and all its output has been read, or -1 on
+// failure.
+//
+// # Safety
+// `session` must come from `tp_session_create`, `buffer` must have room for `capacity`
+// bytes and `len` must point to a `size_t`.
+int tp_session_poll_output(struct TpSession *session,
+                           uint8_t *buffer,
+                           size_t capacity,
+                           uint32_t timeout_ms,
+                           size_t *len);
+
+// The last `lines` complete lines of output, escape sequences included, separated by
+// `\n`. Free the result with `tp_string_free`.
+//
+// # Safety
+// `session` must come from `tp_session_create`.
+char *tp_session_read_screen(struct TpSession *session, size_t lines);
+
+// Free a string returned by this library. NULL is ignored.
+//
+// # Safety
+// `text` must be NULL or come from this library, and not be freed twice.
+void tp_string_free(char *text);
+
+// Let the queue drain, hang up on the shell, killing it if it outlives `grace_ms`, and free
+// the session. Returns the shell's exit code, or -1 on failure.
+//
+// # Safety
+// `session` must come from `tp_session_create` and is invalid after this call.
+int tp_session_shutdown(struct TpSession *session, uint32_t grace_ms);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* TYPEYPIPE_H */
@@ -0,0 +1,377 @@
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use typey_pipe::api::{OutputEvent, OutputEvents, SessionBuilder, SessionHandle};
+
+thread_local! {
+    /// Message of the last failed call on this thread, for `tp_last_error`
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(error: anyhow::Error) {
+    let message = format!("{:#}", error).replace('\0', " ");
+    LAST_ERROR.with(|last| *last.borrow_mut() = CString::new(message).ok());
+}
+
+/// `text` as a string, or an error naming `what` when it is NULL or not UTF-8
+unsafe fn borrow_str<'a>(text: *const c_char, what: &str) -> anyhow::Result<&'a str> {
+    if text.is_null() {
+        anyhow::bail!("{} is NULL", what);
+    }
+    CStr::from_ptr(text)
+        .to_str()
+        .map_err(|_| anyhow::anyhow!("{} is not UTF-8", what))
+}
+
+/// A running session, owned by the host between `tp_session_create` and
+/// `tp_session_shutdown`
+pub struct TpSession {
+    runtime: Runtime,
+    handle: SessionHandle,
+    events: Option<OutputEvents>,
+    /// Output read from the session but not yet returned by `tp_session_poll_output`
+    pending: Vec<u8>,
+}
+
+impl TpSession {
+    fn start(
+        queue_dir: &str,
+        shell: Option<&str>,
+        cols: u16,
+        rows: u16,
+    ) -> anyhow::Result<Box<Self>> {
+        let runtime = Runtime::new()?;
+        let mut builder = SessionBuilder::new(queue_dir).size(cols, rows);
+        if let Some(shell) = shell {
+            builder = builder.shell(shell);
+        }
+        let handle = runtime.block_on(builder.spawn())?;
+        let events = handle.events();
+        Ok(Box::new(Self {
+            runtime,
+            handle,
+            events,
+            pending: Vec::new(),
+        }))
+    }
+
+    /// Wait up to `timeout` for output when none is pending. Returns false once the shell
+    /// has closed the PTY and everything it wrote has been returned.
+    fn fill(&mut self, timeout: Duration) -> bool {
+        if !self.pending.is_empty() {
+            return true;
+        }
+        let Some(events) = self.events.as_mut() else {
+            return false;
+        };
+        let next = self.runtime.block_on(async {
+            tokio::time::timeout(timeout, async {
+                loop {
+                    match events.next().await {
+                        Some(OutputEvent::Output(bytes)) => return Some(bytes),
+                        Some(_) => continue,
+                        None => return None,
+                    }
+                }
+            })
+            .await
+        });
+        match next {
+            Ok(Some(bytes)) => {
+                self.pending = bytes.into();
+                true
+            }
+            Ok(None) => {
+                self.events = None;
+                false
+            }
+            Err(_) => true,
+        }
+    }
+}
+
+/// The message of the last call that failed on this thread, or NULL. Valid until the next
+/// failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn tp_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// Start `shell` (NULL for `$SHELL`) in a `cols` x `rows` PTY, injecting the files queued
+/// in `queue_dir`. Returns NULL on failure; see `tp_last_error`.
+///
+/// # Safety
+/// `queue_dir` and `shell` must be NULL or point to NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn tp_session_create(
+    queue_dir: *const c_char,
+    shell: *const c_char,
+    cols: u16,
+    rows: u16,
+) -> *mut TpSession {
+    let started = borrow_str(queue_dir, "queue_dir").and_then(|queue_dir| {
+        let shell = match shell.is_null() {
+            true => None,
+            false => Some(borrow_str(shell, "shell")?),
+        };
+        TpSession::start(queue_dir, shell, cols, rows)
+    });
+    match started {
+        Ok(session) => Box::into_raw(session),
+        Err(e) => {
+            set_last_error(e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Queue `command`, to be submitted once the queue reaches it. Returns 0, or -1 on failure.
+///
+/// # Safety
+/// `session` must come from `tp_session_create` and `command` must be a NUL-terminated
+/// string.
+#[no_mangle]
+pub unsafe extern "C" fn tp_session_enqueue(
+    session: *mut TpSession,
+    command: *const c_char,
+) -> c_int {
+    let Some(session) = session.as_ref() else {
+        set_last_error(anyhow::anyhow!("session is NULL"));
+        return -1;
+    };
+    let queued = borrow_str(command, "command").and_then(|command| {
+        session
+            .runtime
+            .block_on(session.handle.queue().command(command))
+    });
+    match queued {
+        Ok(_) => 0,
+        Err(e) => {
+            set_last_error(e);
+            -1
+        }
+    }
+}
+
+/// Write `len` bytes to the shell right away, as if typed. Returns 0, or -1 on failure.
+///
+/// # Safety
+/// `session` must come from `tp_session_create` and `data` must point to `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn tp_session_write(
+    session: *mut TpSession,
+    data: *const u8,
+    len: usize,
+) -> c_int {
+    let Some(session) = session.as_ref() else {
+        set_last_error(anyhow::anyhow!("session is NULL"));
+        return -1;
+    };
+    let bytes = match len {
+        0 => &[][..],
+        _ => std::slice::from_raw_parts(data, len),
+    };
+    match session.runtime.block_on(session.handle.write(bytes)) {
+        Ok(()) => 0,
+        Err(e) => {
+            set_last_error(e);
+            -1
+        }
+    }
+}
+
+/// Copy up to `capacity` bytes of the shell's output, escape sequences included, into
+/// `buffer` and their number into `*len`, waiting up to `timeout_ms` for some to arrive.
+/// Returns 1 while the shell is running (`*len` is 0 when the wait ran out), 0 with `*len`
+/// set to 0 once the shell has closed the PTY and all its output has been read, or -1 on
+/// failure.
+///
+/// # Safety
+/// `session` must come from `tp_session_create`, `buffer` must have room for `capacity`
+/// bytes and `len` must point to a `size_t`.
+#[no_mangle]
+pub unsafe extern "C" fn tp_session_poll_output(
+    session: *mut TpSession,
+    buffer: *mut u8,
+    capacity: usize,
+    timeout_ms: u32,
+    len: *mut usize,
+) -> c_int {
+    let Some(session) = session.as_mut() else {
+        set_last_error(anyhow::anyhow!("session is NULL"));
+        return -1;
+    };
+    let Some(len) = len.as_mut() else {
+        set_last_error(anyhow::anyhow!("len is NULL"));
+        return -1;
+    };
+    if buffer.is_null() && capacity > 0 {
+        set_last_error(anyhow::anyhow!("buffer is NULL"));
+        return -1;
+    }
+    *len = 0;
+    if !session.fill(Duration::from_millis(timeout_ms as u64)) {
+        return 0;
+    }
+    let count = session.pending.len().min(capacity);
+    if count > 0 {
+        std::ptr::copy_nonoverlapping(session.pending.as_ptr(), buffer, count);
+        session.pending.drain(..count);
+    }
+    *len = count;
+    1
+}
+
+/// The last `lines` complete lines of output, escape sequences included, separated by
+/// `\n`. Free the result with `tp_string_free`.
+///
+/// # Safety
+/// `session` must come from `tp_session_create`.
+#[no_mangle]
+pub unsafe extern "C" fn tp_session_read_screen(
+    session: *mut TpSession,
+    lines: usize,
+) -> *mut c_char {
+    let Some(session) = session.as_ref() else {
+        set_last_error(anyhow::anyhow!("session is NULL"));
+        return std::ptr::null_mut();
+    };
+    let text = session
+        .handle
+        .scrollback(lines)
+        .join("\n")
+        .replace('\0', "");
+    CString::new(text).map_or(std::ptr::null_mut(), CString::into_raw)
+}
+
+/// Free a string returned by this library. NULL is ignored.
+///
+/// # Safety
+/// `text` must be NULL or come from this library, and not be freed twice.
+#[no_mangle]
+pub unsafe extern "C" fn tp_string_free(text: *mut c_char) {
+    if !text.is_null() {
+        drop(CString::from_raw(text));
+    }
+}
+
+/// Let the queue drain, hang up on the shell, killing it if it outlives `grace_ms`, and free
+/// the session. Returns the shell's exit code, or -1 on failure.
+///
+/// # Safety
+/// `session` must come from `tp_session_create` and is invalid after this call.
+#[no_mangle]
+pub unsafe extern "C" fn tp_session_shutdown(session: *mut TpSession, grace_ms: u32) -> c_int {
+    if session.is_null() {
+        set_last_error(anyhow::anyhow!("session is NULL"));
+        return -1;
+    }
+    let mut session = Box::from_raw(session);
+    let grace = Duration::from_millis(grace_ms as u64);
+    let TpSession {
+        runtime, handle, ..
+    } = &mut *session;
+    match runtime.block_on(handle.shutdown(grace)) {
+        Ok(code) => code as c_int,
+        Err(e) => {
+            set_last_error(e);
+            -1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn test_session_is_driven_through_the_c_abi() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let queue_dir = CString::new(temp_dir.path().join("ffi").to_str().unwrap()).unwrap();
+        let shell = CString::new("/bin/sh").unwrap();
+        unsafe {
+            assert!(tp_session_create(std::ptr::null(), shell.as_ptr(), 80, 24).is_null());
+            assert!(!tp_last_error().is_null());
+
+            let session = tp_session_create(queue_dir.as_ptr(), shell.as_ptr(), 80, 24);
+            assert!(!session.is_null());
+            let command = CString::new("echo ffi-$((40 + 2))").unwrap();
+            assert_eq!(tp_session_enqueue(session, command.as_ptr()), 0);
+
+            let mut seen = Vec::new();
+            let mut buffer = [0u8; 16];
+            let mut len = 0;
+            let started = Instant::now();
+            while !String::from_utf8_lossy(&seen).contains("ffi-42") {
+                assert!(started.elapsed() < Duration::from_secs(10));
+                let polled = tp_session_poll_output(
+                    session,
+                    buffer.as_mut_ptr(),
+                    buffer.len(),
+                    100,
+                    &mut len,
+                );
+                assert_eq!(polled, 1);
+                seen.extend_from_slice(&buffer[..len]);
+            }
+
+            // The scrollback takes in output on its own thread, shortly after the poll sees it
+            loop {
+                let screen = tp_session_read_screen(session, 5);
+                let shown = CStr::from_ptr(screen).to_str().unwrap().contains("ffi-42");
+                tp_string_free(screen);
+                if shown {
+                    break;
+                }
+                assert!(started.elapsed() < Duration::from_secs(10));
+                std::thread::sleep(Duration::from_millis(10));
+            }
+
+            let exit = CString::new("exit 3").unwrap();
+            assert_eq!(tp_session_write(session, exit.as_ptr().cast(), 6), 0);
+            assert_eq!(tp_session_write(session, b"\n".as_ptr(), 1), 0);
+            while tp_session_poll_output(session, buffer.as_mut_ptr(), buffer.len(), 100, &mut len)
+                == 1
+            {
+                assert!(started.elapsed() < Duration::from_secs(20));
+            }
+            // The end of output is reported again rather than as an error
+            len = 7;
+            assert_eq!(
+                tp_session_poll_output(session, buffer.as_mut_ptr(), buffer.len(), 0, &mut len),
+                0
+            );
+            assert_eq!(len, 0);
+            assert_eq!(tp_session_shutdown(session, 5000), 3);
+        }
+    }
+
+    #[test]
+    fn test_poll_output_rejects_a_null_session() {
+        let mut buffer = [0u8; 16];
+        let mut len = 7;
+        unsafe {
+            assert_eq!(
+                tp_session_poll_output(
+                    std::ptr::null_mut(),
+                    buffer.as_mut_ptr(),
+                    buffer.len(),
+                    0,
+                    &mut len
+                ),
+                -1
+            );
+            assert_eq!(
+                CStr::from_ptr(tp_last_error()).to_str().unwrap(),
+                "session is NULL"
+            );
+        }
+        assert_eq!(len, 7);
+    }
+}
@@ -0,0 +1,271 @@
+// pyo3 0.22's #[pymethods] expansion converts every PyResult error into itself
+#![allow(clippy::useless_conversion)]
+
+use pyo3::exceptions::{PyRuntimeError, PyTimeoutError};
+use pyo3::prelude::*;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use typey_pipe::api::{strip_ansi, OutputEvent, OutputEvents, SessionBuilder, SessionHandle};
+
+/// Printed after each `run` command with its exit status, so its output can be cut out of
+/// the stream. The echoed command line holds `%s` where the output holds the digits.
+const MARKER_PREFIX: &str = "__typeypipe_done_";
+
+fn runtime_error(error: anyhow::Error) -> PyErr {
+    PyRuntimeError::new_err(format!("{:#}", error))
+}
+
+/// `text` quoted for a POSIX shell
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+/// Where the exit status marker `marker` was printed in `text`: its start, end and the status
+fn find_marker(text: &str, marker: &str) -> Option<(usize, usize, i32)> {
+    let mut from = 0;
+    while let Some(found) = text[from..].find(marker) {
+        let start = from + found;
+        let digits_start = start + marker.len();
+        let digits: String = text[digits_start..]
+            .chars()
+            .take_while(|c| c.is_ascii_digit())
+            .collect();
+        let after = &text[digits_start + digits.len()..];
+        if !digits.is_empty() && (after.starts_with('\n') || after.starts_with("\r\n")) {
+            let end = digits_start + digits.len() + if after.starts_with('\n') { 1 } else { 2 };
+            return Some((start, end, digits.parse().ok()?));
+        }
+        from = digits_start;
+    }
+    None
+}
+
+/// The output of a `run` command in `text`, which starts with the echoed command line and
+/// ends where the exit status marker was printed
+fn command_output(text: &str) -> String {
+    let output = text.split_once('\n').map_or("", |(_, rest)| rest);
+    // The marker's own leading newline
+    let output = output
+        .strip_suffix("\r\n")
+        .or_else(|| output.strip_suffix('\n'))
+        .unwrap_or(output);
+    strip_ansi(&output.replace("\r\n", "\n"))
+}
+
+/// A shell running in a PTY with a typeypipe queue, driven from Python.
+///
+/// `run` and `expect` block until the shell has printed what they wait for or `timeout`
+/// seconds have passed. Output is kept between calls, so nothing printed in between is lost.
+#[pyclass(module = "typeypipe")]
+struct Session {
+    runtime: Runtime,
+    handle: Option<SessionHandle>,
+    events: OutputEvents,
+    /// Output not yet returned by `run` or `expect`
+    pending: Vec<u8>,
+    commands_run: u64,
+}
+
+impl Session {
+    fn handle(&self) -> PyResult<&SessionHandle> {
+        self.handle
+            .as_ref()
+            .ok_or_else(|| PyRuntimeError::new_err("The session is closed"))
+    }
+
+    /// Read output into `pending` until `done` finds what it waits for in it
+    fn read_until<T: Send>(
+        &mut self,
+        py: Python<'_>,
+        timeout: f64,
+        mut done: impl FnMut(&str) -> Option<T> + Send,
+    ) -> PyResult<T> {
+        let Self {
+            runtime,
+            events,
+            pending,
+            ..
+        } = self;
+        let found = py.allow_threads(|| {
+            runtime.block_on(async {
+                tokio::time::timeout(Duration::from_secs_f64(timeout.max(0.0)), async {
+                    loop {
+                        if let Some(found) = done(&String::from_utf8_lossy(pending)) {
+                            return Some(found);
+                        }
+                        match events.next().await {
+                            Some(OutputEvent::Output(bytes)) => pending.extend_from_slice(&bytes),
+                            Some(_) => {}
+                            None => return None,
+                        }
+                    }
+                })
+                .await
+            })
+        });
+        match found {
+            Ok(Some(found)) => Ok(found),
+            Ok(None) => Err(PyRuntimeError::new_err("The shell exited")),
+            Err(_) => Err(PyTimeoutError::new_err(format!(
+                "Nothing matched within {} seconds",
+                timeout
+            ))),
+        }
+    }
+}
+
+#[pymethods]
+impl Session {
+    /// Start `shell` (default `$SHELL`) with its queue in `queue_dir`
+    #[new]
+    #[pyo3(signature = (queue_dir, shell=None, cwd=None, env=None, cols=80, rows=24))]
+    fn new(
+        py: Python<'_>,
+        queue_dir: &str,
+        shell: Option<&str>,
+        cwd: Option<&str>,
+        env: Option<HashMap<String, String>>,
+        cols: u16,
+        rows: u16,
+    ) -> PyResult<Self> {
+        let runtime = Runtime::new()?;
+        let mut builder = SessionBuilder::new(queue_dir).size(cols, rows);
+        if let Some(shell) = shell {
+            builder = builder.shell(shell);
+        }
+        if let Some(cwd) = cwd {
+            builder = builder.cwd(cwd);
+        }
+        for (name, value) in env.unwrap_or_default() {
+            builder = builder.env(name, value);
+        }
+        let handle = py
+            .allow_threads(|| runtime.block_on(builder.spawn()))
+            .map_err(runtime_error)?;
+        let events = handle
+            .events()
+            .ok_or_else(|| PyRuntimeError::new_err("The shell exited on startup"))?;
+        Ok(Self {
+            runtime,
+            handle: Some(handle),
+            events,
+            pending: Vec::new(),
+            commands_run: 0,
+        })
+    }
+
+    /// Run `command` and return `(output, exit_status)`. Output printed before the call is
+    /// dropped. Needs a POSIX shell (sh, bash, zsh).
+    #[pyo3(signature = (command, timeout=30.0))]
+    fn run(&mut self, py: Python<'_>, command: &str, timeout: f64) -> PyResult<(String, i32)> {
+        self.commands_run += 1;
+        let marker = format!("{}{}_", MARKER_PREFIX, self.commands_run);
+        let line = format!(
+            "eval {}; printf '\\n{}%s\\n' \"$?\"\n",
+            shell_quote(command),
+            marker
+        );
+        self.pending.clear();
+        let handle = self.handle()?;
+        self.runtime
+            .block_on(handle.write(line.as_bytes()))
+            .map_err(runtime_error)?;
+
+        self.read_until(py, timeout, |text| {
+            find_marker(text, &marker).map(|(start, end, status)| {
+                (command_output(&text[..start]), status, text[..end].len())
+            })
+        })
+        .map(|(output, status, consumed)| {
+            let rest = String::from_utf8_lossy(&self.pending)[consumed..].to_string();
+            self.pending = rest.into_bytes();
+            (output, status)
+        })
+    }
+
+    /// Wait until the shell prints `text` and return the output up to and including it
+    #[pyo3(signature = (text, timeout=30.0))]
+    fn expect(&mut self, py: Python<'_>, text: &str, timeout: f64) -> PyResult<String> {
+        let end = self.read_until(py, timeout, |output| {
+            output.find(text).map(|start| start + text.len())
+        })?;
+        let output = String::from_utf8_lossy(&self.pending).to_string();
+        self.pending = output.as_bytes()[end..].to_vec();
+        Ok(output[..end].to_string())
+    }
+
+    /// Write `data` to the shell as if typed, without a trailing Enter
+    fn send(&self, data: &str) -> PyResult<()> {
+        let handle = self.handle()?;
+        self.runtime
+            .block_on(handle.write(data.as_bytes()))
+            .map_err(runtime_error)
+    }
+
+    /// Add `command` to the session's queue and return the queue file's path
+    fn enqueue(&self, command: &str) -> PyResult<String> {
+        let queue = self.handle()?.queue();
+        let path = self
+            .runtime
+            .block_on(queue.command(command))
+            .map_err(runtime_error)?;
+        Ok(path.display().to_string())
+    }
+
+    /// The last `count` lines of output
+    #[pyo3(signature = (count=100))]
+    fn scrollback(&self, count: usize) -> PyResult<Vec<String>> {
+        Ok(self.handle()?.scrollback(count))
+    }
+
+    /// Let the queue drain, hang up on the shell and return its exit code; the shell is
+    /// killed if it is still running after `grace` seconds
+    #[pyo3(signature = (grace=5.0))]
+    fn close(&mut self, py: Python<'_>, grace: f64) -> PyResult<Option<u32>> {
+        let Some(mut handle) = self.handle.take() else {
+            return Ok(None);
+        };
+        let runtime = &self.runtime;
+        py.allow_threads(|| {
+            runtime.block_on(handle.shutdown(Duration::from_secs_f64(grace.max(0.0))))
+        })
+        .map(Some)
+        .map_err(runtime_error)
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    #[pyo3(signature = (*_args))]
+    fn __exit__(
+        &mut self,
+        py: Python<'_>,
+        _args: &Bound<'_, pyo3::types::PyTuple>,
+    ) -> PyResult<()> {
+        self.close(py, 5.0).map(|_| ())
+    }
+}
+
+#[pymodule]
+fn typeypipe(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<Session>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{command_output, find_marker, shell_quote};
+
+    #[test]
+    fn test_run_output_is_cut_at_the_marker() {
+        let marker = "__typeypipe_done_1_";
+        let text = "$ eval 'ls'; printf '\\n__typeypipe_done_1_%s\\n' \"$?\"\r\n\
+                    a.txt\r\nb.txt\r\n\r\n__typeypipe_done_1_2\r\n$ ";
+        let (start, end, status) = find_marker(text, marker).unwrap();
+        assert_eq!(status, 2);
+        assert_eq!(&text[end..], "$ ");
+        assert_eq!(command_output(&text[..start]), "a.txt\nb.txt\n");
+        assert_eq!(shell_quote("echo 'hi' # note"), r"'echo '\''hi'\'' # note'");
+    }
+}